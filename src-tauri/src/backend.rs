@@ -0,0 +1,120 @@
+use futures::future::BoxFuture;
+use std::error::Error;
+use tokio::sync::watch;
+
+use crate::ollama::{
+    ChatCompletion, ChatOptions, ModelSummary, OllamaClient, OllamaMessage, RetryPolicy,
+    StreamChunk,
+};
+
+pub type ChunkCallback = Box<dyn Fn(StreamChunk) + Send + Sync>;
+pub type RetryCallback = Box<dyn Fn(u32) + Send + Sync>;
+
+/// The kind of server a profile points at.
+pub const BACKEND_OLLAMA: &str = "ollama";
+pub const BACKEND_OPENAI: &str = "openai";
+
+/// A server that can stream chat completions. Messages and options use Ollama's
+/// shapes; other backends translate them, so callers don't need to know which
+/// one is active.
+pub trait ChatBackend: Send + Sync {
+    fn base_url(&self) -> &str;
+
+    fn retry_policy(&self) -> &RetryPolicy;
+
+    fn chat<'a>(
+        &'a self,
+        model: &'a str,
+        messages: Vec<OllamaMessage>,
+        options: ChatOptions,
+        cancel: Option<watch::Receiver<bool>>,
+        callback: ChunkCallback,
+        on_retry: RetryCallback,
+    ) -> BoxFuture<'a, Result<ChatCompletion, Box<dyn Error + Send + Sync>>>;
+
+    fn list_model_names(&self) -> BoxFuture<'_, Result<Vec<String>, Box<dyn Error + Send + Sync>>>;
+
+    /// Installed models. Servers that only report names give summaries with
+    /// nothing but the name.
+    fn list_models(
+        &self,
+    ) -> BoxFuture<'_, Result<Vec<ModelSummary>, Box<dyn Error + Send + Sync>>> {
+        Box::pin(async move {
+            let names = self.list_model_names().await?;
+            Ok(names
+                .into_iter()
+                .map(|name| ModelSummary {
+                    name,
+                    size: 0,
+                    digest: None,
+                    modified_at: None,
+                    details: Default::default(),
+                })
+                .collect())
+        })
+    }
+
+    /// The model's context window in tokens, if the server reports it.
+    fn context_length<'a>(
+        &'a self,
+        _model: &'a str,
+    ) -> BoxFuture<'a, Result<Option<u64>, Box<dyn Error + Send + Sync>>> {
+        Box::pin(async { Ok(None) })
+    }
+
+    /// What the model supports, e.g. "vision" or "tools", if the server reports it.
+    fn capabilities<'a>(
+        &'a self,
+        _model: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Vec<String>>, Box<dyn Error + Send + Sync>>> {
+        Box::pin(async { Ok(None) })
+    }
+}
+
+impl ChatBackend for OllamaClient {
+    fn base_url(&self) -> &str {
+        OllamaClient::base_url(self)
+    }
+
+    fn retry_policy(&self) -> &RetryPolicy {
+        OllamaClient::retry_policy(self)
+    }
+
+    fn chat<'a>(
+        &'a self,
+        model: &'a str,
+        messages: Vec<OllamaMessage>,
+        options: ChatOptions,
+        cancel: Option<watch::Receiver<bool>>,
+        callback: ChunkCallback,
+        on_retry: RetryCallback,
+    ) -> BoxFuture<'a, Result<ChatCompletion, Box<dyn Error + Send + Sync>>> {
+        Box::pin(OllamaClient::chat(
+            self, model, messages, options, cancel, callback, on_retry,
+        ))
+    }
+
+    fn list_model_names(&self) -> BoxFuture<'_, Result<Vec<String>, Box<dyn Error + Send + Sync>>> {
+        Box::pin(OllamaClient::list_model_names(self))
+    }
+
+    fn list_models(
+        &self,
+    ) -> BoxFuture<'_, Result<Vec<ModelSummary>, Box<dyn Error + Send + Sync>>> {
+        Box::pin(OllamaClient::list_models(self))
+    }
+
+    fn context_length<'a>(
+        &'a self,
+        model: &'a str,
+    ) -> BoxFuture<'a, Result<Option<u64>, Box<dyn Error + Send + Sync>>> {
+        Box::pin(async move { Ok(self.show_model(model).await?.context_length) })
+    }
+
+    fn capabilities<'a>(
+        &'a self,
+        model: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Vec<String>>, Box<dyn Error + Send + Sync>>> {
+        Box::pin(async move { Ok(Some(self.show_model(model).await?.capabilities)) })
+    }
+}
@@ -0,0 +1,481 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::watch;
+
+/// A generation waiting for the one running in its thread to finish. Its user
+/// message is already saved.
+#[derive(Debug, Clone)]
+pub struct QueuedGeneration {
+    /// The user message to answer
+    pub message_id: i64,
+    pub model: String,
+    pub response_format: Option<serde_json::Value>,
+    pub think: Option<bool>,
+    pub num_predict: Option<i64>,
+}
+
+#[derive(Default)]
+struct Inner {
+    // Threads with a response being generated, and the signal that stops it
+    running: HashMap<i64, watch::Sender<bool>>,
+    queued: HashMap<i64, VecDeque<QueuedGeneration>>,
+}
+
+/// Threads that currently have a response being generated. Two generations in
+/// the same thread would interleave their stream events and both be saved, so
+/// new messages either wait in the thread's queue or are rejected.
+#[derive(Default, Clone)]
+pub struct BusyThreads {
+    inner: Arc<Mutex<Inner>>,
+}
+
+/// Marks a thread as busy until dropped, so the mark is cleared on every path,
+/// errors included.
+pub struct ThreadGuard {
+    inner: Arc<Mutex<Inner>>,
+    thread_id: i64,
+    released: bool,
+}
+
+pub enum Admission {
+    Started(ThreadGuard),
+    /// Another generation is running; holds the queue length including this one
+    Queued(usize),
+}
+
+impl BusyThreads {
+    fn lock(&self) -> Result<MutexGuard<'_, Inner>, String> {
+        self.inner
+            .lock()
+            .map_err(|_| "Failed to lock busy threads".to_string())
+    }
+
+    fn guard(&self, inner: &mut Inner, thread_id: i64) -> ThreadGuard {
+        inner.running.insert(thread_id, watch::channel(false).0);
+        ThreadGuard {
+            inner: Arc::clone(&self.inner),
+            thread_id,
+            released: false,
+        }
+    }
+
+    pub fn acquire(&self, thread_id: i64) -> Result<ThreadGuard, String> {
+        let mut inner = self.lock()?;
+        if inner.running.contains_key(&thread_id) {
+            return Err("A response is already being generated in this thread".to_string());
+        }
+        Ok(self.guard(&mut inner, thread_id))
+    }
+
+    /// Marks the thread busy, or queues `next` behind the generation already running.
+    pub fn acquire_or_queue(
+        &self,
+        thread_id: i64,
+        next: QueuedGeneration,
+    ) -> Result<Admission, String> {
+        let mut inner = self.lock()?;
+        if inner.running.contains_key(&thread_id) {
+            let queue = inner.queued.entry(thread_id).or_default();
+            queue.push_back(next);
+            return Ok(Admission::Queued(queue.len()));
+        }
+        Ok(Admission::Started(self.guard(&mut inner, thread_id)))
+    }
+
+    pub fn is_busy(&self, thread_id: i64) -> bool {
+        self.lock()
+            .map(|inner| inner.running.contains_key(&thread_id))
+            .unwrap_or(false)
+    }
+
+    /// Whether nothing is being generated or waiting to be, in any thread.
+    pub fn is_idle(&self) -> bool {
+        self.lock()
+            .map(|inner| inner.running.is_empty() && inner.queued.is_empty())
+            .unwrap_or(false)
+    }
+
+    pub fn queue_len(&self, thread_id: i64) -> usize {
+        self.lock()
+            .map(|inner| inner.queued.get(&thread_id).map_or(0, VecDeque::len))
+            .unwrap_or(0)
+    }
+
+    /// A receiver that is set when the thread's generation is stopped.
+    pub fn cancel_signal(&self, thread_id: i64) -> Option<watch::Receiver<bool>> {
+        self.lock()
+            .ok()?
+            .running
+            .get(&thread_id)
+            .map(watch::Sender::subscribe)
+    }
+
+    /// Stops the running generation and drops everything queued behind it.
+    /// Returns whether a generation was running.
+    pub fn stop(&self, thread_id: i64) -> Result<bool, String> {
+        let mut inner = self.lock()?;
+        inner.queued.remove(&thread_id);
+        Ok(match inner.running.get(&thread_id) {
+            Some(cancel) => {
+                cancel.send_replace(true);
+                true
+            }
+            None => false,
+        })
+    }
+}
+
+impl ThreadGuard {
+    /// Takes the next queued generation, keeping the thread busy for it. With an
+    /// empty queue the thread is released in the same step, so nothing can be
+    /// queued behind a generation that is already done.
+    pub fn next_queued(&mut self) -> Option<QueuedGeneration> {
+        if self.released {
+            return None;
+        }
+        let mut inner = self.inner.lock().ok()?;
+        let next = inner
+            .queued
+            .get_mut(&self.thread_id)
+            .and_then(VecDeque::pop_front);
+        match next {
+            Some(next) => {
+                // A stop only applies to what was running or queued at the time
+                if let Some(cancel) = inner.running.get(&self.thread_id) {
+                    cancel.send_replace(false);
+                }
+                Some(next)
+            }
+            None => {
+                inner.queued.remove(&self.thread_id);
+                inner.running.remove(&self.thread_id);
+                self.released = true;
+                None
+            }
+        }
+    }
+}
+
+impl Drop for ThreadGuard {
+    fn drop(&mut self) {
+        if self.released {
+            return;
+        }
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.running.remove(&self.thread_id);
+            inner.queued.remove(&self.thread_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{ChatBackend, ChunkCallback, RetryCallback};
+    use crate::db::Database;
+    use crate::ollama::{
+        cancelled, ChatCompletion, ChatOptions, OllamaError, OllamaMessage, RetryPolicy, Role,
+    };
+    use futures::future::BoxFuture;
+    use std::error::Error;
+    use std::time::Duration;
+
+    /// Answers with the model name after a delay, and fails for the model "fail".
+    /// Records each model it was asked for.
+    struct SlowBackend {
+        delay: Duration,
+        retry_policy: RetryPolicy,
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl ChatBackend for SlowBackend {
+        fn base_url(&self) -> &str {
+            "http://mock"
+        }
+
+        fn retry_policy(&self) -> &RetryPolicy {
+            &self.retry_policy
+        }
+
+        fn chat<'a>(
+            &'a self,
+            model: &'a str,
+            _messages: Vec<OllamaMessage>,
+            _options: ChatOptions,
+            cancel: Option<watch::Receiver<bool>>,
+            callback: ChunkCallback,
+            _on_retry: RetryCallback,
+        ) -> BoxFuture<'a, Result<ChatCompletion, Box<dyn Error + Send + Sync>>> {
+            Box::pin(async move {
+                self.calls.lock().unwrap().push(model.to_string());
+                let mut cancel = cancel.unwrap_or_else(|| watch::channel(false).1);
+                tokio::select! {
+                    _ = tokio::time::sleep(self.delay) => {}
+                    _ = cancelled(&mut cancel) => {
+                        return Err(OllamaError::Cancelled {
+                            partial: String::new(),
+                        }
+                        .into())
+                    }
+                }
+                if model == "fail" {
+                    return Err("stream broke".into());
+                }
+                callback(crate::ollama::StreamChunk::Content(model.to_string()));
+                Ok(ChatCompletion {
+                    content: model.to_string(),
+                    ..Default::default()
+                })
+            })
+        }
+
+        fn list_model_names(
+            &self,
+        ) -> BoxFuture<'_, Result<Vec<String>, Box<dyn Error + Send + Sync>>> {
+            Box::pin(async { Ok(Vec::new()) })
+        }
+    }
+
+    fn slow_backend() -> Arc<SlowBackend> {
+        Arc::new(SlowBackend {
+            delay: Duration::from_millis(200),
+            retry_policy: RetryPolicy::default(),
+            calls: Mutex::new(Vec::new()),
+        })
+    }
+
+    async fn chat(
+        busy: &BusyThreads,
+        backend: &dyn ChatBackend,
+        thread_id: i64,
+        model: &str,
+    ) -> Result<String, String> {
+        let completion = backend
+            .chat(
+                model,
+                Vec::new(),
+                ChatOptions::default(),
+                busy.cancel_signal(thread_id),
+                Box::new(|_| {}),
+                Box::new(|_| {}),
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(completion.content)
+    }
+
+    /// Mirrors how regenerate and edit use the registry around a generation.
+    async fn generate(
+        busy: &BusyThreads,
+        backend: Arc<SlowBackend>,
+        thread_id: i64,
+    ) -> Result<String, String> {
+        let _guard = busy.acquire(thread_id)?;
+        chat(busy, backend.as_ref(), thread_id, "Hi").await
+    }
+
+    /// Mirrors send_message: runs the generation, then whatever was queued behind it.
+    async fn send(
+        busy: &BusyThreads,
+        backend: Arc<SlowBackend>,
+        thread_id: i64,
+        model: &str,
+    ) -> Result<(), String> {
+        let job = QueuedGeneration {
+            message_id: 0,
+            model: model.to_string(),
+            response_format: None,
+            think: None,
+            num_predict: None,
+        };
+        let mut guard = match busy.acquire_or_queue(thread_id, job)? {
+            Admission::Started(guard) => guard,
+            Admission::Queued(_) => return Ok(()),
+        };
+        let result = chat(busy, backend.as_ref(), thread_id, model).await;
+        while let Some(next) = guard.next_queued() {
+            let _ = chat(busy, backend.as_ref(), thread_id, &next.model).await;
+        }
+        result.map(|_| ())
+    }
+
+    /// Mirrors send_message and stream_response with the thread saved in `db`.
+    /// Each reply is "re: " and the message it answers, and the history each
+    /// answer was made from is recorded.
+    async fn send_saved(
+        busy: &BusyThreads,
+        backend: Arc<SlowBackend>,
+        db: Arc<Mutex<Database>>,
+        histories: Arc<Mutex<Vec<Vec<String>>>>,
+        thread_id: i64,
+        content: &str,
+    ) -> Result<(), String> {
+        let message_id = db
+            .lock()
+            .unwrap()
+            .add_message(thread_id, Role::User, content, None, None, None, None)
+            .map_err(|e| e.to_string())?;
+        let job = QueuedGeneration {
+            message_id,
+            model: String::new(),
+            response_format: None,
+            think: None,
+            num_predict: None,
+        };
+        let mut guard = match busy.acquire_or_queue(thread_id, job.clone())? {
+            Admission::Started(guard) => guard,
+            Admission::Queued(_) => return Ok(()),
+        };
+        let mut next = Some(job);
+        while let Some(job) = next {
+            let history = {
+                let db = db.lock().unwrap();
+                crate::thread_history(&db, thread_id, Some(job.message_id))?.0
+            };
+            let history: Vec<String> = history.into_iter().map(|m| m.content).collect();
+            let started_at = chrono::Utc::now();
+            let reply = format!("re: {}", history.last().unwrap());
+            histories.lock().unwrap().push(history);
+            chat(busy, backend.as_ref(), thread_id, &reply).await?;
+
+            let db = db.lock().unwrap();
+            let created_at =
+                crate::reply_created_at(&db, thread_id, Some(job.message_id), started_at)?;
+            let reply_id = db
+                .add_message(thread_id, Role::Assistant, &reply, None, None, None, None)
+                .map_err(|e| e.to_string())?;
+            db.set_message_created_at(reply_id, &created_at)
+                .map_err(|e| e.to_string())?;
+            drop(db);
+            next = guard.next_queued();
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_queued_messages_are_answered_in_turn() {
+        let busy = BusyThreads::default();
+        let backend = slow_backend();
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Queued", None).unwrap();
+        let db = Arc::new(Mutex::new(db));
+        let histories = Arc::new(Mutex::new(Vec::new()));
+        let send = |content: &'static str| {
+            let busy = busy.clone();
+            let backend = Arc::clone(&backend);
+            let db = Arc::clone(&db);
+            let histories = Arc::clone(&histories);
+            async move { send_saved(&busy, backend, db, histories, thread_id, content).await }
+        };
+
+        let first = tokio::spawn(send("one"));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // Both sent while the first reply streams
+        send("two").await.unwrap();
+        send("three").await.unwrap();
+        first.await.unwrap().unwrap();
+
+        let contents: Vec<String> = db
+            .lock()
+            .unwrap()
+            .get_messages(thread_id)
+            .unwrap()
+            .into_iter()
+            .map(|m| m.content)
+            .collect();
+        assert_eq!(
+            contents,
+            ["one", "re: one", "two", "re: two", "three", "re: three"]
+        );
+        assert_eq!(
+            *histories.lock().unwrap(),
+            [
+                vec!["one"],
+                vec!["one", "re: one", "two"],
+                vec!["one", "re: one", "two", "re: two", "three"],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_second_generation_in_same_thread_is_rejected() {
+        let busy = BusyThreads::default();
+        let backend = slow_backend();
+
+        let first = tokio::spawn({
+            let busy = busy.clone();
+            let backend = Arc::clone(&backend);
+            async move { generate(&busy, backend, 1).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(busy.is_busy(1));
+
+        let second = generate(&busy, Arc::clone(&backend), 1).await;
+        assert!(second.unwrap_err().contains("already being generated"));
+
+        // Other threads aren't affected
+        assert_eq!(
+            generate(&busy, Arc::clone(&backend), 2).await.unwrap(),
+            "Hi"
+        );
+
+        assert_eq!(first.await.unwrap().unwrap(), "Hi");
+        assert!(!busy.is_busy(1));
+        assert_eq!(generate(&busy, backend, 1).await.unwrap(), "Hi");
+    }
+
+    #[tokio::test]
+    async fn test_thread_is_released_when_generation_fails() {
+        let busy = BusyThreads::default();
+        let backend = slow_backend();
+        let result = send(&busy, backend, 1, "fail").await;
+        assert_eq!(result.unwrap_err(), "stream broke");
+        assert!(!busy.is_busy(1));
+    }
+
+    #[tokio::test]
+    async fn test_queued_messages_run_in_order_past_errors() {
+        let busy = BusyThreads::default();
+        let backend = slow_backend();
+
+        let first = tokio::spawn({
+            let busy = busy.clone();
+            let backend = Arc::clone(&backend);
+            async move { send(&busy, backend, 1, "one").await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        for model in ["fail", "two", "three"] {
+            send(&busy, Arc::clone(&backend), 1, model).await.unwrap();
+        }
+        assert_eq!(busy.queue_len(1), 3);
+
+        first.await.unwrap().unwrap();
+        assert_eq!(
+            *backend.calls.lock().unwrap(),
+            ["one", "fail", "two", "three"]
+        );
+        assert_eq!(busy.queue_len(1), 0);
+        assert!(!busy.is_busy(1));
+    }
+
+    #[tokio::test]
+    async fn test_stop_cancels_and_flushes_queue() {
+        let busy = BusyThreads::default();
+        let backend = slow_backend();
+
+        let first = tokio::spawn({
+            let busy = busy.clone();
+            let backend = Arc::clone(&backend);
+            async move { send(&busy, backend, 1, "one").await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        send(&busy, Arc::clone(&backend), 1, "two").await.unwrap();
+
+        assert!(busy.stop(1).unwrap());
+        let result = first.await.unwrap();
+        assert_eq!(result.unwrap_err(), "Generation was cancelled");
+        assert_eq!(*backend.calls.lock().unwrap(), ["one"]);
+        assert!(!busy.is_busy(1));
+        assert!(!busy.stop(1).unwrap());
+    }
+}
@@ -0,0 +1,481 @@
+use crate::db::Message;
+use crate::ollama::{OllamaMessage, Role};
+
+/// Rough tokens for an attached image; vision models use a few hundred per image.
+const IMAGE_TOKENS: usize = 768;
+/// Role markers and template tokens around each message.
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+const SUMMARY_PREFIX: &str = "Previous conversation summary:";
+const SUMMARIZE_PROMPT: &str = "Summarize the conversation below so it can stand in for it later. \
+Keep names, facts, decisions, open questions and anything the user asked to remember. \
+Write plain prose of at most a few paragraphs, without any preamble.";
+const THREAD_SUMMARY_PROMPT: &str = "Write a TL;DR of the conversation below for someone who \
+hasn't read it. Use these Markdown sections, leaving out any that would be empty:\n\
+## Summary\nTwo or three sentences on what the conversation was about and where it ended up.\n\
+## Key points\nA bulleted list of the main facts and findings.\n\
+## Decisions\nA bulleted list of what was decided.\n\
+## Open questions\nA bulleted list of what is still unresolved.\n\
+Reply with the sections only, without any preamble.";
+
+fn text_message(role: Role, content: String) -> OllamaMessage {
+    OllamaMessage {
+        role,
+        content,
+        images: None,
+        thinking: None,
+        tool_calls: None,
+        tool_name: None,
+    }
+}
+
+/// Removes `<think>...</think>` blocks from a stored reply. Replies saved before
+/// reasoning was kept separately have it inline; an unclosed block runs to the end.
+pub fn strip_thinking(content: &str) -> String {
+    const OPEN: &str = "<think>";
+    const CLOSE: &str = "</think>";
+
+    let mut out = String::new();
+    let mut rest = content;
+    while let Some(start) = rest.find(OPEN) {
+        out.push_str(&rest[..start]);
+        rest = match rest[start..].find(CLOSE) {
+            Some(end) => &rest[start + end + CLOSE.len()..],
+            None => "",
+        };
+    }
+    out.push_str(rest);
+    out.trim().to_string()
+}
+
+/// Keeps only the newest `max` stored messages that aren't system messages or
+/// notes, dropping everything before them. A cut never leaves tool results without
+/// the call that asked for them.
+pub fn keep_newest(mut messages: Vec<Message>, max: usize) -> Vec<Message> {
+    let conversation: Vec<usize> = messages
+        .iter()
+        .enumerate()
+        .filter(|(_, m)| !matches!(m.role, Role::System | Role::Note))
+        .map(|(i, _)| i)
+        .collect();
+    if conversation.len() <= max {
+        return messages;
+    }
+
+    let mut start = match max {
+        0 => messages.len(),
+        max => conversation[conversation.len() - max],
+    };
+    while messages.get(start).is_some_and(|m| m.role == Role::Tool) {
+        start += 1;
+    }
+    messages.drain(..start);
+    messages
+}
+
+/// A stored message as sent back to the model. Earlier replies' reasoning is
+/// left out unless `resend_thinking` is set, and notes go as system messages.
+pub fn history_message(message: Message, resend_thinking: bool) -> OllamaMessage {
+    let (content, thinking) = if message.role == Role::Assistant && !resend_thinking {
+        (strip_thinking(&message.content), None)
+    } else {
+        (
+            message.content,
+            message.thinking_process.filter(|_| resend_thinking),
+        )
+    };
+    let role = match message.role {
+        Role::Note => Role::System,
+        role => role,
+    };
+    OllamaMessage {
+        role,
+        content,
+        images: message.images,
+        thinking,
+        tool_calls: message
+            .tool_calls
+            .and_then(|calls| serde_json::from_value(calls).ok()),
+        tool_name: message.tool_name,
+    }
+}
+
+/// The system message that stands in for summarized history.
+pub fn summary_message(summary: &str) -> OllamaMessage {
+    text_message(Role::System, format!("{}\n{}", SUMMARY_PREFIX, summary))
+}
+
+/// Builds the request that folds `messages` into the running summary. With a
+/// previous summary, only the new messages are sent and the summary is extended.
+pub fn summarization_request(
+    previous: Option<&str>,
+    messages: &[OllamaMessage],
+) -> Vec<OllamaMessage> {
+    let mut transcript = String::new();
+    if let Some(previous) = previous {
+        transcript.push_str(&format!("{}\n{}\n\n", SUMMARY_PREFIX, previous));
+    }
+    for message in messages {
+        transcript.push_str(&format!("{}: {}\n\n", message.role, message.content));
+    }
+
+    vec![
+        text_message(Role::System, SUMMARIZE_PROMPT.to_string()),
+        text_message(Role::User, transcript.trim_end().to_string()),
+    ]
+}
+
+/// Builds the request for a structured TL;DR of a whole conversation, shown to
+/// the user rather than sent back to the model.
+pub fn thread_summary_request(messages: &[OllamaMessage]) -> Vec<OllamaMessage> {
+    let transcript = messages
+        .iter()
+        .map(|message| format!("{}: {}", message.role, message.content))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    vec![
+        text_message(Role::System, THREAD_SUMMARY_PROMPT.to_string()),
+        text_message(Role::User, transcript),
+    ]
+}
+
+/// Estimates how many tokens a message takes up, at roughly four bytes per token.
+/// Only meant to be close enough to decide what fits.
+pub fn estimate_tokens(message: &OllamaMessage) -> usize {
+    let mut bytes = message.content.len();
+    if let Some(ref calls) = message.tool_calls {
+        bytes += calls
+            .iter()
+            .map(|c| c.function.name.len() + c.function.arguments.to_string().len())
+            .sum::<usize>();
+    }
+    let images = message.images.as_ref().map_or(0, Vec::len);
+    bytes.div_ceil(4) + images * IMAGE_TOKENS + MESSAGE_OVERHEAD_TOKENS
+}
+
+/// Estimates the tokens in a piece of text the same way, without a message's
+/// overhead.
+pub fn estimate_text_tokens(text: &str) -> usize {
+    text.len().div_ceil(4)
+}
+
+#[derive(Debug)]
+pub struct TrimmedContext {
+    pub messages: Vec<OllamaMessage>,
+    /// The messages that were left out, oldest first
+    pub dropped: Vec<OllamaMessage>,
+    /// Where the dropped messages were in the input. System messages are never
+    /// dropped, so these need not be a prefix.
+    pub dropped_indices: Vec<usize>,
+    pub estimated_tokens: usize,
+}
+
+/// Drops the oldest messages until the estimated total fits in `budget`. System
+/// messages and everything from the latest user message on are always kept, so
+/// the result can still be over budget. Tool results go together with the call
+/// that produced them.
+pub fn trim_to_budget(messages: Vec<OllamaMessage>, budget: usize) -> TrimmedContext {
+    let sizes: Vec<usize> = messages.iter().map(estimate_tokens).collect();
+    let mut total: usize = sizes.iter().sum();
+    if total <= budget {
+        return TrimmedContext {
+            messages,
+            dropped: Vec::new(),
+            dropped_indices: Vec::new(),
+            estimated_tokens: total,
+        };
+    }
+
+    let keep_from = messages
+        .iter()
+        .rposition(|m| m.role == Role::User)
+        .unwrap_or(messages.len().saturating_sub(1));
+
+    let mut drop = vec![false; messages.len()];
+    let mut prev_dropped = false;
+    for (i, message) in messages.iter().enumerate().take(keep_from) {
+        if message.role == Role::System {
+            continue;
+        }
+        if message.role == Role::Tool {
+            if prev_dropped {
+                drop[i] = true;
+                total -= sizes[i];
+            }
+            continue;
+        }
+        if total <= budget {
+            break;
+        }
+        drop[i] = true;
+        total -= sizes[i];
+        prev_dropped = true;
+    }
+
+    let dropped_indices = (0..messages.len()).filter(|&i| drop[i]).collect();
+    let (dropped, kept): (Vec<_>, Vec<_>) = messages
+        .into_iter()
+        .zip(drop)
+        .partition(|(_, dropped)| *dropped);
+
+    TrimmedContext {
+        messages: kept.into_iter().map(|(m, _)| m).collect(),
+        dropped: dropped.into_iter().map(|(m, _)| m).collect(),
+        dropped_indices,
+        estimated_tokens: total,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(role: Role, content: &str) -> OllamaMessage {
+        OllamaMessage {
+            role,
+            content: content.to_string(),
+            images: None,
+            thinking: None,
+            tool_calls: None,
+            tool_name: None,
+        }
+    }
+
+    #[test]
+    fn test_fits_untouched() {
+        let messages = vec![
+            message(Role::System, "Be brief."),
+            message(Role::User, "Hi"),
+        ];
+        let trimmed = trim_to_budget(messages, 1000);
+        assert_eq!(trimmed.messages.len(), 2);
+        assert!(trimmed.dropped.is_empty());
+    }
+
+    #[test]
+    fn test_drops_oldest_but_keeps_system_and_latest_user() {
+        let long = "x".repeat(400); // about 100 tokens
+        let messages = vec![
+            message(Role::System, "Be brief."),
+            message(Role::User, &long),
+            message(Role::Assistant, &long),
+            message(Role::User, &long),
+            message(Role::Assistant, &long),
+            message(Role::User, "And now?"),
+        ];
+        let trimmed = trim_to_budget(messages, 250);
+
+        let roles: Vec<_> = trimmed.messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, ["system", "user", "assistant", "user"]);
+        assert_eq!(trimmed.messages[0].content, "Be brief.");
+        assert_eq!(trimmed.messages.last().unwrap().content, "And now?");
+        assert_eq!(trimmed.dropped.len(), 2);
+        assert_eq!(trimmed.dropped_indices, [1, 2]);
+        assert!(trimmed.estimated_tokens <= 250);
+    }
+
+    #[test]
+    fn test_dropped_indices_skip_kept_notes() {
+        let long = "x".repeat(400);
+        // Notes are sent as system messages, which are never dropped
+        let messages = vec![
+            message(Role::User, &long),
+            message(Role::System, "Client prefers a formal tone"),
+            message(Role::Assistant, &long),
+            message(Role::User, "And now?"),
+        ];
+        let trimmed = trim_to_budget(messages, 50);
+        assert_eq!(trimmed.dropped_indices, [0, 2]);
+        let roles: Vec<_> = trimmed.messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, ["system", "user"]);
+    }
+
+    #[test]
+    fn test_over_budget_when_only_protected_messages_remain() {
+        let messages = vec![
+            message(Role::System, &"s".repeat(800)),
+            message(Role::Assistant, "old"),
+            message(Role::User, &"u".repeat(800)),
+        ];
+        let trimmed = trim_to_budget(messages, 100);
+        assert_eq!(trimmed.messages.len(), 2);
+        assert!(trimmed.estimated_tokens > 100);
+    }
+
+    #[test]
+    fn test_tool_results_dropped_with_their_call() {
+        let long = "x".repeat(400);
+        let messages = vec![
+            message(Role::User, "Hi"),
+            message(Role::Assistant, &long),
+            message(Role::Tool, "31°C"),
+            message(Role::Assistant, "Sunny"),
+            message(Role::User, "Thanks"),
+        ];
+        // Dropping the call alone fits, but its result must not be left behind
+        let trimmed = trim_to_budget(messages, 20);
+        let roles: Vec<_> = trimmed.messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, ["assistant", "user"]);
+    }
+
+    #[test]
+    fn test_summarization_request_extends_previous_summary() {
+        let request = summarization_request(
+            Some("The user is planning a trip to Pune."),
+            &[
+                message(Role::User, "Book the train for Friday."),
+                message(Role::Assistant, "Done."),
+            ],
+        );
+        assert_eq!(request[0].role, Role::System);
+        assert_eq!(
+            request[1].content,
+            "Previous conversation summary:\nThe user is planning a trip to Pune.\n\n\
+             user: Book the train for Friday.\n\nassistant: Done."
+        );
+        assert!(summary_message("Trip to Pune")
+            .content
+            .starts_with("Previous conversation summary:"));
+    }
+
+    #[test]
+    fn test_strip_thinking() {
+        assert_eq!(strip_thinking("<think>\nHmm, 6 * 7\n</think>\n\n42"), "42");
+        assert_eq!(strip_thinking("A<think>x</think>B<think>y</think>C"), "ABC");
+        assert_eq!(strip_thinking("Answer <think>cut off"), "Answer");
+        assert_eq!(strip_thinking("No reasoning here"), "No reasoning here");
+    }
+
+    #[test]
+    fn test_history_leaves_out_stored_reasoning() {
+        use crate::db::Database;
+
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Test", None).unwrap();
+        db.add_message(
+            thread_id,
+            Role::User,
+            "What is 6 * 7?",
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        // One reply from before reasoning was split out, one after
+        db.add_message(
+            thread_id,
+            Role::Assistant,
+            "<think>Multiply them.</think>\n\n42",
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        db.add_message(
+            thread_id,
+            Role::Assistant,
+            "Still 42",
+            None,
+            None,
+            None,
+            Some("Same as before.".to_string()),
+        )
+        .unwrap();
+
+        let history: Vec<OllamaMessage> = db
+            .get_messages(thread_id)
+            .unwrap()
+            .into_iter()
+            .map(|m| history_message(m, false))
+            .collect();
+        let payload = serde_json::to_string(&history).unwrap();
+        assert!(!payload.contains("think"), "{}", payload);
+        assert!(!payload.contains("Multiply") && !payload.contains("Same as before"));
+        assert_eq!(history[1].content, "42");
+
+        let history: Vec<OllamaMessage> = db
+            .get_messages(thread_id)
+            .unwrap()
+            .into_iter()
+            .map(|m| history_message(m, true))
+            .collect();
+        assert_eq!(history[2].thinking.as_deref(), Some("Same as before."));
+    }
+
+    #[test]
+    fn test_note_sent_as_system() {
+        use crate::db::Database;
+
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Test", None).unwrap();
+        for (role, content) in [
+            (Role::User, "Draft a reply"),
+            (Role::Note, "Client prefers a formal tone"),
+            (Role::Assistant, "Dear Sir"),
+        ] {
+            db.add_message(thread_id, role, content, None, None, None, None)
+                .unwrap();
+        }
+
+        let history: Vec<OllamaMessage> = db
+            .get_messages(thread_id)
+            .unwrap()
+            .into_iter()
+            .map(|m| history_message(m, false))
+            .collect();
+        let roles: Vec<_> = history.iter().map(|m| m.role).collect();
+        assert_eq!(roles, vec![Role::User, Role::System, Role::Assistant]);
+        assert_eq!(history[1].content, "Client prefers a formal tone");
+    }
+
+    #[test]
+    fn test_keep_newest() {
+        use crate::db::Database;
+
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Test", None).unwrap();
+        for (role, content) in [
+            (Role::User, "1"),
+            (Role::Assistant, "call"),
+            (Role::Tool, "result"),
+            (Role::System, "system"),
+            (Role::Note, "note"),
+            (Role::Assistant, "2"),
+            (Role::User, "3"),
+            (Role::Assistant, "4"),
+        ] {
+            db.add_message(thread_id, role, content, None, None, None, None)
+                .unwrap();
+        }
+        let contents = |max| -> Vec<String> {
+            keep_newest(db.get_messages(thread_id).unwrap(), max)
+                .into_iter()
+                .map(|m| m.content)
+                .collect()
+        };
+
+        assert_eq!(contents(3), ["2", "3", "4"]);
+        // A tool result isn't kept without its call, and notes don't count
+        assert_eq!(contents(4), ["system", "note", "2", "3", "4"]);
+        assert_eq!(contents(6).len(), 8);
+        assert!(contents(0).is_empty());
+    }
+
+    #[test]
+    fn test_thread_summary_request() {
+        let request = thread_summary_request(&[
+            message(Role::User, "Which train is faster?"),
+            message(Role::Assistant, "The Deccan Queen."),
+        ]);
+        assert_eq!(request[0].role, Role::System);
+        assert!(request[0].content.contains("## Key points"));
+        assert_eq!(request[1].role, Role::User);
+        assert_eq!(
+            request[1].content,
+            "user: Which train is faster?\n\nassistant: The Deccan Queen."
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use chrono::{DateTime, NaiveDate};
+use std::fmt;
+
+use crate::pdf_utils::truncate_with_marker;
+
+/// Rows shown in the preview table unless the `csv_preview_rows` setting says
+/// otherwise.
+pub const DEFAULT_PREVIEW_ROWS: usize = 10;
+
+/// The narrowest type that fits every non-empty value in a column.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnType {
+    /// No values at all
+    Empty,
+    Integer,
+    Float,
+    Boolean,
+    Date,
+    Text,
+}
+
+impl ColumnType {
+    fn of(value: &str) -> ColumnType {
+        if value.parse::<i64>().is_ok() {
+            ColumnType::Integer
+        } else if value.parse::<f64>().is_ok_and(f64::is_finite) {
+            ColumnType::Float
+        } else if matches!(value.to_lowercase().as_str(), "true" | "false") {
+            ColumnType::Boolean
+        } else if NaiveDate::parse_from_str(value, "%Y-%m-%d").is_ok()
+            || DateTime::parse_from_rfc3339(value).is_ok()
+        {
+            ColumnType::Date
+        } else {
+            ColumnType::Text
+        }
+    }
+
+    /// The type of a column holding values of both types.
+    fn widen(self, other: ColumnType) -> ColumnType {
+        use ColumnType::*;
+        match (self, other) {
+            (Empty, t) | (t, Empty) => t,
+            (a, b) if a == b => a,
+            (Integer, Float) | (Float, Integer) => Float,
+            _ => Text,
+        }
+    }
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ColumnType::Empty => "empty",
+            ColumnType::Integer => "integer",
+            ColumnType::Float => "float",
+            ColumnType::Boolean => "boolean",
+            ColumnType::Date => "date",
+            ColumnType::Text => "text",
+        })
+    }
+}
+
+/// Summarizes a CSV file with a header row for the prompt: its columns and
+/// their types, the row count, and the first `preview_rows` rows as a Markdown
+/// table, or every row when `all_rows` is set. The result is cut off after
+/// `max_chars` characters. Rows with a different number of fields than the
+/// header, or text that isn't UTF-8, are an error.
+pub fn summarize_csv(
+    name: &str,
+    bytes: &[u8],
+    preview_rows: usize,
+    all_rows: bool,
+    max_chars: usize,
+) -> Result<String, csv::Error> {
+    let mut reader = csv::Reader::from_reader(bytes);
+    let headers = reader.headers()?.clone();
+    let mut types = vec![ColumnType::Empty; headers.len()];
+    let mut rows = Vec::new();
+    let mut row_count = 0;
+
+    for record in reader.records() {
+        let record = record?;
+        for (column, value) in types.iter_mut().zip(record.iter()) {
+            let value = value.trim();
+            if !value.is_empty() {
+                *column = column.widen(ColumnType::of(value));
+            }
+        }
+        if all_rows || rows.len() < preview_rows {
+            rows.push(record);
+        }
+        row_count += 1;
+    }
+
+    let mut summary = format!(
+        "--- CSV: {} ---\n{} rows, {} columns:\n",
+        name,
+        row_count,
+        headers.len()
+    );
+    for (header, column) in headers.iter().zip(&types) {
+        summary.push_str(&format!("- {} ({})\n", header, column));
+    }
+    if headers.is_empty() {
+        return Ok(summary.trim_end().to_string());
+    }
+
+    summary.push('\n');
+    summary.push_str(&if all_rows || rows.len() == row_count {
+        "All rows:\n".to_string()
+    } else {
+        format!("First {} rows:\n", rows.len())
+    });
+    summary.push_str(&markdown_row(headers.iter()));
+    summary.push_str(&markdown_row(headers.iter().map(|_| "---")));
+    for row in &rows {
+        summary.push_str(&markdown_row(row.iter()));
+        // No point formatting rows that will be cut off anyway
+        if summary.len() > max_chars * 4 {
+            break;
+        }
+    }
+
+    let mut summary = summary.trim_end().to_string();
+    truncate_with_marker(&mut summary, max_chars);
+    Ok(summary)
+}
+
+fn markdown_row<'a>(cells: impl Iterator<Item = &'a str>) -> String {
+    let cells: Vec<String> = cells
+        .map(|cell| cell.replace('|', "\\|").replace(['\r', '\n'], " "))
+        .collect();
+    format!("| {} |\n", cells.join(" | "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SALES: &str = "region,units,price,shipped,date,notes\n\
+        north,12,3.5,true,2024-01-05,\n\
+        south,7,4,false,2024-01-06,\"rush, a|b\"\n\
+        east,3,2.25,TRUE,2024-01-07,\n";
+
+    #[test]
+    fn test_summary_infers_types_and_previews_rows() {
+        let summary = summarize_csv("sales.csv", SALES.as_bytes(), 2, false, 10_000).unwrap();
+        assert_eq!(
+            summary,
+            "--- CSV: sales.csv ---\n\
+             3 rows, 6 columns:\n\
+             - region (text)\n\
+             - units (integer)\n\
+             - price (float)\n\
+             - shipped (boolean)\n\
+             - date (date)\n\
+             - notes (text)\n\
+             \n\
+             First 2 rows:\n\
+             | region | units | price | shipped | date | notes |\n\
+             | --- | --- | --- | --- | --- | --- |\n\
+             | north | 12 | 3.5 | true | 2024-01-05 |  |\n\
+             | south | 7 | 4 | false | 2024-01-06 | rush, a\\|b |"
+        );
+    }
+
+    #[test]
+    fn test_all_rows_up_to_the_character_cap() {
+        let summary = summarize_csv("sales.csv", SALES.as_bytes(), 1, true, 10_000).unwrap();
+        assert!(summary.contains("All rows:\n"));
+        assert!(summary.ends_with("| east | 3 | 2.25 | TRUE | 2024-01-07 |  |"));
+
+        let summary = summarize_csv("sales.csv", SALES.as_bytes(), 1, true, 40).unwrap();
+        assert!(summary.ends_with("[truncated after 40 characters]"));
+    }
+
+    #[test]
+    fn test_ragged_rows_are_an_error() {
+        assert!(summarize_csv("bad.csv", b"a,b\n1,2\n3\n", 10, false, 10_000).is_err());
+    }
+}
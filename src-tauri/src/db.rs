@@ -1,6 +1,12 @@
-use chrono::Utc;
-use rusqlite::{params, Connection, Result};
+use crate::images::ImageMetadata;
+use crate::ollama::{ModelOptions, Role};
+use crate::pdf_utils::ExtractedPages;
+use crate::redact::Redactor;
+use chrono::{NaiveDate, Utc};
+use rusqlite::types::{FromSql, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::{ffi, params, Connection, OpenFlags, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Thread {
@@ -9,13 +15,103 @@ pub struct Thread {
     pub created_at: String,
     pub system_prompt: Option<String>,
     pub is_archived: bool,
+    pub server_profile_id: Option<i64>,
+    pub think: Option<bool>,
+    pub model_options: Option<ModelOptions>,
+    /// The last generation was cut off by the app closing or by a failed
+    /// stream; cleared by the next message
+    pub needs_attention: bool,
+    /// TL;DR written by `summarize_thread`, for the user rather than the model
+    pub summary: Option<String>,
+    /// Send earlier replies' reasoning back to the model; off by default, as
+    /// it costs context and tends to confuse it
+    pub resend_thinking: bool,
+    /// Overrides the `save_thinking` setting for this thread
+    pub save_thinking: Option<bool>,
+    /// Only this many of the newest messages are sent to the model; `None`
+    /// sends them all
+    pub max_history_messages: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MessageMetrics {
+    pub total_duration: Option<i64>,
+    pub load_duration: Option<i64>,
+    pub prompt_eval_count: Option<i64>,
+    pub eval_count: Option<i64>,
+    pub eval_duration: Option<i64>,
+    pub tokens_per_second: Option<f64>,
+    pub first_token_ms: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ModelAlias {
+    pub model_name: String,
+    pub alias: Option<String>,
+    pub is_favorite: bool,
+    pub sort_order: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PromptPreset {
+    pub id: i64,
+    pub name: String,
+    pub content: String,
+    pub created_at: String,
+}
+
+/// A running summary of the oldest part of a thread, covering every message up
+/// to and including `covered_until_id`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ThreadSummary {
+    pub thread_id: i64,
+    pub summary: String,
+    pub covered_until_id: i64,
+    pub updated_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServerProfile {
+    pub id: i64,
+    pub name: String,
+    pub base_url: String,
+    pub is_default: bool,
+    /// "ollama" or "openai" for OpenAI-compatible servers
+    pub kind: String,
+    /// Never sent to the frontend; `has_auth_token` tells whether one is set
+    #[serde(skip_serializing, default)]
+    pub auth_token: Option<String>,
+    #[serde(default)]
+    pub has_auth_token: bool,
+    /// Extra headers sent with every request to this server
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Skips TLS certificate verification for this server. Insecure, opt-in only
+    #[serde(default)]
+    pub accept_invalid_certs: bool,
+    /// Extra CA certificate (PEM) to trust for this server
+    #[serde(default)]
+    pub ca_cert_path: Option<String>,
+}
+
+/// A message to insert with `Database::add_messages_batch`.
+#[derive(Debug, Clone)]
+pub struct NewMessage {
+    pub role: Role,
+    pub content: String,
+    pub images: Option<Vec<String>>,
+    pub model: Option<String>,
+    pub reply_to_id: Option<i64>,
+    pub thinking_process: Option<String>,
+    /// Kept from the source when importing; now when absent
+    pub created_at: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Message {
     pub id: i64,
     pub thread_id: i64,
-    pub role: String,
+    pub role: Role,
     pub content: String,
     pub images: Option<Vec<String>>,
     pub model: Option<String>,
@@ -28,10 +124,238 @@ pub struct Message {
     pub eval_duration: Option<i64>,
     pub tokens_per_second: Option<f64>,
     pub reply_to_id: Option<i64>,
+    pub response_format: Option<String>,
+    pub tool_calls: Option<serde_json::Value>,
+    pub tool_call_id: Option<String>,
+    pub tool_name: Option<String>,
+    /// "length" when the reply was cut off by the token limit
+    pub done_reason: Option<String>,
+    /// Latency until the first content token; None for older messages
+    pub first_token_ms: Option<i64>,
+    /// Sizes of the attached images, in the same order as `images`
+    pub image_metadata: Option<Vec<ImageMetadata>>,
+    /// Generation options the reply was produced with, to reproduce it later
+    pub generation_options: Option<ModelOptions>,
+    /// File names of the attached PDF and DOCX documents, in the order sent
+    pub document_names: Option<Vec<String>>,
+    /// The output that arrived before the stream failed, so the reply is cut short
+    pub is_incomplete: bool,
+    /// Replaced by an answer from `regenerate_with_model`; kept for comparison
+    /// but no longer sent to the model
+    pub is_superseded: bool,
+    /// The superseded answer this one was generated in place of
+    pub variant_of: Option<i64>,
+    /// The model reasoned, but `save_thinking` was off so it wasn't stored
+    pub thinking_discarded: bool,
+    /// Left out of what is sent to the model, but still shown
+    pub exclude_from_context: bool,
+    /// Small versions of `images`, in the same order; `None` until generated
+    #[serde(skip)]
+    pub image_thumbnails: Option<Vec<String>>,
 }
 
 pub struct Database {
     conn: Connection,
+    read_only: bool,
+}
+
+/// Stands in for the model of replies saved before models were recorded.
+pub const UNKNOWN_MODEL: &str = "unknown";
+
+/// Facts about the database file for bug reports.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DbStats {
+    /// Empty for an in-memory database
+    pub path: String,
+    pub size_bytes: u64,
+    pub thread_count: i64,
+    pub message_count: i64,
+    /// SQLite's `user_version`
+    pub schema_version: i64,
+}
+
+/// What a redaction changed in a thread.
+#[derive(Debug, Default, PartialEq)]
+pub struct Redaction {
+    pub replacements: usize,
+    /// Messages whose content or reasoning was rewritten
+    pub message_ids: Vec<i64>,
+    pub summary_changed: bool,
+}
+
+/// What happened on one calendar day, for an activity heatmap.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DailyActivity {
+    pub date: NaiveDate,
+    pub user_messages: i64,
+    pub assistant_messages: i64,
+    /// Tokens the replies generated
+    pub tokens: i64,
+}
+
+/// How much a model was used and how fast it replied.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ModelStats {
+    pub model: String,
+    /// Replies from the model
+    pub message_count: i64,
+    /// Tokens the replies generated
+    pub tokens: i64,
+    /// `None` when no reply recorded it
+    pub avg_tokens_per_second: Option<f64>,
+    /// In nanoseconds, as Ollama reports it; `None` when no reply recorded it
+    pub avg_total_duration: Option<f64>,
+}
+
+/// How quickly one reply arrived, for a latency sparkline.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LatencyPoint {
+    pub message_id: i64,
+    pub created_at: String,
+    pub first_token_ms: Option<i64>,
+    /// In nanoseconds, as Ollama reports it
+    pub total_duration: Option<i64>,
+}
+
+impl ToSql for Role {
+    fn to_sql(&self) -> Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+/// Roles that aren't recognised load as `Role::Unknown` rather than failing
+/// the whole thread.
+impl FromSql for Role {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        Ok(value.as_str()?.parse().unwrap_or(Role::Unknown))
+    }
+}
+
+const THREAD_COLUMNS: &str =
+    "id, title, created_at, system_prompt, is_archived, server_profile_id, think, model_options,
+    needs_attention, summary, resend_thinking, save_thinking, max_history_messages";
+
+fn thread_from_row(row: &rusqlite::Row) -> Result<Thread> {
+    Ok(Thread {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        created_at: row.get(2)?,
+        system_prompt: row.get(3)?,
+        is_archived: row.get(4)?,
+        server_profile_id: row.get(5)?,
+        think: row.get(6)?,
+        model_options: row
+            .get::<_, Option<String>>(7)?
+            .and_then(|json| serde_json::from_str(&json).ok()),
+        needs_attention: row.get::<_, Option<bool>>(8)?.unwrap_or(false),
+        summary: row.get(9)?,
+        resend_thinking: row.get::<_, Option<bool>>(10)?.unwrap_or(false),
+        save_thinking: row.get(11)?,
+        max_history_messages: row.get(12)?,
+    })
+}
+
+fn preset_from_row(row: &rusqlite::Row) -> Result<PromptPreset> {
+    Ok(PromptPreset {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        content: row.get(2)?,
+        created_at: row.get(3)?,
+    })
+}
+
+const SERVER_PROFILE_COLUMNS: &str = "id, name, base_url, is_default, kind, auth_token, headers,
+    accept_invalid_certs, ca_cert_path";
+
+fn server_profile_from_row(row: &rusqlite::Row) -> Result<ServerProfile> {
+    let auth_token: Option<String> = row.get(5)?;
+    Ok(ServerProfile {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        base_url: row.get(2)?,
+        is_default: row.get(3)?,
+        kind: row.get(4)?,
+        has_auth_token: auth_token.is_some(),
+        auth_token,
+        headers: row
+            .get::<_, Option<String>>(6)?
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default(),
+        accept_invalid_certs: row.get::<_, Option<bool>>(7)?.unwrap_or(false),
+        ca_cert_path: row.get(8)?,
+    })
+}
+
+const MESSAGE_COLUMNS: &str = "id, thread_id, role, content, model, thinking_process,
+    total_duration, load_duration, prompt_eval_count, eval_count, eval_duration, reply_to_id, created_at, images,
+    response_format, tool_calls, tool_call_id, tool_name, tokens_per_second, done_reason, first_token_ms,
+    image_metadata, generation_options, document_names, is_incomplete, is_superseded, variant_of,
+    thinking_discarded, exclude_from_context, image_thumbnails";
+
+fn message_from_row(row: &rusqlite::Row) -> Result<Message> {
+    let images_json: Option<String> = row.get(13)?;
+    let images = if let Some(json) = images_json {
+        serde_json::from_str(&json).unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+
+    Ok(Message {
+        id: row.get(0)?,
+        thread_id: row.get(1)?,
+        role: row.get(2)?,
+        content: row.get(3)?,
+        images: if images.is_empty() {
+            None
+        } else {
+            Some(images)
+        },
+        model: row.get(4)?,
+        thinking_process: row.get(5)?,
+        total_duration: row.get(6)?,
+        load_duration: row.get(7)?,
+        prompt_eval_count: row.get(8)?,
+        eval_count: row.get(9)?,
+        eval_duration: row.get(10)?,
+        reply_to_id: row.get(11)?,
+        created_at: row.get(12)?,
+        tokens_per_second: row.get(18)?,
+        response_format: row.get(14)?,
+        tool_calls: row
+            .get::<_, Option<String>>(15)?
+            .and_then(|json| serde_json::from_str(&json).ok()),
+        tool_call_id: row.get(16)?,
+        tool_name: row.get(17)?,
+        done_reason: row.get(19)?,
+        first_token_ms: row.get(20)?,
+        image_metadata: row
+            .get::<_, Option<String>>(21)?
+            .and_then(|json| serde_json::from_str(&json).ok()),
+        generation_options: row
+            .get::<_, Option<String>>(22)?
+            .and_then(|json| serde_json::from_str(&json).ok()),
+        document_names: row
+            .get::<_, Option<String>>(23)?
+            .and_then(|json| serde_json::from_str(&json).ok()),
+        is_incomplete: row.get::<_, Option<bool>>(24)?.unwrap_or(false),
+        is_superseded: row.get::<_, Option<bool>>(25)?.unwrap_or(false),
+        variant_of: row.get(26)?,
+        thinking_discarded: row.get::<_, Option<bool>>(27)?.unwrap_or(false),
+        exclude_from_context: row.get::<_, Option<bool>>(28)?.unwrap_or(false),
+        image_thumbnails: row
+            .get::<_, Option<String>>(29)?
+            .and_then(|json| serde_json::from_str(&json).ok()),
+    })
+}
+
+fn encode_vector(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+fn decode_vector(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
 }
 
 impl Database {
@@ -44,7 +368,8 @@ impl Database {
                 title TEXT NOT NULL,
                 created_at TEXT NOT NULL,
                 system_prompt TEXT,
-                is_archived BOOLEAN DEFAULT 0
+                is_archived BOOLEAN DEFAULT 0,
+                server_profile_id INTEGER REFERENCES server_profiles(id) ON DELETE SET NULL
             )",
             [],
         )?;
@@ -66,12 +391,116 @@ impl Database {
                 eval_duration INTEGER,
                 tokens_per_second REAL,
                 reply_to_id INTEGER,
+                response_format TEXT,
+                tool_calls TEXT,
+                tool_call_id TEXT,
+                tool_name TEXT,
+                done_reason TEXT,
+                first_token_ms INTEGER,
+                image_metadata TEXT,
+                generation_options TEXT,
+                document_names TEXT,
+                is_incomplete BOOLEAN DEFAULT 0,
+                is_superseded BOOLEAN DEFAULT 0,
+                variant_of INTEGER REFERENCES messages(id) ON DELETE SET NULL,
+                thinking_discarded BOOLEAN DEFAULT 0,
+                exclude_from_context BOOLEAN DEFAULT 0,
+                image_thumbnails TEXT,
                 FOREIGN KEY(thread_id) REFERENCES threads(id),
                 FOREIGN KEY(reply_to_id) REFERENCES messages(id) ON DELETE SET NULL
             )",
             [],
         )?;
 
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS server_profiles (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                base_url TEXT NOT NULL,
+                is_default BOOLEAN DEFAULT 0,
+                kind TEXT NOT NULL DEFAULT 'ollama',
+                auth_token TEXT,
+                headers TEXT,
+                accept_invalid_certs BOOLEAN DEFAULT 0,
+                ca_cert_path TEXT
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS settings (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS embeddings (
+                message_id INTEGER NOT NULL,
+                model TEXT NOT NULL,
+                vector BLOB NOT NULL,
+                PRIMARY KEY(message_id, model),
+                FOREIGN KEY(message_id) REFERENCES messages(id) ON DELETE CASCADE
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS thread_summaries (
+                thread_id INTEGER PRIMARY KEY,
+                summary TEXT NOT NULL,
+                covered_until_id INTEGER NOT NULL,
+                updated_at TEXT NOT NULL,
+                FOREIGN KEY(thread_id) REFERENCES threads(id) ON DELETE CASCADE
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS prompt_presets (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                content TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS model_aliases (
+                model_name TEXT PRIMARY KEY,
+                alias TEXT UNIQUE,
+                is_favorite BOOLEAN NOT NULL DEFAULT 0,
+                sort_order INTEGER NOT NULL DEFAULT 0
+            )",
+            [],
+        )?;
+
+        // `last_used` increases with every read or write, for LRU eviction
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS document_cache (
+                hash TEXT PRIMARY KEY,
+                pages TEXT NOT NULL,
+                size_bytes INTEGER NOT NULL,
+                last_used INTEGER NOT NULL
+            )",
+            [],
+        )?;
+
+        // Documents whose text was put into a thread, so attaching one again
+        // doesn't repeat it. Only counts while `message_id` still exists.
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS thread_documents (
+                thread_id INTEGER NOT NULL,
+                hash TEXT NOT NULL,
+                message_id INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                PRIMARY KEY(thread_id, hash)
+            )",
+            [],
+        )?;
+
         // Migrations for existing tables
         let _ = conn.execute("ALTER TABLE messages ADD COLUMN images TEXT", []);
         let _ = conn.execute("ALTER TABLE messages ADD COLUMN model TEXT", []);
@@ -85,6 +514,46 @@ impl Database {
         let _ = conn.execute("ALTER TABLE messages ADD COLUMN eval_count INTEGER", []);
         let _ = conn.execute("ALTER TABLE messages ADD COLUMN eval_duration INTEGER", []);
         let _ = conn.execute("ALTER TABLE messages ADD COLUMN tokens_per_second REAL", []);
+        let _ = conn.execute("ALTER TABLE messages ADD COLUMN response_format TEXT", []);
+        let _ = conn.execute("ALTER TABLE messages ADD COLUMN tool_calls TEXT", []);
+        let _ = conn.execute("ALTER TABLE messages ADD COLUMN done_reason TEXT", []);
+        let _ = conn.execute("ALTER TABLE messages ADD COLUMN first_token_ms INTEGER", []);
+        let _ = conn.execute("ALTER TABLE messages ADD COLUMN image_metadata TEXT", []);
+        let _ = conn.execute(
+            "ALTER TABLE messages ADD COLUMN generation_options TEXT",
+            [],
+        );
+        let _ = conn.execute("ALTER TABLE messages ADD COLUMN document_names TEXT", []);
+        let _ = conn.execute(
+            "ALTER TABLE messages ADD COLUMN is_incomplete BOOLEAN DEFAULT 0",
+            [],
+        );
+        let _ = conn.execute("ALTER TABLE messages ADD COLUMN client_request_id TEXT", []);
+        let _ = conn.execute(
+            "ALTER TABLE messages ADD COLUMN is_superseded BOOLEAN DEFAULT 0",
+            [],
+        );
+        let _ = conn.execute(
+            "ALTER TABLE messages ADD COLUMN variant_of INTEGER REFERENCES messages(id) ON DELETE SET NULL",
+            [],
+        );
+        let _ = conn.execute(
+            "ALTER TABLE messages ADD COLUMN thinking_discarded BOOLEAN DEFAULT 0",
+            [],
+        );
+        let _ = conn.execute(
+            "ALTER TABLE messages ADD COLUMN exclude_from_context BOOLEAN DEFAULT 0",
+            [],
+        );
+        let _ = conn.execute("ALTER TABLE messages ADD COLUMN image_thumbnails TEXT", []);
+        // A repeated send of the same message is caught even if the check is raced
+        conn.execute(
+            "CREATE UNIQUE INDEX IF NOT EXISTS idx_messages_client_request_id
+             ON messages(thread_id, client_request_id)",
+            [],
+        )?;
+        let _ = conn.execute("ALTER TABLE messages ADD COLUMN tool_call_id TEXT", []);
+        let _ = conn.execute("ALTER TABLE messages ADD COLUMN tool_name TEXT", []);
 
         // Migration for threads table
         let _ = conn.execute("ALTER TABLE threads ADD COLUMN system_prompt TEXT", []);
@@ -92,6 +561,40 @@ impl Database {
             "ALTER TABLE threads ADD COLUMN is_archived BOOLEAN DEFAULT 0",
             [],
         );
+        let _ = conn.execute(
+            "ALTER TABLE threads ADD COLUMN server_profile_id INTEGER",
+            [],
+        );
+        let _ = conn.execute("ALTER TABLE threads ADD COLUMN think BOOLEAN", []);
+        let _ = conn.execute("ALTER TABLE threads ADD COLUMN model_options TEXT", []);
+        let _ = conn.execute(
+            "ALTER TABLE threads ADD COLUMN needs_attention BOOLEAN DEFAULT 0",
+            [],
+        );
+        let _ = conn.execute("ALTER TABLE threads ADD COLUMN summary TEXT", []);
+        let _ = conn.execute(
+            "ALTER TABLE threads ADD COLUMN resend_thinking BOOLEAN DEFAULT 0",
+            [],
+        );
+        let _ = conn.execute("ALTER TABLE threads ADD COLUMN save_thinking BOOLEAN", []);
+        let _ = conn.execute(
+            "ALTER TABLE threads ADD COLUMN max_history_messages INTEGER",
+            [],
+        );
+        let _ = conn.execute(
+            "ALTER TABLE server_profiles ADD COLUMN kind TEXT NOT NULL DEFAULT 'ollama'",
+            [],
+        );
+        let _ = conn.execute("ALTER TABLE server_profiles ADD COLUMN auth_token TEXT", []);
+        let _ = conn.execute("ALTER TABLE server_profiles ADD COLUMN headers TEXT", []);
+        let _ = conn.execute(
+            "ALTER TABLE server_profiles ADD COLUMN accept_invalid_certs BOOLEAN DEFAULT 0",
+            [],
+        );
+        let _ = conn.execute(
+            "ALTER TABLE server_profiles ADD COLUMN ca_cert_path TEXT",
+            [],
+        );
 
         // Check if reply_to_id column exists
         let has_reply_to_id: bool = conn
@@ -106,7 +609,88 @@ impl Database {
             let _ = conn.execute("ALTER TABLE messages ADD COLUMN reply_to_id INTEGER REFERENCES messages(id) ON DELETE SET NULL", []);
         }
 
-        Ok(Database { conn })
+        Ok(Database {
+            conn,
+            read_only: false,
+        })
+    }
+
+    /// Opens an existing database without changing it: no migrations run and
+    /// every write fails. Tables and columns added since the file was written
+    /// read as empty, through temporary views that stand in for its tables.
+    pub fn open_readonly(path: &str) -> Result<Self> {
+        let conn = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        // Each column's name and the SQL of its default, if it has one
+        let columns = |conn: &Connection, table: &str| -> Result<Vec<(String, Option<String>)>> {
+            let mut stmt =
+                conn.prepare("SELECT name, dflt_value FROM pragma_table_info(?1, 'main')")?;
+            let columns = stmt.query_map(params![table], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            columns.collect()
+        };
+        if columns(&conn, "threads")?.is_empty() || columns(&conn, "messages")?.is_empty() {
+            return Err(rusqlite::Error::SqliteFailure(
+                ffi::Error::new(ffi::SQLITE_NOTADB),
+                Some(format!("{} is not a chat database", path)),
+            ));
+        }
+
+        // The current schema, as a fresh database gets it
+        let current = Database::new(":memory:")?;
+        let tables: Vec<String> = {
+            let mut stmt = current.conn.prepare(
+                "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'",
+            )?;
+            let names = stmt.query_map([], |row| row.get(0))?;
+            names.collect::<Result<_>>()?
+        };
+        for table in tables {
+            let existing: Vec<String> = columns(&conn, &table)?
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect();
+            let wanted = columns(&current.conn, &table)?;
+            if wanted.iter().all(|(name, _)| existing.contains(name)) {
+                continue;
+            }
+            // Missing columns read as their default, as a migration would fill them
+            let select = wanted
+                .iter()
+                .map(|(name, default)| {
+                    if existing.contains(name) {
+                        format!("\"{}\"", name)
+                    } else {
+                        format!("{} AS \"{}\"", default.as_deref().unwrap_or("NULL"), name)
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            // Temporary objects live outside the file and are found before it
+            let from = if existing.is_empty() {
+                "WHERE 0".to_string()
+            } else {
+                format!("FROM main.\"{}\"", table)
+            };
+            conn.execute(
+                &format!(
+                    "CREATE TEMP VIEW \"{}\" AS SELECT {} {}",
+                    table, select, from
+                ),
+                [],
+            )?;
+        }
+
+        Ok(Database {
+            conn,
+            read_only: true,
+        })
+    }
+
+    /// Whether this was opened with `open_readonly`.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
     }
 
     pub fn create_thread(&self, title: &str, system_prompt: Option<String>) -> Result<i64> {
@@ -119,18 +703,11 @@ impl Database {
     }
 
     pub fn get_threads(&self) -> Result<Vec<Thread>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT id, title, created_at, system_prompt, is_archived FROM threads WHERE is_archived = 0 ORDER BY created_at DESC",
-        )?;
-        let thread_iter = stmt.query_map([], |row| {
-            Ok(Thread {
-                id: row.get(0)?,
-                title: row.get(1)?,
-                created_at: row.get(2)?,
-                system_prompt: row.get(3)?,
-                is_archived: row.get(4)?,
-            })
-        })?;
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM threads WHERE is_archived = 0 ORDER BY created_at DESC",
+            THREAD_COLUMNS
+        ))?;
+        let thread_iter = stmt.query_map([], thread_from_row)?;
 
         let mut threads = Vec::new();
         for thread in thread_iter {
@@ -139,240 +716,3062 @@ impl Database {
         Ok(threads)
     }
 
-    pub fn get_thread_system_prompt(&self, thread_id: i64) -> Result<Option<String>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT system_prompt FROM threads WHERE id = ?1")?;
-        let mut rows = stmt.query(params![thread_id])?;
+    /// Unarchived threads with at least one reply from `model`. Replies saved
+    /// without a model are found under `UNKNOWN_MODEL`.
+    pub fn get_threads_by_model(&self, model: &str) -> Result<Vec<Thread>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM threads WHERE is_archived = 0 AND id IN (
+                SELECT thread_id FROM messages
+                WHERE role = 'assistant' AND COALESCE(model, ?2) = ?1
+             )
+             ORDER BY created_at DESC",
+            THREAD_COLUMNS
+        ))?;
+        let thread_iter = stmt.query_map(params![model, UNKNOWN_MODEL], thread_from_row)?;
 
-        if let Some(row) = rows.next()? {
-            Ok(row.get(0)?)
-        } else {
-            Ok(None)
+        let mut threads = Vec::new();
+        for thread in thread_iter {
+            threads.push(thread?);
         }
+        Ok(threads)
     }
 
-    pub fn archive_thread(&self, thread_id: i64) -> Result<()> {
-        self.conn.execute(
-            "UPDATE threads SET is_archived = 1 WHERE id = ?1",
-            params![thread_id],
+    /// Every model that replied in an unarchived thread, with the number of
+    /// threads it replied in, most used first.
+    pub fn get_models_used(&self) -> Result<Vec<(String, i64)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT COALESCE(m.model, ?1) AS name, COUNT(DISTINCT m.thread_id) AS thread_count
+             FROM messages m JOIN threads t ON t.id = m.thread_id
+             WHERE m.role = 'assistant' AND t.is_archived = 0
+             GROUP BY name
+             ORDER BY thread_count DESC, name",
         )?;
-        Ok(())
-    }
+        let model_iter =
+            stmt.query_map(params![UNKNOWN_MODEL], |row| Ok((row.get(0)?, row.get(1)?)))?;
 
-    pub fn add_message(
-        &self,
-        thread_id: i64,
-        role: &str,
-        content: &str,
-        images: Option<Vec<String>>,
-        model: Option<String>,
-        reply_to_id: Option<i64>,
-    ) -> Result<i64> {
-        let now = Utc::now().to_rfc3339();
-        let images_json = images.map(|imgs| serde_json::to_string(&imgs).unwrap_or_default());
+        let mut models = Vec::new();
+        for model in model_iter {
+            models.push(model?);
+        }
+        Ok(models)
+    }
 
-        self.conn.execute(
-            "INSERT INTO messages (thread_id, role, content, images, model, created_at, reply_to_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-            params![thread_id, role, content, images_json, model, now, reply_to_id],
+    /// Activity on each local calendar day from `first` to `last`, both
+    /// included, with days without any counted as zero.
+    pub fn activity_stats(&self, first: NaiveDate, last: NaiveDate) -> Result<Vec<DailyActivity>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT date(created_at, 'localtime') AS day,
+                    SUM(role = 'user'), SUM(role = 'assistant'), COALESCE(SUM(eval_count), 0)
+             FROM messages
+             WHERE date(created_at, 'localtime') BETWEEN ?1 AND ?2
+             GROUP BY day",
         )?;
-        Ok(self.conn.last_insert_rowid())
+        let day_iter = stmt.query_map(params![first.to_string(), last.to_string()], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                (row.get(1)?, row.get(2)?, row.get(3)?),
+            ))
+        })?;
+
+        let mut counts: HashMap<String, (i64, i64, i64)> = HashMap::new();
+        for day in day_iter {
+            let (day, day_counts) = day?;
+            counts.insert(day, day_counts);
+        }
+        Ok(first
+            .iter_days()
+            .take_while(|date| *date <= last)
+            .map(|date| {
+                let (user_messages, assistant_messages, tokens) =
+                    counts.get(&date.to_string()).copied().unwrap_or_default();
+                DailyActivity {
+                    date,
+                    user_messages,
+                    assistant_messages,
+                    tokens,
+                }
+            })
+            .collect())
     }
 
-    pub fn get_messages(&self, thread_id: i64) -> Result<Vec<Message>> {
+    /// Usage and speed of each model that replied, most used first. Replies
+    /// saved before models were recorded count under `UNKNOWN_MODEL`.
+    pub fn model_stats(&self) -> Result<Vec<ModelStats>> {
         let mut stmt = self.conn.prepare(
-            "SELECT 
-                id, thread_id, role, content, model, thinking_process,
-                total_duration, load_duration, prompt_eval_count, eval_count, eval_duration, reply_to_id, created_at, images
-             FROM messages WHERE thread_id = ?1 ORDER BY created_at ASC",
+            "SELECT COALESCE(model, ?1) AS name, COUNT(*) AS message_count,
+                    COALESCE(SUM(eval_count), 0), AVG(tokens_per_second), AVG(total_duration)
+             FROM messages
+             WHERE role = 'assistant'
+             GROUP BY name
+             ORDER BY message_count DESC, name",
         )?;
+        let stats_iter = stmt.query_map(params![UNKNOWN_MODEL], |row| {
+            Ok(ModelStats {
+                model: row.get(0)?,
+                message_count: row.get(1)?,
+                tokens: row.get(2)?,
+                avg_tokens_per_second: row.get(3)?,
+                avg_total_duration: row.get(4)?,
+            })
+        })?;
 
-        let message_iter = stmt.query_map(params![thread_id], |row| {
-            let images_json: Option<String> = row.get(13)?;
-            let images = if let Some(json) = images_json {
-                serde_json::from_str(&json).unwrap_or_default()
-            } else {
-                Vec::new()
-            };
+        let mut stats = Vec::new();
+        for model in stats_iter {
+            stats.push(model?);
+        }
+        Ok(stats)
+    }
 
-            Ok(Message {
-                id: row.get(0)?,
-                thread_id,
-                role: row.get(2)?,
-                content: row.get(3)?,
-                images: if images.is_empty() {
-                    None
-                } else {
-                    Some(images)
-                },
-                model: row.get(4)?,
-                thinking_process: row.get(5)?,
-                total_duration: row.get(6)?,
-                load_duration: row.get(7)?,
-                prompt_eval_count: row.get(8)?,
-                eval_count: row.get(9)?,
-                eval_duration: row.get(10)?,
-                reply_to_id: row.get(11)?,
-                created_at: row.get(12)?,
-                tokens_per_second: None, // Need to fix this if column exists or calculate it
+    /// The latency of a model's newest `limit` replies that recorded any,
+    /// oldest first.
+    pub fn latency_history(&self, model: &str, limit: usize) -> Result<Vec<LatencyPoint>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, created_at, first_token_ms, total_duration FROM messages
+             WHERE role = 'assistant' AND COALESCE(model, ?2) = ?1
+               AND (first_token_ms IS NOT NULL OR total_duration IS NOT NULL)
+             ORDER BY id DESC
+             LIMIT ?3",
+        )?;
+        let point_iter = stmt.query_map(params![model, UNKNOWN_MODEL, limit as i64], |row| {
+            Ok(LatencyPoint {
+                message_id: row.get(0)?,
+                created_at: row.get(1)?,
+                first_token_ms: row.get(2)?,
+                total_duration: row.get(3)?,
             })
         })?;
 
-        let mut messages = Vec::new();
-        for message in message_iter {
-            messages.push(message?);
+        let mut points = Vec::new();
+        for point in point_iter {
+            points.push(point?);
         }
+        points.reverse();
+        Ok(points)
+    }
 
-        Ok(messages)
+    /// Flags threads whose last generation never finished: ones ending in a
+    /// user message sent before `unanswered_before`, or in a reply cut off by a
+    /// failed stream. Meant to run at startup, when nothing is generating.
+    /// Returns the ids of the threads newly flagged.
+    pub fn flag_interrupted_threads(
+        &self,
+        unanswered_before: chrono::DateTime<Utc>,
+    ) -> Result<Vec<i64>> {
+        self.update_returning_ids(
+            "UPDATE threads SET needs_attention = 1
+             WHERE COALESCE(needs_attention, 0) = 0 AND id IN (
+                SELECT m.thread_id FROM messages m
+                WHERE m.id = (SELECT id FROM messages WHERE thread_id = m.thread_id
+                              ORDER BY created_at DESC, id DESC LIMIT 1)
+                  AND ((m.role = 'user' AND julianday(m.created_at) < julianday(?1))
+                    OR (m.role = 'assistant' AND m.is_incomplete = 1))
+             )
+             RETURNING id",
+            params![unanswered_before.to_rfc3339()],
+        )
     }
 
-    pub fn update_thread_title(&self, thread_id: i64, new_title: &str) -> Result<()> {
+    /// Archives every thread with no activity since `last_active_before`: its
+    /// latest message, or its creation when it has none, is older. Returns the
+    /// ids of the archived threads.
+    pub fn archive_stale_threads(
+        &self,
+        last_active_before: chrono::DateTime<Utc>,
+    ) -> Result<Vec<i64>> {
+        self.update_returning_ids(
+            "UPDATE threads SET is_archived = 1
+             WHERE COALESCE(is_archived, 0) = 0
+               AND julianday(COALESCE(
+                    (SELECT MAX(created_at) FROM messages WHERE thread_id = threads.id),
+                    created_at
+                   )) < julianday(?1)
+             RETURNING id",
+            params![last_active_before.to_rfc3339()],
+        )
+    }
+
+    /// Runs an UPDATE ending in `RETURNING id` and returns the ids in order.
+    fn update_returning_ids(&self, sql: &str, params: impl rusqlite::Params) -> Result<Vec<i64>> {
+        let mut stmt = self.conn.prepare(sql)?;
+        let id_iter = stmt.query_map(params, |row| row.get(0))?;
+
+        let mut ids = Vec::new();
+        for id in id_iter {
+            ids.push(id?);
+        }
+        // RETURNING gives no order
+        ids.sort();
+        Ok(ids)
+    }
+
+    pub fn get_interrupted_threads(&self) -> Result<Vec<Thread>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM threads WHERE needs_attention = 1 AND is_archived = 0
+             ORDER BY created_at DESC",
+            THREAD_COLUMNS
+        ))?;
+        let thread_iter = stmt.query_map([], thread_from_row)?;
+
+        let mut threads = Vec::new();
+        for thread in thread_iter {
+            threads.push(thread?);
+        }
+        Ok(threads)
+    }
+
+    pub fn get_thread(&self, thread_id: i64) -> Result<Thread> {
+        self.conn.query_row(
+            &format!("SELECT {} FROM threads WHERE id = ?1", THREAD_COLUMNS),
+            params![thread_id],
+            thread_from_row,
+        )
+    }
+
+    pub fn set_thread_system_prompt(
+        &self,
+        thread_id: i64,
+        system_prompt: Option<String>,
+    ) -> Result<()> {
         self.conn.execute(
-            "UPDATE threads SET title = ?1 WHERE id = ?2",
-            params![new_title, thread_id],
+            "UPDATE threads SET system_prompt = ?1 WHERE id = ?2",
+            params![system_prompt, thread_id],
         )?;
         Ok(())
     }
 
-    pub fn delete_thread(&self, thread_id: i64) -> Result<()> {
-        // First delete all messages in the thread
+    pub fn set_thread_summary(&self, thread_id: i64, summary: &str) -> Result<()> {
         self.conn.execute(
-            "DELETE FROM messages WHERE thread_id = ?1",
-            params![thread_id],
+            "UPDATE threads SET summary = ?1 WHERE id = ?2",
+            params![summary, thread_id],
         )?;
-
-        // Then delete the thread itself
-        self.conn
-            .execute("DELETE FROM threads WHERE id = ?1", params![thread_id])?;
         Ok(())
     }
 
-    pub fn update_message(&self, message_id: i64, content: &str) -> Result<()> {
+    pub fn create_preset(&self, name: &str, content: &str) -> Result<i64> {
+        let now = Utc::now().to_rfc3339();
         self.conn.execute(
-            "UPDATE messages SET content = ?1 WHERE id = ?2",
-            params![content, message_id],
+            "INSERT INTO prompt_presets (name, content, created_at) VALUES (?1, ?2, ?3)",
+            params![name, content, now],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn get_presets(&self) -> Result<Vec<PromptPreset>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, name, content, created_at FROM prompt_presets ORDER BY name")?;
+        let preset_iter = stmt.query_map([], preset_from_row)?;
+
+        let mut presets = Vec::new();
+        for preset in preset_iter {
+            presets.push(preset?);
+        }
+        Ok(presets)
+    }
+
+    pub fn get_preset(&self, preset_id: i64) -> Result<PromptPreset> {
+        self.conn.query_row(
+            "SELECT id, name, content, created_at FROM prompt_presets WHERE id = ?1",
+            params![preset_id],
+            preset_from_row,
+        )
+    }
+
+    pub fn update_preset(&self, preset_id: i64, name: &str, content: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE prompt_presets SET name = ?1, content = ?2 WHERE id = ?3",
+            params![name, content, preset_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_preset(&self, preset_id: i64) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM prompt_presets WHERE id = ?1",
+            params![preset_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_model_alias(&self, alias: &ModelAlias) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO model_aliases (model_name, alias, is_favorite, sort_order)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(model_name) DO UPDATE SET
+                alias = excluded.alias,
+                is_favorite = excluded.is_favorite,
+                sort_order = excluded.sort_order",
+            params![
+                alias.model_name,
+                alias.alias,
+                alias.is_favorite,
+                alias.sort_order
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_model_alias(&self, model_name: &str) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM model_aliases WHERE model_name = ?1",
+            params![model_name],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_model_aliases(&self) -> Result<Vec<ModelAlias>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT model_name, alias, is_favorite, sort_order FROM model_aliases
+             ORDER BY sort_order, model_name",
+        )?;
+        let alias_iter = stmt.query_map([], |row| {
+            Ok(ModelAlias {
+                model_name: row.get(0)?,
+                alias: row.get(1)?,
+                is_favorite: row.get(2)?,
+                sort_order: row.get(3)?,
+            })
+        })?;
+
+        let mut aliases = Vec::new();
+        for alias in alias_iter {
+            aliases.push(alias?);
+        }
+        Ok(aliases)
+    }
+
+    /// Maps an alias back to its model name; anything else is returned unchanged.
+    pub fn resolve_model_name(&self, name: &str) -> Result<String> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT model_name FROM model_aliases WHERE alias = ?1")?;
+        let mut rows = stmt.query(params![name])?;
+        match rows.next()? {
+            Some(row) => row.get(0),
+            None => Ok(name.to_string()),
+        }
+    }
+
+    pub fn get_thread_system_prompt(&self, thread_id: i64) -> Result<Option<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT system_prompt FROM threads WHERE id = ?1")?;
+        let mut rows = stmt.query(params![thread_id])?;
+
+        if let Some(row) = rows.next()? {
+            Ok(row.get(0)?)
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn archive_thread(&self, thread_id: i64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE threads SET is_archived = 1 WHERE id = ?1",
+            params![thread_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_thread_server_profile(&self, thread_id: i64, profile_id: Option<i64>) -> Result<()> {
+        self.conn.execute(
+            "UPDATE threads SET server_profile_id = ?1 WHERE id = ?2",
+            params![profile_id, thread_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_thread_model_options(&self, thread_id: i64, options: &ModelOptions) -> Result<()> {
+        let json = if options.is_empty() {
+            None
+        } else {
+            serde_json::to_string(options).ok()
+        };
+        self.conn.execute(
+            "UPDATE threads SET model_options = ?1 WHERE id = ?2",
+            params![json, thread_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_thread_resend_thinking(&self, thread_id: i64, resend: bool) -> Result<()> {
+        self.conn.execute(
+            "UPDATE threads SET resend_thinking = ?1 WHERE id = ?2",
+            params![resend, thread_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_thread_max_history_messages(
+        &self,
+        thread_id: i64,
+        max_messages: Option<u32>,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE threads SET max_history_messages = ?1 WHERE id = ?2",
+            params![max_messages, thread_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_thread_save_thinking(&self, thread_id: i64, save: Option<bool>) -> Result<()> {
+        self.conn.execute(
+            "UPDATE threads SET save_thinking = ?1 WHERE id = ?2",
+            params![save, thread_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_thread_think(&self, thread_id: i64, think: Option<bool>) -> Result<()> {
+        self.conn.execute(
+            "UPDATE threads SET think = ?1 WHERE id = ?2",
+            params![think, thread_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn create_server_profile(
+        &self,
+        name: &str,
+        base_url: &str,
+        kind: &str,
+        is_default: bool,
+    ) -> Result<i64> {
+        if is_default {
+            self.conn
+                .execute("UPDATE server_profiles SET is_default = 0", [])?;
+        }
+        self.conn.execute(
+            "INSERT INTO server_profiles (name, base_url, kind, is_default) VALUES (?1, ?2, ?3, ?4)",
+            params![name, base_url, kind, is_default],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn get_server_profiles(&self) -> Result<Vec<ServerProfile>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM server_profiles ORDER BY name",
+            SERVER_PROFILE_COLUMNS
+        ))?;
+        let profile_iter = stmt.query_map([], server_profile_from_row)?;
+
+        let mut profiles = Vec::new();
+        for profile in profile_iter {
+            profiles.push(profile?);
+        }
+        Ok(profiles)
+    }
+
+    pub fn get_server_profile(&self, profile_id: i64) -> Result<ServerProfile> {
+        self.conn.query_row(
+            &format!(
+                "SELECT {} FROM server_profiles WHERE id = ?1",
+                SERVER_PROFILE_COLUMNS
+            ),
+            params![profile_id],
+            server_profile_from_row,
+        )
+    }
+
+    pub fn get_default_server_profile(&self) -> Result<Option<ServerProfile>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM server_profiles WHERE is_default = 1 LIMIT 1",
+            SERVER_PROFILE_COLUMNS
+        ))?;
+        let mut rows = stmt.query([])?;
+
+        if let Some(row) = rows.next()? {
+            Ok(Some(server_profile_from_row(row)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn update_server_profile(
+        &self,
+        profile_id: i64,
+        name: &str,
+        base_url: &str,
+        kind: &str,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE server_profiles SET name = ?1, base_url = ?2, kind = ?3 WHERE id = ?4",
+            params![name, base_url, kind, profile_id],
+        )?;
+        Ok(())
+    }
+
+    /// Sets or, with `None`, clears the bearer token sent to this server.
+    pub fn set_server_profile_auth_token(
+        &self,
+        profile_id: i64,
+        auth_token: Option<&str>,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE server_profiles SET auth_token = ?1 WHERE id = ?2",
+            params![auth_token, profile_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_server_profile_headers(
+        &self,
+        profile_id: i64,
+        headers: &HashMap<String, String>,
+    ) -> Result<()> {
+        let json = serde_json::to_string(headers).unwrap_or_default();
+        self.conn.execute(
+            "UPDATE server_profiles SET headers = ?1 WHERE id = ?2",
+            params![json, profile_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_server_profile_tls(
+        &self,
+        profile_id: i64,
+        accept_invalid_certs: bool,
+        ca_cert_path: Option<&str>,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE server_profiles SET accept_invalid_certs = ?1, ca_cert_path = ?2 WHERE id = ?3",
+            params![accept_invalid_certs, ca_cert_path, profile_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_default_server_profile(&self, profile_id: i64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE server_profiles SET is_default = (id = ?1)",
+            params![profile_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_server_profile(&self, profile_id: i64) -> Result<()> {
+        // Threads using this profile fall back to the default server
+        self.conn.execute(
+            "UPDATE threads SET server_profile_id = NULL WHERE server_profile_id = ?1",
+            params![profile_id],
+        )?;
+        self.conn.execute(
+            "DELETE FROM server_profiles WHERE id = ?1",
+            params![profile_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_setting(&self, key: &str) -> Result<Option<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT value FROM settings WHERE key = ?1")?;
+        let mut rows = stmt.query(params![key])?;
+
+        if let Some(row) = rows.next()? {
+            Ok(row.get(0)?)
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn set_setting(&self, key: &str, value: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO settings (key, value) VALUES (?1, ?2)
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+            params![key, value],
+        )?;
+        Ok(())
+    }
+
+    /// Returns every setting whose key starts with `prefix`, keys included in full.
+    pub fn stats(&self) -> Result<DbStats> {
+        let pragma = |name: &str| -> Result<i64> {
+            self.conn
+                .query_row(&format!("PRAGMA {}", name), [], |row| row.get(0))
+        };
+        let count = |table: &str| -> Result<i64> {
+            self.conn
+                .query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                    row.get(0)
+                })
+        };
+        Ok(DbStats {
+            path: self.conn.path().unwrap_or_default().to_string(),
+            size_bytes: (pragma("page_count")? * pragma("page_size")?) as u64,
+            thread_count: count("threads")?,
+            message_count: count("messages")?,
+            schema_version: pragma("user_version")?,
+        })
+    }
+
+    pub fn get_settings_with_prefix(&self, prefix: &str) -> Result<Vec<(String, String)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT key, value FROM settings WHERE substr(key, 1, length(?1)) = ?1 ORDER BY key",
+        )?;
+        let setting_iter = stmt.query_map(params![prefix], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+        let mut settings = Vec::new();
+        for setting in setting_iter {
+            settings.push(setting?);
+        }
+        Ok(settings)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_message(
+        &self,
+        thread_id: i64,
+        role: Role,
+        content: &str,
+        images: Option<Vec<String>>,
+        model: Option<String>,
+        reply_to_id: Option<i64>,
+        thinking_process: Option<String>,
+    ) -> Result<i64> {
+        let now = Utc::now().to_rfc3339();
+        let images_json = images.map(|imgs| serde_json::to_string(&imgs).unwrap_or_default());
+
+        self.conn.execute(
+            "INSERT INTO messages (thread_id, role, content, images, model, created_at, reply_to_id, thinking_process) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![thread_id, role, content, images_json, model, now, reply_to_id, thinking_process],
+        )?;
+        let message_id = self.conn.last_insert_rowid();
+        // The thread has moved on from whatever was interrupted
+        self.conn.execute(
+            "UPDATE threads SET needs_attention = 0 WHERE id = ?1 AND needs_attention = 1",
+            params![thread_id],
+        )?;
+        Ok(message_id)
+    }
+
+    /// Inserts many messages in one transaction, returning their ids in order.
+    /// Much faster than `add_message` in a loop, which commits every row.
+    pub fn add_messages_batch(
+        &self,
+        thread_id: i64,
+        messages: Vec<NewMessage>,
+    ) -> Result<Vec<i64>> {
+        let now = Utc::now().to_rfc3339();
+        let tx = self.conn.unchecked_transaction()?;
+        let mut ids = Vec::with_capacity(messages.len());
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT INTO messages (thread_id, role, content, images, model, created_at, reply_to_id, thinking_process) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?;
+            for message in messages {
+                let images_json = message
+                    .images
+                    .map(|imgs| serde_json::to_string(&imgs).unwrap_or_default());
+                stmt.execute(params![
+                    thread_id,
+                    message.role,
+                    message.content,
+                    images_json,
+                    message.model,
+                    message.created_at.as_deref().unwrap_or(&now),
+                    message.reply_to_id,
+                    message.thinking_process
+                ])?;
+                ids.push(tx.last_insert_rowid());
+            }
+        }
+        if !ids.is_empty() {
+            tx.execute(
+                "UPDATE threads SET needs_attention = 0 WHERE id = ?1 AND needs_attention = 1",
+                params![thread_id],
+            )?;
+        }
+        tx.commit()?;
+        Ok(ids)
+    }
+
+    pub fn set_message_created_at(&self, message_id: i64, created_at: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE messages SET created_at = ?1 WHERE id = ?2",
+            params![created_at, message_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_message_done_reason(&self, message_id: i64, done_reason: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE messages SET done_reason = ?1 WHERE id = ?2",
+            params![done_reason, message_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_message_image_thumbnails(
+        &self,
+        message_id: i64,
+        thumbnails: &[String],
+    ) -> Result<()> {
+        let json = serde_json::to_string(thumbnails).unwrap_or_default();
+        self.conn.execute(
+            "UPDATE messages SET image_thumbnails = ?1 WHERE id = ?2",
+            params![json, message_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_message_image_metadata(
+        &self,
+        message_id: i64,
+        metadata: &[ImageMetadata],
+    ) -> Result<()> {
+        let json = serde_json::to_string(metadata).unwrap_or_default();
+        self.conn.execute(
+            "UPDATE messages SET image_metadata = ?1 WHERE id = ?2",
+            params![json, message_id],
+        )?;
+        Ok(())
+    }
+
+    /// The message sent with this client request id, if any.
+    pub fn find_message_by_client_request_id(
+        &self,
+        thread_id: i64,
+        client_request_id: &str,
+    ) -> Result<Option<i64>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id FROM messages WHERE thread_id = ?1 AND client_request_id = ?2")?;
+        let mut rows = stmt.query(params![thread_id, client_request_id])?;
+
+        if let Some(row) = rows.next()? {
+            Ok(Some(row.get(0)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn set_message_client_request_id(
+        &self,
+        message_id: i64,
+        client_request_id: &str,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE messages SET client_request_id = ?1 WHERE id = ?2",
+            params![client_request_id, message_id],
+        )?;
+        Ok(())
+    }
+
+    /// Marks `old_id` as replaced by the answer `new_id`.
+    pub fn supersede_message(&self, old_id: i64, new_id: i64) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "UPDATE messages SET is_superseded = 1 WHERE id = ?1",
+            params![old_id],
+        )?;
+        tx.execute(
+            "UPDATE messages SET variant_of = ?1 WHERE id = ?2",
+            params![old_id, new_id],
+        )?;
+        tx.commit()
+    }
+
+    pub fn set_message_thinking_discarded(&self, message_id: i64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE messages SET thinking_discarded = 1 WHERE id = ?1",
+            params![message_id],
         )?;
         Ok(())
     }
 
-    pub fn delete_messages_from(&self, thread_id: i64, message_id: i64) -> Result<()> {
-        self.conn.execute(
-            "DELETE FROM messages WHERE thread_id = ?1 AND id >= ?2",
-            params![thread_id, message_id],
-        )?;
-        Ok(())
-    }
+    pub fn set_message_excluded(&self, message_id: i64, excluded: bool) -> Result<()> {
+        self.conn.execute(
+            "UPDATE messages SET exclude_from_context = ?1 WHERE id = ?2",
+            params![excluded, message_id],
+        )?;
+        Ok(())
+    }
+
+    /// The id of the newest user message in a thread, if it has one.
+    pub fn get_last_user_message_id(&self, thread_id: i64) -> Result<Option<i64>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id FROM messages WHERE thread_id = ?1 AND role = 'user'
+             ORDER BY created_at DESC, id DESC LIMIT 1",
+        )?;
+        let mut rows = stmt.query(params![thread_id])?;
+
+        if let Some(row) = rows.next()? {
+            Ok(Some(row.get(0)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn set_message_incomplete(&self, message_id: i64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE messages SET is_incomplete = 1 WHERE id = ?1",
+            params![message_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_message_document_names(&self, message_id: i64, names: &[String]) -> Result<()> {
+        let json = serde_json::to_string(names).unwrap_or_default();
+        self.conn.execute(
+            "UPDATE messages SET document_names = ?1 WHERE id = ?2",
+            params![json, message_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_message_generation_options(
+        &self,
+        message_id: i64,
+        options: &ModelOptions,
+    ) -> Result<()> {
+        let json = serde_json::to_string(options).unwrap_or_default();
+        self.conn.execute(
+            "UPDATE messages SET generation_options = ?1 WHERE id = ?2",
+            params![json, message_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_message_metrics(&self, message_id: i64, metrics: &MessageMetrics) -> Result<()> {
+        self.conn.execute(
+            "UPDATE messages SET total_duration = ?1, load_duration = ?2, prompt_eval_count = ?3,
+                eval_count = ?4, eval_duration = ?5, tokens_per_second = ?6, first_token_ms = ?7
+             WHERE id = ?8",
+            params![
+                metrics.total_duration,
+                metrics.load_duration,
+                metrics.prompt_eval_count,
+                metrics.eval_count,
+                metrics.eval_duration,
+                metrics.tokens_per_second,
+                metrics.first_token_ms,
+                message_id
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_message_response_format(&self, message_id: i64, format: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE messages SET response_format = ?1 WHERE id = ?2",
+            params![format, message_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_message_tool_calls(
+        &self,
+        message_id: i64,
+        tool_calls: &serde_json::Value,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE messages SET tool_calls = ?1 WHERE id = ?2",
+            params![tool_calls.to_string(), message_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn add_tool_message(
+        &self,
+        thread_id: i64,
+        tool_call_id: &str,
+        tool_name: &str,
+        content: &str,
+    ) -> Result<i64> {
+        let now = Utc::now().to_rfc3339();
+        self.conn.execute(
+            "INSERT INTO messages (thread_id, role, content, created_at, tool_call_id, tool_name) VALUES (?1, 'tool', ?2, ?3, ?4, ?5)",
+            params![thread_id, content, now, tool_call_id, tool_name],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn get_messages(&self, thread_id: i64) -> Result<Vec<Message>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM messages WHERE thread_id = ?1 ORDER BY created_at ASC, id ASC",
+            MESSAGE_COLUMNS
+        ))?;
+
+        let message_iter = stmt.query_map(params![thread_id], message_from_row)?;
+
+        let mut messages = Vec::new();
+        for message in message_iter {
+            messages.push(message?);
+        }
+
+        Ok(messages)
+    }
+
+    /// Only the messages of a thread with the given role, in the same order as
+    /// `get_messages`.
+    pub fn get_messages_by_role(&self, thread_id: i64, role: Role) -> Result<Vec<Message>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM messages WHERE thread_id = ?1 AND role = ?2 ORDER BY created_at ASC, id ASC",
+            MESSAGE_COLUMNS
+        ))?;
+
+        let message_iter = stmt.query_map(params![thread_id, role], message_from_row)?;
+
+        let mut messages = Vec::new();
+        for message in message_iter {
+            messages.push(message?);
+        }
+
+        Ok(messages)
+    }
+
+    pub fn get_message(&self, message_id: i64) -> Result<Message> {
+        self.conn.query_row(
+            &format!("SELECT {} FROM messages WHERE id = ?1", MESSAGE_COLUMNS),
+            params![message_id],
+            message_from_row,
+        )
+    }
+
+    pub fn get_messages_without_embeddings(
+        &self,
+        thread_id: i64,
+        model: &str,
+    ) -> Result<Vec<Message>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM messages WHERE thread_id = ?1 AND id NOT IN
+                (SELECT message_id FROM embeddings WHERE model = ?2)
+             ORDER BY created_at ASC, id ASC",
+            MESSAGE_COLUMNS
+        ))?;
+
+        let message_iter = stmt.query_map(params![thread_id, model], message_from_row)?;
+
+        let mut messages = Vec::new();
+        for message in message_iter {
+            messages.push(message?);
+        }
+
+        Ok(messages)
+    }
+
+    pub fn save_embedding(&self, message_id: i64, model: &str, vector: &[f32]) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO embeddings (message_id, model, vector) VALUES (?1, ?2, ?3)",
+            params![message_id, model, encode_vector(vector)],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_embeddings(&self, model: &str) -> Result<Vec<(i64, Vec<f32>)>> {
+        // Skip vectors whose message has since been deleted
+        let mut stmt = self.conn.prepare(
+            "SELECT message_id, vector FROM embeddings
+             WHERE model = ?1 AND message_id IN (SELECT id FROM messages)",
+        )?;
+        let embedding_iter = stmt.query_map(params![model], |row| {
+            let blob: Vec<u8> = row.get(1)?;
+            Ok((row.get(0)?, decode_vector(&blob)))
+        })?;
+
+        let mut embeddings = Vec::new();
+        for embedding in embedding_iter {
+            embeddings.push(embedding?);
+        }
+        Ok(embeddings)
+    }
+
+    pub fn update_thread_title(&self, thread_id: i64, new_title: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE threads SET title = ?1 WHERE id = ?2",
+            params![new_title, thread_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_thread(&self, thread_id: i64) -> Result<()> {
+        // First delete all messages in the thread, along with their embeddings
+        self.conn.execute(
+            "DELETE FROM embeddings WHERE message_id IN (SELECT id FROM messages WHERE thread_id = ?1)",
+            params![thread_id],
+        )?;
+        self.conn.execute(
+            "DELETE FROM messages WHERE thread_id = ?1",
+            params![thread_id],
+        )?;
+        self.conn.execute(
+            "DELETE FROM thread_summaries WHERE thread_id = ?1",
+            params![thread_id],
+        )?;
+        self.conn.execute(
+            "DELETE FROM thread_documents WHERE thread_id = ?1",
+            params![thread_id],
+        )?;
+
+        // Then delete the thread itself
+        self.conn
+            .execute("DELETE FROM threads WHERE id = ?1", params![thread_id])?;
+        Ok(())
+    }
+
+    /// Moves every message of `source_id` into `target_id` and then deletes the
+    /// source, or archives it when `archive_source` is set. Messages keep their
+    /// ids and timestamps, so they interleave by time and replies still point at
+    /// the right message. A `divider` is added as a system message just before
+    /// the earliest moved one. Returns how many messages were moved.
+    pub fn merge_threads(
+        &self,
+        source_id: i64,
+        target_id: i64,
+        divider: Option<&str>,
+        archive_source: bool,
+    ) -> Result<usize> {
+        let tx = self.conn.unchecked_transaction()?;
+
+        if let Some(divider) = divider {
+            let first: Option<String> = tx.query_row(
+                "SELECT MIN(created_at) FROM messages WHERE thread_id = ?1",
+                params![source_id],
+                |row| row.get(0),
+            )?;
+            if let Some(first) = first {
+                // Sorts right before the first moved message
+                let at = chrono::DateTime::parse_from_rfc3339(&first)
+                    .map(|t| (t - chrono::Duration::microseconds(1)).to_rfc3339())
+                    .unwrap_or(first);
+                tx.execute(
+                    "INSERT INTO messages (thread_id, role, content, created_at) VALUES (?1, ?2, ?3, ?4)",
+                    params![target_id, Role::System, divider, at],
+                )?;
+            }
+        }
+
+        let moved = tx.execute(
+            "UPDATE messages SET thread_id = ?1 WHERE thread_id = ?2",
+            params![target_id, source_id],
+        )?;
+        // Documents already in the target stay attributed to it
+        tx.execute(
+            "INSERT OR IGNORE INTO thread_documents (thread_id, hash, message_id, name)
+             SELECT ?1, hash, message_id, name FROM thread_documents WHERE thread_id = ?2",
+            params![target_id, source_id],
+        )?;
+        tx.execute(
+            "DELETE FROM thread_documents WHERE thread_id = ?1",
+            params![source_id],
+        )?;
+        // The target's running summary no longer matches its history
+        tx.execute(
+            "DELETE FROM thread_summaries WHERE thread_id IN (?1, ?2)",
+            params![source_id, target_id],
+        )?;
+        tx.execute(
+            "UPDATE threads SET needs_attention = 0 WHERE id = ?1",
+            params![target_id],
+        )?;
+        if archive_source {
+            tx.execute(
+                "UPDATE threads SET is_archived = 1, needs_attention = 0 WHERE id = ?1",
+                params![source_id],
+            )?;
+        } else {
+            tx.execute("DELETE FROM threads WHERE id = ?1", params![source_id])?;
+        }
+
+        tx.commit()?;
+        Ok(moved)
+    }
+
+    /// Moves the messages of `thread_id` from `from_message_id` on into a new
+    /// thread titled `new_title`, which gets the same system prompt, server and
+    /// model settings. Replies that would point across the two threads lose
+    /// their link. Returns the new thread's id.
+    pub fn split_thread(
+        &self,
+        thread_id: i64,
+        from_message_id: i64,
+        new_title: &str,
+    ) -> Result<i64> {
+        let now = Utc::now().to_rfc3339();
+        let tx = self.conn.unchecked_transaction()?;
+
+        tx.execute(
+            "INSERT INTO threads (title, created_at, system_prompt, is_archived, server_profile_id, think, model_options, resend_thinking, save_thinking, max_history_messages)
+             SELECT ?1, ?2, system_prompt, 0, server_profile_id, think, model_options, resend_thinking, save_thinking, max_history_messages FROM threads WHERE id = ?3",
+            params![new_title, now, thread_id],
+        )?;
+        let new_id = tx.last_insert_rowid();
+
+        tx.execute(
+            "UPDATE messages SET thread_id = ?1
+             WHERE thread_id = ?2 AND (created_at, id) >= (SELECT created_at, id FROM messages WHERE id = ?3)",
+            params![new_id, thread_id, from_message_id],
+        )?;
+        tx.execute(
+            "UPDATE messages SET reply_to_id = NULL
+             WHERE thread_id IN (?1, ?2)
+               AND reply_to_id IN (SELECT other.id FROM messages other
+                                   WHERE other.thread_id IN (?1, ?2)
+                                     AND other.thread_id != messages.thread_id)",
+            params![thread_id, new_id],
+        )?;
+        tx.execute(
+            "UPDATE thread_documents SET thread_id = ?1
+             WHERE thread_id = ?2 AND message_id IN (SELECT id FROM messages WHERE thread_id = ?1)",
+            params![new_id, thread_id],
+        )?;
+        // A running summary that covers moved messages no longer fits
+        tx.execute(
+            "DELETE FROM thread_summaries
+             WHERE thread_id = ?1 AND covered_until_id IN (SELECT id FROM messages WHERE thread_id = ?2)",
+            params![thread_id, new_id],
+        )?;
+        tx.execute(
+            "UPDATE threads SET needs_attention = 0 WHERE id = ?1",
+            params![thread_id],
+        )?;
+
+        tx.commit()?;
+        Ok(new_id)
+    }
+
+    /// Replaces every match of `redactor` in a thread's messages, their
+    /// reasoning and the thread's summaries, in one transaction. Cached text
+    /// of documents attached in the thread that matches is dropped rather than
+    /// rewritten, as other threads share it, and so are the embeddings of
+    /// rewritten messages. With `dry_run` nothing is changed, only counted.
+    pub fn redact_thread(
+        &self,
+        thread_id: i64,
+        redactor: &Redactor,
+        dry_run: bool,
+    ) -> Result<Redaction> {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut redaction = Redaction::default();
+
+        let messages: Vec<(i64, String, Option<String>)> = {
+            let mut stmt = tx.prepare(
+                "SELECT id, content, thinking_process FROM messages WHERE thread_id = ?1",
+            )?;
+            let message_iter = stmt.query_map(params![thread_id], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?;
+            message_iter.collect::<Result<_>>()?
+        };
+        for (message_id, content, thinking) in messages {
+            let content = redactor.apply(&content);
+            let thinking = thinking.and_then(|t| redactor.apply(&t));
+            if content.is_none() && thinking.is_none() {
+                continue;
+            }
+            if let Some((content, count)) = content {
+                redaction.replacements += count;
+                tx.execute(
+                    "UPDATE messages SET content = ?1 WHERE id = ?2",
+                    params![content, message_id],
+                )?;
+            }
+            if let Some((thinking, count)) = thinking {
+                redaction.replacements += count;
+                tx.execute(
+                    "UPDATE messages SET thinking_process = ?1 WHERE id = ?2",
+                    params![thinking, message_id],
+                )?;
+            }
+            tx.execute(
+                "DELETE FROM embeddings WHERE message_id = ?1",
+                params![message_id],
+            )?;
+            redaction.message_ids.push(message_id);
+        }
+
+        let summary: Option<String> = tx.query_row(
+            "SELECT summary FROM threads WHERE id = ?1",
+            params![thread_id],
+            |row| row.get(0),
+        )?;
+        if let Some((summary, count)) = summary.and_then(|s| redactor.apply(&s)) {
+            redaction.replacements += count;
+            redaction.summary_changed = true;
+            tx.execute(
+                "UPDATE threads SET summary = ?1 WHERE id = ?2",
+                params![summary, thread_id],
+            )?;
+        }
+        let running: Option<String> = {
+            let mut stmt =
+                tx.prepare("SELECT summary FROM thread_summaries WHERE thread_id = ?1")?;
+            let mut rows = stmt.query(params![thread_id])?;
+            match rows.next()? {
+                Some(row) => row.get(0)?,
+                None => None,
+            }
+        };
+        if let Some((running, count)) = running.and_then(|s| redactor.apply(&s)) {
+            redaction.replacements += count;
+            tx.execute(
+                "UPDATE thread_summaries SET summary = ?1 WHERE thread_id = ?2",
+                params![running, thread_id],
+            )?;
+        }
+
+        // Keys in thread_documents may add the page range after the hash
+        let cached: Vec<(String, String)> = {
+            let mut stmt = tx.prepare(
+                "SELECT DISTINCT c.hash, c.pages FROM document_cache c
+                 JOIN thread_documents d ON d.hash = c.hash OR d.hash LIKE c.hash || ':%'
+                 WHERE d.thread_id = ?1",
+            )?;
+            let cached_iter =
+                stmt.query_map(params![thread_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            cached_iter.collect::<Result<_>>()?
+        };
+        for (hash, pages) in cached {
+            if redactor.apply(&pages).is_some() {
+                tx.execute("DELETE FROM document_cache WHERE hash = ?1", params![hash])?;
+            }
+        }
+
+        if !dry_run {
+            tx.commit()?;
+        }
+        Ok(redaction)
+    }
+
+    pub fn update_message(&self, message_id: i64, content: &str) -> Result<()> {
+        self.invalidate_summary_from(message_id)?;
+        self.conn.execute(
+            "UPDATE messages SET content = ?1 WHERE id = ?2",
+            params![content, message_id],
+        )?;
+        Ok(())
+    }
+
+    /// Adds text to the end of a message whose reply was continued. The
+    /// message is no longer incomplete unless flagged again.
+    pub fn append_to_message(&self, message_id: i64, text: &str) -> Result<()> {
+        self.invalidate_summary_from(message_id)?;
+        self.conn.execute(
+            "UPDATE messages SET content = content || ?1, is_incomplete = 0 WHERE id = ?2",
+            params![text, message_id],
+        )?;
+        Ok(())
+    }
+
+    /// Deletes `message_id` and every message after it in thread order.
+    pub fn delete_messages_from(&self, thread_id: i64, message_id: i64) -> Result<()> {
+        self.invalidate_summary_from(message_id)?;
+        // Merged messages keep their ids, so only (created_at, id) follows the thread
+        self.conn.execute(
+            "DELETE FROM messages
+             WHERE thread_id = ?1 AND (created_at, id) >= (SELECT created_at, id FROM messages WHERE id = ?2)",
+            params![thread_id, message_id],
+        )?;
+        Ok(())
+    }
+
+    /// Deletes every message after `message_id` in thread order.
+    pub fn delete_messages_after(&self, thread_id: i64, message_id: i64) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM messages
+             WHERE thread_id = ?1 AND (created_at, id) > (SELECT created_at, id FROM messages WHERE id = ?2)",
+            params![thread_id, message_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_thread_summary(&self, thread_id: i64) -> Result<Option<ThreadSummary>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT thread_id, summary, covered_until_id, updated_at
+             FROM thread_summaries WHERE thread_id = ?1",
+        )?;
+        let mut rows = stmt.query(params![thread_id])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(ThreadSummary {
+                thread_id: row.get(0)?,
+                summary: row.get(1)?,
+                covered_until_id: row.get(2)?,
+                updated_at: row.get(3)?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    pub fn save_thread_summary(
+        &self,
+        thread_id: i64,
+        summary: &str,
+        covered_until_id: i64,
+    ) -> Result<()> {
+        let updated_at = Utc::now().to_rfc3339();
+        self.conn.execute(
+            "INSERT INTO thread_summaries (thread_id, summary, covered_until_id, updated_at)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(thread_id) DO UPDATE SET
+                summary = excluded.summary,
+                covered_until_id = excluded.covered_until_id,
+                updated_at = excluded.updated_at",
+            params![thread_id, summary, covered_until_id, updated_at],
+        )?;
+        Ok(())
+    }
+
+    /// Drops the summary of a message's thread if it covers that message, since
+    /// the summary no longer matches once the message is edited or deleted.
+    fn invalidate_summary_from(&self, message_id: i64) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM thread_summaries
+             WHERE thread_id = (SELECT thread_id FROM messages WHERE id = ?1)
+               AND covered_until_id IN (
+                    SELECT id FROM messages
+                    WHERE thread_id = thread_summaries.thread_id
+                      AND (created_at, id) >= (SELECT created_at, id FROM messages WHERE id = ?1)
+               )",
+            params![message_id],
+        )?;
+        Ok(())
+    }
+
+    /// Extracted text cached for the file with this content hash, marking the
+    /// entry as just used. Entries that no longer parse are treated as missing.
+    pub fn get_cached_document(&self, hash: &str) -> Result<Option<ExtractedPages>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT pages FROM document_cache WHERE hash = ?1")?;
+        let mut rows = stmt.query(params![hash])?;
+        let Some(row) = rows.next()? else {
+            return Ok(None);
+        };
+        let pages: String = row.get(0)?;
+        self.conn.execute(
+            "UPDATE document_cache
+             SET last_used = (SELECT MAX(last_used) + 1 FROM document_cache)
+             WHERE hash = ?1",
+            params![hash],
+        )?;
+        Ok(serde_json::from_str(&pages).ok())
+    }
+
+    /// Caches a file's extracted text, then evicts the least recently used
+    /// entries until the cache fits in `max_bytes`.
+    pub fn save_cached_document(
+        &self,
+        hash: &str,
+        pages: &ExtractedPages,
+        max_bytes: usize,
+    ) -> Result<()> {
+        let json = serde_json::to_string(pages).unwrap_or_default();
+        self.conn.execute(
+            "INSERT INTO document_cache (hash, pages, size_bytes, last_used)
+             VALUES (?1, ?2, ?3, (SELECT COALESCE(MAX(last_used), 0) + 1 FROM document_cache))
+             ON CONFLICT(hash) DO UPDATE SET
+                pages = excluded.pages,
+                size_bytes = excluded.size_bytes,
+                last_used = excluded.last_used",
+            params![hash, json, json.len() as i64],
+        )?;
+        self.conn.execute(
+            "DELETE FROM document_cache WHERE hash IN (
+                SELECT hash FROM (
+                    SELECT hash, SUM(size_bytes) OVER (ORDER BY last_used DESC) AS total
+                    FROM document_cache
+                ) WHERE total > ?1
+            )",
+            params![max_bytes as i64],
+        )?;
+        Ok(())
+    }
+
+    /// Empties the document cache, returning how many entries were removed.
+    pub fn clear_document_cache(&self) -> Result<usize> {
+        self.conn.execute("DELETE FROM document_cache", [])
+    }
+
+    /// Name the document with this content hash was given when its text was
+    /// put into the thread, if the message holding it is still there.
+    pub fn find_thread_document(&self, thread_id: i64, hash: &str) -> Result<Option<String>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT d.name FROM thread_documents d
+             JOIN messages m ON m.id = d.message_id
+             WHERE d.thread_id = ?1 AND d.hash = ?2",
+        )?;
+        let mut rows = stmt.query(params![thread_id, hash])?;
+
+        if let Some(row) = rows.next()? {
+            Ok(Some(row.get(0)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Records that `message_id` holds the text of these documents, given as
+    /// content hash and name.
+    pub fn record_thread_documents(
+        &self,
+        thread_id: i64,
+        message_id: i64,
+        documents: &[(String, String)],
+    ) -> Result<()> {
+        for (hash, name) in documents {
+            self.conn.execute(
+                "INSERT INTO thread_documents (thread_id, hash, message_id, name)
+                 VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT(thread_id, hash) DO UPDATE SET
+                    message_id = excluded.message_id,
+                    name = excluded.name",
+                params![thread_id, hash, message_id, name],
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn delete_last_message(&self, thread_id: i64) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM messages WHERE id = (SELECT id FROM messages WHERE thread_id = ?1 ORDER BY created_at DESC, id DESC LIMIT 1)",
+            params![thread_id],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_db_creation() {
+        let db = Database::new(":memory:").unwrap();
+        let threads = db.get_threads().unwrap();
+        assert!(threads.is_empty());
+    }
+
+    #[test]
+    fn test_create_thread_and_message() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Test Thread", None).unwrap();
+
+        let threads = db.get_threads().unwrap();
+        assert_eq!(threads.len(), 1);
+        assert_eq!(threads[0].title, "Test Thread");
+
+        db.add_message(thread_id, Role::User, "Hello", None, None, None, None)
+            .unwrap();
+        db.add_message(
+            thread_id,
+            Role::Assistant,
+            "Hi",
+            None,
+            Some("llama2".to_string()),
+            None,
+            None,
+        )
+        .unwrap();
+
+        let messages = db.get_messages(thread_id).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].content, "Hello");
+        assert_eq!(messages[1].content, "Hi");
+        assert_eq!(messages[1].model, Some("llama2".to_string()));
+    }
+
+    #[test]
+    fn test_db_performance() {
+        let db = Database::new(":memory:").unwrap();
+        let start = std::time::Instant::now();
+
+        let thread_id = db.create_thread("Benchmark", None).unwrap();
+        for i in 0..100 {
+            db.add_message(
+                thread_id,
+                Role::User,
+                &format!("Message {}", i),
+                None,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+        }
+
+        let messages = db.get_messages(thread_id).unwrap();
+        assert_eq!(messages.len(), 100);
+
+        let duration = start.elapsed();
+        println!("Inserted and retrieved 100 messages in {:?}", duration);
+        // Ensure it's reasonably fast (e.g. < 500ms for in-memory)
+        assert!(duration.as_millis() < 500);
+    }
+
+    #[test]
+    fn test_batch_insert_performance() {
+        const COUNT: usize = 10_000;
+        let message = |i: usize| NewMessage {
+            role: if i % 2 == 0 {
+                Role::User
+            } else {
+                Role::Assistant
+            },
+            content: format!("Message {}", i),
+            images: None,
+            model: None,
+            reply_to_id: None,
+            thinking_process: None,
+            created_at: None,
+        };
+        let db = Database::new(":memory:").unwrap();
+
+        let one_by_one = db.create_thread("One by one", None).unwrap();
+        let start = std::time::Instant::now();
+        for i in 0..COUNT {
+            let m = message(i);
+            db.add_message(one_by_one, m.role, &m.content, None, None, None, None)
+                .unwrap();
+        }
+        let loop_duration = start.elapsed();
+
+        let batched = db.create_thread("Batched", None).unwrap();
+        let start = std::time::Instant::now();
+        db.add_messages_batch(batched, (0..COUNT).map(message).collect())
+            .unwrap();
+        let batch_duration = start.elapsed();
+        println!(
+            "Inserted {} messages in {:?} one by one, {:?} batched",
+            COUNT, loop_duration, batch_duration
+        );
+
+        assert_eq!(db.get_messages(batched).unwrap().len(), COUNT);
+        assert!(batch_duration < loop_duration);
+    }
+
+    #[test]
+    fn test_add_messages_batch() {
+        // Same timestamp throughout, so only the insertion order tells them apart
+        let message = |content: &str| NewMessage {
+            role: Role::User,
+            content: content.to_string(),
+            images: None,
+            model: None,
+            reply_to_id: None,
+            thinking_process: None,
+            created_at: Some("2025-01-01T00:00:00+00:00".to_string()),
+        };
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Batched", None).unwrap();
+
+        let contents: Vec<String> = (0..50).map(|i| format!("Message {}", i)).collect();
+        let ids = db
+            .add_messages_batch(thread_id, contents.iter().map(|c| message(c)).collect())
+            .unwrap();
+        let messages = db.get_messages(thread_id).unwrap();
+        assert_eq!(ids, messages.iter().map(|m| m.id).collect::<Vec<_>>());
+        assert_eq!(
+            messages.iter().map(|m| &m.content).collect::<Vec<_>>(),
+            contents.iter().collect::<Vec<_>>()
+        );
+
+        // One failing row leaves none of the batch behind
+        db.conn
+            .execute(
+                "CREATE TEMP TRIGGER reject_boom BEFORE INSERT ON messages
+                 WHEN NEW.content = 'boom' BEGIN SELECT RAISE(ABORT, 'boom'); END",
+                [],
+            )
+            .unwrap();
+        let other = db.create_thread("Failed", None).unwrap();
+        assert!(db
+            .add_messages_batch(other, vec![message("fine"), message("boom")])
+            .is_err());
+        assert!(db.get_messages(other).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_edit_and_delete() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Edit Test", None).unwrap();
+
+        let m1 = db
+            .add_message(thread_id, Role::User, "msg1", None, None, None, None)
+            .unwrap();
+        db.add_message(thread_id, Role::Assistant, "msg2", None, None, None, None)
+            .unwrap();
+        db.add_message(thread_id, Role::User, "msg3", None, None, None, None)
+            .unwrap();
+
+        // Update m1
+        db.update_message(m1, "msg1_updated").unwrap();
+        let msgs = db.get_messages(thread_id).unwrap();
+        assert_eq!(msgs[0].content, "msg1_updated");
+
+        // Delete after m1
+        db.delete_messages_after(thread_id, m1).unwrap();
+        let msgs = db.get_messages(thread_id).unwrap();
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].id, m1);
+
+        // Delete last (which is m1 now)
+        db.delete_last_message(thread_id).unwrap();
+        let msgs = db.get_messages(thread_id).unwrap();
+        assert!(msgs.is_empty());
+    }
+
+    #[test]
+    fn test_settings_with_prefix() {
+        let db = Database::new(":memory:").unwrap();
+        db.set_setting("prompt_vars.project_name", "chatZ").unwrap();
+        db.set_setting("prompt_vars.team", "Core").unwrap();
+        // `_` must not act as a wildcard
+        db.set_setting("promptXvars.other", "no").unwrap();
+
+        assert_eq!(
+            db.get_settings_with_prefix("prompt_vars.").unwrap(),
+            vec![
+                ("prompt_vars.project_name".to_string(), "chatZ".to_string()),
+                ("prompt_vars.team".to_string(), "Core".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_settings() {
+        let db = Database::new(":memory:").unwrap();
+        assert_eq!(db.get_setting("keep_alive").unwrap(), None);
+
+        db.set_setting("keep_alive", "10m").unwrap();
+        assert_eq!(
+            db.get_setting("keep_alive").unwrap(),
+            Some("10m".to_string())
+        );
+
+        // Overwrite existing value
+        db.set_setting("keep_alive", "-1").unwrap();
+        assert_eq!(
+            db.get_setting("keep_alive").unwrap(),
+            Some("-1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_embeddings() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Embeddings", None).unwrap();
+        let m1 = db
+            .add_message(thread_id, Role::User, "msg1", None, None, None, None)
+            .unwrap();
+        let m2 = db
+            .add_message(thread_id, Role::Assistant, "msg2", None, None, None, None)
+            .unwrap();
+
+        db.save_embedding(m1, "nomic-embed-text", &[0.5, -1.0, 2.25])
+            .unwrap();
+
+        // Only the message without a vector for this model needs indexing
+        let pending = db
+            .get_messages_without_embeddings(thread_id, "nomic-embed-text")
+            .unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id, m2);
+
+        // A different model has nothing indexed yet
+        let pending = db
+            .get_messages_without_embeddings(thread_id, "other-model")
+            .unwrap();
+        assert_eq!(pending.len(), 2);
+
+        let embeddings = db.get_embeddings("nomic-embed-text").unwrap();
+        assert_eq!(embeddings, vec![(m1, vec![0.5, -1.0, 2.25])]);
+
+        db.delete_thread(thread_id).unwrap();
+        assert!(db.get_embeddings("nomic-embed-text").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_response_format() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Format", None).unwrap();
+        let m1 = db
+            .add_message(thread_id, Role::Assistant, "{}", None, None, None, None)
+            .unwrap();
+
+        let msgs = db.get_messages(thread_id).unwrap();
+        assert_eq!(msgs[0].response_format, None);
+
+        db.set_message_response_format(m1, "json").unwrap();
+        let msgs = db.get_messages(thread_id).unwrap();
+        assert_eq!(msgs[0].response_format, Some("json".to_string()));
+    }
+
+    #[test]
+    fn test_tool_messages() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Tools", None).unwrap();
+        let m1 = db
+            .add_message(thread_id, Role::Assistant, "", None, None, None, None)
+            .unwrap();
+
+        let calls = serde_json::json!([
+            {"id": "call_0", "function": {"name": "get_weather", "arguments": {"city": "Pune"}}}
+        ]);
+        db.set_message_tool_calls(m1, &calls).unwrap();
+        db.add_tool_message(thread_id, "call_0", "get_weather", "{\"temp\": 31}")
+            .unwrap();
+
+        let msgs = db.get_messages(thread_id).unwrap();
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0].tool_calls, Some(calls));
+        assert_eq!(msgs[1].role, Role::Tool);
+        assert_eq!(msgs[1].tool_call_id, Some("call_0".to_string()));
+        assert_eq!(msgs[1].tool_name, Some("get_weather".to_string()));
+    }
+
+    #[test]
+    fn test_server_profiles() {
+        let db = Database::new(":memory:").unwrap();
+        let local = db
+            .create_server_profile("Local", "http://localhost:11434", "ollama", true)
+            .unwrap();
+        let remote = db
+            .create_server_profile("LM Studio", "http://192.168.1.20:1234", "openai", false)
+            .unwrap();
+        assert_eq!(db.get_server_profiles().unwrap().len(), 2);
+        assert_eq!(db.get_server_profile(local).unwrap().kind, "ollama");
+        assert_eq!(db.get_server_profile(remote).unwrap().kind, "openai");
+        assert_eq!(db.get_default_server_profile().unwrap().unwrap().id, local);
+
+        db.set_default_server_profile(remote).unwrap();
+        assert_eq!(db.get_default_server_profile().unwrap().unwrap().id, remote);
+        assert!(!db.get_server_profile(local).unwrap().is_default);
+
+        assert!(!db.get_server_profile(remote).unwrap().accept_invalid_certs);
+        db.set_server_profile_tls(remote, true, Some("/etc/ssl/lab-ca.pem"))
+            .unwrap();
+        let profile = db.get_server_profile(remote).unwrap();
+        assert!(profile.accept_invalid_certs);
+        assert_eq!(profile.ca_cert_path.as_deref(), Some("/etc/ssl/lab-ca.pem"));
+
+        let thread_id = db.create_thread("Big model", None).unwrap();
+        db.set_thread_server_profile(thread_id, Some(remote))
+            .unwrap();
+        assert_eq!(
+            db.get_thread(thread_id).unwrap().server_profile_id,
+            Some(remote)
+        );
+
+        db.delete_server_profile(remote).unwrap();
+        assert_eq!(db.get_thread(thread_id).unwrap().server_profile_id, None);
+        assert!(db.get_default_server_profile().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_server_profile_auth_is_not_serialized() {
+        let db = Database::new(":memory:").unwrap();
+        let id = db
+            .create_server_profile("Proxy", "https://ollama.example.com", "ollama", false)
+            .unwrap();
+        assert!(!db.get_server_profile(id).unwrap().has_auth_token);
+
+        db.set_server_profile_auth_token(id, Some("s3cret-token"))
+            .unwrap();
+        db.set_server_profile_headers(
+            id,
+            &HashMap::from([("X-Proxy-Key".to_string(), "abc".to_string())]),
+        )
+        .unwrap();
+
+        let profile = db.get_server_profile(id).unwrap();
+        assert_eq!(profile.auth_token.as_deref(), Some("s3cret-token"));
+        assert_eq!(profile.headers["X-Proxy-Key"], "abc");
+        let json = serde_json::to_string(&profile).unwrap();
+        assert!(!json.contains("s3cret-token"));
+        assert!(json.contains(r#""has_auth_token":true"#));
+
+        db.set_server_profile_auth_token(id, None).unwrap();
+        assert!(!db.get_server_profile(id).unwrap().has_auth_token);
+    }
+
+    #[test]
+    fn test_thinking_process() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Thinking", None).unwrap();
+        db.add_message(
+            thread_id,
+            Role::Assistant,
+            "42",
+            None,
+            None,
+            None,
+            Some("Let me work it out".to_string()),
+        )
+        .unwrap();
+
+        let msgs = db.get_messages(thread_id).unwrap();
+        assert_eq!(msgs[0].content, "42");
+        assert_eq!(
+            msgs[0].thinking_process,
+            Some("Let me work it out".to_string())
+        );
+    }
+
+    #[test]
+    fn test_message_metrics() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Metrics", None).unwrap();
+        let m1 = db
+            .add_message(thread_id, Role::Assistant, "Hi", None, None, None, None)
+            .unwrap();
+
+        db.set_message_metrics(
+            m1,
+            &MessageMetrics {
+                eval_count: Some(86),
+                eval_duration: Some(2_000_000_000),
+                tokens_per_second: Some(43.0),
+                first_token_ms: Some(320),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let msgs = db.get_messages(thread_id).unwrap();
+        assert_eq!(msgs[0].eval_count, Some(86));
+        assert_eq!(msgs[0].tokens_per_second, Some(43.0));
+        assert_eq!(msgs[0].first_token_ms, Some(320));
+        assert_eq!(msgs[0].total_duration, None);
+    }
+
+    #[test]
+    fn test_thread_resend_thinking() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Test", None).unwrap();
+        assert!(!db.get_thread(thread_id).unwrap().resend_thinking);
+
+        db.set_thread_resend_thinking(thread_id, true).unwrap();
+        assert!(db.get_thread(thread_id).unwrap().resend_thinking);
+    }
+
+    #[test]
+    fn test_thread_max_history_messages() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Test", None).unwrap();
+        assert_eq!(db.get_thread(thread_id).unwrap().max_history_messages, None);
+
+        db.set_thread_max_history_messages(thread_id, Some(20))
+            .unwrap();
+        assert_eq!(
+            db.get_thread(thread_id).unwrap().max_history_messages,
+            Some(20)
+        );
+        db.set_thread_max_history_messages(thread_id, None).unwrap();
+        assert_eq!(db.get_thread(thread_id).unwrap().max_history_messages, None);
+    }
+
+    #[test]
+    fn test_save_thinking() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Test", None).unwrap();
+        assert_eq!(db.get_thread(thread_id).unwrap().save_thinking, None);
+        db.set_thread_save_thinking(thread_id, Some(false)).unwrap();
+        assert_eq!(db.get_thread(thread_id).unwrap().save_thinking, Some(false));
+
+        let message_id = db
+            .add_message(thread_id, Role::Assistant, "42", None, None, None, None)
+            .unwrap();
+        assert!(!db.get_message(message_id).unwrap().thinking_discarded);
+        db.set_message_thinking_discarded(message_id).unwrap();
+        let message = db.get_message(message_id).unwrap();
+        assert!(message.thinking_discarded);
+        assert_eq!(message.thinking_process, None);
+    }
+
+    #[test]
+    fn test_set_message_excluded() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Test", None).unwrap();
+        assert_eq!(db.get_last_user_message_id(thread_id).unwrap(), None);
+        let first = db
+            .add_message(thread_id, Role::User, "log", None, None, None, None)
+            .unwrap();
+        db.add_message(thread_id, Role::Assistant, "ok", None, None, None, None)
+            .unwrap();
+        let last = db
+            .add_message(thread_id, Role::User, "next", None, None, None, None)
+            .unwrap();
+        assert_eq!(db.get_last_user_message_id(thread_id).unwrap(), Some(last));
+
+        assert!(!db.get_message(first).unwrap().exclude_from_context);
+        db.set_message_excluded(first, true).unwrap();
+        assert!(db.get_message(first).unwrap().exclude_from_context);
+        db.set_message_excluded(first, false).unwrap();
+        assert!(!db.get_message(first).unwrap().exclude_from_context);
+    }
+
+    #[test]
+    fn test_thread_think() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Reasoning", None).unwrap();
+        assert_eq!(db.get_thread(thread_id).unwrap().think, None);
+
+        db.set_thread_think(thread_id, Some(false)).unwrap();
+        assert_eq!(db.get_thread(thread_id).unwrap().think, Some(false));
+
+        db.set_thread_think(thread_id, None).unwrap();
+        assert_eq!(db.get_thread(thread_id).unwrap().think, None);
+    }
+
+    #[test]
+    fn test_thread_summaries() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Long", None).unwrap();
+        let m1 = db
+            .add_message(thread_id, Role::User, "First", None, None, None, None)
+            .unwrap();
+        let m2 = db
+            .add_message(thread_id, Role::Assistant, "Second", None, None, None, None)
+            .unwrap();
+        let m3 = db
+            .add_message(thread_id, Role::User, "Third", None, None, None, None)
+            .unwrap();
+        assert!(db.get_thread_summary(thread_id).unwrap().is_none());
+
+        db.save_thread_summary(thread_id, "Greetings", m1).unwrap();
+        db.save_thread_summary(thread_id, "Greetings, twice", m2)
+            .unwrap();
+        let summary = db.get_thread_summary(thread_id).unwrap().unwrap();
+        assert_eq!(summary.summary, "Greetings, twice");
+        assert_eq!(summary.covered_until_id, m2);
+
+        // Editing a message after the summarized part leaves the summary alone
+        db.update_message(m3, "Third, edited").unwrap();
+        assert!(db.get_thread_summary(thread_id).unwrap().is_some());
+
+        // Editing a summarized message invalidates it
+        db.update_message(m1, "First, edited").unwrap();
+        assert!(db.get_thread_summary(thread_id).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_document_cache() {
+        use crate::pdf_utils::{PageText, TextLayout, TextSource};
+
+        let db = Database::new(":memory:").unwrap();
+        let document = |text: &str| ExtractedPages {
+            page_count: 1,
+            layout: TextLayout::Structured,
+            pages: [(
+                1,
+                PageText {
+                    text: text.to_string(),
+                    source: TextSource::TextLayer,
+                },
+            )]
+            .into(),
+        };
+        let size = serde_json::to_string(&document("aaaa")).unwrap().len();
+
+        assert!(db.get_cached_document("a").unwrap().is_none());
+        db.save_cached_document("a", &document("aaaa"), size * 2)
+            .unwrap();
+        db.save_cached_document("b", &document("bbbb"), size * 2)
+            .unwrap();
+        assert_eq!(db.get_cached_document("a").unwrap(), Some(document("aaaa")));
+
+        // "b" is now the least recently used, so it's evicted to make room
+        db.save_cached_document("c", &document("cccc"), size * 2)
+            .unwrap();
+        assert!(db.get_cached_document("b").unwrap().is_none());
+        assert!(db.get_cached_document("a").unwrap().is_some());
+        assert!(db.get_cached_document("c").unwrap().is_some());
+
+        assert_eq!(db.clear_document_cache().unwrap(), 2);
+        assert!(db.get_cached_document("a").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_redact_thread() {
+        use crate::pdf_utils::{PageText, TextLayout, TextSource};
+
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Secrets", None).unwrap();
+        let other_thread = db.create_thread("Other", None).unwrap();
+        let user = db
+            .add_message(
+                thread_id,
+                Role::User,
+                "key sk-1, sk-1",
+                None,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+        let reply = db
+            .add_message(
+                thread_id,
+                Role::Assistant,
+                "Noted",
+                None,
+                None,
+                None,
+                Some("user shared sk-1".to_string()),
+            )
+            .unwrap();
+        let untouched = db
+            .add_message(other_thread, Role::User, "sk-1", None, None, None, None)
+            .unwrap();
+        db.set_thread_summary(thread_id, "About sk-1").unwrap();
+        db.save_embedding(user, "embed", &[1.0]).unwrap();
+        let document = ExtractedPages {
+            page_count: 1,
+            layout: TextLayout::Structured,
+            pages: [(
+                1,
+                PageText {
+                    text: "sk-1".to_string(),
+                    source: TextSource::TextLayer,
+                },
+            )]
+            .into(),
+        };
+        db.save_cached_document("abc", &document, 1 << 20).unwrap();
+        db.record_thread_documents(
+            thread_id,
+            user,
+            &[("abc:1-2".to_string(), "keys.pdf".to_string())],
+        )
+        .unwrap();
+
+        let redactor = Redactor::new("sk-1", "[key]", false).unwrap();
+        let expected = Redaction {
+            replacements: 4,
+            message_ids: vec![user, reply],
+            summary_changed: true,
+        };
+        // A dry run counts without changing anything
+        assert_eq!(
+            db.redact_thread(thread_id, &redactor, true).unwrap(),
+            expected
+        );
+        assert_eq!(db.get_message(user).unwrap().content, "key sk-1, sk-1");
+        assert!(db.get_cached_document("abc").unwrap().is_some());
+
+        assert_eq!(
+            db.redact_thread(thread_id, &redactor, false).unwrap(),
+            expected
+        );
+        assert_eq!(db.get_message(user).unwrap().content, "key [key], [key]");
+        assert_eq!(
+            db.get_message(reply).unwrap().thinking_process.as_deref(),
+            Some("user shared [key]")
+        );
+        assert_eq!(
+            db.get_thread(thread_id).unwrap().summary.as_deref(),
+            Some("About [key]")
+        );
+        assert_eq!(db.get_message(untouched).unwrap().content, "sk-1");
+        assert!(db.get_embeddings("embed").unwrap().is_empty());
+        assert!(db.get_cached_document("abc").unwrap().is_none());
+
+        assert_eq!(
+            db.redact_thread(thread_id, &redactor, false).unwrap(),
+            Redaction::default()
+        );
+    }
+
+    #[test]
+    fn test_thread_documents() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Docs", None).unwrap();
+        let other_thread = db.create_thread("Other", None).unwrap();
+        let message_id = db
+            .add_message(thread_id, Role::User, "report", None, None, None, None)
+            .unwrap();
+
+        assert_eq!(db.find_thread_document(thread_id, "h").unwrap(), None);
+        db.record_thread_documents(
+            thread_id,
+            message_id,
+            &[("h".to_string(), "report.pdf".to_string())],
+        )
+        .unwrap();
+        assert_eq!(
+            db.find_thread_document(thread_id, "h").unwrap(),
+            Some("report.pdf".to_string())
+        );
+        assert_eq!(db.find_thread_document(other_thread, "h").unwrap(), None);
+
+        // Deleting the message takes the document out of the thread
+        db.delete_messages_from(thread_id, message_id).unwrap();
+        assert_eq!(db.find_thread_document(thread_id, "h").unwrap(), None);
+    }
+
+    #[test]
+    fn test_thread_model_options() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Options", None).unwrap();
+        assert!(db.get_thread(thread_id).unwrap().model_options.is_none());
+
+        let options = ModelOptions {
+            stop: Some(vec!["\nUser:".to_string(), "###".to_string()]),
+            num_predict: Some(256),
+            mirostat: Some(2),
+            mirostat_tau: Some(5.0),
+            min_p: Some(0.05),
+            ..Default::default()
+        };
+        db.set_thread_model_options(thread_id, &options).unwrap();
+        assert_eq!(
+            db.get_thread(thread_id).unwrap().model_options,
+            Some(options)
+        );
+
+        db.set_thread_model_options(thread_id, &ModelOptions::default())
+            .unwrap();
+        assert!(db.get_thread(thread_id).unwrap().model_options.is_none());
+    }
+
+    #[test]
+    fn test_message_image_metadata() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Photos", None).unwrap();
+        let m1 = db
+            .add_message(
+                thread_id,
+                Role::User,
+                "What is this?",
+                Some(vec!["aGk=".to_string()]),
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+        assert!(db.get_message(m1).unwrap().image_metadata.is_none());
+
+        let metadata = vec![ImageMetadata {
+            width: 1568,
+            height: 1176,
+            original_width: 4000,
+            original_height: 3000,
+            name: Some("holiday.jpg".to_string()),
+        }];
+        db.set_message_image_metadata(m1, &metadata).unwrap();
+        assert_eq!(db.get_message(m1).unwrap().image_metadata, Some(metadata));
+
+        assert_eq!(db.get_message(m1).unwrap().image_thumbnails, None);
+        let thumbnails = vec!["thumb".to_string()];
+        db.set_message_image_thumbnails(m1, &thumbnails).unwrap();
+        assert_eq!(
+            db.get_message(m1).unwrap().image_thumbnails,
+            Some(thumbnails)
+        );
+    }
+
+    #[test]
+    fn test_unknown_role_loads() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Typo", None).unwrap();
+        db.add_message(thread_id, Role::User, "Hi", None, None, None, None)
+            .unwrap();
+        db.conn
+            .execute(
+                "INSERT INTO messages (thread_id, role, content, created_at) VALUES (?1, 'usr', 'Oops', ?2)",
+                params![thread_id, Utc::now().to_rfc3339()],
+            )
+            .unwrap();
+
+        let roles: Vec<Role> = db
+            .get_messages(thread_id)
+            .unwrap()
+            .iter()
+            .map(|m| m.role)
+            .collect();
+        assert_eq!(roles, vec![Role::User, Role::Unknown]);
+    }
+
+    #[test]
+    fn test_append_to_message() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Continued", None).unwrap();
+        let m1 = db
+            .add_message(
+                thread_id,
+                Role::Assistant,
+                "The answer",
+                None,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+        db.set_message_incomplete(m1).unwrap();
+
+        db.append_to_message(m1, " is 42.").unwrap();
+        let message = db.get_message(m1).unwrap();
+        assert_eq!(message.content, "The answer is 42.");
+        assert!(!message.is_incomplete);
+    }
+
+    #[test]
+    fn test_supersede_message() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Test", None).unwrap();
+        db.add_message(thread_id, Role::User, "Hi", None, None, None, None)
+            .unwrap();
+        let old = db
+            .add_message(
+                thread_id,
+                Role::Assistant,
+                "Hello",
+                None,
+                Some("llama3".to_string()),
+                None,
+                None,
+            )
+            .unwrap();
+        let new = db
+            .add_message(
+                thread_id,
+                Role::Assistant,
+                "Hey there",
+                None,
+                Some("mistral".to_string()),
+                None,
+                None,
+            )
+            .unwrap();
+        assert!(!db.get_message(old).unwrap().is_superseded);
+
+        db.supersede_message(old, new).unwrap();
+        let old = db.get_message(old).unwrap();
+        let new = db.get_message(new).unwrap();
+        assert!(old.is_superseded);
+        assert_eq!(old.model.as_deref(), Some("llama3"));
+        assert!(!new.is_superseded);
+        assert_eq!(new.variant_of, Some(old.id));
+        assert_eq!(new.model.as_deref(), Some("mistral"));
+    }
+
+    #[test]
+    fn test_message_incomplete() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Interrupted", None).unwrap();
+        let m1 = db
+            .add_message(
+                thread_id,
+                Role::Assistant,
+                "The answer is",
+                None,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+        assert!(!db.get_message(m1).unwrap().is_incomplete);
+
+        db.set_message_incomplete(m1).unwrap();
+        assert!(db.get_message(m1).unwrap().is_incomplete);
+    }
+
+    #[test]
+    fn test_merge_threads() {
+        let db = Database::new(":memory:").unwrap();
+        let target = db.create_thread("Trains", None).unwrap();
+        let source = db.create_thread("More trains", None).unwrap();
+        let ask = db
+            .add_message(
+                source,
+                Role::User,
+                "Which is faster?",
+                None,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+        db.add_message(target, Role::User, "Pune trains?", None, None, None, None)
+            .unwrap();
+        let answer = db
+            .add_message(
+                source,
+                Role::Assistant,
+                "Deccan Queen",
+                None,
+                None,
+                Some(ask),
+                None,
+            )
+            .unwrap();
+        let before = db.get_messages(source).unwrap();
+
+        let moved = db
+            .merge_threads(source, target, Some("Merged from \"More trains\""), false)
+            .unwrap();
+        assert_eq!(moved, 2);
+        assert!(db.get_thread(source).is_err());
+
+        let messages = db.get_messages(target).unwrap();
+        let contents: Vec<_> = messages.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(
+            contents,
+            [
+                "Merged from \"More trains\"",
+                "Which is faster?",
+                "Pune trains?",
+                "Deccan Queen"
+            ]
+        );
+        assert_eq!(messages[0].role, Role::System);
+        let moved_answer = messages.iter().find(|m| m.id == answer).unwrap();
+        assert_eq!(moved_answer.reply_to_id, Some(ask));
+        assert_eq!(moved_answer.created_at, before[1].created_at);
+    }
+
+    #[test]
+    fn test_thread_order_after_merge() {
+        // Merged in: divider, "Which is faster?", "Pune trains?", "Deccan Queen",
+        // with the divider holding the newest id
+        let merged = || {
+            let db = Database::new(":memory:").unwrap();
+            let target = db.create_thread("Trains", None).unwrap();
+            let source = db.create_thread("More trains", None).unwrap();
+            let ask = db
+                .add_message(
+                    source,
+                    Role::User,
+                    "Which is faster?",
+                    None,
+                    None,
+                    None,
+                    None,
+                )
+                .unwrap();
+            let pune = db
+                .add_message(target, Role::User, "Pune trains?", None, None, None, None)
+                .unwrap();
+            db.add_message(
+                source,
+                Role::Assistant,
+                "Deccan Queen",
+                None,
+                None,
+                Some(ask),
+                None,
+            )
+            .unwrap();
+            db.merge_threads(source, target, Some("Merged"), false)
+                .unwrap();
+            (db, target, ask, pune)
+        };
+        let contents = |db: &Database, thread_id| -> Vec<String> {
+            db.get_messages(thread_id)
+                .unwrap()
+                .into_iter()
+                .map(|m| m.content)
+                .collect()
+        };
+
+        // Editing "Pune trains?" only drops what follows it
+        let (db, target, _, pune) = merged();
+        db.delete_messages_after(target, pune).unwrap();
+        assert_eq!(
+            contents(&db, target),
+            ["Merged", "Which is faster?", "Pune trains?"]
+        );
+        // The thread now ends in an unanswered question, not the divider
+        let soon = Utc::now() + chrono::Duration::minutes(1);
+        assert_eq!(db.flag_interrupted_threads(soon).unwrap(), vec![target]);
 
-    pub fn delete_messages_after(&self, thread_id: i64, message_id: i64) -> Result<()> {
-        self.conn.execute(
-            "DELETE FROM messages WHERE thread_id = ?1 AND id > ?2",
-            params![thread_id, message_id],
-        )?;
-        Ok(())
+        let (db, target, ask, _) = merged();
+        db.delete_messages_from(target, ask).unwrap();
+        assert_eq!(contents(&db, target), ["Merged"]);
+
+        let (db, target, _, pune) = merged();
+        let new_id = db.split_thread(target, pune, "Pune").unwrap();
+        assert_eq!(contents(&db, target), ["Merged", "Which is faster?"]);
+        assert_eq!(contents(&db, new_id), ["Pune trains?", "Deccan Queen"]);
+        // The answer's question stayed behind
+        assert_eq!(db.get_messages(new_id).unwrap()[1].reply_to_id, None);
     }
 
-    pub fn delete_last_message(&self, thread_id: i64) -> Result<()> {
-        self.conn.execute(
-            "DELETE FROM messages WHERE id = (SELECT id FROM messages WHERE thread_id = ?1 ORDER BY created_at DESC LIMIT 1)",
-            params![thread_id],
-        )?;
-        Ok(())
+    #[test]
+    fn test_merge_threads_archives_source() {
+        let db = Database::new(":memory:").unwrap();
+        let target = db.create_thread("Target", None).unwrap();
+        let source = db.create_thread("Source", None).unwrap();
+        db.add_message(source, Role::User, "Hi", None, None, None, None)
+            .unwrap();
+
+        db.merge_threads(source, target, None, true).unwrap();
+        assert!(db.get_thread(source).unwrap().is_archived);
+        assert!(db.get_messages(source).unwrap().is_empty());
+        assert_eq!(db.get_messages(target).unwrap().len(), 1);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_split_thread() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db
+            .create_thread("Trains", Some("Be brief.".to_string()))
+            .unwrap();
+        let ask = db
+            .add_message(
+                thread_id,
+                Role::User,
+                "Which is faster?",
+                None,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+        db.add_message(
+            thread_id,
+            Role::Assistant,
+            "Deccan Queen",
+            None,
+            None,
+            Some(ask),
+            None,
+        )
+        .unwrap();
+        let drift = db
+            .add_message(
+                thread_id,
+                Role::User,
+                "Any good food?",
+                None,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+        // Quotes the first question, so its link can't survive the split
+        db.add_message(
+            thread_id,
+            Role::User,
+            "Back to trains",
+            None,
+            None,
+            Some(ask),
+            None,
+        )
+        .unwrap();
+        let answer = db
+            .add_message(
+                thread_id,
+                Role::Assistant,
+                "Misal pav",
+                None,
+                None,
+                Some(drift),
+                None,
+            )
+            .unwrap();
+
+        let new_id = db.split_thread(thread_id, drift, "Food").unwrap();
+        let new_thread = db.get_thread(new_id).unwrap();
+        assert_eq!(new_thread.title, "Food");
+        assert_eq!(new_thread.system_prompt.as_deref(), Some("Be brief."));
+
+        let old = db.get_messages(thread_id).unwrap();
+        assert_eq!(old.len(), 2);
+        assert_eq!(old[1].reply_to_id, Some(ask));
+
+        let new = db.get_messages(new_id).unwrap();
+        let contents: Vec<_> = new.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["Any good food?", "Back to trains", "Misal pav"]);
+        assert_eq!(new[1].reply_to_id, None);
+        assert_eq!(
+            new.iter().find(|m| m.id == answer).unwrap().reply_to_id,
+            Some(drift)
+        );
+    }
 
     #[test]
-    fn test_db_creation() {
+    fn test_get_messages_by_role() {
         let db = Database::new(":memory:").unwrap();
-        let threads = db.get_threads().unwrap();
-        assert!(threads.is_empty());
+        let thread_id = db.create_thread("Test", None).unwrap();
+        let other = db.create_thread("Other", None).unwrap();
+        for (thread, role, content) in [
+            (thread_id, Role::User, "First"),
+            (thread_id, Role::Assistant, "Answer"),
+            (other, Role::User, "Elsewhere"),
+            (thread_id, Role::User, "Second"),
+        ] {
+            db.add_message(thread, role, content, None, None, None, None)
+                .unwrap();
+        }
+
+        let prompts = db.get_messages_by_role(thread_id, Role::User).unwrap();
+        let contents: Vec<_> = prompts.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["First", "Second"]);
+        assert!(db
+            .get_messages_by_role(thread_id, Role::Tool)
+            .unwrap()
+            .is_empty());
     }
 
     #[test]
-    fn test_create_thread_and_message() {
+    fn test_threads_by_model() {
         let db = Database::new(":memory:").unwrap();
-        let thread_id = db.create_thread("Test Thread", None).unwrap();
+        let reply = |thread_id, model: Option<&str>| {
+            db.add_message(
+                thread_id,
+                Role::Assistant,
+                "text",
+                None,
+                model.map(str::to_string),
+                None,
+                None,
+            )
+            .unwrap();
+        };
+        let reasoning = db.create_thread("Reasoning", None).unwrap();
+        reply(reasoning, Some("deepseek-r1"));
+        reply(reasoning, Some("deepseek-r1"));
+        let mixed = db.create_thread("Mixed", None).unwrap();
+        reply(mixed, Some("llama3"));
+        reply(mixed, Some("deepseek-r1"));
+        let old = db.create_thread("Old", None).unwrap();
+        reply(old, None);
+        // Only replies count, not the model a prompt was sent to
+        let asked = db.create_thread("Asked", None).unwrap();
+        db.add_message(
+            asked,
+            Role::User,
+            "text",
+            None,
+            Some("mistral".to_string()),
+            None,
+            None,
+        )
+        .unwrap();
 
-        let threads = db.get_threads().unwrap();
-        assert_eq!(threads.len(), 1);
-        assert_eq!(threads[0].title, "Test Thread");
+        let mut ids: Vec<i64> = db
+            .get_threads_by_model("deepseek-r1")
+            .unwrap()
+            .iter()
+            .map(|t| t.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, [reasoning, mixed]);
+        let unknown = db.get_threads_by_model(UNKNOWN_MODEL).unwrap();
+        assert_eq!(unknown.len(), 1);
+        assert_eq!(unknown[0].id, old);
+
+        assert_eq!(
+            db.get_models_used().unwrap(),
+            [
+                ("deepseek-r1".to_string(), 2),
+                ("llama3".to_string(), 1),
+                ("unknown".to_string(), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_activity_stats() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Test", None).unwrap();
+        let add = |role, created_at: &str, eval_count| {
+            let id = db
+                .add_message(thread_id, role, "text", None, None, None, None)
+                .unwrap();
+            db.set_message_metrics(
+                id,
+                &MessageMetrics {
+                    eval_count,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            // Midday, so the local calendar day is the same in any time zone
+            db.conn
+                .execute(
+                    "UPDATE messages SET created_at = ?1 WHERE id = ?2",
+                    params![format!("{}T12:00:00.123456789+00:00", created_at), id],
+                )
+                .unwrap();
+        };
+        add(Role::User, "2026-02-27", None);
+        add(Role::User, "2026-03-01", None);
+        add(Role::Assistant, "2026-03-01", Some(120));
+        add(Role::User, "2026-03-01", None);
+        add(Role::Assistant, "2026-03-01", Some(30));
+        add(Role::Assistant, "2026-03-03", None);
 
-        db.add_message(thread_id, "user", "Hello", None, None, None)
+        let date = |s: &str| s.parse::<NaiveDate>().unwrap();
+        let stats = db
+            .activity_stats(date("2026-02-28"), date("2026-03-03"))
             .unwrap();
+        let days: Vec<_> = stats
+            .iter()
+            .map(|d| {
+                (
+                    d.date.to_string(),
+                    d.user_messages,
+                    d.assistant_messages,
+                    d.tokens,
+                )
+            })
+            .collect();
+        assert_eq!(
+            days,
+            [
+                ("2026-02-28".to_string(), 0, 0, 0),
+                ("2026-03-01".to_string(), 2, 2, 150),
+                ("2026-03-02".to_string(), 0, 0, 0),
+                ("2026-03-03".to_string(), 0, 1, 0),
+            ]
+        );
+        assert_eq!(
+            serde_json::to_value(&stats[1]).unwrap()["date"],
+            "2026-03-01"
+        );
+    }
+
+    #[test]
+    fn test_model_stats() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Test", None).unwrap();
+        let reply = |model: Option<&str>, metrics: MessageMetrics| {
+            let id = db
+                .add_message(
+                    thread_id,
+                    Role::Assistant,
+                    "text",
+                    None,
+                    model.map(str::to_string),
+                    None,
+                    None,
+                )
+                .unwrap();
+            db.set_message_metrics(id, &metrics).unwrap();
+        };
+        let metrics = |eval_count, tokens_per_second, total_duration| MessageMetrics {
+            eval_count: Some(eval_count),
+            tokens_per_second: Some(tokens_per_second),
+            total_duration: Some(total_duration),
+            ..Default::default()
+        };
+        reply(Some("llama3"), metrics(100, 40.0, 2_000));
+        reply(Some("llama3"), metrics(50, 20.0, 4_000));
+        // A reply without metrics doesn't drag the averages down
+        reply(Some("llama3"), MessageMetrics::default());
+        reply(Some("deepseek-r1"), metrics(300, 10.0, 30_000));
+        reply(None, MessageMetrics::default());
         db.add_message(
             thread_id,
-            "assistant",
-            "Hi",
+            Role::User,
+            "text",
+            None,
+            Some("mistral".to_string()),
             None,
-            Some("llama2".to_string()),
             None,
         )
         .unwrap();
 
-        let messages = db.get_messages(thread_id).unwrap();
-        assert_eq!(messages.len(), 2);
-        assert_eq!(messages[0].content, "Hello");
-        assert_eq!(messages[1].content, "Hi");
-        assert_eq!(messages[1].model, Some("llama2".to_string()));
+        assert_eq!(
+            db.model_stats().unwrap(),
+            [
+                ModelStats {
+                    model: "llama3".to_string(),
+                    message_count: 3,
+                    tokens: 150,
+                    avg_tokens_per_second: Some(30.0),
+                    avg_total_duration: Some(3_000.0),
+                },
+                ModelStats {
+                    model: "deepseek-r1".to_string(),
+                    message_count: 1,
+                    tokens: 300,
+                    avg_tokens_per_second: Some(10.0),
+                    avg_total_duration: Some(30_000.0),
+                },
+                ModelStats {
+                    model: UNKNOWN_MODEL.to_string(),
+                    message_count: 1,
+                    tokens: 0,
+                    avg_tokens_per_second: None,
+                    avg_total_duration: None,
+                },
+            ]
+        );
     }
 
     #[test]
-    fn test_db_performance() {
+    fn test_latency_history() {
         let db = Database::new(":memory:").unwrap();
-        let start = std::time::Instant::now();
+        let thread_id = db.create_thread("Test", None).unwrap();
+        let reply = |model: &str, first_token_ms: Option<i64>| {
+            let id = db
+                .add_message(
+                    thread_id,
+                    Role::Assistant,
+                    "text",
+                    None,
+                    Some(model.to_string()),
+                    None,
+                    None,
+                )
+                .unwrap();
+            db.set_message_metrics(
+                id,
+                &MessageMetrics {
+                    first_token_ms,
+                    total_duration: first_token_ms.map(|ms| ms * 2_000_000),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        };
+        reply("llama3", Some(100));
+        reply("llama3", Some(200));
+        reply("mistral", Some(900));
+        reply("llama3", None);
+        reply("llama3", Some(300));
 
-        let thread_id = db.create_thread("Benchmark", None).unwrap();
-        for i in 0..100 {
-            db.add_message(
+        let ms = |limit| -> Vec<_> {
+            db.latency_history("llama3", limit)
+                .unwrap()
+                .iter()
+                .map(|p| p.first_token_ms)
+                .collect()
+        };
+        assert_eq!(ms(10), [Some(100), Some(200), Some(300)]);
+        assert_eq!(ms(2), [Some(200), Some(300)]);
+        assert_eq!(
+            db.latency_history("llama3", 1).unwrap()[0].total_duration,
+            Some(600_000_000)
+        );
+    }
+
+    #[test]
+    fn test_open_readonly() {
+        let path = std::env::temp_dir().join(format!("chatz-readonly-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        {
+            // A database from before most columns and tables existed
+            let conn = Connection::open(path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE threads (id INTEGER PRIMARY KEY, title TEXT NOT NULL, created_at TEXT NOT NULL);
+                 CREATE TABLE messages (id INTEGER PRIMARY KEY, thread_id INTEGER NOT NULL,
+                    role TEXT NOT NULL, content TEXT NOT NULL, created_at TEXT NOT NULL);
+                 INSERT INTO threads VALUES (1, 'Backup', '2025-01-01T00:00:00+00:00');
+                 INSERT INTO messages VALUES (1, 1, 'user', 'Hi', '2025-01-01T00:00:00+00:00');",
+            )
+            .unwrap();
+        }
+
+        let db = Database::open_readonly(path).unwrap();
+        assert!(db.is_read_only());
+        let threads = db.get_threads().unwrap();
+        assert_eq!(threads.len(), 1);
+        assert!(!threads[0].is_archived);
+        let messages = db.get_messages(1).unwrap();
+        assert_eq!(messages[0].content, "Hi");
+        assert_eq!(messages[0].model, None);
+        assert_eq!(db.get_setting("ollama_url").unwrap(), None);
+        assert!(db.create_thread("New", None).is_err());
+        drop(db);
+
+        // Nothing was migrated
+        let conn = Connection::open(path).unwrap();
+        let columns: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('messages')",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(columns, 5);
+        drop(conn);
+
+        let other = std::env::temp_dir().join(format!("chatz-other-{}.db", std::process::id()));
+        Connection::open(&other)
+            .unwrap()
+            .execute("CREATE TABLE notes (id INTEGER)", [])
+            .unwrap();
+        assert!(Database::open_readonly(other.to_str().unwrap()).is_err());
+
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(other).unwrap();
+    }
+
+    #[test]
+    fn test_stats() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Test", None).unwrap();
+        db.add_message(thread_id, Role::User, "Hi", None, None, None, None)
+            .unwrap();
+        db.add_message(thread_id, Role::Assistant, "Hello", None, None, None, None)
+            .unwrap();
+
+        let stats = db.stats().unwrap();
+        assert_eq!(stats.path, "");
+        assert_eq!(stats.thread_count, 1);
+        assert_eq!(stats.message_count, 2);
+        assert!(stats.size_bytes > 0);
+    }
+
+    #[test]
+    fn test_thread_summary() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Research", None).unwrap();
+        assert!(db.get_thread(thread_id).unwrap().summary.is_none());
+
+        db.set_thread_summary(thread_id, "## Summary\nFirst")
+            .unwrap();
+        db.set_thread_summary(thread_id, "## Summary\nSecond")
+            .unwrap();
+        assert_eq!(
+            db.get_thread(thread_id).unwrap().summary.as_deref(),
+            Some("## Summary\nSecond")
+        );
+    }
+
+    #[test]
+    fn test_archive_stale_threads() {
+        let db = Database::new(":memory:").unwrap();
+        let month_ago = (Utc::now() - chrono::Duration::days(30)).to_rfc3339();
+        let stale = db.create_thread("Stale", None).unwrap();
+        db.add_message(stale, Role::User, "old", None, None, None, None)
+            .unwrap();
+        let revived = db.create_thread("Revived", None).unwrap();
+        db.add_message(revived, Role::User, "old", None, None, None, None)
+            .unwrap();
+        let empty = db.create_thread("Empty", None).unwrap();
+        db.conn
+            .execute("UPDATE messages SET created_at = ?1", params![month_ago])
+            .unwrap();
+        db.conn
+            .execute("UPDATE threads SET created_at = ?1", params![month_ago])
+            .unwrap();
+        db.add_message(revived, Role::User, "new", None, None, None, None)
+            .unwrap();
+        let fresh = db.create_thread("Fresh", None).unwrap();
+
+        let cutoff = Utc::now() - chrono::Duration::days(7);
+        assert_eq!(
+            db.archive_stale_threads(cutoff).unwrap(),
+            vec![stale, empty]
+        );
+        assert!(db.get_thread(stale).unwrap().is_archived);
+        assert!(db.get_thread(empty).unwrap().is_archived);
+        assert!(!db.get_thread(revived).unwrap().is_archived);
+        assert!(!db.get_thread(fresh).unwrap().is_archived);
+        // Already archived threads aren't counted again
+        assert!(db.archive_stale_threads(cutoff).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_flag_interrupted_threads() {
+        let db = Database::new(":memory:").unwrap();
+        let add = |thread_id, role| {
+            db.add_message(thread_id, role, "text", None, None, None, None)
+                .unwrap()
+        };
+        let unanswered = db.create_thread("Unanswered", None).unwrap();
+        add(unanswered, Role::User);
+        let answered = db.create_thread("Answered", None).unwrap();
+        add(answered, Role::User);
+        add(answered, Role::Assistant);
+        let cut_off = db.create_thread("Cut off", None).unwrap();
+        add(cut_off, Role::User);
+        let partial = add(cut_off, Role::Assistant);
+        db.set_message_incomplete(partial).unwrap();
+        let just_sent = db.create_thread("Just sent", None).unwrap();
+        add(just_sent, Role::User);
+        db.conn
+            .execute(
+                "UPDATE messages SET created_at = ?1 WHERE thread_id != ?2",
+                params![
+                    (Utc::now() - chrono::Duration::minutes(10)).to_rfc3339(),
+                    just_sent
+                ],
+            )
+            .unwrap();
+
+        let cutoff = Utc::now() - chrono::Duration::minutes(2);
+        assert_eq!(
+            db.flag_interrupted_threads(cutoff).unwrap(),
+            vec![unanswered, cut_off]
+        );
+        let mut flagged: Vec<i64> = db
+            .get_interrupted_threads()
+            .unwrap()
+            .iter()
+            .map(|t| t.id)
+            .collect();
+        flagged.sort();
+        assert_eq!(flagged, vec![unanswered, cut_off]);
+        assert!(db.get_thread(unanswered).unwrap().needs_attention);
+        assert!(!db.get_thread(just_sent).unwrap().needs_attention);
+        // Already flagged threads aren't counted again
+        assert!(db.flag_interrupted_threads(cutoff).unwrap().is_empty());
+
+        // A new message clears the flag
+        add(unanswered, Role::Assistant);
+        assert!(!db.get_thread(unanswered).unwrap().needs_attention);
+    }
+
+    #[test]
+    fn test_client_request_id() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Laggy", None).unwrap();
+        let other_thread = db.create_thread("Other", None).unwrap();
+        let m1 = db
+            .add_message(thread_id, Role::User, "Hello", None, None, None, None)
+            .unwrap();
+        assert_eq!(
+            db.find_message_by_client_request_id(thread_id, "req-1")
+                .unwrap(),
+            None
+        );
+
+        db.set_message_client_request_id(m1, "req-1").unwrap();
+        assert_eq!(
+            db.find_message_by_client_request_id(thread_id, "req-1")
+                .unwrap(),
+            Some(m1)
+        );
+        assert_eq!(
+            db.find_message_by_client_request_id(other_thread, "req-1")
+                .unwrap(),
+            None
+        );
+
+        // The index rejects a second message with the same id in the thread
+        let m2 = db
+            .add_message(thread_id, Role::User, "Hello", None, None, None, None)
+            .unwrap();
+        assert!(db.set_message_client_request_id(m2, "req-1").is_err());
+        // Messages without one don't conflict
+        db.add_message(thread_id, Role::User, "Hi", None, None, None, None)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_message_document_names() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Contracts", None).unwrap();
+        let m1 = db
+            .add_message(
                 thread_id,
-                "user",
-                &format!("Message {}", i),
+                Role::User,
+                "Compare these",
+                None,
                 None,
                 None,
                 None,
             )
             .unwrap();
-        }
-
-        let messages = db.get_messages(thread_id).unwrap();
-        assert_eq!(messages.len(), 100);
+        assert!(db.get_message(m1).unwrap().document_names.is_none());
 
-        let duration = start.elapsed();
-        println!("Inserted and retrieved 100 messages in {:?}", duration);
-        // Ensure it's reasonably fast (e.g. < 500ms for in-memory)
-        assert!(duration.as_millis() < 500);
+        let names = vec!["lease-2023.pdf".to_string(), "lease-2024.docx".to_string()];
+        db.set_message_document_names(m1, &names).unwrap();
+        assert_eq!(db.get_message(m1).unwrap().document_names, Some(names));
     }
 
     #[test]
-    fn test_edit_and_delete() {
+    fn test_message_generation_options() {
         let db = Database::new(":memory:").unwrap();
-        let thread_id = db.create_thread("Edit Test", None).unwrap();
+        let thread_id = db.create_thread("Sampling", None).unwrap();
+        let m1 = db
+            .add_message(thread_id, Role::Assistant, "Hi", None, None, None, None)
+            .unwrap();
+        assert!(db.get_message(m1).unwrap().generation_options.is_none());
+
+        let options = ModelOptions {
+            repeat_penalty: Some(1.15),
+            num_thread: Some(8),
+            ..Default::default()
+        };
+        db.set_message_generation_options(m1, &options).unwrap();
+        assert_eq!(
+            db.get_message(m1).unwrap().generation_options,
+            Some(options)
+        );
+    }
 
+    #[test]
+    fn test_message_done_reason() {
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("Cut off", None).unwrap();
         let m1 = db
-            .add_message(thread_id, "user", "msg1", None, None, None)
+            .add_message(
+                thread_id,
+                Role::Assistant,
+                "Once upon",
+                None,
+                None,
+                None,
+                None,
+            )
             .unwrap();
-        db.add_message(thread_id, "assistant", "msg2", None, None, None)
+        assert_eq!(db.get_message(m1).unwrap().done_reason, None);
+
+        db.set_message_done_reason(m1, "length").unwrap();
+        assert_eq!(
+            db.get_message(m1).unwrap().done_reason.as_deref(),
+            Some("length")
+        );
+    }
+
+    #[test]
+    fn test_prompt_presets() {
+        let db = Database::new(":memory:").unwrap();
+        let reviewer = db
+            .create_preset("Reviewer", "You review Rust code.")
             .unwrap();
-        db.add_message(thread_id, "user", "msg3", None, None, None)
+        let translator = db
+            .create_preset("Translator", "Translate to Marathi.")
             .unwrap();
+        assert_eq!(db.get_presets().unwrap().len(), 2);
 
-        // Update m1
-        db.update_message(m1, "msg1_updated").unwrap();
-        let msgs = db.get_messages(thread_id).unwrap();
-        assert_eq!(msgs[0].content, "msg1_updated");
+        db.update_preset(reviewer, "Reviewer", "You review Rust code strictly.")
+            .unwrap();
+        assert_eq!(
+            db.get_preset(reviewer).unwrap().content,
+            "You review Rust code strictly."
+        );
 
-        // Delete after m1
-        db.delete_messages_after(thread_id, m1).unwrap();
-        let msgs = db.get_messages(thread_id).unwrap();
-        assert_eq!(msgs.len(), 1);
-        assert_eq!(msgs[0].id, m1);
+        db.delete_preset(translator).unwrap();
+        let presets = db.get_presets().unwrap();
+        assert_eq!(presets.len(), 1);
+        assert_eq!(presets[0].name, "Reviewer");
 
-        // Delete last (which is m1 now)
-        db.delete_last_message(thread_id).unwrap();
-        let msgs = db.get_messages(thread_id).unwrap();
-        assert!(msgs.is_empty());
+        let thread_id = db.create_thread("Review", None).unwrap();
+        db.set_thread_system_prompt(thread_id, Some(presets[0].content.clone()))
+            .unwrap();
+        assert_eq!(
+            db.get_thread_system_prompt(thread_id).unwrap().as_deref(),
+            Some("You review Rust code strictly.")
+        );
+    }
+
+    #[test]
+    fn test_model_aliases() {
+        let db = Database::new(":memory:").unwrap();
+        let alias = ModelAlias {
+            model_name: "qwen2.5-coder:7b-instruct-q4_K_M".to_string(),
+            alias: Some("Coder".to_string()),
+            is_favorite: true,
+            sort_order: 1,
+        };
+        db.set_model_alias(&alias).unwrap();
+        assert_eq!(db.get_model_aliases().unwrap(), vec![alias.clone()]);
+
+        assert_eq!(
+            db.resolve_model_name("Coder").unwrap(),
+            "qwen2.5-coder:7b-instruct-q4_K_M"
+        );
+        assert_eq!(db.resolve_model_name("llama3").unwrap(), "llama3");
+
+        // Updating replaces the existing row
+        db.set_model_alias(&ModelAlias {
+            is_favorite: false,
+            ..alias.clone()
+        })
+        .unwrap();
+        assert!(!db.get_model_aliases().unwrap()[0].is_favorite);
+
+        db.delete_model_alias(&alias.model_name).unwrap();
+        assert!(db.get_model_aliases().unwrap().is_empty());
+        assert_eq!(db.resolve_model_name("Coder").unwrap(), "Coder");
     }
 }
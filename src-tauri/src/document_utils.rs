@@ -0,0 +1,213 @@
+use base64::{engine::general_purpose, Engine as _};
+use serde::Deserialize;
+use std::fmt;
+
+use crate::docx_utils;
+use crate::pdf_utils;
+
+const DOCX_MIME_TYPE: &str =
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.document";
+
+/// PDF readers accept a header anywhere in the first kilobyte.
+const PDF_HEADER_WINDOW: usize = 1024;
+
+#[derive(Debug, PartialEq)]
+enum DocumentKind {
+    Pdf,
+    Docx,
+}
+
+/// Tells a document attachment's format from its data URL MIME type, or from the
+/// file's magic bytes when it was sent as bare base64. Defaults to PDF.
+fn document_kind(attachment: &str, bytes: &[u8]) -> DocumentKind {
+    if let Some(mime) = attachment
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split([';', ',']).next())
+    {
+        if mime == DOCX_MIME_TYPE {
+            return DocumentKind::Docx;
+        }
+        if mime == "application/pdf" {
+            return DocumentKind::Pdf;
+        }
+    }
+    // .docx files are zip archives
+    if bytes.starts_with(b"PK\x03\x04") {
+        DocumentKind::Docx
+    } else {
+        DocumentKind::Pdf
+    }
+}
+
+/// A PDF, DOCX or image attachment as sent by the UI: its file name and base64
+/// data, or just the data from clients that don't send names.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Attachment {
+    Named { name: String, data: String },
+    Bare(String),
+}
+
+impl Attachment {
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Attachment::Named { name, .. } => Some(name).filter(|name| !name.is_empty()),
+            Attachment::Bare(_) => None,
+        }
+    }
+
+    pub fn data(&self) -> &str {
+        match self {
+            Attachment::Named { data, .. } | Attachment::Bare(data) => data,
+        }
+    }
+
+    pub fn into_parts(self) -> (Option<String>, String) {
+        match self {
+            Attachment::Named { name, data } => (Some(name).filter(|name| !name.is_empty()), data),
+            Attachment::Bare(data) => (None, data),
+        }
+    }
+}
+
+/// A document attachment after decoding. DOCX files are small enough to be read
+/// right away.
+#[derive(Debug)]
+pub enum DecodedDocument {
+    Pdf(Vec<u8>),
+    Docx(Result<String, String>),
+}
+
+/// Why a document attachment couldn't be read at all.
+#[derive(Debug, PartialEq)]
+pub enum DocumentError {
+    InvalidBase64(String),
+    Empty,
+    /// Neither a PDF nor a DOCX file
+    UnsupportedFormat,
+}
+
+impl fmt::Display for DocumentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DocumentError::InvalidBase64(e) => write!(f, "the file data is corrupt ({})", e),
+            DocumentError::Empty => f.write_str("the file is empty"),
+            DocumentError::UnsupportedFormat => {
+                f.write_str("the file is not a PDF or DOCX document")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DocumentError {}
+
+/// Decodes a base64 document attachment, with or without a data URL prefix,
+/// along with its content hash.
+pub fn decode_document(data: &str) -> Result<(String, DecodedDocument), DocumentError> {
+    // Remove data:application/pdf;base64, prefix if present
+    let clean_base64 = data.find(',').map_or(data, |idx| &data[idx + 1..]);
+    let bytes = general_purpose::STANDARD
+        .decode(clean_base64.trim())
+        .map_err(|e| DocumentError::InvalidBase64(e.to_string()))?;
+    if bytes.is_empty() {
+        return Err(DocumentError::Empty);
+    }
+
+    let hash = pdf_utils::content_hash(&bytes);
+    match document_kind(data, &bytes) {
+        DocumentKind::Docx => Ok((
+            hash,
+            DecodedDocument::Docx(
+                docx_utils::extract_text_from_docx(&bytes).map_err(|e| e.to_string()),
+            ),
+        )),
+        DocumentKind::Pdf => {
+            let window = &bytes[..bytes.len().min(PDF_HEADER_WINDOW)];
+            if !window.windows(5).any(|w| w == b"%PDF-") {
+                return Err(DocumentError::UnsupportedFormat);
+            }
+            Ok((hash, DecodedDocument::Pdf(bytes)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(bytes: &[u8]) -> String {
+        general_purpose::STANDARD.encode(bytes)
+    }
+
+    #[test]
+    fn test_decodes_pdf_with_and_without_data_url() {
+        let pdf = b"%PDF-1.7\n...";
+        for data in [
+            encode(pdf),
+            format!("data:application/pdf;base64,{}", encode(pdf)),
+        ] {
+            let (hash, decoded) = decode_document(&data).unwrap();
+            assert_eq!(hash, pdf_utils::content_hash(pdf));
+            assert!(matches!(decoded, DecodedDocument::Pdf(bytes) if bytes == pdf));
+        }
+    }
+
+    #[test]
+    fn test_truncated_base64_is_an_error() {
+        let data = encode(b"%PDF-1.7\nsome content");
+        let truncated = &data[..data.len() - 3];
+        assert!(matches!(
+            decode_document(truncated),
+            Err(DocumentError::InvalidBase64(_))
+        ));
+    }
+
+    #[test]
+    fn test_non_pdf_bytes_are_an_error() {
+        let data = encode(b"GIF89a not a document at all");
+        assert_eq!(
+            decode_document(&data).unwrap_err(),
+            DocumentError::UnsupportedFormat
+        );
+        // Labelled as a PDF doesn't make it one
+        let data = format!("data:application/pdf;base64,{}", data);
+        assert_eq!(
+            decode_document(&data).unwrap_err(),
+            DocumentError::UnsupportedFormat
+        );
+    }
+
+    #[test]
+    fn test_zero_byte_attachment_is_an_error() {
+        assert_eq!(decode_document("").unwrap_err(), DocumentError::Empty);
+        assert_eq!(
+            decode_document("data:application/pdf;base64,").unwrap_err(),
+            DocumentError::Empty
+        );
+    }
+
+    #[test]
+    fn test_attachment_with_or_without_a_name() {
+        let attachments: Vec<Attachment> = serde_json::from_str(
+            r#"[{"name": "contract.pdf", "data": "JVBERi0="}, "JVBERi0=", {"name": "", "data": "JVBERi0="}]"#,
+        )
+        .unwrap();
+        assert_eq!(attachments[0].name(), Some("contract.pdf"));
+        assert_eq!(attachments[1].name(), None);
+        // An empty name is as good as none
+        assert_eq!(attachments[2].name(), None);
+        assert!(attachments.iter().all(|a| a.data() == "JVBERi0="));
+        assert_eq!(
+            attachments[0].clone().into_parts(),
+            (Some("contract.pdf".to_string()), "JVBERi0=".to_string())
+        );
+    }
+
+    #[test]
+    fn test_document_kind() {
+        let docx = format!("data:{};base64,", DOCX_MIME_TYPE);
+        assert_eq!(document_kind(&docx, b"%PDF-"), DocumentKind::Docx);
+        assert_eq!(document_kind("", b"PK\x03\x04rest"), DocumentKind::Docx);
+        assert_eq!(document_kind("", b"%PDF-1.4"), DocumentKind::Pdf);
+    }
+}
@@ -0,0 +1,183 @@
+use std::io::{Cursor, Read};
+
+/// Extracts the text of a .docx file, one line per paragraph. List items are
+/// prefixed with "- " and indented two spaces per nesting level.
+pub fn extract_text_from_docx(bytes: &[u8]) -> Result<String, Box<dyn std::error::Error>> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+    let mut xml = String::new();
+    archive
+        .by_name("word/document.xml")?
+        .read_to_string(&mut xml)?;
+    Ok(document_text(&xml))
+}
+
+/// Walks the tags of `word/document.xml`. Only a handful of elements matter for
+/// plain text, so this scans them directly instead of building a DOM.
+fn document_text(xml: &str) -> String {
+    let mut paragraphs = Vec::new();
+    let mut paragraph = String::new();
+    let mut list_level: Option<usize> = None;
+    let mut in_run = false;
+    let mut in_text = false;
+
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        if in_text {
+            paragraph.push_str(&unescape(&rest[..start]));
+        }
+        let Some(len) = rest[start..].find('>') else {
+            break;
+        };
+        let tag = &rest[start + 1..start + len];
+        rest = &rest[start + len + 1..];
+
+        let self_closing = tag.ends_with('/');
+        let name = tag
+            .trim_end_matches('/')
+            .split(|c: char| c.is_whitespace())
+            .next()
+            .unwrap_or_default();
+        match name {
+            "w:p" => {
+                paragraph.clear();
+                list_level = None;
+                if self_closing {
+                    paragraphs.push(String::new());
+                }
+            }
+            "/w:p" => {
+                paragraphs.push(match list_level {
+                    Some(level) => format!("{}- {}", "  ".repeat(level), paragraph),
+                    None => paragraph.clone(),
+                });
+            }
+            // Text runs; w:delText (deleted text in tracked changes) is left out
+            "w:r" => in_run = !self_closing,
+            "/w:r" => in_run = false,
+            "w:t" => in_text = !self_closing,
+            "/w:t" => in_text = false,
+            // Outside a run, w:tab is a tab stop definition rather than a tab
+            "w:tab" if in_run => paragraph.push('\t'),
+            "w:br" | "w:cr" if in_run => paragraph.push('\n'),
+            // Numbering properties mark list items; w:ilvl gives the nesting level
+            "w:numPr" => {
+                list_level.get_or_insert(0);
+            }
+            "w:ilvl" => {
+                list_level = Some(
+                    attribute(tag, "w:val")
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or(0),
+                );
+            }
+            _ => {}
+        }
+    }
+
+    paragraphs.join("\n").trim_end().to_string()
+}
+
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!("{}=\"", name))? + name.len() + 2;
+    let len = tag[start..].find('"')?;
+    Some(&tag[start..start + len])
+}
+
+/// Decodes character references. Besides the XML entities this knows the HTML
+/// ones common in web pages, so `url_utils` can use it too.
+pub(crate) fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let Some(semi) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..semi];
+        let decoded = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            "ndash" => Some('–'),
+            "mdash" => Some('—'),
+            "hellip" => Some('…'),
+            "lsquo" => Some('‘'),
+            "rsquo" => Some('’'),
+            "ldquo" => Some('“'),
+            "rdquo" => Some('”'),
+            "copy" => Some('©'),
+            _ => entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(|dec| dec.parse()))
+                .and_then(|code| code.ok())
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[semi + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn stored() -> zip::write::SimpleFileOptions {
+        zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored)
+    }
+
+    fn sample_docx(body: &str) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        writer.start_file("word/document.xml", stored()).unwrap();
+        write!(
+            writer,
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"><w:body>{}</w:body></w:document>"#,
+            body
+        )
+        .unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_paragraphs_and_lists() {
+        let docx = sample_docx(concat!(
+            r#"<w:p><w:r><w:t>Q3 </w:t></w:r><w:r><w:rPr><w:b/></w:rPr><w:t xml:space="preserve">R&amp;D report</w:t></w:r></w:p>"#,
+            r#"<w:p/>"#,
+            r#"<w:p><w:pPr><w:numPr><w:ilvl w:val="0"/><w:numId w:val="1"/></w:numPr></w:pPr><w:r><w:t>Budget</w:t></w:r></w:p>"#,
+            r#"<w:p><w:pPr><w:numPr><w:ilvl w:val="1"/><w:numId w:val="1"/></w:numPr></w:pPr><w:r><w:t>Hardware</w:t></w:r></w:p>"#,
+            r#"<w:p><w:pPr><w:tabs><w:tab w:val="left" w:pos="720"/></w:tabs></w:pPr><w:r><w:t>Total:</w:t><w:tab/><w:t>&#8364;12</w:t><w:delText>removed</w:delText></w:r></w:p>"#,
+        ));
+        assert_eq!(
+            extract_text_from_docx(&docx).unwrap(),
+            "Q3 R&D report\n\n- Budget\n  - Hardware\nTotal:\t€12"
+        );
+    }
+
+    #[test]
+    fn test_corrupt_archive_is_an_error() {
+        assert!(extract_text_from_docx(b"PK\x03\x04 not really a zip").is_err());
+
+        // A valid zip that isn't a Word document
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        writer.start_file("hello.txt", stored()).unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+        assert!(extract_text_from_docx(&bytes).is_err());
+    }
+}
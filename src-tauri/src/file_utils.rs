@@ -0,0 +1,278 @@
+use base64::{engine::general_purpose, Engine as _};
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::csv_utils;
+use crate::pdf_utils::{self, truncate_with_marker};
+
+/// A text-like file attached to a message, such as source code or Markdown.
+#[derive(Deserialize, Debug, Clone)]
+pub struct FileAttachment {
+    pub name: String,
+    /// As reported by the browser; often empty for source files
+    #[serde(default)]
+    pub mime: String,
+    pub data_base64: String,
+    /// For CSV files, include every row instead of a preview
+    #[serde(default)]
+    pub include_all_rows: bool,
+}
+
+/// Extensions of files that are text, mapped to the language used to annotate
+/// their code fence. An empty language means plain text.
+const TEXT_EXTENSIONS: &[(&str, &str)] = &[
+    ("txt", ""),
+    ("log", ""),
+    ("md", "markdown"),
+    ("markdown", "markdown"),
+    ("rs", "rust"),
+    ("py", "python"),
+    ("js", "javascript"),
+    ("mjs", "javascript"),
+    ("jsx", "jsx"),
+    ("ts", "typescript"),
+    ("tsx", "tsx"),
+    ("json", "json"),
+    ("toml", "toml"),
+    ("yaml", "yaml"),
+    ("yml", "yaml"),
+    ("xml", "xml"),
+    ("html", "html"),
+    ("css", "css"),
+    ("csv", "csv"),
+    ("sql", "sql"),
+    ("sh", "bash"),
+    ("bash", "bash"),
+    ("c", "c"),
+    ("h", "c"),
+    ("cpp", "cpp"),
+    ("hpp", "cpp"),
+    ("cs", "csharp"),
+    ("go", "go"),
+    ("java", "java"),
+    ("kt", "kotlin"),
+    ("swift", "swift"),
+    ("rb", "ruby"),
+    ("php", "php"),
+    ("lua", "lua"),
+    ("ini", "ini"),
+];
+
+/// MIME types outside `text/*` that are still text.
+const TEXT_MIME_TYPES: &[&str] = &[
+    "application/json",
+    "application/xml",
+    "application/javascript",
+    "application/x-sh",
+    "application/toml",
+    "application/yaml",
+    "application/x-yaml",
+];
+
+fn extension(name: &str) -> Option<String> {
+    Path::new(name)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+}
+
+/// The language for a file's code fence, from its extension.
+pub fn language_for(name: &str) -> Option<&'static str> {
+    let extension = extension(name)?;
+    TEXT_EXTENSIONS
+        .iter()
+        .find(|(ext, _)| *ext == extension)
+        .map(|(_, language)| *language)
+        .filter(|language| !language.is_empty())
+}
+
+/// Whether the file can be read as text: a known text extension or MIME type,
+/// or, for unknown types, content without NUL bytes that is valid UTF-8.
+fn is_text(attachment: &FileAttachment, bytes: &[u8]) -> bool {
+    let known_extension = extension(&attachment.name)
+        .is_some_and(|extension| TEXT_EXTENSIONS.iter().any(|(ext, _)| *ext == extension));
+    let mime = attachment.mime.to_lowercase();
+    if known_extension || mime.starts_with("text/") || TEXT_MIME_TYPES.contains(&mime.as_str()) {
+        return true;
+    }
+    // Only look at the start; a cut multi-byte character at the end is fine
+    let head = &bytes[..bytes.len().min(8192)];
+    !head.contains(&0)
+        && match std::str::from_utf8(head) {
+            Ok(_) => true,
+            Err(e) => e.error_len().is_none(),
+        }
+}
+
+fn is_csv(attachment: &FileAttachment) -> bool {
+    extension(&attachment.name).as_deref() == Some("csv")
+        || attachment.mime.eq_ignore_ascii_case("text/csv")
+}
+
+fn decode(attachment: &FileAttachment) -> Result<Vec<u8>, String> {
+    // Remove data:...;base64, prefix if present
+    let data = &attachment.data_base64;
+    let clean_base64 = data.find(',').map_or(data.as_str(), |idx| &data[idx + 1..]);
+    // Some encoders wrap base64 into lines
+    let clean_base64: String = clean_base64
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace())
+        .collect();
+    general_purpose::STANDARD
+        .decode(clean_base64)
+        .map_err(|e| format!("Invalid base64 in {}: {}", attachment.name, e))
+}
+
+/// Hash of the file's decoded bytes, so the same file matches however its
+/// base64 was prefixed or wrapped.
+pub fn content_hash(attachment: &FileAttachment) -> Result<String, String> {
+    decode(attachment).map(|bytes| pdf_utils::content_hash(&bytes))
+}
+
+/// Renders an attached file for the prompt. CSV files are summarized, falling
+/// back to their plain text with a warning when they can't be parsed; other
+/// files go through [`render_text_file`].
+pub fn render_file(
+    attachment: &FileAttachment,
+    max_chars: usize,
+    csv_preview_rows: usize,
+) -> Result<String, String> {
+    if !is_csv(attachment) {
+        return render_text_file(attachment, max_chars);
+    }
+    let bytes = decode(attachment)?;
+    match csv_utils::summarize_csv(
+        &attachment.name,
+        &bytes,
+        csv_preview_rows,
+        attachment.include_all_rows,
+        max_chars,
+    ) {
+        Ok(summary) => Ok(summary),
+        Err(e) => Ok(format!(
+            "[Warning: {} could not be parsed as CSV ({}); attached as plain text]\n{}",
+            attachment.name,
+            e,
+            render_text_file(attachment, max_chars)?
+        )),
+    }
+}
+
+/// Decodes a text file and wraps it in a fenced code block annotated with its
+/// name and language, cutting it off after `max_chars` characters. Binary files
+/// are an error.
+pub fn render_text_file(attachment: &FileAttachment, max_chars: usize) -> Result<String, String> {
+    let bytes = decode(attachment)?;
+
+    if !is_text(attachment, &bytes) {
+        return Err(format!(
+            "{} is not a text file and can't be attached",
+            attachment.name
+        ));
+    }
+
+    let mut text = String::from_utf8_lossy(&bytes).into_owned();
+    truncate_with_marker(&mut text, max_chars);
+
+    // The fence has to be longer than any run of backticks in the file
+    let longest_run = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    Ok(format!(
+        "--- File: {} ---\n{}{}\n{}\n{}",
+        attachment.name,
+        fence,
+        language_for(&attachment.name).unwrap_or_default(),
+        text.trim_end_matches('\n'),
+        fence
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attachment(name: &str, mime: &str, bytes: &[u8]) -> FileAttachment {
+        FileAttachment {
+            name: name.to_string(),
+            mime: mime.to_string(),
+            data_base64: general_purpose::STANDARD.encode(bytes),
+            include_all_rows: false,
+        }
+    }
+
+    #[test]
+    fn test_source_file_is_fenced_with_language() {
+        let file = attachment("main.rs", "", b"fn main() {}\n");
+        assert_eq!(
+            render_text_file(&file, 1000).unwrap(),
+            "--- File: main.rs ---\n```rust\nfn main() {}\n```"
+        );
+    }
+
+    #[test]
+    fn test_markdown_with_fences_gets_a_longer_fence() {
+        let file = attachment("README.md", "text/markdown", b"Run:\n```sh\nmake\n```\n");
+        let rendered = render_text_file(&file, 1000).unwrap();
+        assert!(rendered.starts_with("--- File: README.md ---\n````markdown\n"));
+        assert!(rendered.ends_with("```\n````"));
+    }
+
+    #[test]
+    fn test_unknown_types() {
+        // Unknown extension, but clearly text
+        let notes = attachment("NOTES", "", "Grüße".as_bytes());
+        assert!(render_text_file(&notes, 1000)
+            .unwrap()
+            .contains("```\nGrüße\n```"));
+
+        let png = attachment("photo.png", "image/png", b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR");
+        assert_eq!(
+            render_text_file(&png, 1000).unwrap_err(),
+            "photo.png is not a text file and can't be attached"
+        );
+    }
+
+    #[test]
+    fn test_csv_is_summarized_or_falls_back_to_text() {
+        let csv = attachment("data.csv", "text/csv", b"id,name\n1,a\n2,b\n");
+        assert!(render_file(&csv, 1000, 10)
+            .unwrap()
+            .starts_with("--- CSV: data.csv ---\n2 rows, 2 columns:\n- id (integer)\n"));
+
+        let ragged = attachment("data.csv", "", b"id,name\n1\n");
+        let rendered = render_file(&ragged, 1000, 10).unwrap();
+        assert!(rendered.starts_with("[Warning: data.csv could not be parsed as CSV ("));
+        assert!(rendered.ends_with("--- File: data.csv ---\n```csv\nid,name\n1\n```"));
+    }
+
+    #[test]
+    fn test_content_hash_ignores_encoding() {
+        let plain = attachment("notes.txt", "text/plain", b"same bytes, sent twice\n");
+        let encoded = plain.data_base64.clone();
+        let wrapped = FileAttachment {
+            data_base64: format!(
+                "data:text/plain;base64,{}\r\n{}",
+                &encoded[..8],
+                &encoded[8..]
+            ),
+            ..plain.clone()
+        };
+        assert_eq!(
+            content_hash(&plain).unwrap(),
+            content_hash(&wrapped).unwrap()
+        );
+        assert_ne!(
+            content_hash(&plain).unwrap(),
+            content_hash(&attachment("notes.txt", "", b"other bytes")).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_large_file_is_truncated() {
+        let file = attachment("big.txt", "text/plain", "x".repeat(50).as_bytes());
+        let rendered = render_text_file(&file, 10).unwrap();
+        assert!(rendered.contains(&format!(
+            "{}\n[truncated after 10 characters]",
+            "x".repeat(10)
+        )));
+    }
+}
@@ -0,0 +1,308 @@
+use base64::{engine::general_purpose, Engine as _};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageFormat};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+/// Longest side images are scaled down to; vision models don't use more.
+pub const DEFAULT_MAX_DIMENSION: u32 = 1568;
+/// Longest side of the thumbnails shown in the message list.
+pub const THUMBNAIL_DIMENSION: u32 = 256;
+const JPEG_QUALITY: u8 = 85;
+const THUMBNAIL_JPEG_QUALITY: u8 = 75;
+/// Image files `send_message` reads from disk, by extension and by content.
+pub const SUPPORTED_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "webp", "bmp"];
+const SUPPORTED_FORMATS: &[ImageFormat] = &[
+    ImageFormat::Png,
+    ImageFormat::Jpeg,
+    ImageFormat::Gif,
+    ImageFormat::WebP,
+    ImageFormat::Bmp,
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ImageMetadata {
+    pub width: u32,
+    pub height: u32,
+    /// Size of the image as attached, before any downscaling
+    pub original_width: u32,
+    pub original_height: u32,
+    /// File name of the attachment, when the UI sent one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct PreparedImage {
+    /// Base64 without a data URL prefix
+    pub data: String,
+    pub metadata: ImageMetadata,
+}
+
+/// Decodes a base64 image and scales it down so neither side exceeds
+/// `max_dimension`, re-encoding as PNG if it was one (to keep transparency) and as
+/// JPEG otherwise. Images that already fit are passed through untouched.
+pub fn prepare_image(base64_data: &str, max_dimension: u32) -> Result<PreparedImage, String> {
+    // Remove data:image/...;base64, prefix if present
+    let clean_base64 = base64_data
+        .find(',')
+        .map_or(base64_data, |idx| &base64_data[idx + 1..]);
+
+    let bytes = general_purpose::STANDARD
+        .decode(clean_base64.trim())
+        .map_err(|e| format!("Invalid base64: {}", e))?;
+    let format = image::guess_format(&bytes).map_err(|e| e.to_string())?;
+    let image = image::load_from_memory_with_format(&bytes, format).map_err(|e| e.to_string())?;
+    let (original_width, original_height) = image.dimensions();
+
+    if original_width.max(original_height) <= max_dimension {
+        return Ok(PreparedImage {
+            data: clean_base64.trim().to_string(),
+            metadata: ImageMetadata {
+                width: original_width,
+                height: original_height,
+                original_width,
+                original_height,
+                name: None,
+            },
+        });
+    }
+
+    // Keeps the aspect ratio, fitting the longest side to max_dimension
+    let resized = image.resize(max_dimension, max_dimension, FilterType::Lanczos3);
+    let (width, height) = resized.dimensions();
+
+    let mut encoded = Vec::new();
+    if format == ImageFormat::Png {
+        resized
+            .write_to(&mut Cursor::new(&mut encoded), ImageFormat::Png)
+            .map_err(|e| e.to_string())?;
+    } else {
+        // JPEG has no alpha channel
+        DynamicImage::ImageRgb8(resized.to_rgb8())
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut encoded, JPEG_QUALITY))
+            .map_err(|e| e.to_string())?;
+    }
+
+    Ok(PreparedImage {
+        data: general_purpose::STANDARD.encode(encoded),
+        metadata: ImageMetadata {
+            width,
+            height,
+            original_width,
+            original_height,
+            name: None,
+        },
+    })
+}
+
+/// A small JPEG of a base64 image for the message list, as base64.
+pub fn make_thumbnail(base64_data: &str) -> Result<String, String> {
+    let clean_base64 = base64_data
+        .find(',')
+        .map_or(base64_data, |idx| &base64_data[idx + 1..]);
+    let bytes = general_purpose::STANDARD
+        .decode(clean_base64.trim())
+        .map_err(|e| format!("Invalid base64: {}", e))?;
+    let image = image::load_from_memory(&bytes).map_err(|e| e.to_string())?;
+    let thumbnail = image.thumbnail(THUMBNAIL_DIMENSION, THUMBNAIL_DIMENSION);
+
+    let mut encoded = Vec::new();
+    DynamicImage::ImageRgb8(thumbnail.to_rgb8())
+        .write_with_encoder(JpegEncoder::new_with_quality(
+            &mut encoded,
+            THUMBNAIL_JPEG_QUALITY,
+        ))
+        .map_err(|e| e.to_string())?;
+    Ok(general_purpose::STANDARD.encode(encoded))
+}
+
+/// Thumbnails for a message's images, in the same order. An image that can't
+/// be decoded is kept as it is, so it still shows up.
+pub fn make_thumbnails(images: &[String]) -> Vec<String> {
+    images
+        .iter()
+        .map(|image| make_thumbnail(image).unwrap_or_else(|_| image.clone()))
+        .collect()
+}
+
+/// Reads an image the user picked as a file, returning its file name and base64
+/// data. Only files in `picked`, the resolved paths the file dialog returned,
+/// are read, and only if they are images by both extension and content, so a
+/// path can't be used to pull in any other file.
+pub fn read_image_file(
+    path: &Path,
+    picked: &HashSet<PathBuf>,
+    max_bytes: usize,
+) -> Result<(String, String), String> {
+    if !path.is_absolute() {
+        return Err(format!("{} is not an absolute path", path.display()));
+    }
+    // Resolves `..` and symlinks, so the checks below apply to the real file
+    let path = path
+        .canonicalize()
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    if !picked.contains(&path) {
+        return Err(format!(
+            "{} wasn't picked in the file dialog",
+            path.display()
+        ));
+    }
+    let metadata = path
+        .metadata()
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    if !metadata.is_file() {
+        return Err(format!("{} is not a file", path.display()));
+    }
+    let supported = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| SUPPORTED_EXTENSIONS.contains(&ext.to_lowercase().as_str()));
+    if !supported {
+        return Err(format!("{} is not a supported image type", path.display()));
+    }
+    if metadata.len() > max_bytes as u64 {
+        return Err(format!(
+            "{} is {} bytes, over the limit of {} (max_attachment_bytes)",
+            path.display(),
+            metadata.len(),
+            max_bytes
+        ));
+    }
+
+    let bytes = std::fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if !image::guess_format(&bytes).is_ok_and(|format| SUPPORTED_FORMATS.contains(&format)) {
+        return Err(format!("{} is not a supported image", path.display()));
+    }
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    Ok((name, general_purpose::STANDARD.encode(bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage, Rgba, RgbaImage};
+
+    fn encode(image: DynamicImage, format: ImageFormat) -> String {
+        let mut bytes = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut bytes), format)
+            .unwrap();
+        general_purpose::STANDARD.encode(bytes)
+    }
+
+    fn decode(data: &str) -> (ImageFormat, DynamicImage) {
+        let bytes = general_purpose::STANDARD.decode(data).unwrap();
+        let format = image::guess_format(&bytes).unwrap();
+        (format, image::load_from_memory(&bytes).unwrap())
+    }
+
+    #[test]
+    fn test_large_photo_is_downscaled_as_jpeg() {
+        let photo = DynamicImage::ImageRgb8(RgbImage::from_pixel(4000, 3000, Rgb([200, 80, 40])));
+        let data = format!(
+            "data:image/jpeg;base64,{}",
+            encode(photo, ImageFormat::Jpeg)
+        );
+
+        let prepared = prepare_image(&data, DEFAULT_MAX_DIMENSION).unwrap();
+        assert_eq!(
+            prepared.metadata,
+            ImageMetadata {
+                width: 1568,
+                height: 1176,
+                original_width: 4000,
+                original_height: 3000,
+                name: None,
+            }
+        );
+        let (format, image) = decode(&prepared.data);
+        assert_eq!(format, ImageFormat::Jpeg);
+        assert_eq!(image.dimensions(), (1568, 1176));
+    }
+
+    #[test]
+    fn test_png_stays_png() {
+        let png = DynamicImage::ImageRgba8(RgbaImage::from_pixel(300, 600, Rgba([0, 0, 0, 0])));
+        let prepared = prepare_image(&encode(png, ImageFormat::Png), 100).unwrap();
+
+        let (format, image) = decode(&prepared.data);
+        assert_eq!(format, ImageFormat::Png);
+        assert_eq!(image.dimensions(), (50, 100));
+    }
+
+    #[test]
+    fn test_small_image_is_untouched() {
+        let small = DynamicImage::ImageRgb8(RgbImage::new(64, 48));
+        let data = encode(small, ImageFormat::Png);
+        let prepared = prepare_image(&data, DEFAULT_MAX_DIMENSION).unwrap();
+        assert_eq!(prepared.data, data);
+        assert_eq!(prepared.metadata.original_width, 64);
+    }
+
+    #[test]
+    fn test_corrupt_image_is_an_error() {
+        assert!(prepare_image("not base64!", DEFAULT_MAX_DIMENSION).is_err());
+        let garbage = general_purpose::STANDARD.encode(b"definitely not an image");
+        assert!(prepare_image(&garbage, DEFAULT_MAX_DIMENSION).is_err());
+    }
+
+    #[test]
+    fn test_thumbnail() {
+        let photo = DynamicImage::ImageRgba8(RgbaImage::from_pixel(1000, 500, Rgba([0, 0, 0, 0])));
+        let data = encode(photo, ImageFormat::Png);
+        let thumbnails = make_thumbnails(&[data, "broken".to_string()]);
+
+        let (format, image) = decode(&thumbnails[0]);
+        assert_eq!(format, ImageFormat::Jpeg);
+        assert_eq!(image.dimensions(), (256, 128));
+        assert_eq!(thumbnails[1], "broken");
+    }
+
+    #[test]
+    fn test_read_image_file() {
+        let dir = std::env::temp_dir().join(format!("chatz-images-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let png = dir.join("photo.PNG");
+        let data = encode(
+            DynamicImage::ImageRgb8(RgbImage::new(8, 8)),
+            ImageFormat::Png,
+        );
+        std::fs::write(&png, general_purpose::STANDARD.decode(&data).unwrap()).unwrap();
+        let text = dir.join("notes.txt");
+        std::fs::write(&text, "secret").unwrap();
+        let disguised = dir.join("notes.png");
+        std::fs::write(&disguised, "secret").unwrap();
+        let unpicked = dir.join("other.png");
+        std::fs::copy(&png, &unpicked).unwrap();
+        let picked: HashSet<PathBuf> = [&png, &text, &disguised, &dir]
+            .iter()
+            .map(|path| path.canonicalize().unwrap())
+            .collect();
+        let read = |path: &Path, max_bytes| read_image_file(path, &picked, max_bytes);
+
+        assert_eq!(read(&png, 1024).unwrap(), ("photo.PNG".to_string(), data));
+        // Through `..`, the real path is what gets checked
+        let traversed = dir
+            .join("..")
+            .join(dir.file_name().unwrap())
+            .join("photo.PNG");
+        assert!(read(&traversed, 1024).is_ok());
+        assert!(read(Path::new("photo.PNG"), 1024).is_err());
+        assert!(read(&dir, 1024).is_err());
+        assert!(read(&text, 1024).is_err());
+        assert!(read(&disguised, 1024).is_err());
+        assert!(read(&dir.join("missing.png"), 1024).is_err());
+        // A valid image, but not one the dialog returned
+        assert!(read(&unpicked, 1024).unwrap_err().contains("file dialog"));
+        assert!(read(&png, 8).unwrap_err().contains("max_attachment_bytes"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,297 +1,4534 @@
+pub mod backend;
+pub mod busy;
+pub mod context;
+pub mod csv_utils;
 pub mod db;
+pub mod document_utils;
+pub mod docx_utils;
+pub mod file_utils;
+pub mod images;
+pub mod limits;
+#[cfg(feature = "ocr")]
+pub mod ocr;
 pub mod ollama;
+pub mod openai;
+pub mod pdf_layout;
 pub mod pdf_utils;
+pub mod prompt_vars;
+pub mod redact;
+pub mod search;
+pub mod stream_buffer;
+pub mod thread_events;
+pub mod url_utils;
+pub mod workspaces;
 
+use backend::{ChatBackend, BACKEND_OLLAMA, BACKEND_OPENAI};
 use base64::{engine::general_purpose, Engine as _};
-use db::{Database, Message, Thread};
-use ollama::{OllamaClient, OllamaMessage};
-use std::sync::Mutex;
-use tauri::{AppHandle, Emitter, State};
+use busy::{Admission, BusyThreads, QueuedGeneration};
+use db::{
+    DailyActivity, Database, DbStats, LatencyPoint, Message, MessageMetrics, ModelAlias,
+    ModelStats, PromptPreset, ServerProfile, Thread,
+};
+use document_utils::{Attachment, DecodedDocument};
+use file_utils::FileAttachment;
+use limits::MessageLimits;
+use ollama::{
+    ChatOptions, ClientAuth, GenerateOptions, ModelDetails, ModelOptions, ModelSummary,
+    OllamaClient, OllamaError, OllamaMessage, RetryPolicy, Role, RunningModel, StreamChunk,
+    StreamMetrics, Timeouts, TlsOptions, ToolCall, ToolDefinition,
+};
+use openai::OpenAiCompatClient;
+use pdf_utils::{
+    ExtractedPages, ExtractionProgress, OcrProgress, PageRange, PdfError, PdfLimitError, PdfLimits,
+    PdfMetadata,
+};
+use redact::Redactor;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use stream_buffer::ChunkCoalescer;
+use tauri::{AppHandle, Emitter, Manager, State};
+use tauri_plugin_dialog::DialogExt;
+use thread_events::ThreadArchivedEvent;
+use tokio::sync::watch;
+use workspaces::{Workspace, WorkspaceIndex};
+
+const DEFAULT_OLLAMA_URL: &str = "http://localhost:11434";
+const DEFAULT_EMBEDDING_MODEL: &str = "nomic-embed-text";
+const EMBEDDING_BATCH_SIZE: usize = 32;
+// Tokens left free in the context window for the model's reply
+const CONTEXT_RESPONSE_RESERVE: usize = 512;
+const DEFAULT_THREAD_TITLE_PREFIX: &str = "New Chat";
+const TITLE_PROMPT: &str = "Write a short title of 3 to 6 words for the following conversation. \
+Reply with the title only, without quotes or punctuation at the end.";
+/// Exchanges sent when a title is asked for by hand, where the thread may be long.
+const TITLE_EXCHANGES: usize = 3;
 
 struct AppState {
     db: Mutex<Database>,
-    ollama: OllamaClient,
+    ollama: RwLock<Arc<OllamaClient>>,
+    // One client per server profile, created on first use
+    profile_clients: Mutex<HashMap<i64, Arc<OllamaClient>>>,
+    // Same, for profiles that point at OpenAI-compatible servers
+    openai_clients: Mutex<HashMap<i64, Arc<OpenAiCompatClient>>>,
+    // Context window per (server URL, model), looked up once
+    context_lengths: Mutex<HashMap<(String, String), u64>>,
+    // Capabilities per (server URL, model), looked up once
+    model_capabilities: Mutex<HashMap<(String, String), Vec<String>>>,
+    // Threads with a response being generated
+    busy_threads: BusyThreads,
+    // Threads with a summarization in flight
+    summarizing: Mutex<HashSet<i64>>,
+    /// Image files picked in the file dialog, resolved; the only paths
+    /// `send_message` reads images from
+    picked_images: Mutex<HashSet<PathBuf>>,
+    /// Set once the startup tidy-up has run
+    tidied_up: AtomicBool,
+    // Tools offered to the model, per thread, for the current session
+    thread_tools: Mutex<HashMap<i64, Vec<ToolDefinition>>>,
+    // Model pulls in progress, by model name, with the signal that cancels them
+    active_pulls: Mutex<HashMap<String, watch::Sender<bool>>>,
+    // The workspaces there are; `db` is the active one's database
+    workspaces: Mutex<WorkspaceIndex>,
+}
+
+impl AppState {
+    fn is_read_only(&self) -> bool {
+        self.db.lock().is_ok_and(|db| db.is_read_only())
+    }
+
+    /// Returns the current client; it is swapped out when the base URL changes.
+    fn ollama(&self) -> Result<Arc<OllamaClient>, String> {
+        let ollama = self
+            .ollama
+            .read()
+            .map_err(|_| "Failed to lock Ollama client")?;
+        Ok(Arc::clone(&*ollama))
+    }
+
+    /// Returns the client for a server profile, or for the default profile when none
+    /// is given. Falls back to the global client if no profiles are configured.
+    fn ollama_for_profile(&self, profile_id: Option<i64>) -> Result<Arc<OllamaClient>, String> {
+        let profile = {
+            let db = self.db.lock().map_err(|_| "Failed to lock DB")?;
+            match profile_id {
+                Some(id) => Some(db.get_server_profile(id).map_err(|e| e.to_string())?),
+                None => db.get_default_server_profile().map_err(|e| e.to_string())?,
+            }
+        };
+        let Some(profile) = profile else {
+            return self.ollama();
+        };
+
+        let auth = profile_auth(&profile);
+        let tls = profile_tls(&profile);
+        let mut clients = self
+            .profile_clients
+            .lock()
+            .map_err(|_| "Failed to lock Ollama clients")?;
+        if let Some(client) = clients.get(&profile.id) {
+            // A profile whose URL, credentials or TLS settings were edited gets a fresh client
+            if client.base_url() == profile.base_url
+                && client.auth() == &auth
+                && client.tls() == &tls
+            {
+                return Ok(Arc::clone(client));
+            }
+        }
+
+        let client = Arc::new(
+            self.ollama()?
+                .with_base_url(profile.base_url)
+                .with_auth(auth)
+                .with_tls(tls),
+        );
+        clients.insert(profile.id, Arc::clone(&client));
+        Ok(client)
+    }
+
+    fn ollama_for_thread(&self, thread_id: i64) -> Result<Arc<OllamaClient>, String> {
+        let profile_id = {
+            let db = self.db.lock().map_err(|_| "Failed to lock DB")?;
+            db.get_thread(thread_id)
+                .map_err(|e| e.to_string())?
+                .server_profile_id
+        };
+        self.ollama_for_profile(profile_id)
+    }
+
+    /// Returns the chat backend for a server profile, chosen by the profile's kind.
+    fn backend_for_profile(&self, profile_id: Option<i64>) -> Result<Arc<dyn ChatBackend>, String> {
+        let profile = {
+            let db = self.db.lock().map_err(|_| "Failed to lock DB")?;
+            match profile_id {
+                Some(id) => Some(db.get_server_profile(id).map_err(|e| e.to_string())?),
+                None => db.get_default_server_profile().map_err(|e| e.to_string())?,
+            }
+        };
+        let profile = match profile {
+            Some(profile) if profile.kind == BACKEND_OPENAI => profile,
+            _ => {
+                let client: Arc<dyn ChatBackend> = self.ollama_for_profile(profile_id)?;
+                return Ok(client);
+            }
+        };
+
+        let auth = profile_auth(&profile);
+        let tls = profile_tls(&profile);
+        let mut clients = self
+            .openai_clients
+            .lock()
+            .map_err(|_| "Failed to lock OpenAI clients")?;
+        if let Some(client) = clients.get(&profile.id) {
+            if client.base_url() == profile.base_url
+                && client.auth() == &auth
+                && client.tls() == &tls
+            {
+                return Ok(Arc::clone(client) as Arc<dyn ChatBackend>);
+            }
+        }
+
+        let ollama = self.ollama()?;
+        let client = Arc::new(
+            OpenAiCompatClient::new(profile.base_url)
+                .with_retry_policy(ollama.retry_policy().clone())
+                .with_timeouts(ollama.timeouts().clone())
+                .with_auth(auth)
+                .with_tls(tls),
+        );
+        clients.insert(profile.id, Arc::clone(&client));
+        Ok(client)
+    }
+
+    /// Returns how many tokens of history can be sent to `model`: the
+    /// `context_budget` setting if set, otherwise the model's context window
+    /// minus room for the reply. `None` when neither is known.
+    async fn context_budget(
+        &self,
+        backend: &dyn ChatBackend,
+        model: &str,
+    ) -> Result<Option<usize>, String> {
+        {
+            let db = self.db.lock().map_err(|_| "Failed to lock DB")?;
+            if let Some(budget) = db
+                .get_setting("context_budget")
+                .map_err(|e| e.to_string())?
+                .and_then(|v| v.parse::<usize>().ok())
+            {
+                return Ok(Some(budget));
+            }
+        }
+
+        let length = self.context_length(backend, model).await?;
+        Ok(length.map(|length| (length as usize).saturating_sub(CONTEXT_RESPONSE_RESERVE)))
+    }
+
+    /// Returns the size of `model`'s context window, or `None` if the server
+    /// doesn't say.
+    async fn context_length(
+        &self,
+        backend: &dyn ChatBackend,
+        model: &str,
+    ) -> Result<Option<u64>, String> {
+        let key = (backend.base_url().to_string(), model.to_string());
+        let cached = {
+            let lengths = self
+                .context_lengths
+                .lock()
+                .map_err(|_| "Failed to lock context lengths")?;
+            lengths.get(&key).copied()
+        };
+        Ok(match cached {
+            Some(length) => Some(length),
+            // A failed lookup shouldn't block the chat; it is retried next time
+            None => match backend.context_length(model).await {
+                Ok(Some(length)) => {
+                    let mut lengths = self
+                        .context_lengths
+                        .lock()
+                        .map_err(|_| "Failed to lock context lengths")?;
+                    lengths.insert(key, length);
+                    Some(length)
+                }
+                _ => None,
+            },
+        })
+    }
+
+    /// Returns what `model` supports, or `None` if the server doesn't say.
+    async fn model_capabilities(
+        &self,
+        backend: &dyn ChatBackend,
+        model: &str,
+    ) -> Result<Option<Vec<String>>, String> {
+        let key = (backend.base_url().to_string(), model.to_string());
+        {
+            let capabilities = self
+                .model_capabilities
+                .lock()
+                .map_err(|_| "Failed to lock model capabilities")?;
+            if let Some(cached) = capabilities.get(&key) {
+                return Ok(Some(cached.clone()));
+            }
+        }
+
+        let fetched = backend
+            .capabilities(model)
+            .await
+            .map_err(|e| e.to_string())?;
+        if let Some(ref fetched) = fetched {
+            let mut capabilities = self
+                .model_capabilities
+                .lock()
+                .map_err(|_| "Failed to lock model capabilities")?;
+            capabilities.insert(key, fetched.clone());
+        }
+        Ok(fetched)
+    }
+
+    fn backend_for_thread(&self, thread_id: i64) -> Result<Arc<dyn ChatBackend>, String> {
+        let profile_id = {
+            let db = self.db.lock().map_err(|_| "Failed to lock DB")?;
+            db.get_thread(thread_id)
+                .map_err(|e| e.to_string())?
+                .server_profile_id
+        };
+        self.backend_for_profile(profile_id)
+    }
+}
+
+fn profile_auth(profile: &ServerProfile) -> ClientAuth {
+    ClientAuth {
+        token: profile.auth_token.clone(),
+        headers: profile.headers.clone(),
+    }
+}
+
+fn profile_tls(profile: &ServerProfile) -> TlsOptions {
+    TlsOptions {
+        accept_invalid_certs: profile.accept_invalid_certs,
+        ca_cert_path: profile.ca_cert_path.clone(),
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct PullProgressEvent {
+    name: String,
+    status: String,
+    percent: Option<f64>,
+}
+
+#[derive(Clone, Serialize)]
+struct ContextTrimmedEvent {
+    thread_id: i64,
+    dropped_messages: usize,
+    estimated_tokens: usize,
+    budget: usize,
+}
+
+#[derive(Clone, Serialize)]
+struct StreamChunkEvent {
+    thread_id: i64,
+    chunk: String,
+    /// The message being continued, so the UI appends to it in place
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_id: Option<i64>,
+    /// The answer this one is generated as an alternative to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    variant_of: Option<i64>,
+}
+
+#[derive(Clone, Serialize)]
+struct StreamMetricsEvent {
+    thread_id: i64,
+    #[serde(flatten)]
+    metrics: StreamMetrics,
+}
+
+fn emit_stream_chunk(app: &AppHandle, thread_id: i64, target: ReplyTarget, chunk: StreamChunk) {
+    let (message_id, variant_of) = match target {
+        ReplyTarget::New | ReplyTarget::Answer(_) => (None, None),
+        ReplyTarget::Continue(message_id) => (Some(message_id), None),
+        ReplyTarget::VariantOf(message_id) => (None, Some(message_id)),
+    };
+    // Scoped by thread so a stream doesn't leak into another open thread
+    let _ = match chunk {
+        StreamChunk::Thinking(chunk) => app.emit(
+            "stream-thinking",
+            StreamChunkEvent {
+                thread_id,
+                chunk,
+                message_id,
+                variant_of,
+            },
+        ),
+        StreamChunk::Content(chunk) => app.emit(
+            "stream-response",
+            StreamChunkEvent {
+                thread_id,
+                chunk,
+                message_id,
+                variant_of,
+            },
+        ),
+        StreamChunk::Metrics(metrics) => {
+            app.emit("stream-metrics", StreamMetricsEvent { thread_id, metrics })
+        }
+    };
+}
+
+#[derive(Clone, Serialize)]
+struct StreamDoneEvent {
+    thread_id: i64,
+    message_id: Option<i64>,
+    cancelled: bool,
+    /// The answer the new one is an alternative to, which is now superseded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    variant_of: Option<i64>,
+}
+
+#[derive(Clone, Serialize)]
+struct QueueUpdatedEvent {
+    thread_id: i64,
+    length: usize,
+}
+
+#[derive(Clone, Serialize)]
+struct StreamErrorEvent {
+    thread_id: i64,
+    error: String,
+}
+
+#[derive(Clone, Serialize)]
+struct StreamRetryingEvent {
+    thread_id: i64,
+    attempt: u32,
+    max_attempts: u32,
+}
+
+#[derive(Clone, Serialize)]
+struct EmbeddingProgressEvent {
+    thread_id: i64,
+    indexed: usize,
+    total: usize,
+}
+
+#[derive(Clone, Serialize)]
+struct ModelReadyEvent {
+    name: String,
+}
+
+#[derive(Clone, Serialize)]
+struct ToolCallRequestedEvent {
+    thread_id: i64,
+    message_id: i64,
+    tool_calls: Vec<ToolCall>,
+}
+
+#[derive(Serialize)]
+struct OllamaStatus {
+    reachable: bool,
+    version: Option<String>,
+    error: Option<String>,
+}
+
+/// Everything a bug report needs, with credentials left out.
+#[derive(Serialize)]
+struct AppDiagnostics {
+    app_version: String,
+    database: DbStats,
+    ollama_url: String,
+    /// Whether a token or extra headers are set; their values are never included
+    ollama_auth_configured: bool,
+    ollama: OllamaStatus,
+    limits: MessageLimits,
+}
+
+#[derive(Serialize)]
+struct AnnotatedModel {
+    #[serde(flatten)]
+    model: ModelSummary,
+    alias: Option<String>,
+    is_favorite: bool,
+    sort_order: i64,
+}
+
+#[derive(Serialize)]
+struct SemanticSearchResult {
+    message: Message,
+    score: f32,
+}
+
+/// A preset, when given, takes the place of the raw system prompt. Its text is
+/// copied into the thread, so later edits to the preset don't change old threads.
+fn resolve_system_prompt(
+    db: &Database,
+    system_prompt: Option<String>,
+    preset_id: Option<i64>,
+) -> Result<Option<String>, String> {
+    match preset_id {
+        Some(id) => Ok(Some(db.get_preset(id).map_err(|e| e.to_string())?.content)),
+        None => Ok(system_prompt),
+    }
+}
+
+/// A change to stored threads or messages. Every window is told about it, with
+/// the data as it now is, so each can patch its cache instead of refetching.
+enum DataChange {
+    ThreadCreated(i64),
+    ThreadUpdated(i64),
+    ThreadDeleted(i64),
+    MessageAdded(i64),
+    MessageUpdated(i64),
+    MessagesDeleted {
+        thread_id: i64,
+        message_ids: Vec<i64>,
+    },
+}
+
+#[derive(Clone, Serialize)]
+struct ThreadDeletedEvent {
+    thread_id: i64,
+}
+
+#[derive(Clone, Serialize)]
+struct MessagesDeletedEvent {
+    thread_id: i64,
+    message_ids: Vec<i64>,
+}
+
+/// Emits the event for `change`. The change has already been saved, so a
+/// failure to load what changed is only logged.
+fn notify_change(app: &AppHandle, db: &Database, change: DataChange) {
+    let emit_thread = |event: &str, thread_id: i64| -> Result<(), String> {
+        let thread = db.get_thread(thread_id).map_err(|e| e.to_string())?;
+        let _ = app.emit(event, &thread);
+        Ok(())
+    };
+    let emit_message = |event: &str, message_id: i64| -> Result<(), String> {
+        let mut message = db.get_message(message_id).map_err(|e| e.to_string())?;
+        show_thumbnails(db, &mut message)?;
+        let _ = app.emit(event, &message);
+        Ok(())
+    };
+
+    let result = match change {
+        DataChange::ThreadCreated(thread_id) => emit_thread("thread-created", thread_id),
+        DataChange::ThreadUpdated(thread_id) => emit_thread("thread-updated", thread_id),
+        DataChange::ThreadDeleted(thread_id) => {
+            let _ = app.emit("thread-deleted", ThreadDeletedEvent { thread_id });
+            Ok(())
+        }
+        DataChange::MessageAdded(message_id) => emit_message("message-added", message_id),
+        DataChange::MessageUpdated(message_id) => emit_message("message-updated", message_id),
+        DataChange::MessagesDeleted {
+            thread_id,
+            message_ids,
+        } => {
+            let _ = app.emit(
+                "messages-deleted",
+                MessagesDeletedEvent {
+                    thread_id,
+                    message_ids,
+                },
+            );
+            Ok(())
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to announce a change: {}", e);
+    }
+}
+
+/// Announces a reply saved by the stream: a new message, or more text on the
+/// one being continued, and the variant it replaces.
+fn notify_reply_saved(
+    app: &AppHandle,
+    db: &Database,
+    message_id: i64,
+    continuing: Option<i64>,
+    variant_of: Option<i64>,
+) {
+    if continuing.is_some() {
+        notify_change(app, db, DataChange::MessageUpdated(message_id));
+        return;
+    }
+    notify_change(app, db, DataChange::MessageAdded(message_id));
+    if let Some(variant_of) = variant_of {
+        notify_change(app, db, DataChange::MessageUpdated(variant_of));
+    }
+}
+
+/// Ids of a thread's messages in thread order.
+fn message_ids(db: &Database, thread_id: i64) -> Result<Vec<i64>, String> {
+    Ok(db
+        .get_messages(thread_id)
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|m| m.id)
+        .collect())
+}
+
+/// Ids of a thread's messages from `message_id` on, before they are deleted.
+/// Merged messages keep their ids, so this goes by position rather than id.
+fn message_ids_from(db: &Database, thread_id: i64, message_id: i64) -> Result<Vec<i64>, String> {
+    Ok(message_ids(db, thread_id)?
+        .into_iter()
+        .skip_while(|&id| id != message_id)
+        .collect())
+}
+
+#[tauri::command]
+fn create_thread(
+    app: AppHandle,
+    state: State<AppState>,
+    title: String,
+    system_prompt: Option<String>,
+    preset_id: Option<i64>,
+) -> Result<Thread, String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    let system_prompt = resolve_system_prompt(&db, system_prompt, preset_id)?;
+    let id = db
+        .create_thread(&title, system_prompt.clone())
+        .map_err(|e| e.to_string())?;
+    notify_change(&app, &db, DataChange::ThreadCreated(id));
+    Ok(Thread {
+        id,
+        title,
+        created_at: chrono::Utc::now().to_rfc3339(), // Approximate return
+        system_prompt,
+        is_archived: false,
+        server_profile_id: None,
+        think: None,
+        model_options: None,
+        needs_attention: false,
+        summary: None,
+        resend_thinking: false,
+        save_thinking: None,
+        max_history_messages: None,
+    })
+}
+
+#[tauri::command]
+fn update_thread_system_prompt(
+    app: AppHandle,
+    state: State<AppState>,
+    thread_id: i64,
+    system_prompt: Option<String>,
+    preset_id: Option<i64>,
+) -> Result<(), String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    let system_prompt = resolve_system_prompt(&db, system_prompt, preset_id)?;
+    db.set_thread_system_prompt(thread_id, system_prompt)
+        .map_err(|e| e.to_string())?;
+    notify_change(&app, &db, DataChange::ThreadUpdated(thread_id));
+    Ok(())
+}
+
+#[tauri::command]
+fn create_preset(
+    state: State<AppState>,
+    name: String,
+    content: String,
+) -> Result<PromptPreset, String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    let id = db
+        .create_preset(&name, &content)
+        .map_err(|e| e.to_string())?;
+    db.get_preset(id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn update_preset(
+    state: State<AppState>,
+    preset_id: i64,
+    name: String,
+    content: String,
+) -> Result<(), String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.update_preset(preset_id, &name, &content)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn delete_preset(state: State<AppState>, preset_id: i64) -> Result<(), String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.delete_preset(preset_id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn list_presets(state: State<AppState>) -> Result<Vec<PromptPreset>, String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.get_presets().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_threads(state: State<AppState>) -> Result<Vec<Thread>, String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.get_threads().map_err(|e| e.to_string())
+}
+
+/// Threads whose last generation was cut off by the app closing or a failed
+/// stream, flagged at startup
+#[tauri::command]
+fn get_interrupted_threads(state: State<AppState>) -> Result<Vec<Thread>, String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.get_interrupted_threads().map_err(|e| e.to_string())
+}
+
+/// Threads with replies from `model`; "unknown" finds replies saved without one.
+#[tauri::command]
+fn get_threads_by_model(state: State<AppState>, model: String) -> Result<Vec<Thread>, String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.get_threads_by_model(&model).map_err(|e| e.to_string())
+}
+
+/// Each model that replied in a thread, with how many threads it replied in.
+#[tauri::command]
+fn get_models_used(state: State<AppState>) -> Result<Vec<(String, i64)>, String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.get_models_used().map_err(|e| e.to_string())
+}
+
+/// Messages and tokens per day over the last `days` days, today included, for
+/// an activity heatmap.
+#[tauri::command]
+fn get_activity_stats(state: State<AppState>, days: u32) -> Result<Vec<DailyActivity>, String> {
+    let last = chrono::Local::now().date_naive();
+    let first = days
+        .checked_sub(1)
+        .and_then(|back| last.checked_sub_days(chrono::Days::new(back.into())))
+        .ok_or("days must be at least 1")?;
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.activity_stats(first, last).map_err(|e| e.to_string())
+}
+
+/// Usage and speed of each model that replied, most used first.
+#[tauri::command]
+fn get_model_stats(state: State<AppState>) -> Result<Vec<ModelStats>, String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.model_stats().map_err(|e| e.to_string())
+}
+
+/// Most points `get_latency_history` returns, whatever the UI asks for.
+const MAX_LATENCY_HISTORY: usize = 500;
+
+/// The latency of a model's last `limit` replies, oldest first, to show
+/// whether it has been getting slower.
+#[tauri::command]
+fn get_latency_history(
+    state: State<AppState>,
+    model: String,
+    limit: usize,
+) -> Result<Vec<LatencyPoint>, String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.latency_history(&model, limit.min(MAX_LATENCY_HISTORY))
+        .map_err(|e| e.to_string())
+}
+
+/// All messages of a thread, or only those with `role` ("system", "user",
+/// "assistant", "tool" or "note") when given.
+#[tauri::command]
+fn get_messages(
+    state: State<AppState>,
+    thread_id: i64,
+    role: Option<String>,
+) -> Result<Vec<Message>, String> {
+    let role = role.map(|r| r.parse::<Role>()).transpose()?;
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    let mut messages = match role {
+        Some(role) => db.get_messages_by_role(thread_id, role),
+        None => db.get_messages(thread_id),
+    }
+    .map_err(|e| e.to_string())?;
+
+    for message in &mut messages {
+        show_thumbnails(&db, message)?;
+    }
+    Ok(messages)
+}
+
+/// Puts a message's thumbnails in place of its images, the way the UI lists
+/// them; `get_full_image` loads an image at full size.
+fn show_thumbnails(db: &Database, message: &mut Message) -> Result<(), String> {
+    let Some(ref full) = message.images else {
+        return Ok(());
+    };
+    let thumbnails = match message.image_thumbnails.take() {
+        Some(thumbnails) if thumbnails.len() == full.len() => thumbnails,
+        // Messages saved before thumbnails existed get them on first access
+        _ => {
+            let thumbnails = images::make_thumbnails(full);
+            if !db.is_read_only() {
+                db.set_message_image_thumbnails(message.id, &thumbnails)
+                    .map_err(|e| e.to_string())?;
+            }
+            thumbnails
+        }
+    };
+    message.images = Some(thumbnails);
+    Ok(())
+}
+
+/// An image attached to a message at full size, by its position among the
+/// message's images.
+#[tauri::command]
+fn get_full_image(state: State<AppState>, message_id: i64, index: usize) -> Result<String, String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.get_message(message_id)
+        .map_err(|e| e.to_string())?
+        .images
+        .and_then(|images| images.into_iter().nth(index))
+        .ok_or_else(|| "Image not found".to_string())
+}
+
+async fn generate_response_stream(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    thread_id: i64,
+    model: String,
+    response_format: Option<serde_json::Value>,
+    think: Option<bool>,
+    num_predict: Option<i64>,
+) -> Result<(), String> {
+    let result = stream_response(
+        &app,
+        &state,
+        thread_id,
+        model,
+        response_format,
+        think,
+        num_predict,
+        ReplyTarget::New,
+    )
+    .await;
+    report_stream_error(&app, thread_id, result)
+}
+
+fn report_stream_error(
+    app: &AppHandle,
+    thread_id: i64,
+    result: Result<(), String>,
+) -> Result<(), String> {
+    // Without this the UI would wait for a stream-done that never comes
+    if let Err(ref error) = result {
+        let _ = app.emit(
+            "stream-error",
+            StreamErrorEvent {
+                thread_id,
+                error: error.clone(),
+            },
+        );
+    }
+    result
+}
+
+/// The messages sent as context for the next reply in a thread: its system
+/// prompt with variables filled in, the summary of older messages when summary
+/// memory is on, and the messages the summary doesn't cover. Also returns the
+/// ids of those messages and whether summary memory is on.
+fn thread_history(
+    db: &Database,
+    thread_id: i64,
+    answering: Option<i64>,
+) -> Result<(Vec<OllamaMessage>, Vec<i64>, bool), String> {
+    let system_prompt = db
+        .get_thread_system_prompt(thread_id)
+        .map_err(|e| e.to_string())?;
+    // Variables are filled in for this request only; the stored prompt keeps them
+    let system_prompt = match system_prompt {
+        Some(prompt) if prompt.contains("{{") => {
+            let mut vars = prompt_vars::builtin_vars(&chrono::Local::now());
+            for (key, value) in db
+                .get_settings_with_prefix(prompt_vars::SETTINGS_PREFIX)
+                .map_err(|e| e.to_string())?
+            {
+                vars.insert(key[prompt_vars::SETTINGS_PREFIX.len()..].to_string(), value);
+            }
+            Some(prompt_vars::substitute(&prompt, &vars))
+        }
+        other => other,
+    };
+    let mut messages = db.get_messages(thread_id).map_err(|e| e.to_string())?;
+    // Messages queued behind this one are answered in their own turn
+    if let Some(answering) = answering {
+        let end = messages
+            .iter()
+            .position(|m| m.id == answering)
+            .ok_or("The message to answer is no longer in the thread")?;
+        messages.truncate(end + 1);
+    }
+
+    let summary_memory = db
+        .get_setting("summary_memory")
+        .map_err(|e| e.to_string())?
+        .is_some_and(|v| v == "true");
+    let summary = if summary_memory {
+        db.get_thread_summary(thread_id)
+            .map_err(|e| e.to_string())?
+    } else {
+        None
+    };
+    // Messages the summary already covers are replaced by it
+    if let Some(ref summary) = summary {
+        messages.drain(..covered_until(&messages, summary.covered_until_id));
+    }
+    // A model can't make sense of a role it doesn't know either, a
+    // superseded answer has been replaced by another, and excluded messages
+    // were taken out of the context on purpose
+    messages.retain(|m| m.role != Role::Unknown && !m.is_superseded && !m.exclude_from_context);
+    let thread = db.get_thread(thread_id).map_err(|e| e.to_string())?;
+    // A capped thread only ever sends its newest messages, before any trimming
+    if let Some(max) = thread.max_history_messages {
+        messages = context::keep_newest(messages, max as usize);
+    }
+    let message_ids: Vec<i64> = messages.iter().map(|m| m.id).collect();
+
+    let mut ollama_messages = Vec::new();
+
+    if let Some(prompt) = system_prompt {
+        if !prompt.is_empty() {
+            ollama_messages.push(OllamaMessage {
+                role: Role::System,
+                content: prompt,
+                images: None,
+                thinking: None,
+                tool_calls: None,
+                tool_name: None,
+            });
+        }
+    }
+    if let Some(summary) = summary {
+        ollama_messages.push(context::summary_message(&summary.summary));
+    }
+
+    ollama_messages.extend(
+        messages
+            .into_iter()
+            .map(|m| context::history_message(m, thread.resend_thinking)),
+    );
+
+    Ok((ollama_messages, message_ids, summary_memory))
+}
+
+/// The messages a reply is generated from, trimmed to fit the model.
+struct PreparedContext {
+    /// The model name with any alias resolved
+    model: String,
+    messages: Vec<OllamaMessage>,
+    /// Stored messages left out to fit the budget, oldest first
+    dropped_ids: Vec<i64>,
+    estimated_tokens: usize,
+    /// Estimated size of everything, before trimming
+    total_tokens: usize,
+    budget: Option<usize>,
+    summary_memory: bool,
+}
+
+/// Assembles what `model` is sent for the thread's next reply: system prompt,
+/// running summary and stored messages, then `draft` as the next user message
+/// if given, trimmed to the context budget. The answer a `VariantOf` target
+/// replaces is left out so it can be asked again, and an `Answer` target only
+/// sees the thread up to the message it answers. Used for both generating and
+/// previewing, so the preview shows exactly what is sent.
+async fn prepare_context(
+    state: &AppState,
+    thread_id: i64,
+    model: &str,
+    target: ReplyTarget,
+    draft: Option<OllamaMessage>,
+) -> Result<PreparedContext, String> {
+    let (mut history, mut message_ids, summary_memory, model) = {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        // Regenerate and edit pass the model straight from the picker
+        let model = db.resolve_model_name(model).map_err(|e| e.to_string())?;
+        let answering = match target {
+            ReplyTarget::Answer(message_id) => Some(message_id),
+            _ => None,
+        };
+        let (history, message_ids, summary_memory) = thread_history(&db, thread_id, answering)?;
+        (history, message_ids, summary_memory, model)
+    };
+    // The answer being replaced is asked again, so the model mustn't see it
+    if let ReplyTarget::VariantOf(variant_of) = target {
+        if message_ids.last() != Some(&variant_of) {
+            return Err("Only the thread's last answer can be regenerated".to_string());
+        }
+        message_ids.pop();
+        history.pop();
+    }
+    // The system prompt and summary come before the stored messages
+    let stored_from = history.len() - message_ids.len();
+    history.extend(draft);
+
+    // Long threads would overflow the context window, and Ollama would then cut
+    // from the front, system prompt included
+    let backend = state.backend_for_thread(thread_id)?;
+    let budget = state.context_budget(backend.as_ref(), &model).await?;
+    let total_tokens: usize = history.iter().map(context::estimate_tokens).sum();
+    let (messages, dropped_ids, estimated_tokens) = match budget {
+        Some(budget) => {
+            let trimmed = context::trim_to_budget(history, budget);
+            // Neither the system messages nor the draft are ever dropped
+            let dropped_ids = trimmed
+                .dropped_indices
+                .iter()
+                .map(|&i| message_ids[i - stored_from])
+                .collect();
+            (trimmed.messages, dropped_ids, trimmed.estimated_tokens)
+        }
+        None => (history, Vec::new(), total_tokens),
+    };
+
+    Ok(PreparedContext {
+        model,
+        messages,
+        dropped_ids,
+        estimated_tokens,
+        total_tokens,
+        budget,
+        summary_memory,
+    })
+}
+
+#[derive(Serialize)]
+struct PreviewMessage {
+    #[serde(flatten)]
+    message: OllamaMessage,
+    estimated_tokens: usize,
+}
+
+#[derive(Serialize)]
+struct ContextPreview {
+    model: String,
+    messages: Vec<PreviewMessage>,
+    estimated_tokens: usize,
+    budget: Option<usize>,
+    /// Oldest stored messages left out to fit the budget
+    dropped_messages: usize,
+}
+
+/// Shows the messages that would be sent to `model` for the thread's next
+/// reply, with `draft_content` as the next user message, without sending them.
+/// Attachments of the draft aren't included, as they're only read on send.
+#[tauri::command]
+async fn preview_context(
+    state: State<'_, AppState>,
+    thread_id: i64,
+    model: String,
+    draft_content: Option<String>,
+) -> Result<ContextPreview, String> {
+    let draft = draft_content
+        .filter(|content| !content.trim().is_empty())
+        .map(|content| OllamaMessage {
+            role: Role::User,
+            content,
+            images: None,
+            thinking: None,
+            tool_calls: None,
+            tool_name: None,
+        });
+    let prepared = prepare_context(&state, thread_id, &model, ReplyTarget::New, draft).await?;
+
+    Ok(ContextPreview {
+        model: prepared.model,
+        messages: prepared
+            .messages
+            .into_iter()
+            .map(|message| PreviewMessage {
+                estimated_tokens: context::estimate_tokens(&message),
+                message,
+            })
+            .collect(),
+        estimated_tokens: prepared.estimated_tokens,
+        budget: prepared.budget,
+        dropped_messages: prepared.dropped_ids.len(),
+    })
+}
+
+/// Where a streamed reply is saved.
+#[derive(Clone, Copy, PartialEq)]
+enum ReplyTarget {
+    /// A new assistant message
+    New,
+    /// A new assistant message answering this user message, which may have
+    /// been queued behind others; later messages are left out of its history
+    Answer(i64),
+    /// Appended to this assistant message, which must be the thread's last;
+    /// the model picks up where it left off
+    Continue(i64),
+    /// A new assistant message in place of this one, the thread's last, which
+    /// is kept as a superseded variant and left out of the model's history
+    VariantOf(i64),
+}
+
+/// Streams the next reply in a thread and saves it where `target` says.
+#[allow(clippy::too_many_arguments)]
+async fn stream_response(
+    app: &AppHandle,
+    state: &AppState,
+    thread_id: i64,
+    model: String,
+    response_format: Option<serde_json::Value>,
+    think: Option<bool>,
+    num_predict: Option<i64>,
+    target: ReplyTarget,
+) -> Result<(), String> {
+    let continuing = match target {
+        ReplyTarget::Continue(message_id) => Some(message_id),
+        _ => None,
+    };
+    let variant_of = match target {
+        ReplyTarget::VariantOf(message_id) => Some(message_id),
+        _ => None,
+    };
+    let answering = match target {
+        ReplyTarget::Answer(message_id) => Some(message_id),
+        _ => None,
+    };
+    // A new reply is dated from here, so messages sent while it streams come after it
+    let started_at = chrono::Utc::now();
+
+    // 1. Prepare context (fetch recent messages)
+    let prepared = prepare_context(state, thread_id, &model, target, None).await?;
+    let model = prepared.model;
+    if let (Some(budget), Some(&covered_until_id)) = (prepared.budget, prepared.dropped_ids.last())
+    {
+        if prepared.summary_memory {
+            let app_handle = app.clone();
+            let model = model.clone();
+            tauri::async_runtime::spawn(async move {
+                if let Err(e) =
+                    summarize_history(app_handle, thread_id, model, covered_until_id).await
+                {
+                    eprintln!("Failed to summarize thread {}: {}", thread_id, e);
+                }
+            });
+        }
+        let _ = app.emit(
+            "context-trimmed",
+            ContextTrimmedEvent {
+                thread_id,
+                dropped_messages: prepared.dropped_ids.len(),
+                estimated_tokens: prepared.estimated_tokens,
+                budget,
+            },
+        );
+    }
+    let history = prepared.messages;
+    let backend = state.backend_for_thread(thread_id)?;
+
+    let tools = {
+        let thread_tools = state
+            .thread_tools
+            .lock()
+            .map_err(|_| "Failed to lock tools")?;
+        thread_tools.get(&thread_id).cloned()
+    };
+
+    let (options, flush_interval, save_thinking) = {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        let flush_interval = db
+            .get_setting("stream_flush_interval_ms")
+            .map_err(|e| e.to_string())?
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(stream_buffer::DEFAULT_FLUSH_INTERVAL_MS);
+        let thread = db.get_thread(thread_id).map_err(|e| e.to_string())?;
+        let mut model_options = thread.model_options.unwrap_or_default();
+        if num_predict.is_some() {
+            model_options.num_predict = num_predict;
+        }
+        let options = ChatOptions {
+            keep_alive: db.get_setting("keep_alive").map_err(|e| e.to_string())?,
+            format: response_format.clone(),
+            tools,
+            think: think.or(thread.think),
+            model_options: Some(model_options).filter(|o| !o.is_empty()),
+        };
+        // Reasoning is always streamed, but only stored when wanted
+        let save_thinking = match thread.save_thinking {
+            Some(save) => save,
+            None => db
+                .get_setting("save_thinking")
+                .map_err(|e| e.to_string())?
+                .map_or(true, |v| v != "false"),
+        };
+        (
+            options,
+            std::time::Duration::from_millis(flush_interval),
+            save_thinking,
+        )
+    };
+
+    // 2. Call Ollama and stream
+    let app_handle_clone = app.clone();
+    let app_handle_retry = app.clone();
+    let max_attempts = backend.retry_policy().max_attempts;
+    let cancel = state.busy_threads.cancel_signal(thread_id);
+    let generation_options = options.model_options.clone();
+    // Content and thinking received so far, kept if the stream fails
+    let received = Arc::new(Mutex::new((String::new(), String::new())));
+    let received_clone = Arc::clone(&received);
+    // Tokens are emitted in batches; see stream_buffer
+    let coalescer = Arc::new(Mutex::new(ChunkCoalescer::new(flush_interval)));
+    let coalescer_clone = Arc::clone(&coalescer);
+    let completion = backend
+        .chat(
+            &model,
+            history,
+            options,
+            cancel,
+            Box::new(move |chunk| {
+                if let Ok(mut received) = received_clone.lock() {
+                    match chunk {
+                        StreamChunk::Content(ref text) => received.0.push_str(text),
+                        StreamChunk::Thinking(ref text) => received.1.push_str(text),
+                        StreamChunk::Metrics(_) => {}
+                    }
+                }
+                let due = match coalescer_clone.lock() {
+                    Ok(mut coalescer) => coalescer.push(chunk),
+                    Err(_) => vec![chunk],
+                };
+                for chunk in due {
+                    emit_stream_chunk(&app_handle_clone, thread_id, target, chunk);
+                }
+            }),
+            Box::new(move |attempt| {
+                let _ = app_handle_retry.emit(
+                    "stream-retrying",
+                    StreamRetryingEvent {
+                        thread_id,
+                        attempt,
+                        max_attempts,
+                    },
+                );
+            }),
+        )
+        .await;
+
+    // The rest of the text goes out before the done or error event
+    let remaining = coalescer
+        .lock()
+        .map(|mut coalescer| coalescer.flush())
+        .unwrap_or_default();
+    for chunk in remaining {
+        emit_stream_chunk(app, thread_id, target, chunk);
+    }
+
+    let completion = match completion {
+        Ok(completion) => completion,
+        Err(e) => {
+            // Keep whatever arrived before the stream broke off
+            let (partial, thinking) = received.lock().map(|r| r.clone()).unwrap_or_default();
+            let cancelled = matches!(
+                e.downcast_ref::<OllamaError>(),
+                Some(OllamaError::Cancelled { .. })
+            );
+            let message_id = if partial.is_empty() {
+                continuing
+            } else {
+                let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+                let message_id = match continuing {
+                    Some(message_id) => {
+                        db.append_to_message(message_id, &partial)
+                            .map_err(|e| e.to_string())?;
+                        message_id
+                    }
+                    None => {
+                        let discarded = !save_thinking && !thinking.is_empty();
+                        let created_at = reply_created_at(&db, thread_id, answering, started_at)?;
+                        let message_id = db
+                            .add_message(
+                                thread_id,
+                                Role::Assistant,
+                                &partial,
+                                None,
+                                Some(model),
+                                None,
+                                Some(thinking).filter(|t| save_thinking && !t.is_empty()),
+                            )
+                            .map_err(|e| e.to_string())?;
+                        db.set_message_created_at(message_id, &created_at)
+                            .map_err(|e| e.to_string())?;
+                        if discarded {
+                            db.set_message_thinking_discarded(message_id)
+                                .map_err(|e| e.to_string())?;
+                        }
+                        if let Some(variant_of) = variant_of {
+                            db.supersede_message(variant_of, message_id)
+                                .map_err(|e| e.to_string())?;
+                        }
+                        message_id
+                    }
+                };
+                // Flagged so the UI can offer to regenerate it; the caller
+                // emits stream-error once it's saved
+                if !cancelled {
+                    db.set_message_incomplete(message_id)
+                        .map_err(|e| e.to_string())?;
+                }
+                notify_reply_saved(app, &db, message_id, continuing, variant_of);
+                Some(message_id)
+            };
+            // Stopping on purpose isn't a failure
+            if cancelled {
+                let _ = app.emit(
+                    "stream-done",
+                    StreamDoneEvent {
+                        thread_id,
+                        message_id,
+                        cancelled: true,
+                        variant_of,
+                    },
+                );
+                return Ok(());
+            }
+            return Err(e.to_string());
+        }
+    };
+
+    // 3. Save AI message
+    let (message_id, needs_title) = {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        let message_id = match continuing {
+            Some(message_id) => {
+                db.append_to_message(message_id, &completion.content)
+                    .map_err(|e| e.to_string())?;
+                message_id
+            }
+            None => {
+                let created_at = reply_created_at(&db, thread_id, answering, started_at)?;
+                let message_id = db
+                    .add_message(
+                        thread_id,
+                        Role::Assistant,
+                        &completion.content,
+                        None,
+                        Some(model.clone()),
+                        None,
+                        Some(completion.thinking.clone())
+                            .filter(|t| save_thinking && !t.is_empty()),
+                    )
+                    .map_err(|e| e.to_string())?;
+                db.set_message_created_at(message_id, &created_at)
+                    .map_err(|e| e.to_string())?;
+                if !save_thinking && !completion.thinking.is_empty() {
+                    db.set_message_thinking_discarded(message_id)
+                        .map_err(|e| e.to_string())?;
+                }
+                if let Some(variant_of) = variant_of {
+                    db.supersede_message(variant_of, message_id)
+                        .map_err(|e| e.to_string())?;
+                }
+                message_id
+            }
+        };
+
+        if completion.stats.is_some() || completion.first_token_ms.is_some() {
+            let stats = completion.stats.clone().unwrap_or_default();
+            let metrics = MessageMetrics {
+                total_duration: stats.total_duration,
+                load_duration: stats.load_duration,
+                prompt_eval_count: stats.prompt_eval_count,
+                eval_count: stats.eval_count,
+                eval_duration: stats.eval_duration,
+                tokens_per_second: stats.tokens_per_second(),
+                first_token_ms: completion.first_token_ms,
+            };
+            db.set_message_metrics(message_id, &metrics)
+                .map_err(|e| e.to_string())?;
+        }
+
+        if let Some(ref done_reason) = completion.done_reason {
+            db.set_message_done_reason(message_id, done_reason)
+                .map_err(|e| e.to_string())?;
+        }
+
+        if let Some(ref generation_options) = generation_options {
+            db.set_message_generation_options(message_id, generation_options)
+                .map_err(|e| e.to_string())?;
+        }
+
+        if !completion.tool_calls.is_empty() {
+            let tool_calls =
+                serde_json::to_value(&completion.tool_calls).map_err(|e| e.to_string())?;
+            db.set_message_tool_calls(message_id, &tool_calls)
+                .map_err(|e| e.to_string())?;
+            let _ = app.emit(
+                "tool-call-requested",
+                ToolCallRequestedEvent {
+                    thread_id,
+                    message_id,
+                    tool_calls: completion.tool_calls.clone(),
+                },
+            );
+        }
+
+        // Remember the requested format so the UI can render the reply as a code block
+        if let Some(format) = response_format {
+            let format = match format {
+                serde_json::Value::String(s) => s,
+                schema => schema.to_string(),
+            };
+            db.set_message_response_format(message_id, &format)
+                .map_err(|e| e.to_string())?;
+        }
+
+        notify_reply_saved(app, &db, message_id, continuing, variant_of);
+
+        let auto_title = db
+            .get_setting("auto_title")
+            .map_err(|e| e.to_string())?
+            .map_or(true, |v| v != "false");
+        let thread = db.get_thread(thread_id).map_err(|e| e.to_string())?;
+        let assistant_count = db
+            .get_messages(thread_id)
+            .map_err(|e| e.to_string())?
+            .iter()
+            .filter(|m| m.role == Role::Assistant)
+            .count();
+
+        (
+            message_id,
+            auto_title
+                && matches!(target, ReplyTarget::New | ReplyTarget::Answer(_))
+                && assistant_count == 1
+                && is_placeholder_title(&thread.title),
+        )
+    };
+
+    // Emit done event
+    let _ = app.emit(
+        "stream-done",
+        StreamDoneEvent {
+            thread_id,
+            message_id: Some(message_id),
+            cancelled: false,
+            variant_of,
+        },
+    );
+
+    if needs_title {
+        let app_handle = app.clone();
+        tauri::async_runtime::spawn(async move {
+            // Failures keep the placeholder title
+            let _ = auto_title_thread(app_handle, thread_id, model).await;
+        });
+    }
+
+    Ok(())
+}
+
+/// When a new reply is dated: when its generation started. A queued message's
+/// reply starts after the messages sent behind it, so it goes just before the
+/// first of those instead, keeping each reply right after its question.
+fn reply_created_at(
+    db: &Database,
+    thread_id: i64,
+    answering: Option<i64>,
+    started_at: chrono::DateTime<chrono::Utc>,
+) -> Result<String, String> {
+    let mut created_at = started_at;
+    if let Some(answering) = answering {
+        let messages = db.get_messages(thread_id).map_err(|e| e.to_string())?;
+        let next = messages
+            .iter()
+            .skip_while(|m| m.id != answering)
+            .nth(1)
+            .and_then(|m| chrono::DateTime::parse_from_rfc3339(&m.created_at).ok());
+        if let Some(next) = next {
+            created_at = created_at
+                .min(next.with_timezone(&chrono::Utc) - chrono::Duration::microseconds(1));
+        }
+    }
+    Ok(created_at.to_rfc3339())
+}
+
+fn is_placeholder_title(title: &str) -> bool {
+    let title = title.trim();
+    title.is_empty()
+        || title
+            .to_lowercase()
+            .starts_with(&DEFAULT_THREAD_TITLE_PREFIX.to_lowercase())
+}
+
+/// The text after any reasoning block a model wrote into its reply.
+fn strip_reasoning(response: &str) -> &str {
+    match response.rfind("</think>") {
+        Some(idx) => &response[idx + "</think>".len()..],
+        None => response,
+    }
+}
+
+/// Asks `model` for a title based on the first `exchanges` user/assistant
+/// exchanges of the thread.
+async fn generate_title(
+    state: &AppState,
+    thread_id: i64,
+    model: &str,
+    exchanges: usize,
+) -> Result<String, String> {
+    let transcript = {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        db.get_messages(thread_id)
+            .map_err(|e| e.to_string())?
+            .iter()
+            .filter(|m| matches!(m.role, Role::User | Role::Assistant))
+            .take(exchanges * 2)
+            .map(|m| format!("{}: {}", m.role, m.content))
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    };
+    if transcript.is_empty() {
+        return Err("The thread has no messages to title".to_string());
+    }
+
+    let messages = vec![
+        OllamaMessage {
+            role: Role::System,
+            content: TITLE_PROMPT.to_string(),
+            images: None,
+            thinking: None,
+            tool_calls: None,
+            tool_name: None,
+        },
+        OllamaMessage {
+            role: Role::User,
+            content: transcript,
+            images: None,
+            thinking: None,
+            tool_calls: None,
+            tool_name: None,
+        },
+    ];
+    let response = state
+        .backend_for_thread(thread_id)?
+        .chat(
+            model,
+            messages,
+            ChatOptions::default(),
+            None,
+            Box::new(|_| {}),
+            Box::new(|_| {}),
+        )
+        .await
+        .map_err(|e| e.to_string())?
+        .content;
+
+    // Keep the first non-empty line
+    let title = strip_reasoning(&response)
+        .lines()
+        .map(|line| {
+            line.trim().trim_matches(|c: char| {
+                // Quotes, including curly ones, Markdown emphasis and trailing punctuation
+                "\"'`*#.,:;!?\u{201C}\u{201D}\u{2018}\u{2019}".contains(c)
+            })
+        })
+        .find(|line| !line.is_empty())
+        .ok_or("Model returned an empty title")?;
+
+    Ok(title
+        .split_whitespace()
+        .take(6)
+        .collect::<Vec<_>>()
+        .join(" "))
+}
+
+/// How many of a thread's messages, in thread order, a summary covering up to
+/// and including `covered_until_id` stands in for.
+fn covered_until(messages: &[Message], covered_until_id: i64) -> usize {
+    messages
+        .iter()
+        .position(|m| m.id == covered_until_id)
+        .map_or(0, |i| i + 1)
+}
+
+/// Folds the messages up to `covered_until_id` into the thread's running summary,
+/// so they can be left out of later requests without being forgotten.
+async fn summarize_history(
+    app: AppHandle,
+    thread_id: i64,
+    model: String,
+    covered_until_id: i64,
+) -> Result<(), String> {
+    let state = app.state::<AppState>();
+    {
+        let mut summarizing = state
+            .summarizing
+            .lock()
+            .map_err(|_| "Failed to lock summaries")?;
+        // One at a time per thread; the next trim picks up whatever this one missed
+        if !summarizing.insert(thread_id) {
+            return Ok(());
+        }
+    }
+
+    let result: Result<(), String> = async {
+        let (previous, messages) = {
+            let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+            let previous = db
+                .get_thread_summary(thread_id)
+                .map_err(|e| e.to_string())?;
+            let mut messages = db.get_messages(thread_id).map_err(|e| e.to_string())?;
+            messages.truncate(covered_until(&messages, covered_until_id));
+            if let Some(ref previous) = previous {
+                messages.drain(..covered_until(&messages, previous.covered_until_id));
+            }
+            let messages: Vec<OllamaMessage> = messages
+                .into_iter()
+                .filter(|m| m.role != Role::Unknown)
+                .map(|m| OllamaMessage {
+                    role: m.role,
+                    content: context::strip_thinking(&m.content),
+                    images: None,
+                    thinking: None,
+                    tool_calls: None,
+                    tool_name: None,
+                })
+                .collect();
+            (previous.map(|s| s.summary), messages)
+        };
+        if messages.is_empty() {
+            return Ok(());
+        }
+
+        let completion = state
+            .backend_for_thread(thread_id)?
+            .chat(
+                &model,
+                context::summarization_request(previous.as_deref(), &messages),
+                ChatOptions::default(),
+                None,
+                Box::new(|_| {}),
+                Box::new(|_| {}),
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        let summary = completion.content.trim();
+        if summary.is_empty() {
+            return Err("Model returned an empty summary".to_string());
+        }
+
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        db.save_thread_summary(thread_id, summary, covered_until_id)
+            .map_err(|e| e.to_string())
+    }
+    .await;
+
+    if let Ok(mut summarizing) = state.summarizing.lock() {
+        summarizing.remove(&thread_id);
+    }
+    result
+}
+
+/// Writes a structured TL;DR of a thread with `model`, saves it on the thread
+/// in place of any earlier one and returns it. The oldest messages are left out
+/// when the conversation doesn't fit the model's context.
+#[tauri::command]
+async fn summarize_thread(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    thread_id: i64,
+    model: String,
+) -> Result<String, String> {
+    let (model, messages) = {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        let model = db.resolve_model_name(&model).map_err(|e| e.to_string())?;
+        let messages: Vec<OllamaMessage> = db
+            .get_messages(thread_id)
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .filter(|m| matches!(m.role, Role::User | Role::Assistant))
+            .map(|m| OllamaMessage {
+                role: m.role,
+                content: context::strip_thinking(&m.content),
+                images: None,
+                thinking: None,
+                tool_calls: None,
+                tool_name: None,
+            })
+            .collect();
+        (model, messages)
+    };
+    if messages.is_empty() {
+        return Err("The thread has no messages to summarize".to_string());
+    }
+
+    let backend = state.backend_for_thread(thread_id)?;
+    let messages = match state.context_budget(backend.as_ref(), &model).await? {
+        Some(budget) => context::trim_to_budget(messages, budget).messages,
+        None => messages,
+    };
+    let completion = backend
+        .chat(
+            &model,
+            context::thread_summary_request(&messages),
+            ChatOptions::default(),
+            None,
+            Box::new(|_| {}),
+            Box::new(|_| {}),
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+    let summary = strip_reasoning(&completion.content).trim();
+    if summary.is_empty() {
+        return Err("Model returned an empty summary".to_string());
+    }
+
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.set_thread_summary(thread_id, summary)
+        .map_err(|e| e.to_string())?;
+    notify_change(&app, &db, DataChange::ThreadUpdated(thread_id));
+    Ok(summary.to_string())
+}
+
+async fn auto_title_thread(app: AppHandle, thread_id: i64, model: String) -> Result<(), String> {
+    let state = app.state::<AppState>();
+    let title = generate_title(&state, thread_id, &model, 1).await?;
+
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    thread_events::rename_thread(&app, &db, thread_id, &title)?;
+    notify_change(&app, &db, DataChange::ThreadUpdated(thread_id));
+    Ok(())
+}
+
+#[derive(Clone, Serialize)]
+struct AttachmentErrorEvent {
+    thread_id: i64,
+    /// "document", "image" or "url"
+    kind: &'static str,
+    /// 1-based, among the attachments of the same kind
+    index: usize,
+    name: String,
+    reason: String,
+}
+
+/// Tells the UI an attachment was left out of a message, and keeps the reason
+/// in `errors` in case nothing at all can be sent.
+fn report_attachment_error(app: &AppHandle, errors: &mut Vec<String>, event: AttachmentErrorEvent) {
+    eprintln!("Failed to read {}: {}", event.name, event.reason);
+    errors.push(format!("{}: {}", event.name, event.reason));
+    let _ = app.emit("attachment-error", event);
+}
+
+#[derive(Clone, Serialize)]
+struct PdfOcrProgressEvent {
+    thread_id: i64,
+    /// 1-based, in the order the PDFs were attached
+    attachment: usize,
+    #[serde(flatten)]
+    progress: OcrProgress,
+}
+
+/// Key a document's text is recorded under in a thread: its content hash, and
+/// the pages read if not all of them.
+fn document_key(hash: &str, range: Option<PageRange>) -> String {
+    match range {
+        Some(range) => format!("{}:{}-{}", hash, range.start_page, range.end_page),
+        None => hash.to_string(),
+    }
+}
+
+/// Whether a document's text is already in a message of the thread, so it
+/// needn't be sent again.
+fn already_in_thread(state: &AppState, thread_id: i64, key: &str) -> Result<bool, String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    Ok(db
+        .find_thread_document(thread_id, key)
+        .map_err(|e| e.to_string())?
+        .is_some())
+}
+
+#[derive(Clone, Serialize)]
+struct PdfExtractProgressEvent {
+    thread_id: i64,
+    filename: String,
+    /// Pages read so far, out of the `total_pages` selected
+    page: u32,
+    total_pages: u32,
+}
+
+#[derive(Clone, Serialize)]
+struct PdfExtractDoneEvent {
+    thread_id: i64,
+    filename: String,
+    /// Length of the text added to the prompt
+    characters: usize,
+}
+
+fn join_pages(pages: &[u32]) -> String {
+    pages
+        .iter()
+        .map(|page| page.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// PDF limits from the `pdf_max_bytes`, `pdf_max_pages`, `pdf_max_chars` and
+/// `pdf_truncation` settings, falling back to the defaults for any that are unset.
+fn pdf_limits(db: &Database) -> Result<PdfLimits, String> {
+    let setting = |key: &str| -> Result<Option<u64>, String> {
+        Ok(db
+            .get_setting(key)
+            .map_err(|e| e.to_string())?
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|&v| v > 0))
+    };
+    let defaults = PdfLimits::default();
+    Ok(PdfLimits {
+        max_bytes: setting("pdf_max_bytes")?.map_or(defaults.max_bytes, |v| v as usize),
+        max_pages: setting("pdf_max_pages")?.map_or(defaults.max_pages, |v| v as u32),
+        max_chars: setting("pdf_max_chars")?.map_or(defaults.max_chars, |v| v as usize),
+        truncation: db
+            .get_setting("pdf_truncation")
+            .map_err(|e| e.to_string())?
+            .and_then(|v| pdf_utils::Truncation::from_setting(&v))
+            .unwrap_or(defaults.truncation),
+        layout: db
+            .get_setting("pdf_text_layout")
+            .map_err(|e| e.to_string())?
+            .and_then(|v| pdf_utils::TextLayout::from_setting(&v))
+            .unwrap_or(defaults.layout),
+    })
+}
+
+/// Message limits from the `max_message_chars`, `max_message_images` and
+/// `max_attachment_bytes` settings, falling back to the defaults for any that
+/// are unset.
+fn message_limits(db: &Database) -> Result<MessageLimits, String> {
+    let setting = |key: &str| -> Result<Option<usize>, String> {
+        Ok(db
+            .get_setting(key)
+            .map_err(|e| e.to_string())?
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|&v| v > 0))
+    };
+    let defaults = MessageLimits::default();
+    Ok(MessageLimits {
+        max_content_chars: setting("max_message_chars")?.unwrap_or(defaults.max_content_chars),
+        max_images: setting("max_message_images")?.unwrap_or(defaults.max_images),
+        max_attachment_bytes: setting("max_attachment_bytes")?
+            .unwrap_or(defaults.max_attachment_bytes),
+    })
+}
+
+/// The limits `send_message` enforces, so the UI can check a message first.
+#[tauri::command]
+fn get_limits(state: State<AppState>) -> Result<MessageLimits, String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    message_limits(&db)
+}
+
+/// Fetches a web page and returns its readable text.
+#[tauri::command]
+async fn fetch_url_content(url: String) -> Result<String, String> {
+    url_utils::fetch_page(&url_utils::web_client(), &url, url_utils::MAX_PAGE_BYTES)
+        .await
+        .map(|page| page.text)
+        .map_err(|e| format!("{} could not be read: {}", url, e))
+}
+
+/// Cap on the document cache unless the `document_cache_max_bytes` setting says
+/// otherwise.
+const DEFAULT_DOCUMENT_CACHE_BYTES: usize = 100 * 1024 * 1024;
+
+fn document_cache_max_bytes(db: &Database) -> Result<usize, String> {
+    Ok(db
+        .get_setting("document_cache_max_bytes")
+        .map_err(|e| e.to_string())?
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_DOCUMENT_CACHE_BYTES))
+}
+
+/// Stores the pages read from a PDF. Failing to is only logged, as the text
+/// has been read either way.
+fn save_cached_document(
+    state: &AppState,
+    hash: &str,
+    pages: &ExtractedPages,
+) -> Result<(), String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    let max_bytes = document_cache_max_bytes(&db)?;
+    if let Err(e) = db.save_cached_document(hash, pages, max_bytes) {
+        eprintln!("Failed to cache PDF text: {}", e);
+    }
+    Ok(())
+}
+
+fn csv_preview_rows(db: &Database) -> Result<usize, String> {
+    Ok(db
+        .get_setting("csv_preview_rows")
+        .map_err(|e| e.to_string())?
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|&v| v > 0)
+        .unwrap_or(csv_utils::DEFAULT_PREVIEW_ROWS))
+}
+
+/// Removes all cached PDF text, returning how many documents were dropped.
+#[tauri::command]
+fn clear_document_cache(state: State<AppState>) -> Result<usize, String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.clear_document_cache().map_err(|e| e.to_string())
+}
+
+/// Reads a PDF attachment's title, author, page count and size so they can be
+/// shown before sending.
+#[tauri::command]
+fn inspect_pdf(base64: String) -> Result<PdfMetadata, String> {
+    // Remove data:application/pdf;base64, prefix if present
+    let clean_base64 = base64
+        .find(',')
+        .map_or(base64.as_str(), |idx| &base64[idx + 1..]);
+    let bytes = general_purpose::STANDARD
+        .decode(clean_base64.trim())
+        .map_err(|e| format!("Invalid base64: {}", e))?;
+    pdf_utils::extract_metadata(&bytes).map_err(|e| e.to_string())
+}
+
+#[derive(Serialize)]
+struct AttachmentEstimate {
+    /// The attachments' text plus the thread's history so far
+    estimated_tokens: usize,
+    /// `None` when the server doesn't report it
+    context_length: Option<u64>,
+    /// Whether it all fits in the context budget; `true` when that isn't known
+    fits: bool,
+}
+
+/// Estimates whether attachments would fit in `model`'s context before they're
+/// sent: reads them the way `send_message` would, taking PDF text from the
+/// document cache and adding what it reads there, and adds the thread's
+/// history. Images in PDFs aren't counted.
+#[tauri::command]
+async fn estimate_attachments(
+    state: State<'_, AppState>,
+    thread_id: i64,
+    pdfs: Option<Vec<Attachment>>,
+    files: Option<Vec<FileAttachment>>,
+    model: String,
+) -> Result<AttachmentEstimate, String> {
+    let (model, limits, csv_preview_rows, history) = {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        let model = db.resolve_model_name(&model).map_err(|e| e.to_string())?;
+        let (history, _, _) = thread_history(&db, thread_id, None)?;
+        (model, pdf_limits(&db)?, csv_preview_rows(&db)?, history)
+    };
+
+    let mut content = String::new();
+    // Repeats aren't sent, so they don't count
+    let mut seen = HashSet::new();
+    for (i, pdf) in pdfs.unwrap_or_default().into_iter().enumerate() {
+        let (name, pdf_base64) = pdf.into_parts();
+        let decoded =
+            pdf_utils::spawn_extraction(move || document_utils::decode_document(&pdf_base64))
+                .await?;
+        // Sending would leave it out
+        let Ok((hash, decoded)) = decoded else {
+            continue;
+        };
+        if !seen.insert(hash.clone()) || already_in_thread(&state, thread_id, &hash)? {
+            continue;
+        }
+        let bytes = match decoded {
+            DecodedDocument::Pdf(bytes) => bytes,
+            DecodedDocument::Docx(text) => {
+                content.push_str(&text.unwrap_or_default());
+                continue;
+            }
+        };
+
+        let cached = {
+            let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+            db.get_cached_document(&hash).map_err(|e| e.to_string())?
+        };
+        let cached_pages = cached
+            .as_ref()
+            .map(|cached| (cached.layout, cached.pages.len()));
+        let (extracted, cache) = pdf_utils::spawn_extraction(move || {
+            let mut cache = cached;
+            let extracted =
+                pdf_utils::extract_text_cached(&bytes, None, None, &limits, &mut cache, |_| {})
+                    .map_err(|e| {
+                        let needs_user = e.downcast_ref::<PdfLimitError>().is_some()
+                            || e.downcast_ref::<PdfError>().is_some();
+                        (needs_user, e.to_string())
+                    });
+            (extracted, cache)
+        })
+        .await?;
+        if let Some(cache) =
+            cache.filter(|cache| Some((cache.layout, cache.pages.len())) != cached_pages)
+        {
+            save_cached_document(&state, &hash, &cache)?;
+        }
+
+        match extracted {
+            Ok(pdf) => content.push_str(&pdf.text),
+            // Sending would fail the same way
+            Err((true, e)) => {
+                let name = name.unwrap_or_else(|| format!("PDF Attachment {}", i + 1));
+                return Err(format!("{}: {}", name, e));
+            }
+            Err((false, _)) => {}
+        }
+    }
+    for file in files.unwrap_or_default() {
+        let key = file_utils::content_hash(&file)?;
+        if !seen.insert(key.clone()) || already_in_thread(&state, thread_id, &key)? {
+            continue;
+        }
+        content.push_str(&file_utils::render_file(
+            &file,
+            limits.max_chars,
+            csv_preview_rows,
+        )?);
+    }
+
+    let estimated_tokens = history.iter().map(context::estimate_tokens).sum::<usize>()
+        + context::estimate_text_tokens(&content);
+    let backend = state.backend_for_thread(thread_id)?;
+    let context_length = state.context_length(backend.as_ref(), &model).await?;
+    let budget = state.context_budget(backend.as_ref(), &model).await?;
+    Ok(AttachmentEstimate {
+        estimated_tokens,
+        context_length,
+        fits: budget.is_none_or(|budget| estimated_tokens <= budget),
+    })
+}
+
+#[derive(Serialize)]
+struct SendEstimate {
+    /// The thread's context plus the draft, before any trimming
+    prompt_tokens: usize,
+    /// `None` when the server doesn't report it
+    context_length: Option<u64>,
+    /// What trimming fits the context into: the `context_budget` setting, or
+    /// the context window less room for the reply
+    budget: Option<usize>,
+    /// Tokens left in the budget; negative once it overflows and the oldest
+    /// messages will be left out
+    remaining: Option<i64>,
+    /// Oldest stored messages that will be left out to fit the budget
+    dropped_messages: usize,
+}
+
+/// Estimates how much of `model`'s context budget the thread's context and
+/// `draft_content` take up, for a context meter in the composer. Built from the
+/// same context and budget as generating, so the meter and the trimming agree.
+#[tauri::command]
+async fn estimate_send(
+    state: State<'_, AppState>,
+    thread_id: i64,
+    draft_content: Option<String>,
+    model: String,
+) -> Result<SendEstimate, String> {
+    let draft = draft_content
+        .filter(|content| !content.trim().is_empty())
+        .map(|content| OllamaMessage {
+            role: Role::User,
+            content,
+            images: None,
+            thinking: None,
+            tool_calls: None,
+            tool_name: None,
+        });
+    let prepared = prepare_context(&state, thread_id, &model, ReplyTarget::New, draft).await?;
+
+    // Looked up once per model and cached
+    let backend = state.backend_for_thread(thread_id)?;
+    let context_length = state
+        .context_length(backend.as_ref(), &prepared.model)
+        .await?;
+    Ok(SendEstimate {
+        prompt_tokens: prepared.total_tokens,
+        context_length,
+        budget: prepared.budget,
+        remaining: prepared
+            .budget
+            .map(|budget| budget as i64 - prepared.total_tokens as i64),
+        dropped_messages: prepared.dropped_ids.len(),
+    })
+}
+
+/// Opens the file dialog for images and returns the paths picked, which can
+/// then be passed to `send_message` as `image_paths`. No other path is read.
+#[tauri::command]
+async fn pick_image_files(
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<Vec<String>, String> {
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    app.dialog()
+        .file()
+        .add_filter("Images", images::SUPPORTED_EXTENSIONS)
+        .pick_files(move |files| {
+            let _ = sender.send(files);
+        });
+    let files = receiver
+        .await
+        .map_err(|e| e.to_string())?
+        .unwrap_or_default();
+
+    let mut picked = state
+        .picked_images
+        .lock()
+        .map_err(|_| "Failed to lock picked images")?;
+    let mut paths = Vec::new();
+    for file in files {
+        let path = file.into_path().map_err(|e| e.to_string())?;
+        // Kept resolved, the way `read_image_file` compares them
+        if let Ok(resolved) = path.canonicalize() {
+            picked.insert(resolved);
+        }
+        paths.push(path.to_string_lossy().into_owned());
+    }
+    Ok(paths)
+}
+
+/// Saves a user message and streams the reply. Returns the id of the saved
+/// message, or of the one first sent with the same `client_request_id`.
+#[tauri::command]
+async fn send_message(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    thread_id: i64,
+    mut content: String,
+    images: Option<Vec<Attachment>>,
+    // Paths returned by pick_image_files, read here instead of sent as base64
+    image_paths: Option<Vec<String>>,
+    pdfs: Option<Vec<Attachment>>,
+    model: String,
+    reply_to_id: Option<i64>,
+    response_format: Option<String>,
+    tools: Option<Vec<ToolDefinition>>,
+    think: Option<bool>,
+    num_predict: Option<i64>,
+    // Pages to read from each PDF, in the same order as `pdfs`; all pages when absent
+    pdf_page_ranges: Option<Vec<Option<PageRange>>>,
+    // Passwords of encrypted PDFs, in the same order as `pdfs`; only held for this call
+    pdf_passwords: Option<Vec<Option<String>>>,
+    // File names of `pdfs` sent as bare strings, from before attachments had names
+    pdf_names: Option<Vec<String>>,
+    // Web pages whose text is added like a document's
+    urls: Option<Vec<String>>,
+    // Text, Markdown and source files, injected as fenced code blocks
+    files: Option<Vec<FileAttachment>>,
+    // Set by the UI once per send, so a repeated invoke doesn't send the message twice
+    client_request_id: Option<String>,
+) -> Result<i64, String> {
+    let response_format = response_format
+        .map(|format| parse_response_format(&format))
+        .transpose()?;
+    // A message that is nothing but unreadable attachments isn't sent
+    let typed_content = !content.trim().is_empty();
+    let mut attachment_errors = Vec::new();
+    let images = match image_paths {
+        Some(paths) if !paths.is_empty() => {
+            let max_bytes = {
+                let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+                message_limits(&db)?.max_attachment_bytes
+            };
+            let picked = state
+                .picked_images
+                .lock()
+                .map_err(|_| "Failed to lock picked images")?
+                .clone();
+            let mut images = images.unwrap_or_default();
+            let sent_images = images.len();
+            for (i, path) in paths.into_iter().enumerate() {
+                let path = std::path::PathBuf::from(path);
+                match images::read_image_file(&path, &picked, max_bytes) {
+                    Ok((name, data)) => images.push(Attachment::Named { name, data }),
+                    Err(e) => {
+                        let name = path
+                            .file_name()
+                            .unwrap_or(path.as_os_str())
+                            .to_string_lossy()
+                            .into_owned();
+                        content.push_str(&format!("\n\n[System Error: Failed to read {}]", name));
+                        report_attachment_error(
+                            &app,
+                            &mut attachment_errors,
+                            AttachmentErrorEvent {
+                                thread_id,
+                                kind: "image",
+                                index: sent_images + i + 1,
+                                name,
+                                reason: e,
+                            },
+                        );
+                    }
+                }
+            }
+            Some(images)
+        }
+        _ => images,
+    };
+    let model = {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        if let Some(ref request_id) = client_request_id {
+            if let Some(message_id) = db
+                .find_message_by_client_request_id(thread_id, request_id)
+                .map_err(|e| e.to_string())?
+            {
+                return Ok(message_id);
+            }
+        }
+        let attachments = images
+            .iter()
+            .chain(pdfs.iter())
+            .flatten()
+            .map(Attachment::data)
+            .chain(files.iter().flatten().map(|file| file.data_base64.as_str()));
+        message_limits(&db)?.check(&content, images.as_ref().map_or(0, Vec::len), attachments)?;
+        db.resolve_model_name(&model).map_err(|e| e.to_string())?
+    };
+
+    // Ollama silently ignores images for text-only models
+    if images.as_ref().is_some_and(|images| !images.is_empty()) {
+        let backend = state.backend_for_thread(thread_id)?;
+        // A failed lookup shouldn't block the message
+        if let Ok(Some(capabilities)) = state.model_capabilities(backend.as_ref(), &model).await {
+            if !capabilities.iter().any(|c| c == "vision") {
+                return Err(format!("Model {} does not support image input", model));
+            }
+        }
+    }
+
+    {
+        let mut thread_tools = state
+            .thread_tools
+            .lock()
+            .map_err(|_| "Failed to lock tools")?;
+        match tools {
+            Some(tools) if !tools.is_empty() => {
+                thread_tools.insert(thread_id, tools);
+            }
+            _ => {
+                thread_tools.remove(&thread_id);
+            }
+        }
+    }
+
+    let limits = {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        pdf_limits(&db)?
+    };
+
+    // Vision models also get the images in PDFs, as diagrams have little text
+    let mut pdf_image_budget = 0;
+    if pdfs.as_ref().is_some_and(|pdfs| !pdfs.is_empty()) {
+        let backend = state.backend_for_thread(thread_id)?;
+        if let Ok(Some(capabilities)) = state.model_capabilities(backend.as_ref(), &model).await {
+            if capabilities.iter().any(|c| c == "vision") {
+                let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+                pdf_image_budget = db
+                    .get_setting("pdf_max_images")
+                    .map_err(|e| e.to_string())?
+                    .and_then(|v| v.parse::<usize>().ok())
+                    .unwrap_or(pdf_utils::DEFAULT_MAX_IMAGES);
+            }
+        }
+    }
+    let mut pdf_images = Vec::new();
+
+    // Documents and files whose text goes into this message, by key and name.
+    // Ones already in the thread get a note instead.
+    let mut seen_documents = HashSet::new();
+    let mut provided_documents = Vec::new();
+    let mut attachment_read = false;
+    let mut document_names = Vec::new();
+
+    // Process document attachments (PDF or DOCX) if any
+    if let Some(pdf_list) = pdfs {
+        let page_ranges = pdf_page_ranges.unwrap_or_default();
+        let mut passwords = pdf_passwords.unwrap_or_default();
+        let pdf_names = pdf_names.unwrap_or_default();
+        for (i, pdf) in pdf_list.into_iter().enumerate() {
+            let (name, pdf_base64) = pdf.into_parts();
+            // Decoding and hashing a large file take a while too
+            let decoded =
+                pdf_utils::spawn_extraction(move || document_utils::decode_document(&pdf_base64))
+                    .await?;
+            let name = name.or_else(|| pdf_names.get(i).cloned());
+            let filename = name
+                .clone()
+                .unwrap_or_else(|| format!("PDF Attachment {}", i + 1));
+            document_names.push(filename.clone());
+            let document_error = |reason: String| AttachmentErrorEvent {
+                thread_id,
+                kind: "document",
+                index: i + 1,
+                name: filename.clone(),
+                reason,
+            };
+            let (hash, decoded) = match decoded {
+                Ok(decoded) => decoded,
+                Err(e) => {
+                    content.push_str(&format!(
+                        "\n\n[System Error: Failed to read {}: {}]",
+                        filename, e
+                    ));
+                    report_attachment_error(
+                        &app,
+                        &mut attachment_errors,
+                        document_error(e.to_string()),
+                    );
+                    continue;
+                }
+            };
+
+            let range = page_ranges.get(i).copied().flatten();
+            // The same file dropped in twice is only read once
+            let key = document_key(&hash, range);
+            if !seen_documents.insert(key.clone()) {
+                continue;
+            }
+            if already_in_thread(&state, thread_id, &key)? {
+                content.push_str(&format!(
+                    "\n\n[document {} already provided above]",
+                    filename
+                ));
+                attachment_read = true;
+                continue;
+            }
+
+            let bytes = match decoded {
+                DecodedDocument::Pdf(bytes) => bytes,
+                DecodedDocument::Docx(Ok(text)) => {
+                    let filename = name.unwrap_or_else(|| format!("DOCX Attachment {}", i + 1));
+                    if let Some(last) = document_names.last_mut() {
+                        *last = filename.clone();
+                    }
+                    content.push_str(&format!(
+                        "\n\n--- {} Content ---\n{}\n-----------------------------------\n",
+                        filename, text
+                    ));
+                    provided_documents.push((key, filename));
+                    attachment_read = true;
+                    continue;
+                }
+                DecodedDocument::Docx(Err(e)) => {
+                    content.push_str(&format!(
+                        "\n\n[System Error: Failed to extract text from {}]",
+                        name.unwrap_or_else(|| format!("DOCX Attachment {}", i + 1))
+                    ));
+                    report_attachment_error(&app, &mut attachment_errors, document_error(e));
+                    continue;
+                }
+            };
+
+            let password = passwords.get_mut(i).and_then(Option::take);
+            let cached = {
+                let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+                db.get_cached_document(&hash).map_err(|e| e.to_string())?
+            };
+            let cached_pages = cached
+                .as_ref()
+                .map(|cached| (cached.layout, cached.pages.len()));
+            let max_images = pdf_image_budget.saturating_sub(pdf_images.len());
+            let progress_filename = filename.clone();
+            let app_handle = app.clone();
+            // Extraction, and OCR especially, can take a while on big documents
+            let (extracted, cache, found_images) = pdf_utils::spawn_extraction(move || {
+                let mut cache = cached;
+                let extracted = pdf_utils::extract_text_cached(
+                    &bytes,
+                    range,
+                    password.as_deref(),
+                    &limits,
+                    &mut cache,
+                    |progress| {
+                        let _ = match progress {
+                            ExtractionProgress::Page { page, total_pages } => app_handle.emit(
+                                "pdf-extract-progress",
+                                PdfExtractProgressEvent {
+                                    thread_id,
+                                    filename: progress_filename.clone(),
+                                    page,
+                                    total_pages,
+                                },
+                            ),
+                            ExtractionProgress::Ocr(progress) => app_handle.emit(
+                                "pdf-ocr-progress",
+                                PdfOcrProgressEvent {
+                                    thread_id,
+                                    attachment: i + 1,
+                                    progress,
+                                },
+                            ),
+                        };
+                    },
+                )
+                .map_err(|e| {
+                    let needs_user = e.downcast_ref::<PdfLimitError>().is_some()
+                        || e.downcast_ref::<PdfError>().is_some();
+                    (needs_user, e.to_string())
+                });
+                let found_images = (extracted.is_ok() && max_images > 0).then(|| {
+                    pdf_utils::extract_images(&bytes, password.as_deref(), max_images)
+                        .map_err(|e| e.to_string())
+                });
+                (extracted, cache, found_images)
+            })
+            .await?;
+
+            if let Some(cache) =
+                cache.filter(|cache| Some((cache.layout, cache.pages.len())) != cached_pages)
+            {
+                save_cached_document(&state, &hash, &cache)?;
+            }
+
+            match extracted {
+                Ok(pdf) => {
+                    provided_documents.push((key, filename.clone()));
+                    attachment_read = true;
+                    let _ = app.emit(
+                        "pdf-extract-done",
+                        PdfExtractDoneEvent {
+                            thread_id,
+                            filename: filename.clone(),
+                            characters: pdf.text.chars().count(),
+                        },
+                    );
+                    let pages = if range.is_some() {
+                        format!(
+                            " (pages {}-{} of {})",
+                            pdf.first_page, pdf.last_page, pdf.page_count
+                        )
+                    } else {
+                        String::new()
+                    };
+                    let mut notes = String::new();
+                    if !pdf.ocr_pages.is_empty() {
+                        notes.push_str(&format!(
+                            "[Page(s) {} were scanned and read with OCR; the text may contain recognition errors]\n",
+                            join_pages(&pdf.ocr_pages)
+                        ));
+                    }
+                    if !pdf.unreadable_pages.is_empty() {
+                        notes.push_str(&format!(
+                            "[Page(s) {} have no text layer and could not be read]\n",
+                            join_pages(&pdf.unreadable_pages)
+                        ));
+                    }
+                    match found_images {
+                        Some(Ok(found)) => {
+                            if !found.images.is_empty() {
+                                notes.push_str(&format!(
+                                    "[{} image(s) from this document are attached]\n",
+                                    found.images.len()
+                                ));
+                            }
+                            if found.unsupported + found.omitted > 0 {
+                                notes.push_str(&format!(
+                                    "[{} image(s) were skipped: {} in unsupported encodings, {} over the image limit]\n",
+                                    found.unsupported + found.omitted,
+                                    found.unsupported,
+                                    found.omitted
+                                ));
+                            }
+                            pdf_images.extend(found.images);
+                        }
+                        Some(Err(e)) => eprintln!("Failed to extract PDF images: {}", e),
+                        None => {}
+                    }
+                    content.push_str(&format!(
+                        "\n\n--- {}{} Content ---\n{}{}\n-----------------------------------\n",
+                        filename, pages, notes, pdf.text
+                    ));
+                }
+                // Over a limit or locked: the user has to pick pages or enter the
+                // password, so don't send a degraded prompt
+                Err((true, e)) => {
+                    return Err(format!("{}: {}", filename, e));
+                }
+                Err((false, e)) => {
+                    content.push_str(&format!(
+                        "\n\n[System Error: Failed to extract text from {}]",
+                        filename
+                    ));
+                    report_attachment_error(&app, &mut attachment_errors, document_error(e));
+                }
+            }
+        }
+    }
+
+    // Binary files are rejected outright rather than sent as gibberish
+    let csv_preview_rows = {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        csv_preview_rows(&db)?
+    };
+    for file in files.unwrap_or_default() {
+        let key = file_utils::content_hash(&file)?;
+        if !seen_documents.insert(key.clone()) {
+            continue;
+        }
+        if already_in_thread(&state, thread_id, &key)? {
+            content.push_str(&format!(
+                "\n\n[document {} already provided above]",
+                file.name
+            ));
+            attachment_read = true;
+            continue;
+        }
+        let rendered = file_utils::render_file(&file, limits.max_chars, csv_preview_rows)?;
+        content.push_str("\n\n");
+        content.push_str(&rendered);
+        provided_documents.push((key, file.name));
+        attachment_read = true;
+    }
+
+    // A page that can't be read gets a note rather than failing the message
+    if let Some(urls) = urls.filter(|urls| !urls.is_empty()) {
+        let client = url_utils::web_client();
+        for (i, url) in urls.into_iter().enumerate() {
+            match url_utils::fetch_page(&client, &url, url_utils::MAX_PAGE_BYTES).await {
+                Ok(page) => {
+                    content.push_str("\n\n");
+                    content.push_str(&url_utils::render_page(&url, &page, limits.max_chars));
+                    attachment_read = true;
+                }
+                Err(e) => {
+                    content.push_str(&format!("\n\n[Note: {} could not be read: {}]", url, e));
+                    report_attachment_error(
+                        &app,
+                        &mut attachment_errors,
+                        AttachmentErrorEvent {
+                            thread_id,
+                            kind: "url",
+                            index: i + 1,
+                            name: url,
+                            reason: e.to_string(),
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    let images = if pdf_images.is_empty() {
+        images
+    } else {
+        Some(
+            images
+                .unwrap_or_default()
+                .into_iter()
+                .chain(pdf_images.into_iter().map(Attachment::Bare))
+                .collect(),
+        )
+    };
+
+    // Downscale image attachments; vision models don't need full-size photos
+    let mut image_metadata = Vec::new();
+    let images = match images {
+        Some(image_list) if !image_list.is_empty() => {
+            let max_dimension = {
+                let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+                db.get_setting("image_max_dimension")
+                    .map_err(|e| e.to_string())?
+                    .and_then(|v| v.parse::<u32>().ok())
+                    .filter(|&v| v > 0)
+                    .unwrap_or(images::DEFAULT_MAX_DIMENSION)
+            };
+            let mut prepared_images = Vec::new();
+            for (i, image) in image_list.into_iter().enumerate() {
+                let (name, image_base64) = image.into_parts();
+                match images::prepare_image(&image_base64, max_dimension) {
+                    Ok(prepared) => {
+                        prepared_images.push(prepared.data);
+                        image_metadata.push(images::ImageMetadata {
+                            name,
+                            ..prepared.metadata
+                        });
+                        attachment_read = true;
+                    }
+                    Err(e) => {
+                        let name = name.unwrap_or_else(|| format!("Image Attachment {}", i + 1));
+                        content.push_str(&format!("\n\n[System Error: Failed to read {}]", name));
+                        report_attachment_error(
+                            &app,
+                            &mut attachment_errors,
+                            AttachmentErrorEvent {
+                                thread_id,
+                                kind: "image",
+                                index: i + 1,
+                                name,
+                                reason: e,
+                            },
+                        );
+                    }
+                }
+            }
+            Some(prepared_images).filter(|images| !images.is_empty())
+        }
+        _ => None,
+    };
+
+    if !typed_content && !attachment_read && !attachment_errors.is_empty() {
+        return Err(format!(
+            "None of the attachments could be read: {}",
+            attachment_errors.join("; ")
+        ));
+    }
+
+    // Made now so the message list never has to decode the full images
+    let thumbnails = images.as_deref().map(images::make_thumbnails);
+
+    // Save user message
+    let message_id = {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        // The first of two overlapping sends may have been saved while this one
+        // read its attachments
+        if let Some(ref request_id) = client_request_id {
+            if let Some(message_id) = db
+                .find_message_by_client_request_id(thread_id, request_id)
+                .map_err(|e| e.to_string())?
+            {
+                return Ok(message_id);
+            }
+        }
+        let message_id = db
+            .add_message(
+                thread_id,
+                Role::User,
+                &content,
+                images,
+                Some(model.clone()),
+                reply_to_id,
+                None,
+            )
+            .map_err(|e| e.to_string())?;
+        if !image_metadata.is_empty() {
+            db.set_message_image_metadata(message_id, &image_metadata)
+                .map_err(|e| e.to_string())?;
+        }
+        if let Some(ref thumbnails) = thumbnails {
+            db.set_message_image_thumbnails(message_id, thumbnails)
+                .map_err(|e| e.to_string())?;
+        }
+        if !document_names.is_empty() {
+            db.set_message_document_names(message_id, &document_names)
+                .map_err(|e| e.to_string())?;
+        }
+        db.record_thread_documents(thread_id, message_id, &provided_documents)
+            .map_err(|e| e.to_string())?;
+        if let Some(ref request_id) = client_request_id {
+            db.set_message_client_request_id(message_id, request_id)
+                .map_err(|e| e.to_string())?;
+        }
+        notify_change(&app, &db, DataChange::MessageAdded(message_id));
+        message_id
+    };
+
+    // Sent while a response is streaming: answered once the ones before it are
+    let job = QueuedGeneration {
+        message_id,
+        model,
+        response_format,
+        think,
+        num_predict,
+    };
+    let mut busy = match state
+        .busy_threads
+        .acquire_or_queue(thread_id, job.clone())?
+    {
+        Admission::Started(guard) => guard,
+        Admission::Queued(length) => {
+            let _ = app.emit("queue-updated", QueueUpdatedEvent { thread_id, length });
+            return Ok(message_id);
+        }
+    };
+
+    let result = answer_queued(&app, &state, thread_id, job).await;
+
+    // A failed generation has already reported itself and doesn't hold up the rest
+    while let Some(next) = busy.next_queued() {
+        let length = state.busy_threads.queue_len(thread_id);
+        let _ = app.emit("queue-updated", QueueUpdatedEvent { thread_id, length });
+        let _ = answer_queued(&app, &state, thread_id, next).await;
+    }
+    result.map(|()| message_id)
+}
+
+/// Streams the reply to a message sent with `send_message`.
+async fn answer_queued(
+    app: &AppHandle,
+    state: &AppState,
+    thread_id: i64,
+    job: QueuedGeneration,
+) -> Result<(), String> {
+    let result = stream_response(
+        app,
+        state,
+        thread_id,
+        job.model,
+        job.response_format,
+        job.think,
+        job.num_predict,
+        ReplyTarget::Answer(job.message_id),
+    )
+    .await;
+    report_stream_error(app, thread_id, result)
+}
+
+/// Stops the response being generated in a thread and drops the messages queued
+/// behind it; they stay in the thread unanswered. Returns whether anything was running.
+#[tauri::command]
+fn stop_generation(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    thread_id: i64,
+) -> Result<bool, String> {
+    let stopped = state.busy_threads.stop(thread_id)?;
+    let _ = app.emit(
+        "queue-updated",
+        QueueUpdatedEvent {
+            thread_id,
+            length: 0,
+        },
+    );
+    Ok(stopped)
+}
+
+/// Accepts either "json" for Ollama's JSON mode or a JSON schema for structured output.
+fn parse_response_format(format: &str) -> Result<serde_json::Value, String> {
+    let format = format.trim();
+    if format == "json" {
+        return Ok(serde_json::Value::String(format.to_string()));
+    }
+    let schema: serde_json::Value =
+        serde_json::from_str(format).map_err(|e| format!("Invalid JSON schema: {}", e))?;
+    if !schema.is_object() {
+        return Err("Invalid JSON schema: expected a JSON object".to_string());
+    }
+    Ok(schema)
+}
+
+#[tauri::command]
+async fn submit_tool_result(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    thread_id: i64,
+    call_id: String,
+    result_json: String,
+) -> Result<(), String> {
+    let _busy = state.busy_threads.acquire(thread_id)?;
+    serde_json::from_str::<serde_json::Value>(&result_json)
+        .map_err(|e| format!("Invalid tool result JSON: {}", e))?;
+
+    let (model, remaining) = {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        let messages = db.get_messages(thread_id).map_err(|e| e.to_string())?;
+
+        // Tool results always answer the most recent assistant turn that requested tools
+        let (idx, assistant) = messages
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, m)| m.role == Role::Assistant && m.tool_calls.is_some())
+            .ok_or("No tool calls are pending in this thread")?;
+        let calls: Vec<ToolCall> = assistant
+            .tool_calls
+            .clone()
+            .map(serde_json::from_value)
+            .transpose()
+            .map_err(|e| e.to_string())?
+            .unwrap_or_default();
+        let call = calls
+            .iter()
+            .find(|c| c.id.as_deref() == Some(call_id.as_str()))
+            .ok_or_else(|| format!("Unknown tool call id {}", call_id))?;
+
+        let answered: Vec<&str> = messages[idx + 1..]
+            .iter()
+            .filter(|m| m.role == Role::Tool)
+            .filter_map(|m| m.tool_call_id.as_deref())
+            .collect();
+        if answered.contains(&call_id.as_str()) {
+            return Err(format!("Tool call {} already has a result", call_id));
+        }
+
+        let message_id = db
+            .add_tool_message(thread_id, &call_id, &call.function.name, &result_json)
+            .map_err(|e| e.to_string())?;
+        notify_change(&app, &db, DataChange::MessageAdded(message_id));
+
+        (
+            assistant.model.clone(),
+            calls.len().saturating_sub(answered.len() + 1),
+        )
+    };
+
+    // Wait until every call from this turn has a result before continuing
+    if remaining > 0 {
+        return Ok(());
+    }
+    let model = model.ok_or("The assistant message has no model recorded")?;
+    generate_response_stream(app, state, thread_id, model, None, None, None).await
+}
+
+#[tauri::command]
+async fn regenerate_response(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    thread_id: i64,
+    model: String,
+) -> Result<(), String> {
+    let _busy = state.busy_threads.acquire(thread_id)?;
+    {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        let messages = db.get_messages(thread_id).map_err(|e| e.to_string())?;
+        if let Some(last) = messages.last() {
+            if last.role == Role::Assistant {
+                db.delete_last_message(thread_id)
+                    .map_err(|e| e.to_string())?;
+                let message_ids = vec![last.id];
+                notify_change(
+                    &app,
+                    &db,
+                    DataChange::MessagesDeleted {
+                        thread_id,
+                        message_ids,
+                    },
+                );
+            }
+        }
+    }
+    generate_response_stream(app, state, thread_id, model, None, None, None).await
+}
+
+/// Generates a new answer to the thread's last prompt with `model`, keeping
+/// `message_id`, the current answer, as a superseded variant so the two can be
+/// compared. Stream events carry `variant_of` with the old answer's id.
+#[tauri::command]
+async fn regenerate_with_model(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    thread_id: i64,
+    message_id: i64,
+    model: String,
+) -> Result<(), String> {
+    let _busy = state.busy_threads.acquire(thread_id)?;
+    {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        let messages = db.get_messages(thread_id).map_err(|e| e.to_string())?;
+        match messages.last() {
+            Some(last) if last.id == message_id && last.role == Role::Assistant => {}
+            _ => return Err("Only the thread's last answer can be regenerated".into()),
+        }
+    }
+    let result = stream_response(
+        &app,
+        &state,
+        thread_id,
+        model,
+        None,
+        None,
+        None,
+        ReplyTarget::VariantOf(message_id),
+    )
+    .await;
+    report_stream_error(&app, thread_id, result)
+}
+
+/// Continues a reply that was cut off by the token limit, a stop or a failed
+/// stream. The model gets the thread with the reply as the last turn and its
+/// new text is appended to that same message; stream events carry its id.
+#[tauri::command]
+async fn continue_generation(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    thread_id: i64,
+    model: String,
+) -> Result<(), String> {
+    let _busy = state.busy_threads.acquire(thread_id)?;
+    let message_id = {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        let messages = db.get_messages(thread_id).map_err(|e| e.to_string())?;
+        match messages.last() {
+            Some(last) if last.role == Role::Assistant && last.tool_calls.is_none() => last.id,
+            _ => return Err("The thread doesn't end in a reply that can be continued".into()),
+        }
+    };
+    let result = stream_response(
+        &app,
+        &state,
+        thread_id,
+        model,
+        None,
+        None,
+        None,
+        ReplyTarget::Continue(message_id),
+    )
+    .await;
+    report_stream_error(&app, thread_id, result)
+}
+
+#[tauri::command]
+async fn edit_message(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    thread_id: i64,
+    message_id: i64,
+    new_content: String,
+    model: String,
+) -> Result<(), String> {
+    let _busy = state.busy_threads.acquire(thread_id)?;
+    {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        // Update the message content
+        db.update_message(message_id, &new_content)
+            .map_err(|e| e.to_string())?;
+
+        // Delete all subsequent messages (to invalidate old conversation flow)
+        let message_ids = message_ids_from(&db, thread_id, message_id)?
+            .into_iter()
+            .skip(1)
+            .collect();
+        db.delete_messages_after(thread_id, message_id)
+            .map_err(|e| e.to_string())?;
+        notify_change(&app, &db, DataChange::MessageUpdated(message_id));
+        notify_change(
+            &app,
+            &db,
+            DataChange::MessagesDeleted {
+                thread_id,
+                message_ids,
+            },
+        );
+    }
+
+    // Regenerate response from this point
+    generate_response_stream(app, state, thread_id, model, None, None, None).await
+}
+
+/// Adds a private note to a thread, sent to the model as a system message at
+/// this point in the conversation.
+#[tauri::command]
+async fn add_thread_note(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    thread_id: i64,
+    content: String,
+) -> Result<i64, String> {
+    let content = content.trim();
+    if content.is_empty() {
+        return Err("Note cannot be empty".to_string());
+    }
+    // A note added mid-reply would land before the answer it came after
+    let _busy = state.busy_threads.acquire(thread_id)?;
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    let message_id = db
+        .add_message(thread_id, Role::Note, content, None, None, None, None)
+        .map_err(|e| e.to_string())?;
+    notify_change(&app, &db, DataChange::MessageAdded(message_id));
+    Ok(message_id)
+}
+
+#[tauri::command]
+async fn delete_message(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    thread_id: i64,
+    message_id: i64,
+) -> Result<(), String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    let message_ids = message_ids_from(&db, thread_id, message_id)?;
+    db.delete_messages_from(thread_id, message_id)
+        .map_err(|e| e.to_string())?;
+    notify_change(
+        &app,
+        &db,
+        DataChange::MessagesDeleted {
+            thread_id,
+            message_ids,
+        },
+    );
+    Ok(())
+}
+
+#[tauri::command]
+async fn delete_thread(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    thread_id: i64,
+) -> Result<(), String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.delete_thread(thread_id).map_err(|e| e.to_string())?;
+    notify_change(&app, &db, DataChange::ThreadDeleted(thread_id));
+    Ok(())
+}
+
+/// Replaces `pattern` in a thread's messages, their reasoning and its summaries,
+/// and returns how many spans were replaced. With `regex`, `pattern` is a
+/// regular expression; with `dry_run`, matches are only counted.
+#[tauri::command]
+fn redact_in_thread(
+    app: AppHandle,
+    state: State<AppState>,
+    thread_id: i64,
+    pattern: String,
+    replacement: String,
+    regex: Option<bool>,
+    dry_run: Option<bool>,
+) -> Result<usize, String> {
+    let redactor = Redactor::new(&pattern, &replacement, regex.unwrap_or(false))?;
+    let dry_run = dry_run.unwrap_or(false);
+    // A reply still streaming would be saved unredacted
+    let _busy = if dry_run {
+        None
+    } else {
+        Some(state.busy_threads.acquire(thread_id)?)
+    };
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    let redaction = db
+        .redact_thread(thread_id, &redactor, dry_run)
+        .map_err(|e| e.to_string())?;
+    if !dry_run {
+        for message_id in redaction.message_ids {
+            notify_change(&app, &db, DataChange::MessageUpdated(message_id));
+        }
+        if redaction.summary_changed {
+            notify_change(&app, &db, DataChange::ThreadUpdated(thread_id));
+        }
+    }
+    Ok(redaction.replacements)
+}
+
+/// Moves all messages of `source_id` into `target_id`, keeping their times, and
+/// deletes the source, or archives it when `archive_source` is set. With
+/// `add_divider`, a system note marks where the merged messages start.
+#[tauri::command]
+async fn merge_threads(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    source_id: i64,
+    target_id: i64,
+    add_divider: Option<bool>,
+    archive_source: Option<bool>,
+) -> Result<usize, String> {
+    if source_id == target_id {
+        return Err("Can't merge a thread into itself".to_string());
+    }
+    if state.busy_threads.is_busy(source_id) || state.busy_threads.is_busy(target_id) {
+        return Err("Wait for the current response to finish before merging".to_string());
+    }
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    let source = db.get_thread(source_id).map_err(|e| e.to_string())?;
+    db.get_thread(target_id).map_err(|e| e.to_string())?;
+    let divider = format!("Merged from \"{}\"", source.title);
+    let kept = message_ids(&db, target_id)?;
+    let moved_ids = message_ids(&db, source_id)?;
+    let archive_source = archive_source.unwrap_or(false);
+    let moved = db
+        .merge_threads(
+            source_id,
+            target_id,
+            add_divider.unwrap_or(false).then_some(divider.as_str()),
+            archive_source,
+        )
+        .map_err(|e| e.to_string())?;
+
+    for message_id in message_ids(&db, target_id)? {
+        if !kept.contains(&message_id) {
+            notify_change(&app, &db, DataChange::MessageAdded(message_id));
+        }
+    }
+    notify_change(&app, &db, DataChange::ThreadUpdated(target_id));
+    if archive_source {
+        notify_change(
+            &app,
+            &db,
+            DataChange::MessagesDeleted {
+                thread_id: source_id,
+                message_ids: moved_ids,
+            },
+        );
+        notify_change(&app, &db, DataChange::ThreadUpdated(source_id));
+    } else {
+        notify_change(&app, &db, DataChange::ThreadDeleted(source_id));
+    }
+    Ok(moved)
+}
+
+/// Moves the messages of a thread from `from_message_id` on into a new thread
+/// with the same system prompt and settings, and returns the new thread.
+#[tauri::command]
+async fn split_thread(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    thread_id: i64,
+    from_message_id: i64,
+    new_title: String,
+) -> Result<Thread, String> {
+    if state.busy_threads.is_busy(thread_id) {
+        return Err("Wait for the current response to finish before splitting".to_string());
+    }
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    let messages = db.get_messages(thread_id).map_err(|e| e.to_string())?;
+    let split_at = messages
+        .iter()
+        .position(|m| m.id == from_message_id)
+        .ok_or("That message isn't in this thread")?;
+    if split_at == 0 {
+        return Err("Splitting at the first message would leave the thread empty".to_string());
+    }
+    let new_id = db
+        .split_thread(thread_id, from_message_id, new_title.trim())
+        .map_err(|e| e.to_string())?;
+
+    let (kept, moved) = messages.split_at(split_at);
+    notify_change(&app, &db, DataChange::ThreadCreated(new_id));
+    notify_change(
+        &app,
+        &db,
+        DataChange::MessagesDeleted {
+            thread_id,
+            message_ids: moved.iter().map(|m| m.id).collect(),
+        },
+    );
+    for message in moved {
+        notify_change(&app, &db, DataChange::MessageAdded(message.id));
+    }
+    // Replies that pointed across the split lost their quote
+    for message in kept {
+        if message
+            .reply_to_id
+            .is_some_and(|id| moved.iter().any(|m| m.id == id))
+        {
+            notify_change(&app, &db, DataChange::MessageUpdated(message.id));
+        }
+    }
+    notify_change(&app, &db, DataChange::ThreadUpdated(thread_id));
+    db.get_thread(new_id).map_err(|e| e.to_string())
+}
+
+/// Suggests a title for an existing thread from its first few exchanges, saves
+/// it and returns it.
+#[tauri::command]
+async fn generate_thread_title(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    thread_id: i64,
+    model: String,
+) -> Result<String, String> {
+    let model = {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        db.resolve_model_name(&model).map_err(|e| e.to_string())?
+    };
+    let title = generate_title(&state, thread_id, &model, TITLE_EXCHANGES).await?;
+
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.update_thread_title(thread_id, &title)
+        .map_err(|e| e.to_string())?;
+    notify_change(&app, &db, DataChange::ThreadUpdated(thread_id));
+    Ok(title)
+}
+
+#[tauri::command]
+async fn rename_thread(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    thread_id: i64,
+    new_title: String,
+) -> Result<(), String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.update_thread_title(thread_id, &new_title)
+        .map_err(|e| e.to_string())?;
+    notify_change(&app, &db, DataChange::ThreadUpdated(thread_id));
+    Ok(())
+}
+
+#[tauri::command]
+async fn list_models(
+    state: State<'_, AppState>,
+    profile_id: Option<i64>,
+) -> Result<Vec<ModelSummary>, String> {
+    state
+        .backend_for_profile(profile_id)?
+        .list_models()
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Lists installed models with their aliases, favorites first in their chosen order.
+#[tauri::command]
+async fn list_models_annotated(
+    state: State<'_, AppState>,
+    profile_id: Option<i64>,
+) -> Result<Vec<AnnotatedModel>, String> {
+    let models = state
+        .backend_for_profile(profile_id)?
+        .list_models()
+        .await
+        .map_err(|e| e.to_string())?;
+    let aliases: HashMap<String, ModelAlias> = {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        db.get_model_aliases()
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .map(|a| (a.model_name.clone(), a))
+            .collect()
+    };
+
+    let mut annotated: Vec<AnnotatedModel> = models
+        .into_iter()
+        .map(|model| {
+            let alias = aliases.get(&model.name);
+            AnnotatedModel {
+                alias: alias.and_then(|a| a.alias.clone()),
+                is_favorite: alias.is_some_and(|a| a.is_favorite),
+                sort_order: alias.map_or(0, |a| a.sort_order),
+                model,
+            }
+        })
+        .collect();
+    // Stable, so the rest keep Ollama's order
+    annotated.sort_by_key(|m| (!m.is_favorite, if m.is_favorite { m.sort_order } else { 0 }));
+    Ok(annotated)
+}
+
+#[tauri::command]
+fn set_model_alias(
+    state: State<AppState>,
+    model_name: String,
+    alias: Option<String>,
+    is_favorite: Option<bool>,
+    sort_order: Option<i64>,
+) -> Result<(), String> {
+    let alias = alias
+        .map(|a| a.trim().to_string())
+        .filter(|a| !a.is_empty());
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.set_model_alias(&ModelAlias {
+        model_name,
+        alias,
+        is_favorite: is_favorite.unwrap_or(false),
+        sort_order: sort_order.unwrap_or(0),
+    })
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn unset_model_alias(state: State<AppState>, model_name: String) -> Result<(), String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.delete_model_alias(&model_name)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn check_ollama(
+    state: State<'_, AppState>,
+    profile_id: Option<i64>,
+) -> Result<OllamaStatus, String> {
+    match state.ollama_for_profile(profile_id)?.health().await {
+        Ok(version) => Ok(OllamaStatus {
+            reachable: true,
+            version: Some(version),
+            error: None,
+        }),
+        Err(e) => Ok(OllamaStatus {
+            reachable: false,
+            version: None,
+            error: Some(e.to_string()),
+        }),
+    }
+}
+
+/// How long diagnostics wait for the server before reporting it unreachable.
+const DIAGNOSTICS_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Gathers app, database and server details for an About panel or a bug
+/// report. Never waits on the server for longer than `DIAGNOSTICS_TIMEOUT`.
+#[tauri::command]
+async fn get_app_diagnostics(
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<AppDiagnostics, String> {
+    let (database, limits) = {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        (db.stats().map_err(|e| e.to_string())?, message_limits(&db)?)
+    };
+
+    let client = state.ollama_for_profile(None)?;
+    let base_url = client.base_url().to_string();
+    let ollama_url = url_utils::redact_url(&base_url);
+    // Request errors can quote the URL, credentials and all
+    let redact = |error: String| error.replace(&base_url, &ollama_url);
+    let ollama = match tokio::time::timeout(DIAGNOSTICS_TIMEOUT, client.health()).await {
+        Ok(Ok(version)) => OllamaStatus {
+            reachable: true,
+            version: Some(version),
+            error: None,
+        },
+        Ok(Err(e)) => OllamaStatus {
+            reachable: false,
+            version: None,
+            error: Some(redact(e.to_string())),
+        },
+        Err(_) => OllamaStatus {
+            reachable: false,
+            version: None,
+            error: Some(format!(
+                "No answer within {} seconds",
+                DIAGNOSTICS_TIMEOUT.as_secs()
+            )),
+        },
+    };
+
+    Ok(AppDiagnostics {
+        app_version: app.package_info().version.to_string(),
+        database,
+        ollama_auth_configured: client.auth().token.is_some() || !client.auth().headers.is_empty(),
+        ollama_url,
+        ollama,
+        limits,
+    })
+}
+
+#[tauri::command]
+async fn list_model_names(
+    state: State<'_, AppState>,
+    profile_id: Option<i64>,
+) -> Result<Vec<String>, String> {
+    state
+        .backend_for_profile(profile_id)?
+        .list_model_names()
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn pull_model(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    name: String,
+) -> Result<Vec<ModelSummary>, String> {
+    let cancel = {
+        let mut pulls = state
+            .active_pulls
+            .lock()
+            .map_err(|_| "Failed to lock active pulls")?;
+        if pulls.contains_key(&name) {
+            return Err(format!("Model {} is already being pulled", name));
+        }
+        let (sender, receiver) = watch::channel(false);
+        pulls.insert(name.clone(), sender);
+        receiver
+    };
+
+    let app_handle_clone = app.clone();
+    let model_name = name.clone();
+    let result = state
+        .ollama()?
+        .pull_model(&name, Some(cancel), move |progress| {
+            let percent = match (progress.total, progress.completed) {
+                (Some(total), Some(completed)) if total > 0 => {
+                    Some(completed as f64 / total as f64 * 100.0)
+                }
+                _ => None,
+            };
+            let _ = app_handle_clone.emit(
+                "model-pull-progress",
+                PullProgressEvent {
+                    name: model_name.clone(),
+                    status: progress.status,
+                    percent,
+                },
+            );
+        })
+        .await;
+    if let Ok(mut pulls) = state.active_pulls.lock() {
+        pulls.remove(&name);
+    }
+
+    match result {
+        Ok(()) => {
+            let _ = app.emit("model-pull-done", name);
+        }
+        // cancel_model_pull already told the frontend
+        Err(e)
+            if matches!(
+                e.downcast_ref::<OllamaError>(),
+                Some(OllamaError::Cancelled { .. })
+            ) => {}
+        Err(e) => return Err(e.to_string()),
+    }
+
+    // Return the refreshed list so the model picker picks up the new model
+    state
+        .ollama()?
+        .list_models()
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Stops an in-progress pull of `name`. Returns false if it wasn't being pulled.
+#[tauri::command]
+fn cancel_model_pull(app: AppHandle, state: State<AppState>, name: String) -> Result<bool, String> {
+    let sender = state
+        .active_pulls
+        .lock()
+        .map_err(|_| "Failed to lock active pulls")?
+        .remove(&name);
+    let Some(sender) = sender else {
+        return Ok(false);
+    };
+    let _ = sender.send(true);
+    let _ = app.emit("model-pull-cancelled", name);
+    Ok(true)
+}
+
+#[derive(Clone, Serialize)]
+struct ModelCreateProgressEvent {
+    name: String,
+    status: String,
+}
+
+/// How long to wait for a freshly created model to show up in the model list.
+const MODEL_LIST_POLL_ATTEMPTS: u32 = 10;
+const MODEL_LIST_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Saves a thread's system prompt and generation options as a new Ollama model,
+/// built on the model that last replied in the thread.
+#[tauri::command]
+async fn export_thread_as_model(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    thread_id: i64,
+    model_name: String,
+) -> Result<Vec<ModelSummary>, String> {
+    let model_name = model_name.trim().to_string();
+    if model_name.is_empty() {
+        return Err("Model name cannot be empty".to_string());
+    }
+
+    let (thread, from) = {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        let thread = db.get_thread(thread_id).map_err(|e| e.to_string())?;
+        let from = db
+            .get_messages(thread_id)
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .rev()
+            .find(|m| m.role == Role::Assistant)
+            .and_then(|m| m.model)
+            .ok_or("Thread has no reply to take the base model from")?;
+        (thread, from)
+    };
+
+    let ollama = state.ollama_for_thread(thread_id)?;
+    let app_handle_clone = app.clone();
+    let name = model_name.clone();
+    ollama
+        .create_model(
+            &model_name,
+            &from,
+            thread
+                .system_prompt
+                .as_deref()
+                .filter(|p| !p.trim().is_empty()),
+            thread.model_options.as_ref(),
+            move |status| {
+                let _ = app_handle_clone.emit(
+                    "model-create-progress",
+                    ModelCreateProgressEvent {
+                        name: name.clone(),
+                        status,
+                    },
+                );
+            },
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // Ollama reports success once the manifest is written; wait until the model
+    // is listed so the picker can select it right away
+    let mut attempts = 0;
+    while !ollama
+        .has_model(&model_name)
+        .await
+        .map_err(|e| e.to_string())?
+    {
+        attempts += 1;
+        if attempts >= MODEL_LIST_POLL_ATTEMPTS {
+            return Err(format!(
+                "Model {} was created but is not listed",
+                model_name
+            ));
+        }
+        tokio::time::sleep(MODEL_LIST_POLL_INTERVAL).await;
+    }
+
+    let _ = app.emit("model-create-done", &model_name);
+    ollama.list_models().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_model(
+    state: State<'_, AppState>,
+    name: String,
+    force: Option<bool>,
+) -> Result<(), String> {
+    if !force.unwrap_or(false) {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        let default_model = db.get_setting("default_model").map_err(|e| e.to_string())?;
+        if default_model.as_deref() == Some(name.as_str()) {
+            return Err(format!(
+                "Model {} is set as the default model; pass force to delete it anyway",
+                name
+            ));
+        }
+    }
+    state
+        .ollama()?
+        .delete_model(&name)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Loads a model ahead of time so the first message doesn't wait for it.
+#[tauri::command]
+async fn warm_up_model(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    name: String,
+    profile_id: Option<i64>,
+) -> Result<(), String> {
+    let (name, keep_alive) = {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        (
+            db.resolve_model_name(&name).map_err(|e| e.to_string())?,
+            db.get_setting("keep_alive").map_err(|e| e.to_string())?,
+        )
+    };
+
+    state
+        .ollama_for_profile(profile_id)?
+        .load_model(&name, keep_alive)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let _ = app.emit("model-ready", ModelReadyEvent { name });
+    Ok(())
+}
+
+#[tauri::command]
+async fn unload_model(
+    state: State<'_, AppState>,
+    name: String,
+    profile_id: Option<i64>,
+) -> Result<Vec<RunningModel>, String> {
+    let ollama = state.ollama_for_profile(profile_id)?;
+    ollama
+        .unload_model(&name)
+        .await
+        .map_err(|e| e.to_string())?;
+    ollama.get_running_models().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_running_models(
+    state: State<'_, AppState>,
+    profile_id: Option<i64>,
+) -> Result<Vec<RunningModel>, String> {
+    state
+        .ollama_for_profile(profile_id)?
+        .get_running_models()
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn show_model(state: State<'_, AppState>, name: String) -> Result<ModelDetails, String> {
+    state
+        .ollama()?
+        .show_model(&name)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Returns what a model supports, e.g. "vision" or "tools", or `None` when the
+/// server doesn't report it.
+#[tauri::command]
+async fn get_model_capabilities(
+    state: State<'_, AppState>,
+    name: String,
+    profile_id: Option<i64>,
+) -> Result<Option<Vec<String>>, String> {
+    let name = {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        db.resolve_model_name(&name).map_err(|e| e.to_string())?
+    };
+    let backend = state.backend_for_profile(profile_id)?;
+    state.model_capabilities(backend.as_ref(), &name).await
+}
+
+#[tauri::command]
+async fn generate_embeddings(
+    state: State<'_, AppState>,
+    model: String,
+    texts: Vec<String>,
+) -> Result<Vec<Vec<f32>>, String> {
+    state
+        .ollama()?
+        .embed(&model, texts)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn index_thread_embeddings(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    thread_id: i64,
+    embedding_model: String,
+) -> Result<usize, String> {
+    // Messages that already have a vector for this model are skipped
+    let pending = {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        db.get_messages_without_embeddings(thread_id, &embedding_model)
+            .map_err(|e| e.to_string())?
+    };
+
+    let total = pending.len();
+    let mut indexed = 0;
+    for batch in pending.chunks(EMBEDDING_BATCH_SIZE) {
+        let inputs = batch.iter().map(|m| m.content.clone()).collect();
+        let vectors = state
+            .ollama()?
+            .embed(&embedding_model, inputs)
+            .await
+            .map_err(|e| e.to_string())?;
+        // Pairing a short answer up would leave the rest unindexed for good
+        if vectors.len() != batch.len() {
+            return Err(format!(
+                "{} returned {} embeddings for {} messages",
+                embedding_model,
+                vectors.len(),
+                batch.len()
+            ));
+        }
+
+        {
+            let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+            for (message, vector) in batch.iter().zip(vectors) {
+                db.save_embedding(message.id, &embedding_model, &vector)
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+
+        indexed += batch.len();
+        let _ = app.emit(
+            "embedding-index-progress",
+            EmbeddingProgressEvent {
+                thread_id,
+                indexed,
+                total,
+            },
+        );
+    }
+
+    let _ = app.emit("embedding-index-done", thread_id);
+
+    Ok(indexed)
+}
+
+#[tauri::command]
+async fn semantic_search(
+    state: State<'_, AppState>,
+    query: String,
+    top_k: usize,
+    embedding_model: Option<String>,
+) -> Result<Vec<SemanticSearchResult>, String> {
+    let embedding_model = match embedding_model {
+        Some(model) => model,
+        None => {
+            let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+            db.get_setting("embedding_model")
+                .map_err(|e| e.to_string())?
+                .unwrap_or_else(|| DEFAULT_EMBEDDING_MODEL.to_string())
+        }
+    };
+
+    let query_vector = state
+        .ollama()?
+        .embed(&embedding_model, vec![query])
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .next()
+        .ok_or("Ollama returned no embedding for the query")?;
+
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    let candidates = db
+        .get_embeddings(&embedding_model)
+        .map_err(|e| e.to_string())?;
+
+    search::rank_by_similarity(&query_vector, candidates, top_k)
+        .into_iter()
+        .map(|(message_id, score)| {
+            let message = db.get_message(message_id).map_err(|e| e.to_string())?;
+            Ok(SemanticSearchResult { message, score })
+        })
+        .collect()
+}
+
+#[tauri::command]
+async fn complete_text(
+    state: State<'_, AppState>,
+    model: String,
+    prompt: String,
+) -> Result<String, String> {
+    state
+        .ollama()?
+        .generate(&model, &prompt, GenerateOptions::default(), |_| {})
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn regenerate_from_message(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    thread_id: i64,
+    message_id: i64,
+    model: String,
+) -> Result<(), String> {
+    let _busy = state.busy_threads.acquire(thread_id)?;
+    {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        let message_ids = message_ids_from(&db, thread_id, message_id)?;
+        db.delete_messages_from(thread_id, message_id)
+            .map_err(|e| e.to_string())?;
+        notify_change(
+            &app,
+            &db,
+            DataChange::MessagesDeleted {
+                thread_id,
+                message_ids,
+            },
+        );
+    }
+    generate_response_stream(app, state, thread_id, model, None, None, None).await
+}
+
+/// Archives every thread with no messages in the last `older_than_days` days
+/// and returns how many were archived.
+#[tauri::command]
+fn archive_stale_threads(
+    app: AppHandle,
+    state: State<AppState>,
+    older_than_days: u32,
+) -> Result<usize, String> {
+    if older_than_days == 0 {
+        return Err("older_than_days must be at least 1".to_string());
+    }
+    let cutoff = chrono::Utc::now() - chrono::Duration::days(older_than_days.into());
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    let archived = thread_events::archive_stale_threads(&app, &db, cutoff)?;
+    for &thread_id in &archived {
+        notify_change(&app, &db, DataChange::ThreadUpdated(thread_id));
+    }
+    Ok(archived.len())
+}
+
+#[tauri::command]
+async fn archive_thread(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    thread_id: i64,
+) -> Result<(), String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.archive_thread(thread_id).map_err(|e| e.to_string())?;
+    let _ = app.emit("thread-archived", ThreadArchivedEvent { thread_id });
+    notify_change(&app, &db, DataChange::ThreadUpdated(thread_id));
+    Ok(())
+}
+
+#[tauri::command]
+fn get_setting(state: State<AppState>, key: String) -> Result<Option<String>, String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.get_setting(&key).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn set_setting(state: State<AppState>, key: String, value: String) -> Result<(), String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.set_setting(&key, &value).map_err(|e| e.to_string())
+}
+
+/// Validates an Ollama base URL and normalizes it without a trailing slash.
+fn normalize_ollama_url(url: &str) -> Result<String, String> {
+    let trimmed = url.trim().trim_end_matches('/');
+    let parsed =
+        reqwest::Url::parse(trimmed).map_err(|e| format!("Invalid Ollama URL {}: {}", url, e))?;
+    if parsed.scheme() != "http" && parsed.scheme() != "https" {
+        return Err(format!(
+            "Invalid Ollama URL {}: expected http or https",
+            url
+        ));
+    }
+    Ok(trimmed.to_string())
+}
+
+#[tauri::command]
+fn get_ollama_url(state: State<AppState>) -> Result<String, String> {
+    Ok(state.ollama()?.base_url().to_string())
+}
+
+#[tauri::command]
+fn set_ollama_url(state: State<AppState>, url: String) -> Result<String, String> {
+    let url = normalize_ollama_url(&url)?;
+
+    {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        db.set_setting("ollama_url", &url)
+            .map_err(|e| e.to_string())?;
+    }
+
+    // Swap in a client for the new host; in-flight requests keep their old client
+    let mut ollama = state
+        .ollama
+        .write()
+        .map_err(|_| "Failed to lock Ollama client")?;
+    *ollama = Arc::new(ollama.with_base_url(url.clone()));
+
+    Ok(url)
 }
 
+/// Sets the credentials for the default Ollama server. An empty token clears it.
 #[tauri::command]
-fn create_thread(
+fn set_ollama_auth(
     state: State<AppState>,
-    title: String,
-    system_prompt: Option<String>,
-) -> Result<Thread, String> {
+    auth_token: Option<String>,
+    headers: Option<HashMap<String, String>>,
+) -> Result<(), String> {
+    let auth = ClientAuth {
+        token: auth_token
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty()),
+        headers: headers.unwrap_or_default(),
+    };
+    auth.validate()?;
+
+    {
+        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        db.set_setting("ollama_auth_token", auth.token.as_deref().unwrap_or(""))
+            .map_err(|e| e.to_string())?;
+        let headers = serde_json::to_string(&auth.headers).map_err(|e| e.to_string())?;
+        db.set_setting("ollama_headers", &headers)
+            .map_err(|e| e.to_string())?;
+    }
+
+    let mut ollama = state
+        .ollama
+        .write()
+        .map_err(|_| "Failed to lock Ollama client")?;
+    *ollama = Arc::new(
+        ollama
+            .with_base_url(ollama.base_url().to_string())
+            .with_auth(auth),
+    );
+    Ok(())
+}
+
+fn parse_backend_kind(kind: Option<String>) -> Result<&'static str, String> {
+    match kind.as_deref() {
+        None | Some(BACKEND_OLLAMA) => Ok(BACKEND_OLLAMA),
+        Some(BACKEND_OPENAI) => Ok(BACKEND_OPENAI),
+        Some(other) => Err(format!("Unknown server kind: {}", other)),
+    }
+}
+
+/// OpenAI-compatible URLs are often copied with the `/v1` suffix; the client adds it itself.
+fn normalize_profile_url(url: &str, kind: &str) -> Result<String, String> {
+    let url = normalize_ollama_url(url)?;
+    if kind == BACKEND_OPENAI {
+        if let Some(stripped) = url.strip_suffix("/v1") {
+            return Ok(stripped.to_string());
+        }
+    }
+    Ok(url)
+}
+
+/// Saves a profile's bearer token and headers. An empty token clears it; an absent one
+/// leaves it as it is.
+fn save_profile_auth(
+    db: &Database,
+    profile_id: i64,
+    auth_token: Option<String>,
+    headers: Option<HashMap<String, String>>,
+) -> Result<(), String> {
+    let auth_token = auth_token.map(|t| t.trim().to_string());
+    let auth = ClientAuth {
+        token: auth_token.clone().filter(|t| !t.is_empty()),
+        headers: headers.clone().unwrap_or_default(),
+    };
+    auth.validate()?;
+
+    if auth_token.is_some() {
+        db.set_server_profile_auth_token(profile_id, auth.token.as_deref())
+            .map_err(|e| e.to_string())?;
+    }
+    if headers.is_some() {
+        db.set_server_profile_headers(profile_id, &auth.headers)
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn create_server_profile(
+    state: State<AppState>,
+    name: String,
+    base_url: String,
+    kind: Option<String>,
+    is_default: Option<bool>,
+    auth_token: Option<String>,
+    headers: Option<HashMap<String, String>>,
+) -> Result<ServerProfile, String> {
+    let kind = parse_backend_kind(kind)?;
+    let base_url = normalize_profile_url(&base_url, kind)?;
     let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
     let id = db
-        .create_thread(&title, system_prompt.clone())
+        .create_server_profile(&name, &base_url, kind, is_default.unwrap_or(false))
         .map_err(|e| e.to_string())?;
-    Ok(Thread {
-        id,
-        title,
-        created_at: chrono::Utc::now().to_rfc3339(), // Approximate return
-        system_prompt,
-        is_archived: false,
-    })
+    save_profile_auth(&db, id, auth_token, headers)?;
+    db.get_server_profile(id).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-fn get_threads(state: State<AppState>) -> Result<Vec<Thread>, String> {
+fn get_server_profiles(state: State<AppState>) -> Result<Vec<ServerProfile>, String> {
     let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
-    db.get_threads().map_err(|e| e.to_string())
+    db.get_server_profiles().map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-fn get_messages(state: State<AppState>, thread_id: i64) -> Result<Vec<Message>, String> {
+fn update_server_profile(
+    state: State<AppState>,
+    profile_id: i64,
+    name: String,
+    base_url: String,
+    kind: Option<String>,
+    auth_token: Option<String>,
+    headers: Option<HashMap<String, String>>,
+) -> Result<(), String> {
     let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
-    db.get_messages(thread_id).map_err(|e| e.to_string())
+    // Like the auth, an absent kind leaves it as it is
+    let kind = match kind {
+        Some(kind) => parse_backend_kind(Some(kind))?,
+        None => parse_backend_kind(Some(
+            db.get_server_profile(profile_id)
+                .map_err(|e| e.to_string())?
+                .kind,
+        ))?,
+    };
+    let base_url = normalize_profile_url(&base_url, kind)?;
+    db.update_server_profile(profile_id, &name, &base_url, kind)
+        .map_err(|e| e.to_string())?;
+    save_profile_auth(&db, profile_id, auth_token, headers)
 }
 
-async fn generate_response_stream(
-    app: AppHandle,
-    state: State<'_, AppState>,
-    thread_id: i64,
-    model: String,
+/// Sets how a server's TLS certificate is checked. `accept_invalid_certs` turns
+/// verification off entirely, so it is kept out of the regular profile form and
+/// has to be set explicitly here.
+#[tauri::command]
+fn set_server_profile_tls(
+    state: State<AppState>,
+    profile_id: i64,
+    accept_invalid_certs: bool,
+    ca_cert_path: Option<String>,
 ) -> Result<(), String> {
-    // 1. Prepare context (fetch recent messages)
-    let history = {
-        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
-        let system_prompt = db
-            .get_thread_system_prompt(thread_id)
-            .map_err(|e| e.to_string())?;
-        let messages = db.get_messages(thread_id).map_err(|e| e.to_string())?;
-
-        let mut ollama_messages = Vec::new();
-
-        if let Some(prompt) = system_prompt {
-            if !prompt.is_empty() {
-                ollama_messages.push(OllamaMessage {
-                    role: "system".to_string(),
-                    content: prompt,
-                    images: None,
-                    thinking: None,
-                });
-            }
-        }
-
-        ollama_messages.extend(messages.into_iter().map(|m| OllamaMessage {
-            role: m.role,
-            content: m.content,
-            images: m.images,
-            thinking: None,
-        }));
-
-        ollama_messages
+    let tls = TlsOptions {
+        accept_invalid_certs,
+        ca_cert_path: ca_cert_path
+            .map(|p| p.trim().to_string())
+            .filter(|p| !p.is_empty()),
     };
+    tls.validate()?;
 
-    // 2. Call Ollama and stream
-    let app_handle_clone = app.clone();
-    let response_content = state
-        .ollama
-        .chat(&model, history, move |chunk| {
-            let _ = app_handle_clone.emit("stream-response", chunk);
-        })
-        .await
-        .map_err(|e| e.to_string())?;
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.set_server_profile_tls(
+        profile_id,
+        tls.accept_invalid_certs,
+        tls.ca_cert_path.as_deref(),
+    )
+    .map_err(|e| e.to_string())
+}
 
-    // 3. Save AI message
+#[tauri::command]
+fn set_default_server_profile(state: State<AppState>, profile_id: i64) -> Result<(), String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.set_default_server_profile(profile_id)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn delete_server_profile(state: State<AppState>, profile_id: i64) -> Result<(), String> {
     {
         let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
-        db.add_message(
-            thread_id,
-            "assistant",
-            &response_content,
-            None,
-            Some(model),
-            None,
-        )
-        .map_err(|e| e.to_string())?;
+        db.delete_server_profile(profile_id)
+            .map_err(|e| e.to_string())?;
     }
-
-    // Emit done event
-    let _ = app.emit("stream-done", ());
-
+    let mut clients = state
+        .profile_clients
+        .lock()
+        .map_err(|_| "Failed to lock Ollama clients")?;
+    clients.remove(&profile_id);
+    let mut clients = state
+        .openai_clients
+        .lock()
+        .map_err(|_| "Failed to lock OpenAI clients")?;
+    clients.remove(&profile_id);
     Ok(())
 }
 
 #[tauri::command]
-async fn send_message(
+fn set_thread_options(
     app: AppHandle,
-    state: State<'_, AppState>,
+    state: State<AppState>,
     thread_id: i64,
-    mut content: String,
-    images: Option<Vec<String>>,
-    pdfs: Option<Vec<String>>,
-    model: String,
-    reply_to_id: Option<i64>,
+    options: ModelOptions,
 ) -> Result<(), String> {
-    // Process PDF attachments if any
-    if let Some(pdf_list) = pdfs {
-        for (i, pdf_base64) in pdf_list.iter().enumerate() {
-            // Remove data:application/pdf;base64, prefix if present
-            let clean_base64 = pdf_base64
-                .find(',')
-                .map_or(pdf_base64.as_str(), |idx| &pdf_base64[idx + 1..]);
-
-            if let Ok(bytes) = general_purpose::STANDARD.decode(clean_base64) {
-                match pdf_utils::extract_text_from_pdf(&bytes) {
-                    Ok(text) => {
-                        content.push_str(&format!("\n\n--- PDF Attachment {} Content ---\n{}\n-----------------------------------\n", i + 1, text));
-                    }
-                    Err(e) => {
-                        content.push_str(&format!(
-                            "\n\n[System Error: Failed to extract text from PDF Attachment {}]",
-                            i + 1
-                        ));
-                        eprintln!("Failed to extract PDF text: {}", e);
-                    }
-                }
-            }
-        }
-    }
-
-    // Save user message
-    {
-        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
-        db.add_message(
-            thread_id,
-            "user",
-            &content,
-            images,
-            Some(model.clone()),
-            reply_to_id,
-        )
+    options.validate()?;
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.set_thread_model_options(thread_id, &options)
         .map_err(|e| e.to_string())?;
-    }
-    generate_response_stream(app, state, thread_id, model).await
+    notify_change(&app, &db, DataChange::ThreadUpdated(thread_id));
+    Ok(())
 }
 
+/// Whether earlier replies' reasoning is sent back to the model in this thread.
 #[tauri::command]
-async fn regenerate_response(
+fn set_thread_resend_thinking(
     app: AppHandle,
-    state: State<'_, AppState>,
+    state: State<AppState>,
     thread_id: i64,
-    model: String,
+    resend: bool,
 ) -> Result<(), String> {
-    {
-        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
-        let messages = db.get_messages(thread_id).map_err(|e| e.to_string())?;
-        if let Some(last) = messages.last() {
-            if last.role == "assistant" {
-                db.delete_last_message(thread_id)
-                    .map_err(|e| e.to_string())?;
-            }
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.set_thread_resend_thinking(thread_id, resend)
+        .map_err(|e| e.to_string())?;
+    notify_change(&app, &db, DataChange::ThreadUpdated(thread_id));
+    Ok(())
+}
+
+/// Leaves a message out of (or puts it back into) what is sent to the model.
+#[tauri::command]
+fn set_message_excluded(
+    app: AppHandle,
+    state: State<AppState>,
+    message_id: i64,
+    excluded: bool,
+) -> Result<(), String> {
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    if excluded {
+        let message = db.get_message(message_id).map_err(|e| e.to_string())?;
+        // Without it there would be nothing left to reply to
+        if db
+            .get_last_user_message_id(message.thread_id)
+            .map_err(|e| e.to_string())?
+            == Some(message_id)
+        {
+            return Err("The last user message can't be excluded".to_string());
         }
     }
-    generate_response_stream(app, state, thread_id, model).await
+    db.set_message_excluded(message_id, excluded)
+        .map_err(|e| e.to_string())?;
+    notify_change(&app, &db, DataChange::MessageUpdated(message_id));
+    Ok(())
 }
 
+/// Caps how many of a thread's newest messages are sent to the model; `None`
+/// sends them all.
 #[tauri::command]
-async fn edit_message(
+fn set_thread_max_history_messages(
     app: AppHandle,
-    state: State<'_, AppState>,
+    state: State<AppState>,
     thread_id: i64,
-    message_id: i64,
-    new_content: String,
-    model: String,
+    max_messages: Option<u32>,
 ) -> Result<(), String> {
-    {
-        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
-        // Update the message content
-        db.update_message(message_id, &new_content)
-            .map_err(|e| e.to_string())?;
-
-        // Delete all subsequent messages (to invalidate old conversation flow)
-        db.delete_messages_after(thread_id, message_id)
-            .map_err(|e| e.to_string())?;
+    if max_messages == Some(0) {
+        return Err("The history cap must be at least 1 message".to_string());
     }
-
-    // Regenerate response from this point
-    generate_response_stream(app, state, thread_id, model).await
+    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+    db.set_thread_max_history_messages(thread_id, max_messages)
+        .map_err(|e| e.to_string())?;
+    notify_change(&app, &db, DataChange::ThreadUpdated(thread_id));
+    Ok(())
 }
 
+/// Overrides the `save_thinking` setting for a thread; `None` follows it again.
 #[tauri::command]
-async fn delete_message(
-    state: State<'_, AppState>,
+fn set_thread_save_thinking(
+    app: AppHandle,
+    state: State<AppState>,
     thread_id: i64,
-    message_id: i64,
+    save: Option<bool>,
 ) -> Result<(), String> {
     let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
-    db.delete_messages_from(thread_id, message_id)
+    db.set_thread_save_thinking(thread_id, save)
         .map_err(|e| e.to_string())?;
+    notify_change(&app, &db, DataChange::ThreadUpdated(thread_id));
     Ok(())
 }
 
 #[tauri::command]
-async fn delete_thread(state: State<'_, AppState>, thread_id: i64) -> Result<(), String> {
+fn set_thread_think(
+    app: AppHandle,
+    state: State<AppState>,
+    thread_id: i64,
+    think: Option<bool>,
+) -> Result<(), String> {
     let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
-    db.delete_thread(thread_id).map_err(|e| e.to_string())?;
+    db.set_thread_think(thread_id, think)
+        .map_err(|e| e.to_string())?;
+    notify_change(&app, &db, DataChange::ThreadUpdated(thread_id));
     Ok(())
 }
 
 #[tauri::command]
-async fn rename_thread(
-    state: State<'_, AppState>,
+fn set_thread_server_profile(
+    app: AppHandle,
+    state: State<AppState>,
     thread_id: i64,
-    new_title: String,
+    profile_id: Option<i64>,
 ) -> Result<(), String> {
     let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
-    db.update_thread_title(thread_id, &new_title)
+    db.set_thread_server_profile(thread_id, profile_id)
         .map_err(|e| e.to_string())?;
+    notify_change(&app, &db, DataChange::ThreadUpdated(thread_id));
+    Ok(())
+}
+
+fn load_retry_policy(db: &Database) -> RetryPolicy {
+    let mut policy = RetryPolicy::default();
+    if let Ok(Some(attempts)) = db.get_setting("retry_max_attempts") {
+        if let Ok(attempts) = attempts.parse::<u32>() {
+            policy.max_attempts = attempts.max(1);
+        }
+    }
+    if let Ok(Some(backoff)) = db.get_setting("retry_backoff_ms") {
+        if let Ok(backoff) = backoff.parse::<u64>() {
+            policy.initial_backoff = std::time::Duration::from_millis(backoff);
+        }
+    }
+    policy
+}
+
+fn load_auth(db: &Database) -> ClientAuth {
+    let setting = |key: &str| db.get_setting(key).ok().flatten();
+    ClientAuth {
+        token: setting("ollama_auth_token").filter(|t| !t.is_empty()),
+        headers: setting("ollama_headers")
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default(),
+    }
+}
+
+fn load_timeouts(db: &Database) -> Timeouts {
+    let seconds = |key: &str| {
+        db.get_setting(key)
+            .ok()
+            .flatten()
+            .and_then(|v| v.parse::<u64>().ok())
+            .map(std::time::Duration::from_secs)
+    };
+
+    let mut timeouts = Timeouts::default();
+    if let Some(connect) = seconds("connect_timeout_secs") {
+        timeouts.connect = connect;
+    }
+    if let Some(request) = seconds("request_timeout_secs") {
+        timeouts.request = request;
+    }
+    if let Some(stream_idle) = seconds("stream_idle_timeout_secs") {
+        timeouts.stream_idle = stream_idle;
+    }
+    timeouts
+}
+
+/// The client for the default Ollama server, as a database's settings describe it.
+fn load_ollama_client(db: &Database) -> OllamaClient {
+    let ollama_url = db
+        .get_setting("ollama_url")
+        .ok()
+        .flatten()
+        .unwrap_or_else(|| DEFAULT_OLLAMA_URL.to_string());
+    OllamaClient::new(ollama_url)
+        .with_retry_policy(load_retry_policy(db))
+        .with_timeouts(load_timeouts(db))
+        .with_auth(load_auth(db))
+}
+
+#[tauri::command]
+fn list_workspaces(state: State<AppState>) -> Result<WorkspaceIndex, String> {
+    let workspaces = state
+        .workspaces
+        .lock()
+        .map_err(|_| "Failed to lock workspaces")?;
+    Ok(workspaces.clone())
+}
+
+/// Adds a workspace with an empty database of its own. It isn't switched to.
+#[tauri::command]
+fn create_workspace(state: State<AppState>, name: String) -> Result<Workspace, String> {
+    let mut workspaces = state
+        .workspaces
+        .lock()
+        .map_err(|_| "Failed to lock workspaces")?;
+    let dir = std::path::Path::new(DEFAULT_DB_PATH)
+        .parent()
+        .unwrap_or(std::path::Path::new(""));
+    let mut index = workspaces.clone();
+    let workspace = index.add(&name, dir)?;
+    // Creates the file, so a bad path shows up now rather than on switching
+    Database::new(&workspace.db_path).map_err(|e| e.to_string())?;
+    index.save(std::path::Path::new(WORKSPACE_INDEX_PATH))?;
+    *workspaces = index;
+    Ok(workspace)
+}
+
+/// Closes the open database and puts `new_db` in its place. Refused while
+/// anything is being generated or summarized, since that would be saved to
+/// whichever database is open when it finishes.
+fn replace_database(state: &AppState, new_db: Database) -> Result<(), String> {
+    {
+        let mut db = state.db.lock().map_err(|_| "Failed to lock DB")?;
+        // Checked with the database locked, so nothing new starts writing to it
+        let summarizing = !state
+            .summarizing
+            .lock()
+            .map_err(|_| "Failed to lock summaries")?
+            .is_empty();
+        if !state.busy_threads.is_idle() || summarizing {
+            return Err(
+                "Stop or wait for the responses being generated before changing databases"
+                    .to_string(),
+            );
+        }
+        *db = new_db;
+
+        // Server settings and profiles belong to the database
+        let mut ollama = state
+            .ollama
+            .write()
+            .map_err(|_| "Failed to lock Ollama client")?;
+        *ollama = Arc::new(load_ollama_client(&db));
+    }
+    state
+        .profile_clients
+        .lock()
+        .map_err(|_| "Failed to lock Ollama clients")?
+        .clear();
+    state
+        .openai_clients
+        .lock()
+        .map_err(|_| "Failed to lock OpenAI clients")?
+        .clear();
+    state
+        .thread_tools
+        .lock()
+        .map_err(|_| "Failed to lock tools")?
+        .clear();
     Ok(())
 }
 
+/// Closes the current database and opens the workspace's instead.
 #[tauri::command]
-async fn list_models(state: State<'_, AppState>) -> Result<Vec<String>, String> {
-    state.ollama.list_models().await.map_err(|e| e.to_string())
+fn switch_workspace(app: AppHandle, state: State<AppState>, name: String) -> Result<(), String> {
+    let mut workspaces = state
+        .workspaces
+        .lock()
+        .map_err(|_| "Failed to lock workspaces")?;
+    let workspace = workspaces
+        .get(&name)
+        .cloned()
+        .ok_or_else(|| format!("No workspace named {}", name.trim()))?;
+    if workspace.name == workspaces.active().name {
+        return Ok(());
+    }
+
+    let new_db = Database::new(&workspace.db_path).map_err(|e| e.to_string())?;
+    replace_database(&state, new_db)?;
+
+    workspaces.active = workspace.name.clone();
+    // The switch has happened either way; it just won't be remembered
+    if let Err(e) = workspaces.save(std::path::Path::new(WORKSPACE_INDEX_PATH)) {
+        eprintln!("Failed to save the active workspace: {}", e);
+    }
+    let _ = app.emit("workspace-changed", &workspace);
+    Ok(())
 }
 
+/// Commands that still work while a database is open read-only. Every other
+/// command is refused with `READ_ONLY_ERROR` before it runs.
+const READ_ONLY_COMMANDS: &[&str] = &[
+    "get_threads",
+    "get_interrupted_threads",
+    "get_messages",
+    "get_full_image",
+    "get_threads_by_model",
+    "get_models_used",
+    "get_activity_stats",
+    "get_model_stats",
+    "get_latency_history",
+    "list_workspaces",
+    "close_readonly",
+    "preview_context",
+    "estimate_send",
+    "get_limits",
+    "stop_generation",
+    "semantic_search",
+    "get_app_diagnostics",
+    "get_setting",
+    "get_ollama_url",
+    "get_server_profiles",
+    "list_presets",
+    "list_models",
+    "list_model_names",
+    "list_models_annotated",
+    "check_ollama",
+    "show_model",
+    "get_model_capabilities",
+    "get_running_models",
+];
+const READ_ONLY_ERROR: &str = "This is a read-only workspace";
+
+/// Opens a database file, such as a backup, for browsing without changing
+/// it: nothing is migrated and commands that would write are refused until
+/// `close_readonly`.
 #[tauri::command]
-async fn regenerate_from_message(
+fn open_database_readonly(
     app: AppHandle,
-    state: State<'_, AppState>,
-    thread_id: i64,
-    message_id: i64,
-    model: String,
+    state: State<AppState>,
+    path: String,
 ) -> Result<(), String> {
-    {
-        let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
-        db.delete_messages_from(thread_id, message_id)
-            .map_err(|e| e.to_string())?;
-    }
-    generate_response_stream(app, state, thread_id, model).await
+    let new_db = Database::open_readonly(&path).map_err(|e| e.to_string())?;
+    replace_database(&state, new_db)?;
+    let name = std::path::Path::new(&path)
+        .file_name()
+        .map_or(path.clone(), |name| name.to_string_lossy().into_owned());
+    let _ = app.emit(
+        "workspace-changed",
+        Workspace {
+            name,
+            db_path: path,
+        },
+    );
+    Ok(())
 }
 
+/// Goes back from a read-only database to the active workspace's.
 #[tauri::command]
-async fn archive_thread(state: State<'_, AppState>, thread_id: i64) -> Result<(), String> {
-    let db = state.db.lock().map_err(|_| "Failed to lock DB")?;
-    db.archive_thread(thread_id).map_err(|e| e.to_string())?;
+fn close_readonly(app: AppHandle, state: State<AppState>) -> Result<(), String> {
+    let is_read_only = state
+        .db
+        .lock()
+        .map_err(|_| "Failed to lock DB")?
+        .is_read_only();
+    if !is_read_only {
+        return Err("No read-only database is open".to_string());
+    }
+    let workspace = state
+        .workspaces
+        .lock()
+        .map_err(|_| "Failed to lock workspaces")?
+        .active()
+        .clone();
+    let db = Database::new(&workspace.db_path).map_err(|e| e.to_string())?;
+    replace_database(&state, db)?;
+    let _ = app.emit("workspace-changed", &workspace);
     Ok(())
 }
 
+/// A thread ending in a user message older than this is taken to have had its
+/// reply cut off.
+const INTERRUPTED_AFTER_MINUTES: i64 = 2;
+
+/// Called by the window once its event listeners are registered. The first
+/// call runs the startup tidy-up, so the UI hears about what it changed.
+#[tauri::command]
+fn window_ready(app: AppHandle, state: State<AppState>) {
+    if !state.tidied_up.swap(true, Ordering::SeqCst) {
+        tidy_up_threads(&app);
+    }
+}
+
+/// Flags threads left interrupted by the last run and, when the user opted in,
+/// archives stale ones, announcing each as it would a change made later.
+fn tidy_up_threads(app: &AppHandle) {
+    let state = app.state::<AppState>();
+    let Ok(db) = state.db.lock() else {
+        return;
+    };
+    // Recent messages may belong to another instance that is still answering
+    let unanswered_before =
+        chrono::Utc::now() - chrono::Duration::minutes(INTERRUPTED_AFTER_MINUTES);
+    match thread_events::flag_interrupted_threads(app, &db, unanswered_before) {
+        Ok(flagged) => {
+            for thread_id in flagged {
+                notify_change(app, &db, DataChange::ThreadUpdated(thread_id));
+            }
+        }
+        Err(e) => eprintln!("Failed to check for interrupted threads: {}", e),
+    }
+    // Opt-in tidy-up of threads nobody has touched in a while
+    if let Some(days) = db
+        .get_setting("auto_archive_after_days")
+        .ok()
+        .flatten()
+        .and_then(|v| v.parse::<u32>().ok())
+        .filter(|&days| days > 0)
+    {
+        let cutoff = chrono::Utc::now() - chrono::Duration::days(days.into());
+        match thread_events::archive_stale_threads(app, &db, cutoff) {
+            Ok(archived) => {
+                for thread_id in archived {
+                    notify_change(app, &db, DataChange::ThreadUpdated(thread_id));
+                }
+            }
+            Err(e) => eprintln!("Failed to archive stale threads: {}", e),
+        }
+    }
+}
+
+const DEFAULT_DB_PATH: &str = "chat.db"; // In production, use app_data_dir
+const WORKSPACE_INDEX_PATH: &str = "workspaces.json";
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
-    let db_path = "chat.db"; // In production, use app_data_dir
-    let db = Database::new(db_path).expect("Failed to initialize database");
-    let ollama = OllamaClient::new("http://localhost:11434".to_string());
+    let workspaces =
+        WorkspaceIndex::load(std::path::Path::new(WORKSPACE_INDEX_PATH), DEFAULT_DB_PATH)
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to read workspaces, using the default one: {}", e);
+                WorkspaceIndex::new(DEFAULT_DB_PATH)
+            });
+    let db = Database::new(&workspaces.active().db_path).expect("Failed to initialize database");
+    let ollama = load_ollama_client(&db);
+
+    let handler = tauri::generate_handler![
+        create_thread,
+        get_threads,
+        get_interrupted_threads,
+        get_messages,
+        get_full_image,
+        get_threads_by_model,
+        get_models_used,
+        get_activity_stats,
+        get_model_stats,
+        get_latency_history,
+        list_workspaces,
+        create_workspace,
+        switch_workspace,
+        window_ready,
+        pick_image_files,
+        send_message,
+        inspect_pdf,
+        clear_document_cache,
+        estimate_attachments,
+        estimate_send,
+        get_limits,
+        fetch_url_content,
+        stop_generation,
+        regenerate_response,
+        regenerate_with_model,
+        continue_generation,
+        summarize_thread,
+        preview_context,
+        submit_tool_result,
+        edit_message,
+        add_thread_note,
+        redact_in_thread,
+        delete_message,
+        delete_thread,
+        rename_thread,
+        generate_thread_title,
+        merge_threads,
+        split_thread,
+        list_models,
+        list_model_names,
+        check_ollama,
+        get_app_diagnostics,
+        pull_model,
+        cancel_model_pull,
+        export_thread_as_model,
+        delete_model,
+        show_model,
+        get_model_capabilities,
+        generate_embeddings,
+        index_thread_embeddings,
+        semantic_search,
+        complete_text,
+        archive_thread,
+        archive_stale_threads,
+        regenerate_from_message,
+        get_setting,
+        set_setting,
+        get_ollama_url,
+        set_ollama_url,
+        set_ollama_auth,
+        create_server_profile,
+        get_server_profiles,
+        update_server_profile,
+        set_server_profile_tls,
+        set_default_server_profile,
+        delete_server_profile,
+        set_thread_server_profile,
+        set_thread_think,
+        set_thread_resend_thinking,
+        set_thread_save_thinking,
+        set_thread_max_history_messages,
+        set_message_excluded,
+        set_thread_options,
+        update_thread_system_prompt,
+        create_preset,
+        update_preset,
+        delete_preset,
+        list_presets,
+        list_models_annotated,
+        set_model_alias,
+        unset_model_alias,
+        warm_up_model,
+        unload_model,
+        get_running_models,
+        open_database_readonly,
+        close_readonly,
+    ];
 
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
+        .plugin(tauri_plugin_dialog::init())
         .manage(AppState {
             db: Mutex::new(db),
-            ollama,
+            ollama: RwLock::new(Arc::new(ollama)),
+            profile_clients: Mutex::new(HashMap::new()),
+            openai_clients: Mutex::new(HashMap::new()),
+            context_lengths: Mutex::new(HashMap::new()),
+            model_capabilities: Mutex::new(HashMap::new()),
+            summarizing: Mutex::new(HashSet::new()),
+            picked_images: Mutex::new(HashSet::new()),
+            tidied_up: AtomicBool::new(false),
+            busy_threads: BusyThreads::default(),
+            thread_tools: Mutex::new(HashMap::new()),
+            active_pulls: Mutex::new(HashMap::new()),
+            workspaces: Mutex::new(workspaces),
+        })
+        .invoke_handler(move |invoke| {
+            let command = invoke.message.command();
+            if !READ_ONLY_COMMANDS.contains(&command)
+                && invoke.message.webview().state::<AppState>().is_read_only()
+            {
+                invoke.resolver.reject(READ_ONLY_ERROR);
+                return true;
+            }
+            handler(invoke)
         })
-        .invoke_handler(tauri::generate_handler![
-            create_thread,
-            get_threads,
-            get_messages,
-            send_message,
-            regenerate_response,
-            edit_message,
-            delete_message,
-            delete_thread,
-            rename_thread,
-            list_models,
-            archive_thread,
-            regenerate_from_message,
-        ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
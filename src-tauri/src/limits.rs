@@ -0,0 +1,101 @@
+use serde::Serialize;
+
+/// Caps on what a single message may carry, checked before anything is read or
+/// saved. Everything in a message is stored and sent again on every later turn.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct MessageLimits {
+    pub max_content_chars: usize,
+    pub max_images: usize,
+    /// Decoded size of all images, documents and files together
+    pub max_attachment_bytes: usize,
+}
+
+impl Default for MessageLimits {
+    fn default() -> Self {
+        Self {
+            max_content_chars: 100_000,
+            max_images: 10,
+            max_attachment_bytes: 50 * 1024 * 1024,
+        }
+    }
+}
+
+impl MessageLimits {
+    /// Checks a message's text, image count and the base64 data of all its
+    /// attachments, naming the limit that was exceeded.
+    pub fn check<'a>(
+        &self,
+        content: &str,
+        image_count: usize,
+        attachments: impl IntoIterator<Item = &'a str>,
+    ) -> Result<(), String> {
+        let chars = content.chars().count();
+        if chars > self.max_content_chars {
+            return Err(format!(
+                "Message is {} characters, over the limit of {} (max_message_chars)",
+                chars, self.max_content_chars
+            ));
+        }
+        if image_count > self.max_images {
+            return Err(format!(
+                "Message has {} images, over the limit of {} (max_message_images)",
+                image_count, self.max_images
+            ));
+        }
+        let bytes: usize = attachments.into_iter().map(decoded_len).sum();
+        if bytes > self.max_attachment_bytes {
+            return Err(format!(
+                "Attachments total {} bytes, over the limit of {} (max_attachment_bytes)",
+                bytes, self.max_attachment_bytes
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Size of base64 data once decoded, with or without a data URL prefix, without
+/// decoding it.
+fn decoded_len(data: &str) -> usize {
+    let base64 = data.find(',').map_or(data, |idx| &data[idx + 1..]).trim();
+    let padding = base64.bytes().rev().take_while(|&b| b == b'=').count();
+    (base64.len() / 4 * 3).saturating_sub(padding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: MessageLimits = MessageLimits {
+        max_content_chars: 10,
+        max_images: 2,
+        max_attachment_bytes: 6,
+    };
+
+    #[test]
+    fn test_within_limits() {
+        // Characters, not bytes
+        assert!(LIMITS.check("héllo wörl", 2, ["aGk=", "aGk="]).is_ok());
+    }
+
+    #[test]
+    fn test_errors_name_the_limit_and_size() {
+        let err = LIMITS.check("01234567890", 0, []).unwrap_err();
+        assert!(err.contains("11 characters") && err.contains("max_message_chars"));
+
+        let err = LIMITS.check("", 3, []).unwrap_err();
+        assert!(err.contains("3 images") && err.contains("max_message_images"));
+
+        let err = LIMITS
+            .check("", 0, ["data:image/png;base64,aGVsbG8=", "aGk="])
+            .unwrap_err();
+        assert!(err.contains("7 bytes") && err.contains("max_attachment_bytes"));
+    }
+
+    #[test]
+    fn test_decoded_len() {
+        assert_eq!(decoded_len(""), 0);
+        assert_eq!(decoded_len("aGk="), 2);
+        assert_eq!(decoded_len("aGVsbG8="), 5);
+        assert_eq!(decoded_len("data:application/pdf;base64,aGVsbG8h"), 6);
+    }
+}
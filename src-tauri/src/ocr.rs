@@ -0,0 +1,31 @@
+//! Text recognition for scanned PDF pages, built with the `ocr` feature. Needs
+//! Tesseract and its English language data installed on the system.
+
+use image::ImageFormat;
+use lopdf::{Document, ObjectId};
+use std::error::Error;
+use std::io::Cursor;
+
+use crate::pdf_utils::decode_image;
+
+/// Recognizes the text in the images on a page. Scanners store each page as one
+/// full-page image, so that covers the page without rendering it.
+pub fn recognize_page(doc: &Document, page_id: ObjectId) -> Result<String, Box<dyn Error>> {
+    let mut texts = Vec::new();
+    for pdf_image in doc.get_page_images(page_id)? {
+        let Some(image) = decode_image(doc, pdf_image.id)? else {
+            continue;
+        };
+        let mut png = Vec::new();
+        image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+
+        let text = tesseract::Tesseract::new(None, Some("eng"))?
+            .set_image_from_mem(&png)?
+            .recognize()?
+            .get_text()?;
+        if !text.trim().is_empty() {
+            texts.push(text);
+        }
+    }
+    Ok(texts.join("\n"))
+}
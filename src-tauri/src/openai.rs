@@ -0,0 +1,553 @@
+use futures::future::BoxFuture;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::time::Instant;
+use tokio::sync::watch;
+
+use crate::backend::{ChatBackend, ChunkCallback, RetryCallback};
+use crate::ollama::{
+    build_http_client, cancelled, estimate_rate, read_lines, send_with_retry, ChatCompletion,
+    ChatOptions, ChatStats, ClientAuth, OllamaError, OllamaMessage, RetryPolicy, Role, StreamChunk,
+    StreamMetrics, ThinkTagSplitter, Timeouts, TlsOptions, ToolCall, ToolCallFunction,
+    ToolDefinition, METRICS_INTERVAL,
+};
+
+/// Client for servers that speak the OpenAI chat API, such as LM Studio or
+/// llama.cpp's `llama-server`.
+pub struct OpenAiCompatClient {
+    client: Client,
+    base_url: String,
+    retry_policy: RetryPolicy,
+    timeouts: Timeouts,
+    auth: ClientAuth,
+    tls: TlsOptions,
+}
+
+#[derive(Serialize, Debug)]
+struct CompletionRequest<'a> {
+    model: &'a str,
+    messages: Vec<Value>,
+    stream: bool,
+    stream_options: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<ToolDefinition>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CompletionChunk {
+    #[serde(default)]
+    choices: Vec<ChunkChoice>,
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChunkChoice {
+    #[serde(default)]
+    delta: Delta,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct Delta {
+    content: Option<String>,
+    // Reasoning models served by llama.cpp and LM Studio
+    reasoning_content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ToolCallDelta>,
+}
+
+/// Tool calls arrive in pieces; the arguments string is split across chunks.
+#[derive(Deserialize, Debug)]
+struct ToolCallDelta {
+    #[serde(default)]
+    index: usize,
+    id: Option<String>,
+    function: Option<FunctionDelta>,
+}
+
+#[derive(Deserialize, Debug)]
+struct FunctionDelta {
+    name: Option<String>,
+    arguments: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Usage {
+    prompt_tokens: Option<i64>,
+    completion_tokens: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ModelList {
+    data: Vec<ModelEntry>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ModelEntry {
+    id: String,
+}
+
+#[derive(Default)]
+struct PartialToolCall {
+    id: Option<String>,
+    name: String,
+    arguments: String,
+}
+
+/// Converts Ollama-style messages to the OpenAI format. Images become
+/// `image_url` parts and tool results are matched to their call by name.
+fn to_openai_messages(messages: Vec<OllamaMessage>) -> Vec<Value> {
+    let mut pending_calls: Vec<ToolCall> = Vec::new();
+
+    messages
+        .into_iter()
+        .map(|m| match m.role {
+            Role::Tool => {
+                let call_id = m.tool_name.as_ref().and_then(|name| {
+                    let pos = pending_calls
+                        .iter()
+                        .position(|c| &c.function.name == name)?;
+                    pending_calls.remove(pos).id
+                });
+                json!({
+                    "role": "tool",
+                    "content": m.content,
+                    "tool_call_id": call_id,
+                })
+            }
+            _ => {
+                let content = match m.images {
+                    Some(images) if !images.is_empty() => {
+                        let mut parts = vec![json!({ "type": "text", "text": m.content })];
+                        parts.extend(images.into_iter().map(|image| {
+                            let url = if image.starts_with("data:") {
+                                image
+                            } else {
+                                format!("data:image/jpeg;base64,{}", image)
+                            };
+                            json!({ "type": "image_url", "image_url": { "url": url } })
+                        }));
+                        Value::Array(parts)
+                    }
+                    _ => Value::String(m.content),
+                };
+
+                let mut message = json!({ "role": m.role, "content": content });
+                if let Some(calls) = m.tool_calls.filter(|calls| !calls.is_empty()) {
+                    message["tool_calls"] = calls
+                        .iter()
+                        .map(|call| {
+                            json!({
+                                "id": call.id,
+                                "type": "function",
+                                "function": {
+                                    "name": call.function.name,
+                                    "arguments": call.function.arguments.to_string(),
+                                },
+                            })
+                        })
+                        .collect();
+                    pending_calls = calls;
+                }
+                message
+            }
+        })
+        .collect()
+}
+
+/// Maps Ollama's `format` option ("json" or a schema) to `response_format`.
+fn to_response_format(format: Value) -> Value {
+    match format {
+        Value::String(_) => json!({ "type": "json_object" }),
+        schema => json!({
+            "type": "json_schema",
+            "json_schema": { "name": "response", "schema": schema },
+        }),
+    }
+}
+
+impl OpenAiCompatClient {
+    pub fn new(base_url: String) -> Self {
+        let timeouts = Timeouts::default();
+        let auth = ClientAuth::default();
+        let tls = TlsOptions::default();
+        Self {
+            client: build_http_client(&timeouts, &auth, &tls),
+            base_url,
+            retry_policy: RetryPolicy::default(),
+            timeouts,
+            auth,
+            tls,
+        }
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.client = build_http_client(&timeouts, &self.auth, &self.tls);
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Sends the given credentials, e.g. an API key as the bearer token, with every request.
+    pub fn with_auth(mut self, auth: ClientAuth) -> Self {
+        self.client = build_http_client(&self.timeouts, &auth, &self.tls);
+        self.auth = auth;
+        self
+    }
+
+    pub fn with_tls(mut self, tls: TlsOptions) -> Self {
+        self.client = build_http_client(&self.timeouts, &self.auth, &tls);
+        self.tls = tls;
+        self
+    }
+
+    pub fn auth(&self) -> &ClientAuth {
+        &self.auth
+    }
+
+    pub fn tls(&self) -> &TlsOptions {
+        &self.tls
+    }
+
+    pub async fn chat<F, R>(
+        &self,
+        model: &str,
+        messages: Vec<OllamaMessage>,
+        options: ChatOptions,
+        cancel: Option<watch::Receiver<bool>>,
+        callback: F,
+        on_retry: R,
+    ) -> Result<ChatCompletion, Box<dyn Error + Send + Sync>>
+    where
+        F: Fn(StreamChunk) + Send + Sync + 'static,
+        R: Fn(u32) + Send + Sync,
+    {
+        let url = format!("{}/v1/chat/completions", self.base_url);
+        let think_disabled = options.think == Some(false);
+        let request = CompletionRequest {
+            model,
+            messages: to_openai_messages(messages),
+            stream: true,
+            stream_options: json!({ "include_usage": true }),
+            response_format: options.format.map(to_response_format),
+            tools: options.tools,
+            // Ollama uses -1 for no limit; here that's expressed by leaving it out
+            max_tokens: options
+                .model_options
+                .as_ref()
+                .and_then(|o| o.num_predict)
+                .filter(|n| *n > 0),
+            stop: options
+                .model_options
+                .and_then(|o| o.stop)
+                .filter(|stop| !stop.is_empty()),
+        };
+
+        let sent_at = Instant::now();
+        let mut cancel_send = cancel.clone().unwrap_or_else(|| watch::channel(false).1);
+        let response = tokio::select! {
+            response = send_with_retry(
+                self.client.post(&url).json(&request),
+                &self.base_url,
+                &self.retry_policy,
+                on_retry,
+            ) => response?,
+            _ = cancelled(&mut cancel_send) => {
+                return Err(OllamaError::Cancelled {
+                    partial: String::new(),
+                }
+                .into())
+            }
+        };
+
+        let mut full_response = String::new();
+        let mut first_token_ms = None;
+        let mut thinking = String::new();
+        let mut partial_calls: Vec<PartialToolCall> = Vec::new();
+        let mut usage = None;
+        let mut done_reason = None;
+        let mut finished = false;
+        let mut think_tags = ThinkTagSplitter::default();
+
+        let mut first_chunk_at: Option<Instant> = None;
+        let mut last_metrics_at = Instant::now();
+        let mut chunk_count: u64 = 0;
+
+        let result = read_lines(response, self.timeouts.stream_idle, cancel, |line| {
+            // Server-sent events: only `data:` lines carry payloads
+            let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+                return Ok(false);
+            };
+            if data == "[DONE]" {
+                finished = true;
+                return Ok(true);
+            }
+
+            let value: Value = match serde_json::from_str(data) {
+                Ok(value) => value,
+                Err(_) => return Ok(false),
+            };
+            if let Some(error) = value.get("error") {
+                let message = error
+                    .get("message")
+                    .and_then(Value::as_str)
+                    .or_else(|| error.as_str())
+                    .unwrap_or("unknown error");
+                return Err(OllamaError::Stream(message.to_string()).into());
+            }
+            let Ok(chunk) = serde_json::from_value::<CompletionChunk>(value) else {
+                return Ok(false);
+            };
+
+            let started = *first_chunk_at.get_or_insert_with(Instant::now);
+            for choice in chunk.choices {
+                if choice.finish_reason.is_some() {
+                    done_reason = choice.finish_reason;
+                }
+                let delta = choice.delta;
+
+                if let Some(text) = delta.reasoning_content.filter(|t| !t.is_empty()) {
+                    if !think_disabled {
+                        chunk_count += 1;
+                        thinking.push_str(&text);
+                        callback(StreamChunk::Thinking(text));
+                    }
+                }
+
+                // Servers without a reasoning parser leave it inline
+                if let Some(text) = delta.content.filter(|t| !t.is_empty()) {
+                    let split = think_tags.push(&text);
+                    if !split.thinking.is_empty() && !think_disabled {
+                        chunk_count += 1;
+                        thinking.push_str(&split.thinking);
+                        callback(StreamChunk::Thinking(split.thinking));
+                    }
+                    if !split.content.is_empty() {
+                        chunk_count += 1;
+                        first_token_ms.get_or_insert(sent_at.elapsed().as_millis() as i64);
+                        full_response.push_str(&split.content);
+                        callback(StreamChunk::Content(split.content));
+                    }
+                }
+
+                for call in delta.tool_calls {
+                    if partial_calls.len() <= call.index {
+                        partial_calls.resize_with(call.index + 1, PartialToolCall::default);
+                    }
+                    let partial = &mut partial_calls[call.index];
+                    if call.id.is_some() {
+                        partial.id = call.id;
+                    }
+                    if let Some(function) = call.function {
+                        if let Some(name) = function.name {
+                            partial.name.push_str(&name);
+                        }
+                        if let Some(arguments) = function.arguments {
+                            partial.arguments.push_str(&arguments);
+                        }
+                    }
+                }
+            }
+            if chunk.usage.is_some() {
+                usage = chunk.usage;
+            }
+
+            if last_metrics_at.elapsed() >= METRICS_INTERVAL {
+                let elapsed = started.elapsed();
+                callback(StreamChunk::Metrics(StreamMetrics {
+                    elapsed_ms: elapsed.as_millis() as u64,
+                    chunk_count,
+                    tokens_per_second: estimate_rate(chunk_count, elapsed),
+                    done: false,
+                }));
+                last_metrics_at = Instant::now();
+            }
+            Ok(false)
+        })
+        .await;
+
+        if let Err(e) = result {
+            // Hand back what was received so the caller can keep it
+            return Err(match e.downcast::<OllamaError>() {
+                Ok(err) => err.with_partial(full_response).into(),
+                Err(e) => e,
+            });
+        }
+        if !finished && done_reason.is_none() {
+            return Err(OllamaError::Interrupted.into());
+        }
+        let rest = think_tags.finish();
+        if !rest.thinking.is_empty() && !think_disabled {
+            thinking.push_str(&rest.thinking);
+            callback(StreamChunk::Thinking(rest.thinking));
+        }
+        if !rest.content.is_empty() {
+            first_token_ms.get_or_insert(sent_at.elapsed().as_millis() as i64);
+            full_response.push_str(&rest.content);
+            callback(StreamChunk::Content(rest.content));
+        }
+
+        // These servers don't report timings, so measure generation time here
+        let elapsed = first_chunk_at.map(|t| t.elapsed()).unwrap_or_default();
+        let stats = ChatStats {
+            prompt_eval_count: usage.as_ref().and_then(|u| u.prompt_tokens),
+            eval_count: usage.as_ref().and_then(|u| u.completion_tokens),
+            eval_duration: Some(elapsed.as_nanos() as i64),
+            ..Default::default()
+        };
+        callback(StreamChunk::Metrics(StreamMetrics {
+            elapsed_ms: elapsed.as_millis() as u64,
+            chunk_count,
+            tokens_per_second: stats
+                .tokens_per_second()
+                .unwrap_or_else(|| estimate_rate(chunk_count, elapsed)),
+            done: true,
+        }));
+
+        let tool_calls = partial_calls
+            .into_iter()
+            .enumerate()
+            .map(|(i, call)| {
+                let arguments = if call.arguments.trim().is_empty() {
+                    json!({})
+                } else {
+                    serde_json::from_str(&call.arguments).unwrap_or(Value::String(call.arguments))
+                };
+                ToolCall {
+                    id: Some(call.id.unwrap_or_else(|| format!("call_{}", i))),
+                    function: ToolCallFunction {
+                        name: call.name,
+                        arguments,
+                    },
+                }
+            })
+            .collect();
+
+        Ok(ChatCompletion {
+            content: full_response,
+            thinking,
+            tool_calls,
+            stats: Some(stats),
+            done_reason,
+            first_token_ms,
+        })
+    }
+
+    pub async fn list_model_names(&self) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let url = format!("{}/v1/models", self.base_url);
+        let resp = send_with_retry(
+            self.client.get(&url).timeout(self.timeouts.request),
+            &self.base_url,
+            &self.retry_policy,
+            |_| {},
+        )
+        .await?
+        .json::<ModelList>()
+        .await?;
+        Ok(resp.data.into_iter().map(|m| m.id).collect())
+    }
+}
+
+impl ChatBackend for OpenAiCompatClient {
+    fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    fn chat<'a>(
+        &'a self,
+        model: &'a str,
+        messages: Vec<OllamaMessage>,
+        options: ChatOptions,
+        cancel: Option<watch::Receiver<bool>>,
+        callback: ChunkCallback,
+        on_retry: RetryCallback,
+    ) -> BoxFuture<'a, Result<ChatCompletion, Box<dyn Error + Send + Sync>>> {
+        Box::pin(OpenAiCompatClient::chat(
+            self, model, messages, options, cancel, callback, on_retry,
+        ))
+    }
+
+    fn list_model_names(&self) -> BoxFuture<'_, Result<Vec<String>, Box<dyn Error + Send + Sync>>> {
+        Box::pin(OpenAiCompatClient::list_model_names(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(role: Role, content: &str) -> OllamaMessage {
+        OllamaMessage {
+            role,
+            content: content.to_string(),
+            images: None,
+            thinking: None,
+            tool_calls: None,
+            tool_name: None,
+        }
+    }
+
+    #[test]
+    fn test_images_become_image_url_parts() {
+        let mut user = message(Role::User, "What is this?");
+        user.images = Some(vec!["aGVsbG8=".to_string()]);
+
+        let converted = to_openai_messages(vec![user]);
+        let parts = converted[0]["content"].as_array().unwrap();
+        assert_eq!(parts[0]["text"], "What is this?");
+        assert_eq!(
+            parts[1]["image_url"]["url"],
+            "data:image/jpeg;base64,aGVsbG8="
+        );
+    }
+
+    #[test]
+    fn test_tool_results_reference_their_call() {
+        let mut assistant = message(Role::Assistant, "");
+        assistant.tool_calls = Some(vec![ToolCall {
+            id: Some("call_0".to_string()),
+            function: ToolCallFunction {
+                name: "get_weather".to_string(),
+                arguments: json!({ "city": "Pune" }),
+            },
+        }]);
+        let mut tool = message(Role::Tool, "31°C");
+        tool.tool_name = Some("get_weather".to_string());
+
+        let converted = to_openai_messages(vec![assistant, tool]);
+        assert_eq!(
+            converted[0]["tool_calls"][0]["function"]["arguments"],
+            r#"{"city":"Pune"}"#
+        );
+        assert_eq!(converted[1]["tool_call_id"], "call_0");
+    }
+
+    #[test]
+    fn test_response_format_mapping() {
+        assert_eq!(
+            to_response_format(json!("json")),
+            json!({ "type": "json_object" })
+        );
+        let schema = json!({ "type": "object" });
+        assert_eq!(
+            to_response_format(schema.clone())["json_schema"]["schema"],
+            schema
+        );
+    }
+}
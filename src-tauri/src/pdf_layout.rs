@@ -0,0 +1,418 @@
+//! Text extraction that keeps the structure of a page. Headings, paragraphs and
+//! bullet lists are told apart by the font sizes and positions in the page's
+//! content stream and come out as Markdown.
+
+use lopdf::content::Operation;
+use lopdf::{Document, Encoding, Object, ObjectId};
+use std::collections::BTreeMap;
+
+/// Share of the font size a character is assumed to take up. Fonts' widths
+/// aren't read, so where a run of text ends is only estimated.
+const CHAR_WIDTH: f32 = 0.5;
+
+/// A line of text, in page coordinates (y grows upwards).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub x: f32,
+    pub y: f32,
+    /// Font size as drawn, after scaling
+    pub size: f32,
+    pub text: String,
+}
+
+/// Reads the text of a page as Markdown: `#` headings for text noticeably
+/// larger than the body, blank lines between paragraphs and `-` bullets for
+/// list items.
+pub fn extract_page_markdown(doc: &Document, page_id: ObjectId) -> lopdf::Result<String> {
+    Ok(to_markdown(&page_lines(doc, page_id)?))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Matrix([f32; 6]);
+
+impl Matrix {
+    const IDENTITY: Matrix = Matrix([1.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+
+    fn translate(tx: f32, ty: f32) -> Matrix {
+        Matrix([1.0, 0.0, 0.0, 1.0, tx, ty])
+    }
+
+    fn from_operands(operands: &[Object]) -> Option<Matrix> {
+        let values: Vec<f32> = operands.iter().filter_map(|o| o.as_float().ok()).collect();
+        values.try_into().ok().map(Matrix)
+    }
+
+    /// `self` applied first, then `other`.
+    fn then(self, other: Matrix) -> Matrix {
+        let [a, b, c, d, e, f] = self.0;
+        let [a2, b2, c2, d2, e2, f2] = other.0;
+        Matrix([
+            a * a2 + b * c2,
+            a * b2 + b * d2,
+            c * a2 + d * c2,
+            c * b2 + d * d2,
+            e * a2 + f * c2 + e2,
+            e * b2 + f * d2 + f2,
+        ])
+    }
+}
+
+/// What the content stream has set up so far.
+struct TextState<'a> {
+    ctm: Matrix,
+    saved: Vec<Matrix>,
+    text_matrix: Matrix,
+    line_matrix: Matrix,
+    leading: f32,
+    font_size: f32,
+    encoding: Option<&'a Encoding<'a>>,
+    /// Whether the text position was set since text was last drawn
+    moved: bool,
+}
+
+impl TextState<'_> {
+    fn next_line(&mut self, tx: f32, ty: f32) {
+        self.line_matrix = Matrix::translate(tx, ty).then(self.line_matrix);
+        self.text_matrix = self.line_matrix;
+        self.moved = true;
+    }
+
+    fn decode(&self, bytes: &[u8]) -> String {
+        match self.encoding {
+            Some(encoding) => Document::decode_text(encoding, bytes).unwrap_or_default(),
+            None => bytes.iter().map(|&b| b as char).collect(),
+        }
+    }
+}
+
+fn page_lines(doc: &Document, page_id: ObjectId) -> lopdf::Result<Vec<Line>> {
+    let encodings: BTreeMap<Vec<u8>, Encoding> = doc
+        .get_page_fonts(page_id)?
+        .into_iter()
+        .filter_map(|(name, font)| Some((name, font.get_font_encoding(doc).ok()?)))
+        .collect();
+    let content = doc.get_and_decode_page_content(page_id)?;
+
+    let mut state = TextState {
+        ctm: Matrix::IDENTITY,
+        saved: Vec::new(),
+        text_matrix: Matrix::IDENTITY,
+        line_matrix: Matrix::IDENTITY,
+        leading: 0.0,
+        font_size: 0.0,
+        encoding: None,
+        moved: true,
+    };
+    let mut lines = Vec::new();
+    for Operation { operator, operands } in &content.operations {
+        let number = |i: usize| {
+            operands
+                .get(i)
+                .and_then(|o| o.as_float().ok())
+                .unwrap_or(0.0)
+        };
+        match operator.as_str() {
+            "q" => state.saved.push(state.ctm),
+            "Q" => state.ctm = state.saved.pop().unwrap_or(Matrix::IDENTITY),
+            "cm" => {
+                if let Some(matrix) = Matrix::from_operands(operands) {
+                    state.ctm = matrix.then(state.ctm);
+                }
+            }
+            "BT" => {
+                state.text_matrix = Matrix::IDENTITY;
+                state.line_matrix = Matrix::IDENTITY;
+                state.moved = true;
+            }
+            "Tf" => {
+                state.encoding = operands
+                    .first()
+                    .and_then(|o| o.as_name().ok())
+                    .and_then(|name| encodings.get(name));
+                state.font_size = number(1);
+            }
+            "TL" => state.leading = number(0),
+            "Td" => state.next_line(number(0), number(1)),
+            "TD" => {
+                state.leading = -number(1);
+                state.next_line(number(0), number(1));
+            }
+            "T*" => state.next_line(0.0, -state.leading),
+            "Tm" => {
+                if let Some(matrix) = Matrix::from_operands(operands) {
+                    state.text_matrix = matrix;
+                    state.line_matrix = matrix;
+                    state.moved = true;
+                }
+            }
+            "Tj" | "'" | "\"" | "TJ" => {
+                if operator != "Tj" && operator != "TJ" {
+                    state.next_line(0.0, -state.leading);
+                }
+                let mut text = String::new();
+                // In thousandths of the font size
+                let mut adjustment = 0.0;
+                for operand in operands {
+                    match operand {
+                        Object::String(bytes, _) => text.push_str(&state.decode(bytes)),
+                        Object::Array(items) => {
+                            for item in items {
+                                match item {
+                                    Object::String(bytes, _) => text.push_str(&state.decode(bytes)),
+                                    _ => {
+                                        let Ok(n) = item.as_float() else { continue };
+                                        adjustment += n;
+                                        // Gaps this wide are spaces between words
+                                        if n < -100.0 && !text.ends_with(' ') {
+                                            text.push(' ');
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                show_text(&mut lines, &mut state, text, adjustment);
+            }
+            _ => {}
+        }
+    }
+    Ok(lines)
+}
+
+/// Adds `text`, drawn at the current position, to `lines`: to the last line if
+/// it continues it, or as a new one.
+fn show_text(lines: &mut Vec<Line>, state: &mut TextState, text: String, adjustment: f32) {
+    let device = state.text_matrix.then(state.ctm);
+    let [_, _, c, d, x, y] = device.0;
+    let size = state.font_size.abs() * c.hypot(d);
+    let chars = text.chars().count() as f32;
+    let moved = std::mem::replace(&mut state.moved, false);
+    let advance = state.font_size * (chars * CHAR_WIDTH - adjustment / 1000.0);
+    state.text_matrix = Matrix::translate(advance, 0.0).then(state.text_matrix);
+
+    let same_line = |line: &&mut Line| (line.y - y).abs() <= 0.3 * line.size.max(size);
+    let Some(line) = lines.last_mut().filter(same_line) else {
+        if !text.trim().is_empty() {
+            lines.push(Line { x, y, size, text });
+        }
+        return;
+    };
+    // Words drawn separately are a gap apart; a run continuing the last has none
+    let end = line.x + line.text.chars().count() as f32 * CHAR_WIDTH * line.size;
+    if moved
+        && x - end > 0.1 * size
+        && !line.text.ends_with(char::is_whitespace)
+        && !text.starts_with(char::is_whitespace)
+    {
+        line.text.push(' ');
+    }
+    line.text.push_str(&text);
+    line.size = line.size.max(size);
+}
+
+#[derive(Debug, PartialEq)]
+enum BlockKind {
+    Heading(usize),
+    Paragraph,
+    /// A list item, with its marker when numbered
+    Item(Option<String>),
+}
+
+struct Block {
+    kind: BlockKind,
+    text: String,
+    /// Where the block's first line starts
+    x: f32,
+    size: f32,
+    last_y: f32,
+}
+
+/// Heading level for text of `size` when the body text is `body_size`.
+fn heading_level(size: f32, body_size: f32) -> Option<usize> {
+    let ratio = size / body_size;
+    if ratio >= 1.8 {
+        Some(1)
+    } else if ratio >= 1.4 {
+        Some(2)
+    } else if ratio >= 1.15 {
+        Some(3)
+    } else {
+        None
+    }
+}
+
+/// Splits a list item into its marker (`None` for a bullet) and text.
+fn list_item(text: &str) -> Option<(Option<String>, &str)> {
+    const BULLETS: &[char] = &['•', '◦', '▪', '‣', '●', '○', '■', '□', '·', '–', '-', '*'];
+    let mut chars = text.chars();
+    let first = chars.next()?;
+    if BULLETS.contains(&first) {
+        let rest = chars.as_str();
+        // A hyphen or dash needs a space after it, or it's just a word starting with one
+        let glyph = !matches!(first, '-' | '–' | '*');
+        if rest.starts_with(char::is_whitespace) || (glyph && !rest.is_empty()) {
+            return Some((None, rest.trim_start()));
+        }
+        return None;
+    }
+
+    let digits = text.find(|c: char| !c.is_ascii_digit())?;
+    let rest = &text[digits..];
+    if (1..=3).contains(&digits) && (rest.starts_with(". ") || rest.starts_with(") ")) {
+        let marker = format!("{}.", &text[..digits]);
+        return Some((Some(marker), rest[2..].trim_start()));
+    }
+    None
+}
+
+/// Appends a line to the text of a paragraph, rejoining words hyphenated across
+/// the line break.
+fn join_line(text: &mut String, line: &str) {
+    let hyphenated = text.ends_with('-')
+        && text[..text.len() - 1].ends_with(char::is_alphabetic)
+        && line.starts_with(char::is_lowercase);
+    if hyphenated {
+        text.pop();
+    } else if !text.is_empty() {
+        text.push(' ');
+    }
+    text.push_str(line);
+}
+
+/// Groups the lines of a page into headings, paragraphs and list items, and
+/// writes them out as Markdown.
+pub fn to_markdown(lines: &[Line]) -> String {
+    // The body size is the one most of the text is set in
+    let mut sizes: BTreeMap<u32, usize> = BTreeMap::new();
+    for line in lines {
+        *sizes.entry((line.size * 2.0).round() as u32).or_default() +=
+            line.text.trim().chars().count();
+    }
+    let body_size = sizes
+        .iter()
+        .max_by_key(|(_, chars)| **chars)
+        .map_or(0.0, |(size, _)| *size as f32 / 2.0);
+
+    let mut blocks: Vec<Block> = Vec::new();
+    for line in lines {
+        let text = line.text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if text.is_empty() {
+            continue;
+        }
+        let item = list_item(&text).map(|(marker, item)| (marker, item.to_string()));
+        let (kind, text) = match (heading_level(line.size, body_size), item) {
+            (Some(level), _) if text.chars().count() <= 200 => (BlockKind::Heading(level), text),
+            (_, Some((marker, item))) => (BlockKind::Item(marker), item),
+            _ => (BlockKind::Paragraph, text),
+        };
+
+        if let Some(block) = blocks.last_mut() {
+            let gap = block.last_y - line.y;
+            let close = gap >= 0.0 && gap <= 1.6 * line.size.max(block.size);
+            let same_size = (line.size - block.size).abs() <= 0.1 * block.size;
+            let continues = match (&block.kind, &kind) {
+                // A title set over several lines
+                (BlockKind::Heading(a), BlockKind::Heading(b)) => a == b,
+                // The wrapped text of an item is indented past its bullet
+                (BlockKind::Item(_), BlockKind::Paragraph) => line.x > block.x + 0.5 * line.size,
+                // An indented first line starts a new paragraph
+                (BlockKind::Paragraph, BlockKind::Paragraph) => line.x <= block.x + line.size,
+                _ => false,
+            };
+            if close && same_size && continues {
+                join_line(&mut block.text, &text);
+                block.last_y = line.y;
+                if block.kind == BlockKind::Paragraph {
+                    block.x = block.x.min(line.x);
+                }
+                continue;
+            }
+        }
+
+        blocks.push(Block {
+            kind,
+            text,
+            x: line.x,
+            size: line.size,
+            last_y: line.y,
+        });
+    }
+
+    let mut markdown = String::new();
+    let mut previous: Option<&BlockKind> = None;
+    for block in &blocks {
+        if let Some(previous) = previous {
+            let in_list =
+                matches!(previous, BlockKind::Item(_)) && matches!(block.kind, BlockKind::Item(_));
+            markdown.push_str(if in_list { "\n" } else { "\n\n" });
+        }
+        match &block.kind {
+            BlockKind::Heading(level) => {
+                markdown.push_str(&"#".repeat(*level));
+                markdown.push(' ');
+            }
+            BlockKind::Item(None) => markdown.push_str("- "),
+            BlockKind::Item(Some(marker)) => {
+                markdown.push_str(marker);
+                markdown.push(' ');
+            }
+            BlockKind::Paragraph => {}
+        }
+        markdown.push_str(&block.text);
+        previous = Some(&block.kind);
+    }
+    markdown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(x: f32, y: f32, size: f32, text: &str) -> Line {
+        Line {
+            x,
+            y,
+            size,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_headings_paragraphs_and_lists() {
+        let lines = [
+            line(72.0, 750.0, 24.0, "Annual Report"),
+            line(72.0, 710.0, 13.0, "3.2 Revenue"),
+            line(72.0, 690.0, 10.0, "Revenue grew in every re-"),
+            line(72.0, 678.0, 10.0, "gion this year."),
+            line(90.0, 650.0, 10.0, "A second paragraph, indented."),
+            line(72.0, 620.0, 10.0, "• Europe up 4%"),
+            line(72.0, 608.0, 10.0, "- Asia up 9%, the"),
+            line(82.0, 596.0, 10.0, "fastest growth"),
+            line(72.0, 570.0, 10.0, "1. First step"),
+        ];
+        assert_eq!(
+            to_markdown(&lines),
+            "# Annual Report\n\n\
+             ### 3.2 Revenue\n\n\
+             Revenue grew in every region this year.\n\n\
+             A second paragraph, indented.\n\n\
+             - Europe up 4%\n\
+             - Asia up 9%, the fastest growth\n\
+             1. First step"
+        );
+    }
+
+    #[test]
+    fn test_list_items() {
+        assert_eq!(list_item("•Item"), Some((None, "Item")));
+        assert_eq!(list_item("- Item"), Some((None, "Item")));
+        assert_eq!(list_item("-5 degrees"), None);
+        assert_eq!(
+            list_item("12) Step"),
+            Some((Some("12.".to_string()), "Step"))
+        );
+        assert_eq!(list_item("2024. A year"), None);
+    }
+}
@@ -1,27 +1,1472 @@
-use lopdf::Document;
+use base64::{engine::general_purpose, Engine as _};
+use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeZone};
+use image::{DynamicImage, GrayImage, ImageFormat, RgbImage};
+use lopdf::{Document, Object, ObjectId};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
 use std::io::Cursor;
 
-pub fn extract_text_from_pdf(bytes: &[u8]) -> Result<String, Box<dyn std::error::Error>> {
+use crate::pdf_layout;
+
+/// Inclusive, 1-based range of pages to read from a PDF.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct PageRange {
+    pub start_page: u32,
+    pub end_page: u32,
+}
+
+#[derive(Debug)]
+pub struct PdfText {
+    pub text: String,
+    /// Pages actually read, after clamping the requested range; 0 for an empty document
+    pub first_page: u32,
+    pub last_page: u32,
+    pub page_count: u32,
+    /// Whether part of the text was left out to fit `PdfLimits::max_chars`
+    pub truncated: bool,
+    /// Pages without a text layer whose text was recognized with OCR
+    pub ocr_pages: Vec<u32>,
+    /// Pages without a text layer that OCR couldn't read, or that weren't tried
+    /// because this build has no OCR
+    pub unreadable_pages: Vec<u32>,
+}
+
+/// The text read from a document's pages so far, cached by the hash of the file
+/// so re-attaching it doesn't read it again.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ExtractedPages {
+    pub page_count: u32,
+    /// Entries cached before structured extraction hold plain text
+    #[serde(default = "plain_layout")]
+    pub layout: TextLayout,
+    /// By page number
+    pub pages: BTreeMap<u32, PageText>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PageText {
+    pub text: String,
+    pub source: TextSource,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TextSource {
+    TextLayer,
+    Ocr,
+    /// No text layer, and OCR found nothing or isn't available
+    Unreadable,
+}
+
+fn plain_layout() -> TextLayout {
+    TextLayout::Plain
+}
+
+/// Hex SHA-256 of a file, the key its extracted text is cached under.
+pub fn content_hash(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Caps on what a PDF attachment may cost, so a huge scan can't stall the app or
+/// flood the prompt.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PdfLimits {
+    pub max_bytes: usize,
+    /// Applies to the pages selected, so picking a range gets around it
+    pub max_pages: u32,
+    /// Longer text is cut down according to `truncation` rather than rejected
+    pub max_chars: usize,
+    pub truncation: Truncation,
+    pub layout: TextLayout,
+}
+
+impl Default for PdfLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: 50 * 1024 * 1024,
+            max_pages: 50,
+            max_chars: 200_000,
+            truncation: Truncation::default(),
+            layout: TextLayout::default(),
+        }
+    }
+}
+
+impl PdfLimits {
+    pub fn unlimited() -> Self {
+        Self {
+            max_bytes: usize::MAX,
+            max_pages: u32::MAX,
+            max_chars: usize::MAX,
+            truncation: Truncation::default(),
+            layout: TextLayout::default(),
+        }
+    }
+}
+
+/// Which part of a document's text to keep when it's over budget.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Truncation {
+    /// The first `max_chars` characters
+    Head,
+    /// Half the budget from the start and half from the end, as conclusions and
+    /// appendices tend to matter as much as the introduction
+    #[default]
+    HeadAndTail,
+}
+
+impl Truncation {
+    /// Parses the `pdf_truncation` setting: `head` or `head_and_tail`.
+    pub fn from_setting(value: &str) -> Option<Truncation> {
+        match value {
+            "head" => Some(Truncation::Head),
+            "head_and_tail" => Some(Truncation::HeadAndTail),
+            _ => None,
+        }
+    }
+}
+
+/// How the text of a page is written out.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TextLayout {
+    /// Markdown headings, paragraphs and bullet lists, guessed from font sizes
+    /// and positions
+    #[default]
+    Structured,
+    /// The text as lopdf reads it, one line per text object
+    Plain,
+}
+
+impl TextLayout {
+    /// Parses the `pdf_text_layout` setting: `structured` or `plain`.
+    pub fn from_setting(value: &str) -> Option<TextLayout> {
+        match value {
+            "structured" => Some(TextLayout::Structured),
+            "plain" => Some(TextLayout::Plain),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PdfLimitError {
+    TooLarge {
+        bytes: usize,
+        max_bytes: usize,
+    },
+    TooManyPages {
+        pages: u32,
+        max_pages: u32,
+        selected: bool,
+    },
+}
+
+impl fmt::Display for PdfLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PdfLimitError::TooLarge { bytes, max_bytes } => write!(
+                f,
+                "PDF exceeds the {} size limit (was {})",
+                format_size(*max_bytes),
+                format_size(*bytes)
+            ),
+            PdfLimitError::TooManyPages {
+                pages,
+                max_pages,
+                selected: false,
+            } => write!(
+                f,
+                "PDF exceeds the {}-page limit (had {} pages); select a page range",
+                max_pages, pages
+            ),
+            PdfLimitError::TooManyPages {
+                pages,
+                max_pages,
+                selected: true,
+            } => write!(
+                f,
+                "Selected page range exceeds the {}-page limit ({} pages); select fewer pages",
+                max_pages, pages
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PdfLimitError {}
+
+/// A password-protected document that can't be read as given. The messages are
+/// matched by the UI to ask for the password, so keep them stable.
+#[derive(Debug, PartialEq)]
+pub enum PdfError {
+    PasswordRequired,
+    WrongPassword,
+}
+
+impl fmt::Display for PdfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PdfError::PasswordRequired => "PDF is password-protected; a password is required",
+            PdfError::WrongPassword => "Incorrect PDF password",
+        })
+    }
+}
+
+impl std::error::Error for PdfError {}
+
+fn format_size(bytes: usize) -> String {
+    const MB: f64 = 1024.0 * 1024.0;
+    format!("{:.1} MB", bytes as f64 / MB)
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct PdfMetadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub producer: Option<String>,
+    /// Creation date as RFC 3339, or as written in the file if it can't be parsed
+    pub created: Option<String>,
+    pub page_count: u32,
+    pub size_bytes: u64,
+    /// Password-protected documents can't be read; only `size_bytes` is filled in
+    pub encrypted: bool,
+}
+
+/// Reads the document's Info dictionary and page count. Entries missing from the
+/// Info dictionary are `None`.
+pub fn extract_metadata(bytes: &[u8]) -> Result<PdfMetadata, Box<dyn std::error::Error>> {
+    let size_bytes = bytes.len() as u64;
+    let encrypted = PdfMetadata {
+        size_bytes,
+        encrypted: true,
+        ..Default::default()
+    };
+
+    let doc = match Document::load_from(Cursor::new(bytes)) {
+        Ok(doc) => doc,
+        // Loading fails when the document can't be decrypted without a password
+        Err(_) if contains(bytes, b"/Encrypt") => return Ok(encrypted),
+        Err(e) => return Err(e.into()),
+    };
+    if doc.is_encrypted() {
+        return Ok(encrypted);
+    }
+
+    let info = doc
+        .trailer
+        .get(b"Info")
+        .and_then(|info| match info {
+            Object::Reference(id) => doc.get_dictionary(*id),
+            other => other.as_dict(),
+        })
+        .ok();
+    let entry = |key: &[u8]| {
+        info.and_then(|info| info.get(key).ok())
+            .and_then(|value| value.as_str().ok())
+            .map(decode_text_string)
+            .filter(|value| !value.trim().is_empty())
+    };
+
+    Ok(PdfMetadata {
+        title: entry(b"Title"),
+        author: entry(b"Author"),
+        producer: entry(b"Producer"),
+        created: entry(b"CreationDate").map(|date| parse_pdf_date(&date).unwrap_or(date)),
+        page_count: doc.get_pages().len() as u32,
+        size_bytes,
+        encrypted: false,
+    })
+}
+
+/// Loads the document, decrypting it with `password` if it's encrypted. Also
+/// tells whether it was, as the text of such documents mustn't be cached.
+/// Documents with only an owner password (no password needed to open them) are
+/// decrypted by lopdf on load and count as unencrypted.
+fn load_document(
+    bytes: &[u8],
+    password: Option<&str>,
+) -> Result<(Document, bool), Box<dyn std::error::Error>> {
+    let password_error = match password {
+        Some(_) => PdfError::WrongPassword,
+        None => PdfError::PasswordRequired,
+    };
+    let mut doc = match Document::load_from(Cursor::new(bytes)) {
+        Ok(doc) => doc,
+        Err(_) if contains(bytes, b"/Encrypt") => return Err(password_error.into()),
+        Err(e) => return Err(e.into()),
+    };
+    if !doc.is_encrypted() {
+        return Ok((doc, false));
+    }
+    match password {
+        Some(password) if doc.decrypt(password).is_ok() => Ok((doc, true)),
+        _ => Err(password_error.into()),
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+/// Decodes a PDF text string: UTF-16BE when it starts with a byte order mark,
+/// otherwise PDFDocEncoding, which matches Latin-1 for printable text.
+fn decode_text_string(bytes: &[u8]) -> String {
+    match bytes {
+        [0xFE, 0xFF, rest @ ..] => {
+            let units: Vec<u16> = rest
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        [0xEF, 0xBB, 0xBF, rest @ ..] => String::from_utf8_lossy(rest).into_owned(),
+        _ => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+/// Parses a PDF date such as `D:20240315093000+01'00'` into RFC 3339.
+fn parse_pdf_date(date: &str) -> Option<String> {
+    let date = date.strip_prefix("D:").unwrap_or(date);
+    let digits: String = date.chars().take_while(|c| c.is_ascii_digit()).collect();
+    if digits.len() < 4 {
+        return None;
+    }
+    // Everything after the year is optional and defaults to the start of the period
+    let padded = format!("{}{}", digits, &"0101000000"[(digits.len() - 4).min(10)..]);
+    let naive = NaiveDateTime::parse_from_str(&padded[..14], "%Y%m%d%H%M%S").ok()?;
+
+    let zone = &date[digits.len()..];
+    let offset_seconds = match zone.chars().next() {
+        Some(sign @ ('+' | '-')) => {
+            let parts: Vec<i32> = zone[1..]
+                .split('\'')
+                .filter_map(|part| part.parse().ok())
+                .collect();
+            let seconds = parts.first().copied().unwrap_or(0) * 3600
+                + parts.get(1).copied().unwrap_or(0) * 60;
+            if sign == '-' {
+                -seconds
+            } else {
+                seconds
+            }
+        }
+        _ => 0,
+    };
+    let offset = FixedOffset::east_opt(offset_seconds)?;
+    let datetime: DateTime<FixedOffset> = offset.from_local_datetime(&naive).single()?;
+    Some(datetime.to_rfc3339())
+}
+
+/// Runs `extract` on the blocking thread pool. Extraction is CPU-bound and can
+/// take seconds, which would stall every other command on the async runtime.
+pub async fn spawn_extraction<T, F>(extract: F) -> Result<T, String>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    tauri::async_runtime::spawn_blocking(extract)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Extracts the text of every page, calling `on_progress` as it goes. Fails
+/// with `PdfError::PasswordRequired` if the document is encrypted.
+pub fn extract_text_from_pdf<F>(
+    bytes: &[u8],
+    on_progress: F,
+) -> Result<String, Box<dyn std::error::Error>>
+where
+    F: FnMut(ExtractionProgress),
+{
+    extract_text_with_ocr(bytes, None, &PdfLimits::unlimited(), on_progress).map(|pdf| pdf.text)
+}
+
+/// Extracts the text of the pages in `range`, or of all pages without one. A range
+/// reaching past either end of the document is clamped to it. Fails with a
+/// `PdfLimitError` if the file or the page selection is over `limits`; text past
+/// `limits.max_chars` is cut down with [`fit_to_budget`].
+pub fn extract_text_from_pdf_range(
+    bytes: &[u8],
+    range: Option<PageRange>,
+    limits: &PdfLimits,
+) -> Result<PdfText, Box<dyn std::error::Error>> {
+    extract_text_with_ocr(bytes, range, limits, |_| {})
+}
+
+/// Like `extract_text_from_pdf_range`, but pages without a usable text layer
+/// (scans) are run through OCR when the app is built with the `ocr` feature.
+/// `on_progress` is called as each page is read and before each page is
+/// recognized, as that is slow.
+pub fn extract_text_with_ocr<F>(
+    bytes: &[u8],
+    range: Option<PageRange>,
+    limits: &PdfLimits,
+    on_progress: F,
+) -> Result<PdfText, Box<dyn std::error::Error>>
+where
+    F: FnMut(ExtractionProgress),
+{
+    extract_text_cached(bytes, range, None, limits, &mut None, on_progress)
+}
+
+/// Like `extract_text_with_ocr`, taking pages already in `cache` from there
+/// instead of reading them again. The document isn't even parsed if every
+/// selected page is cached. Pages read are added to `cache`, which is filled in
+/// if it was `None`. Encrypted documents are decrypted with `password`, failing
+/// with a `PdfError` without one or with the wrong one, and are never added to
+/// `cache`.
+pub fn extract_text_cached<F>(
+    bytes: &[u8],
+    range: Option<PageRange>,
+    password: Option<&str>,
+    limits: &PdfLimits,
+    cache: &mut Option<ExtractedPages>,
+    mut on_progress: F,
+) -> Result<PdfText, Box<dyn std::error::Error>>
+where
+    F: FnMut(ExtractionProgress),
+{
+    if bytes.len() > limits.max_bytes {
+        return Err(PdfLimitError::TooLarge {
+            bytes: bytes.len(),
+            max_bytes: limits.max_bytes,
+        }
+        .into());
+    }
+
+    // Pages read in the other layout are no use
+    if cache
+        .as_ref()
+        .is_some_and(|cached| cached.layout != limits.layout)
+    {
+        *cache = None;
+    }
+
     // Load the PDF document from bytes
-    let doc = Document::load_from(Cursor::new(bytes))?;
-    
-    // Get all page numbers
-    let pages = doc.get_pages();
+    let mut doc = None;
+    // Pages of an encrypted document, kept out of `cache`
+    let mut uncached = None;
+    if cache.is_none() {
+        let (loaded, encrypted) = load_document(bytes, password)?;
+        let pages = ExtractedPages {
+            page_count: loaded.get_pages().len() as u32,
+            layout: limits.layout,
+            pages: BTreeMap::new(),
+        };
+        if encrypted {
+            uncached = Some(pages);
+        } else {
+            *cache = Some(pages);
+        }
+        doc = Some(loaded);
+    }
+    let cached = match uncached.as_mut() {
+        Some(pages) => pages,
+        None => cache.get_or_insert_with(ExtractedPages::default),
+    };
+    let page_count = cached.page_count;
+    let (first_page, last_page) = match (range, page_count) {
+        (_, 0) => (0, 0),
+        (Some(range), _) => {
+            let first = range.start_page.clamp(1, page_count);
+            (first, range.end_page.clamp(first, page_count))
+        }
+        (None, _) => (1, page_count),
+    };
+
+    let selected_pages = if page_count == 0 {
+        0
+    } else {
+        last_page - first_page + 1
+    };
+    if selected_pages > limits.max_pages {
+        return Err(PdfLimitError::TooManyPages {
+            pages: selected_pages,
+            max_pages: limits.max_pages,
+            selected: range.is_some(),
+        }
+        .into());
+    }
+
+    let selection = if page_count == 0 {
+        Vec::new()
+    } else {
+        (first_page..=last_page).collect()
+    };
+    if doc.is_none() && selection.iter().any(|n| !cached.pages.contains_key(n)) {
+        doc = Some(load_document(bytes, password)?.0);
+    }
+    // Ordered by page number
+    let page_ids = doc.as_ref().map(Document::get_pages).unwrap_or_default();
+
+    // Keeping only the head means later pages would be cut off anyway
+    let head_only = limits.truncation == Truncation::Head;
     let mut texts = Vec::new();
+    let mut chars = 0;
+    let mut scanned = Vec::new();
+    let mut ocr_pages = Vec::new();
+    let mut unreadable_pages = Vec::new();
+    let total_pages = selection.len() as u32;
+    for page_num in selection {
+        let text = if let Some(page) = cached.pages.get(&page_num) {
+            match page.source {
+                TextSource::TextLayer => {}
+                TextSource::Ocr => ocr_pages.push(page_num),
+                TextSource::Unreadable => unreadable_pages.push(page_num),
+            }
+            page.text.clone()
+        } else if let Some(doc) = &doc {
+            let text = read_page(doc, page_ids[&page_num], page_num, limits.layout);
+            if lacks_text_layer(&text) {
+                scanned.push(page_num);
+            } else {
+                cached.pages.insert(
+                    page_num,
+                    PageText {
+                        text: text.clone(),
+                        source: TextSource::TextLayer,
+                    },
+                );
+            }
+            text
+        } else {
+            String::new()
+        };
+        chars += text.chars().count();
+        texts.push((page_num, text));
+        on_progress(ExtractionProgress::Page {
+            page: texts.len() as u32,
+            total_pages,
+        });
+        if head_only && chars > limits.max_chars {
+            break;
+        }
+    }
+
+    for (done, &page_num) in scanned.iter().enumerate() {
+        let Some(entry) = texts.iter_mut().find(|(n, _)| *n == page_num) else {
+            continue;
+        };
+        let recognized = match &doc {
+            Some(doc) if OCR_AVAILABLE && !(head_only && chars > limits.max_chars) => {
+                on_progress(ExtractionProgress::Ocr(OcrProgress {
+                    page: page_num,
+                    done: done as u32,
+                    total: scanned.len() as u32,
+                }));
+                Some(ocr_page(doc, page_ids[&page_num]))
+            }
+            _ => None,
+        };
+        // Pages where OCR failed or was skipped aren't cached, so it's tried again
+        let (source, keep) = match recognized {
+            Some(Ok(recognized)) if !recognized.trim().is_empty() => {
+                chars += recognized.chars().count();
+                entry.1 = recognized;
+                ocr_pages.push(page_num);
+                (TextSource::Ocr, true)
+            }
+            Some(Ok(_)) => (TextSource::Unreadable, true),
+            Some(Err(e)) => {
+                eprintln!("OCR failed on page {}: {}", page_num, e);
+                (TextSource::Unreadable, false)
+            }
+            None => (TextSource::Unreadable, !OCR_AVAILABLE),
+        };
+        if source == TextSource::Unreadable {
+            unreadable_pages.push(page_num);
+        }
+        if keep {
+            cached.pages.insert(
+                page_num,
+                PageText {
+                    text: entry.1.clone(),
+                    source,
+                },
+            );
+        }
+    }
+    ocr_pages.sort_unstable();
+    unreadable_pages.sort_unstable();
+
+    let (text, truncated) = fit_to_budget(&texts, limits.max_chars, limits.truncation);
+
+    Ok(PdfText {
+        text,
+        first_page,
+        last_page,
+        page_count,
+        truncated,
+        ocr_pages,
+        unreadable_pages,
+    })
+}
+
+/// Joins the text of `pages` (page number and text), leaving out what doesn't fit
+/// in `max_chars` characters. The cuts fall between paragraphs where one is near
+/// enough, and the gap is marked with e.g.
+/// `[... 312,000 characters omitted from pages 40–290 ...]`. Returns the text and
+/// whether anything was left out.
+pub fn fit_to_budget(
+    pages: &[(u32, String)],
+    max_chars: usize,
+    truncation: Truncation,
+) -> (String, bool) {
+    // Byte offset in `text` where each page starts
+    let mut starts = Vec::new();
+    let mut text = String::new();
+    for (page_num, page_text) in pages {
+        if page_text.trim().is_empty() {
+            continue;
+        }
+        if !text.is_empty() {
+            text.push_str("\n\n");
+        }
+        starts.push((text.len(), *page_num));
+        text.push_str(page_text);
+    }
+
+    let total = text.chars().count();
+    if total <= max_chars {
+        return (text, false);
+    }
+    let (head_chars, tail_chars) = match truncation {
+        Truncation::Head => (max_chars, 0),
+        Truncation::HeadAndTail => (max_chars / 2, max_chars - max_chars / 2),
+    };
+    let byte_at = |chars: usize| {
+        text.char_indices()
+            .nth(chars)
+            .map_or(text.len(), |(b, _)| b)
+    };
+
+    // Prefer a paragraph break, unless it would waste more than half the budget
+    let head_limit = byte_at(head_chars);
+    let head_end = match text[..head_limit].rfind("\n\n") {
+        Some(b) if text[..b].chars().count() * 2 >= head_chars => b,
+        _ => head_limit,
+    };
+    let tail_limit = byte_at(total - tail_chars);
+    let tail_start = match text[tail_limit..].find("\n\n") {
+        Some(b) if text[tail_limit + b..].chars().count() * 2 >= tail_chars => tail_limit + b,
+        _ => tail_limit,
+    };
+
+    let omitted = &text[head_end..tail_start];
+    let first_byte = head_end + (omitted.len() - omitted.trim_start().len());
+    let last_byte = (head_end + omitted.trim_end().len()).max(first_byte + 1) - 1;
+    let page_at = |byte: usize| {
+        starts
+            .iter()
+            .take_while(|(start, _)| *start <= byte)
+            .last()
+            .map_or(0, |&(_, page_num)| page_num)
+    };
+    let (first_page, last_page) = (page_at(first_byte), page_at(last_byte));
+    let marker = format!(
+        "[... {} characters omitted from {} ...]",
+        format_count(omitted.trim().chars().count()),
+        if first_page == last_page {
+            format!("page {}", first_page)
+        } else {
+            format!("pages {}–{}", first_page, last_page)
+        }
+    );
+
+    let kept = [
+        text[..head_end].trim_end(),
+        &marker,
+        text[tail_start..].trim_start(),
+    ];
+    let kept: Vec<&str> = kept.into_iter().filter(|part| !part.is_empty()).collect();
+    (kept.join("\n\n"), true)
+}
+
+/// 312000 as "312,000".
+fn format_count(count: usize) -> String {
+    let digits = count.to_string();
+    let mut out = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            out.push(',');
+        }
+        out.push(digit);
+    }
+    out
+}
+
+/// Cuts `text` off after `max_chars` characters, appending a
+/// `[truncated after N characters]` marker. Returns whether it was cut.
+pub fn truncate_with_marker(text: &mut String, max_chars: usize) -> bool {
+    let Some((cut, _)) = text.char_indices().nth(max_chars) else {
+        return false;
+    };
+    text.truncate(cut);
+    text.push_str(&format!("\n[truncated after {} characters]", max_chars));
+    true
+}
+
+/// Images taken from the PDFs in a message unless the `pdf_max_images` setting
+/// says otherwise.
+pub const DEFAULT_MAX_IMAGES: usize = 8;
+
+/// Total size of the images taken from one PDF, so a document full of photos
+/// can't blow up the request.
+pub const MAX_IMAGE_BYTES: usize = 20 * 1024 * 1024;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct PdfImages {
+    /// Base64: JPEGs as stored in the file, anything else re-encoded as PNG
+    pub images: Vec<String>,
+    /// Images in encodings that can't be decoded, such as JBIG2 or CCITT fax
+    pub unsupported: usize,
+    /// Images left out by `max_images` or `MAX_IMAGE_BYTES`
+    pub omitted: usize,
+}
+
+/// Collects the images drawn on the document's pages, in page order, for vision
+/// models. An image used on several pages is only taken once.
+pub fn extract_images(
+    bytes: &[u8],
+    password: Option<&str>,
+    max_images: usize,
+) -> Result<PdfImages, Box<dyn std::error::Error>> {
+    collect_images(bytes, password, max_images, MAX_IMAGE_BYTES)
+}
+
+fn collect_images(
+    bytes: &[u8],
+    password: Option<&str>,
+    max_images: usize,
+    max_bytes: usize,
+) -> Result<PdfImages, Box<dyn std::error::Error>> {
+    let (doc, _) = load_document(bytes, password)?;
+    let mut result = PdfImages::default();
+    let mut seen = HashSet::new();
+    let mut total_bytes = 0;
+
+    for (_, page_id) in doc.get_pages() {
+        for pdf_image in doc.get_page_images(page_id)? {
+            if !seen.insert(pdf_image.id) {
+                continue;
+            }
+            if result.images.len() >= max_images {
+                result.omitted += 1;
+                continue;
+            }
+            let Some(encoded) = encode_image(&doc, pdf_image.id)? else {
+                result.unsupported += 1;
+                continue;
+            };
+            if total_bytes + encoded.len() > max_bytes {
+                result.omitted += 1;
+                continue;
+            }
+            total_bytes += encoded.len();
+            result
+                .images
+                .push(general_purpose::STANDARD.encode(encoded));
+        }
+    }
+    Ok(result)
+}
+
+/// The image as a JPEG or PNG file, or `None` if its encoding isn't supported.
+fn encode_image(
+    doc: &Document,
+    id: ObjectId,
+) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+    let stream = doc.get_object(id)?.as_stream()?;
+    if stream
+        .filters()
+        .unwrap_or_default()
+        .iter()
+        .any(|f| *f == b"DCTDecode")
+    {
+        return Ok(Some(stream.content.clone()));
+    }
+    let Some(image) = decode_image(doc, id)? else {
+        return Ok(None);
+    };
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(Some(png))
+}
+
+/// Decodes an image XObject. Returns `None` for encodings this doesn't handle,
+/// such as JBIG2 or CCITT fax.
+pub(crate) fn decode_image(
+    doc: &Document,
+    id: ObjectId,
+) -> Result<Option<DynamicImage>, Box<dyn std::error::Error>> {
+    let stream = doc.get_object(id)?.as_stream()?;
+    let filters = stream.filters().unwrap_or_default();
+
+    if filters.iter().any(|f| *f == b"DCTDecode") {
+        return Ok(Some(image::load_from_memory_with_format(
+            &stream.content,
+            ImageFormat::Jpeg,
+        )?));
+    }
+    if filters.iter().any(|f| *f != b"FlateDecode") {
+        return Ok(None);
+    }
+
+    let dict = &stream.dict;
+    let width = dict.get(b"Width")?.as_i64()? as u32;
+    let height = dict.get(b"Height")?.as_i64()? as u32;
+    let bits = dict
+        .get(b"BitsPerComponent")
+        .and_then(|b| b.as_i64())
+        .unwrap_or(8);
+    if bits != 8 {
+        return Ok(None);
+    }
+
+    let pixels = if filters.is_empty() {
+        stream.content.clone()
+    } else {
+        stream.decompressed_content()?
+    };
+    let color_space = dict
+        .get(b"ColorSpace")
+        .and_then(|c| c.as_name())
+        .unwrap_or(b"DeviceGray");
+    Ok(match color_space {
+        b"DeviceGray" => GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        b"DeviceRGB" => RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+        _ => None,
+    })
+}
+
+/// Pages with less text than this are taken to be scans. Scans often still carry a
+/// page number or a stray character in their text layer.
+const MIN_CHARS_PER_PAGE: usize = 16;
+
+/// The text layer of a page, in `layout`. Pages the structured reader can't
+/// make sense of are read as plain text instead.
+fn read_page(doc: &Document, page_id: ObjectId, page_num: u32, layout: TextLayout) -> String {
+    if layout == TextLayout::Structured {
+        match pdf_layout::extract_page_markdown(doc, page_id) {
+            Ok(text) => return text,
+            Err(e) => eprintln!("Falling back to plain text on page {}: {}", page_num, e),
+        }
+    }
+    // Note: extract_text takes a slice of page numbers, we do one by one here
+    doc.extract_text(&[page_num]).unwrap_or_default()
+}
+
+fn lacks_text_layer(text: &str) -> bool {
+    text.chars().filter(|c| !c.is_whitespace()).count() < MIN_CHARS_PER_PAGE
+}
+
+/// Whether this build can read scanned pages.
+pub const OCR_AVAILABLE: bool = cfg!(feature = "ocr");
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExtractionProgress {
+    /// `page` pages have been read, out of the `total_pages` selected
+    Page { page: u32, total_pages: u32 },
+    /// A scanned page is about to be recognized
+    Ocr(OcrProgress),
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct OcrProgress {
+    pub page: u32,
+    /// Pages recognized so far, out of `total`
+    pub done: u32,
+    pub total: u32,
+}
+
+#[cfg(feature = "ocr")]
+fn ocr_page(
+    doc: &Document,
+    page_id: lopdf::ObjectId,
+) -> Result<String, Box<dyn std::error::Error>> {
+    crate::ocr::recognize_page(doc, page_id)
+}
+
+#[cfg(not(feature = "ocr"))]
+fn ocr_page(
+    _doc: &Document,
+    _page_id: lopdf::ObjectId,
+) -> Result<String, Box<dyn std::error::Error>> {
+    Err("built without OCR support".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::content::{Content, Operation};
+    use lopdf::{dictionary, Object, Stream};
+
+    /// Builds a PDF whose page N reads "Page N".
+    fn sample_pdf(pages: u32) -> Vec<u8> {
+        sample_pdf_with(pages, |_| {})
+    }
+
+    /// Like `sample_pdf`, letting `customize` change the document before it's saved.
+    fn sample_pdf_with(pages: u32, customize: impl FnOnce(&mut Document)) -> Vec<u8> {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let font_id = doc.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Courier",
+        });
+        let resources_id = doc.add_object(dictionary! {
+            "Font" => dictionary! { "F1" => font_id },
+        });
+
+        let kids: Vec<Object> = (1..=pages)
+            .map(|n| {
+                let content = Content {
+                    operations: vec![
+                        Operation::new("BT", vec![]),
+                        Operation::new("Tf", vec!["F1".into(), 24.into()]),
+                        Operation::new("Td", vec![100.into(), 600.into()]),
+                        Operation::new("Tj", vec![Object::string_literal(format!("Page {}", n))]),
+                        Operation::new("ET", vec![]),
+                    ],
+                };
+                let content_id =
+                    doc.add_object(Stream::new(dictionary! {}, content.encode().unwrap()));
+                doc.add_object(dictionary! {
+                    "Type" => "Page",
+                    "Parent" => pages_id,
+                    "Contents" => content_id,
+                })
+                .into()
+            })
+            .collect();
+
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => kids,
+                "Count" => pages as i64,
+                "Resources" => resources_id,
+                "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+            }),
+        );
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        doc.trailer.set("Root", catalog_id);
+        customize(&mut doc);
+
+        let mut bytes = Vec::new();
+        doc.save_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_page_range_selects_pages() {
+        let pdf = extract_text_from_pdf_range(
+            &sample_pdf(5),
+            Some(PageRange {
+                start_page: 2,
+                end_page: 3,
+            }),
+            &PdfLimits::default(),
+        )
+        .unwrap();
+        assert_eq!((pdf.first_page, pdf.last_page, pdf.page_count), (2, 3, 5));
+        assert!(pdf.text.contains("Page 2") && pdf.text.contains("Page 3"));
+        assert!(!pdf.text.contains("Page 1") && !pdf.text.contains("Page 4"));
+    }
 
-    // Sort pages by number to ensure correct order
-    let mut page_numbers: Vec<u32> = pages.keys().cloned().collect();
-    page_numbers.sort();
+    #[test]
+    fn test_out_of_range_pages_are_clamped() {
+        let bytes = sample_pdf(3);
+        let pdf = extract_text_from_pdf_range(
+            &bytes,
+            Some(PageRange {
+                start_page: 0,
+                end_page: 99,
+            }),
+            &PdfLimits::default(),
+        )
+        .unwrap();
+        assert_eq!((pdf.first_page, pdf.last_page), (1, 3));
 
-    for page_num in page_numbers {
-        // Extract text from each page
-        // Note: extract_text takes a slice of page numbers, we do one by one here
-        if let Ok(text) = doc.extract_text(&[page_num]) {
-            if !text.trim().is_empty() {
-                texts.push(text);
+        // A start past the end still yields the last page rather than nothing
+        let pdf = extract_text_from_pdf_range(
+            &bytes,
+            Some(PageRange {
+                start_page: 7,
+                end_page: 2,
+            }),
+            &PdfLimits::default(),
+        )
+        .unwrap();
+        assert_eq!((pdf.first_page, pdf.last_page), (3, 3));
+        assert!(pdf.text.contains("Page 3"));
+    }
+
+    #[test]
+    fn test_limits() {
+        let bytes = sample_pdf(12);
+        let limits = PdfLimits {
+            max_pages: 10,
+            ..Default::default()
+        };
+
+        let error = extract_text_from_pdf_range(&bytes, None, &limits).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "PDF exceeds the 10-page limit (had 12 pages); select a page range"
+        );
+        let range = Some(PageRange {
+            start_page: 3,
+            end_page: 12,
+        });
+        assert!(extract_text_from_pdf_range(&bytes, range, &limits).is_ok());
+
+        let limits = PdfLimits {
+            max_bytes: 100,
+            ..Default::default()
+        };
+        let error = extract_text_from_pdf_range(&bytes, None, &limits).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<PdfLimitError>(),
+            Some(PdfLimitError::TooLarge { max_bytes: 100, .. })
+        ));
+    }
+
+    #[test]
+    fn test_progress_is_reported_per_page() {
+        let mut progress = Vec::new();
+        let range = Some(PageRange {
+            start_page: 2,
+            end_page: 4,
+        });
+        extract_text_with_ocr(&sample_pdf(5), range, &PdfLimits::default(), |p| {
+            if let ExtractionProgress::Page { page, total_pages } = p {
+                progress.push((page, total_pages));
+            }
+        })
+        .unwrap();
+        assert_eq!(progress, vec![(1, 3), (2, 3), (3, 3)]);
+
+        let mut pages = 0;
+        let text = extract_text_from_pdf(&sample_pdf(2), |p| {
+            pages += matches!(p, ExtractionProgress::Page { .. }) as u32;
+        })
+        .unwrap();
+        assert!(text.contains("Page 2"));
+        assert_eq!(pages, 2);
+    }
+
+    #[tokio::test]
+    async fn test_extraction_leaves_the_runtime_free() {
+        // A single-threaded runtime, so an extraction run inline would stall the
+        // other tasks until it's done, and it only finishes once they have
+        let (release, released) = std::sync::mpsc::channel();
+        let bytes = sample_pdf(3);
+        let extraction = tokio::spawn(spawn_extraction(move || {
+            released
+                .recv_timeout(std::time::Duration::from_secs(5))
+                .expect("other commands were stalled");
+            extract_text_from_pdf_range(&bytes, None, &PdfLimits::default())
+                .map(|pdf| pdf.page_count)
+                .map_err(|e| e.to_string())
+        }));
+
+        let commands: Vec<_> = (0..2)
+            .map(|n| {
+                tokio::spawn(async move {
+                    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                    n
+                })
+            })
+            .collect();
+        for (n, command) in commands.into_iter().enumerate() {
+            assert_eq!(command.await.unwrap(), n);
+        }
+
+        release.send(()).unwrap();
+        assert_eq!(extraction.await.unwrap().unwrap(), Ok(3));
+    }
+
+    #[test]
+    fn test_long_text_is_truncated_with_marker() {
+        let limits = PdfLimits {
+            max_chars: 10,
+            truncation: Truncation::Head,
+            ..Default::default()
+        };
+        let pdf = extract_text_from_pdf_range(&sample_pdf(3), None, &limits).unwrap();
+        assert!(pdf.truncated);
+        assert!(pdf.text.contains("Page 1"));
+        assert!(pdf.text.ends_with(" omitted from page 2 ...]"));
+        assert!(!pdf.text.contains("Page 3"));
+
+        let pdf = extract_text_from_pdf_range(
+            &sample_pdf(5),
+            None,
+            &PdfLimits {
+                max_chars: 14,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(pdf.text.contains("Page 1") && pdf.text.contains("Page 5"));
+        assert!(!pdf.text.contains("Page 3"));
+    }
+
+    fn paragraphs(pages: u32, paragraphs: usize) -> Vec<(u32, String)> {
+        (1..=pages)
+            .map(|n| {
+                let text = (0..paragraphs)
+                    .map(|i| format!("p{:03}.{}", n, i))
+                    .collect::<Vec<_>>()
+                    .join("\n\n");
+                (n, text)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_fit_to_budget_within_budget() {
+        let pages = paragraphs(2, 2);
+        let whole = "p001.0\n\np001.1\n\np002.0\n\np002.1";
+        assert_eq!(
+            fit_to_budget(&pages, 1000, Truncation::HeadAndTail),
+            (whole.to_string(), false)
+        );
+        // Exactly at the budget is still whole
+        assert_eq!(
+            fit_to_budget(&pages, whole.chars().count(), Truncation::Head),
+            (whole.to_string(), false)
+        );
+        // Blank pages don't leave gaps
+        let with_blank = vec![
+            pages[0].clone(),
+            (2, " \n".to_string()),
+            (3, "end".to_string()),
+        ];
+        assert_eq!(
+            fit_to_budget(&with_blank, 1000, Truncation::Head).0,
+            "p001.0\n\np001.1\n\nend"
+        );
+    }
+
+    #[test]
+    fn test_fit_to_budget_far_over_budget() {
+        // 300 pages of 10 paragraphs; 24,000 characters including breaks
+        let pages = paragraphs(300, 10);
+
+        let (text, truncated) = fit_to_budget(&pages, 100, Truncation::HeadAndTail);
+        assert!(truncated);
+        let parts: Vec<&str> = text.split("\n\n").collect();
+        assert_eq!(parts.first(), Some(&"p001.0"));
+        assert_eq!(parts.last(), Some(&"p300.9"));
+        // Cut between paragraphs, so every kept paragraph is whole
+        assert!(parts.iter().all(|p| p.len() == 6 || p.starts_with("[... ")));
+        let marker = parts.iter().find(|p| p.starts_with("[... ")).unwrap();
+        assert!(marker.ends_with(" characters omitted from pages 1–300 ...]"));
+        assert!(text.chars().count() < 100 + marker.chars().count() + 4);
+
+        let (text, _) = fit_to_budget(&pages, 100, Truncation::Head);
+        assert!(text.starts_with("p001.0\n\n"));
+        assert!(text.ends_with("characters omitted from pages 2–300 ...]"));
+        assert!(!text.contains("p300"));
+    }
+
+    #[test]
+    fn test_fit_to_budget_cuts_inside_a_huge_paragraph() {
+        let pages = vec![(7, "x".repeat(312_020))];
+        let (text, truncated) = fit_to_budget(&pages, 20, Truncation::HeadAndTail);
+        assert!(truncated);
+        assert_eq!(
+            text,
+            format!(
+                "{}\n\n[... 312,000 characters omitted from page 7 ...]\n\n{}",
+                "x".repeat(10),
+                "x".repeat(10)
+            )
+        );
+        assert_eq!(format_count(999), "999");
+        assert_eq!(format_count(1_234_567), "1,234,567");
+    }
+
+    #[test]
+    #[cfg(not(feature = "ocr"))]
+    fn test_pages_without_text_layer_are_reported() {
+        // "Page N" is too little text to count as a text layer
+        let pdf = extract_text_with_ocr(&sample_pdf(2), None, &PdfLimits::default(), |progress| {
+            assert!(
+                !matches!(progress, ExtractionProgress::Ocr(_)),
+                "OCR should not run without the ocr feature"
+            )
+        })
+        .unwrap();
+        assert!(pdf.ocr_pages.is_empty());
+        assert_eq!(pdf.unreadable_pages, vec![1, 2]);
+        assert!(lacks_text_layer("  12 \n"));
+        assert!(!lacks_text_layer("A full sentence of real extracted text."));
+    }
+
+    #[test]
+    fn test_cached_pages_are_not_read_again() {
+        let bytes = sample_pdf(4);
+        let range = Some(PageRange {
+            start_page: 2,
+            end_page: 3,
+        });
+        let mut cache = None;
+        let pdf = extract_text_cached(
+            &bytes,
+            range,
+            None,
+            &PdfLimits::default(),
+            &mut cache,
+            |_| {},
+        )
+        .unwrap();
+        let cached = cache.clone().unwrap();
+        assert_eq!(cached.page_count, 4);
+        assert_eq!(cached.pages.keys().copied().collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(
+            cached,
+            serde_json::from_str(&serde_json::to_string(&cached).unwrap()).unwrap()
+        );
+
+        // Served from the cache; the bytes aren't even parsed
+        let again = extract_text_cached(
+            b"not a pdf",
+            range,
+            None,
+            &PdfLimits::default(),
+            &mut cache,
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(again.text, pdf.text);
+        assert_eq!(again.unreadable_pages, pdf.unreadable_pages);
+
+        // Pages outside the cache still need the document
+        assert!(extract_text_cached(
+            b"not a pdf",
+            None,
+            None,
+            &PdfLimits::default(),
+            &mut cache,
+            |_| {}
+        )
+        .is_err());
+        assert_eq!(content_hash(b"abc").len(), 64);
+    }
+
+    /// One page with a title, a section heading, two paragraphs and a bulleted list.
+    const REPORT_PDF: &[u8] = include_bytes!("../tests/fixtures/report.pdf");
+
+    #[test]
+    fn test_structured_and_plain_layouts() {
+        let extract = |layout| {
+            let limits = PdfLimits {
+                layout,
+                ..PdfLimits::unlimited()
+            };
+            extract_text_from_pdf_range(REPORT_PDF, None, &limits)
+                .unwrap()
+                .text
+        };
+
+        assert_eq!(
+            extract(TextLayout::Structured),
+            "# Annual Report\n\n\
+             ## 1 Introduction\n\n\
+             Revenue grew in every region this year, led by strong demand.\n\n\
+             Costs were flat.\n\n\
+             - Europe up 4%\n\
+             - Asia up 9%"
+        );
+
+        let plain = extract(TextLayout::Plain);
+        assert!(plain.contains("Annual Report") && plain.contains("Asia up 9%"));
+        assert!(!plain.contains("# "));
+    }
+
+    #[test]
+    fn test_cache_in_another_layout_is_discarded() {
+        let plain = PdfLimits {
+            layout: TextLayout::Plain,
+            ..Default::default()
+        };
+        let mut cache = None;
+        extract_text_cached(REPORT_PDF, None, None, &plain, &mut cache, |_| {}).unwrap();
+        assert_eq!(cache.as_ref().unwrap().layout, TextLayout::Plain);
+
+        let pdf = extract_text_cached(
+            REPORT_PDF,
+            None,
+            None,
+            &PdfLimits::default(),
+            &mut cache,
+            |_| {},
+        )
+        .unwrap();
+        assert!(pdf.text.starts_with("# Annual Report"));
+        assert_eq!(cache.unwrap().layout, TextLayout::Structured);
+    }
+
+    /// One page, encrypted with the user password "secret" (RC4, 40-bit).
+    const ENCRYPTED_PDF: &[u8] = include_bytes!("../tests/fixtures/encrypted.pdf");
+
+    #[test]
+    fn test_encrypted_pdf_needs_its_password() {
+        let error = extract_text_from_pdf(ENCRYPTED_PDF, |_| {}).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<PdfError>(),
+            Some(&PdfError::PasswordRequired)
+        );
+
+        let mut cache = None;
+        let extract = |password, cache: &mut Option<ExtractedPages>| {
+            extract_text_cached(
+                ENCRYPTED_PDF,
+                None,
+                password,
+                &PdfLimits::default(),
+                cache,
+                |_| {},
+            )
+        };
+        let error = extract(Some("guess"), &mut cache).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<PdfError>(),
+            Some(&PdfError::WrongPassword)
+        );
+
+        let pdf = extract(Some("secret"), &mut cache).unwrap();
+        assert!(pdf.text.contains("Quarterly secret figures"));
+        // The decrypted text mustn't end up in the document cache
+        assert!(cache.is_none());
+    }
+
+    /// Puts images on page 1: raw RGB, a JPEG, and one in JBIG2 (unsupported).
+    fn add_images(doc: &mut Document) {
+        let image_dict = |filter: Option<&str>| {
+            let mut dict = dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Image",
+                "Width" => 2,
+                "Height" => 2,
+                "ColorSpace" => "DeviceRGB",
+                "BitsPerComponent" => 8,
+            };
+            if let Some(filter) = filter {
+                dict.set("Filter", Object::Name(filter.as_bytes().to_vec()));
             }
+            dict
+        };
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(2, 2))
+            .write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)
+            .unwrap();
+
+        let raw = doc.add_object(Stream::new(image_dict(None), vec![200; 12]));
+        let dct = doc.add_object(Stream::new(image_dict(Some("DCTDecode")), jpeg));
+        let jbig2 = doc.add_object(Stream::new(image_dict(Some("JBIG2Decode")), vec![0; 4]));
+        let page_id = doc.get_pages()[&1];
+        doc.get_object_mut(page_id)
+            .and_then(Object::as_dict_mut)
+            .unwrap()
+            .set(
+                "Resources",
+                dictionary! {
+                    "XObject" => dictionary! { "Im1" => raw, "Im2" => dct, "Im3" => jbig2 },
+                },
+            );
+    }
+
+    #[test]
+    fn test_extract_images() {
+        let bytes = sample_pdf_with(2, add_images);
+
+        let pdf_images = extract_images(&bytes, None, 10).unwrap();
+        assert_eq!(pdf_images.images.len(), 2);
+        assert_eq!((pdf_images.unsupported, pdf_images.omitted), (1, 0));
+        for encoded in &pdf_images.images {
+            let decoded = general_purpose::STANDARD.decode(encoded).unwrap();
+            let image = image::load_from_memory(&decoded).unwrap();
+            assert_eq!((image.width(), image.height()), (2, 2));
         }
+
+        // Over the count cap, then over the size cap
+        let pdf_images = extract_images(&bytes, None, 1).unwrap();
+        assert_eq!((pdf_images.images.len(), pdf_images.omitted), (1, 2));
+        let pdf_images = collect_images(&bytes, None, 10, 10).unwrap();
+        assert_eq!((pdf_images.images.len(), pdf_images.omitted), (0, 2));
+
+        assert_eq!(
+            extract_images(&sample_pdf(2), None, 10).unwrap(),
+            PdfImages::default()
+        );
     }
 
-    Ok(texts.join("\n\n"))
+    #[test]
+    fn test_metadata_from_info_dictionary() {
+        let bytes = sample_pdf_with(42, |doc| {
+            let info_id = doc.add_object(dictionary! {
+                "Title" => Object::string_literal("Quarterly report"),
+                // UTF-16BE with a byte order mark
+                "Author" => Object::String(
+                    b"\xFE\xFF\x00J\x00a\x00n\x00e\x00 \x00D\x00o\x00\xEB".to_vec(),
+                    lopdf::StringFormat::Hexadecimal,
+                ),
+                "CreationDate" => Object::string_literal("D:20240315093000+01'00'"),
+            });
+            doc.trailer.set("Info", info_id);
+        });
+
+        let metadata = extract_metadata(&bytes).unwrap();
+        assert_eq!(
+            metadata,
+            PdfMetadata {
+                title: Some("Quarterly report".to_string()),
+                author: Some("Jane Doë".to_string()),
+                producer: None,
+                created: Some("2024-03-15T09:30:00+01:00".to_string()),
+                page_count: 42,
+                size_bytes: bytes.len() as u64,
+                encrypted: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_metadata_without_info_dictionary() {
+        let metadata = extract_metadata(&sample_pdf(1)).unwrap();
+        assert_eq!(metadata.title, None);
+        assert_eq!(metadata.created, None);
+        assert_eq!(metadata.page_count, 1);
+        assert!(!metadata.encrypted);
+    }
+
+    #[test]
+    fn test_pdf_dates() {
+        assert_eq!(
+            parse_pdf_date("D:20240315093000Z").as_deref(),
+            Some("2024-03-15T09:30:00+00:00")
+        );
+        assert_eq!(
+            parse_pdf_date("D:2023").as_deref(),
+            Some("2023-01-01T00:00:00+00:00")
+        );
+        assert_eq!(
+            parse_pdf_date("D:19991231235959-05'30'").as_deref(),
+            Some("1999-12-31T23:59:59-05:30")
+        );
+        assert_eq!(parse_pdf_date("yesterday"), None);
+    }
 }
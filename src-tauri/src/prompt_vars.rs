@@ -0,0 +1,84 @@
+use chrono::{DateTime, TimeZone};
+use std::collections::HashMap;
+use std::fmt::Display;
+
+/// Settings key prefix for user-defined variables, e.g. `prompt_vars.project_name`.
+pub const SETTINGS_PREFIX: &str = "prompt_vars.";
+
+/// The variables every prompt can use: `date`, `time` and `weekday`.
+pub fn builtin_vars<Tz>(now: &DateTime<Tz>) -> HashMap<String, String>
+where
+    Tz: TimeZone,
+    Tz::Offset: Display,
+{
+    HashMap::from([
+        ("date".to_string(), now.format("%Y-%m-%d").to_string()),
+        ("time".to_string(), now.format("%H:%M").to_string()),
+        ("weekday".to_string(), now.format("%A").to_string()),
+    ])
+}
+
+/// Replaces each `{{name}}` in `template` with its value. Placeholders with no
+/// matching variable are left exactly as written.
+pub fn substitute(template: &str, vars: &HashMap<String, String>) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            rest = &rest[start..];
+            break;
+        };
+
+        let name = after[..end].trim();
+        if name.contains('{') {
+            // Not a placeholder itself, but one may start inside it
+            out.push_str("{{");
+            rest = after;
+            continue;
+        }
+        match vars.get(name) {
+            Some(value) => out.push_str(value),
+            None => out.push_str(&rest[start..start + end + 4]),
+        }
+        rest = &after[end + 2..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn vars() -> HashMap<String, String> {
+        let now = Utc.with_ymd_and_hms(2025, 3, 14, 9, 5, 0).unwrap();
+        let mut vars = builtin_vars(&now);
+        vars.insert("project_name".to_string(), "chatZ".to_string());
+        vars
+    }
+
+    #[test]
+    fn test_builtin_and_custom_vars() {
+        assert_eq!(
+            substitute(
+                "Today is {{weekday}}, {{date}} at {{ time }}. Project: {{project_name}}.",
+                &vars()
+            ),
+            "Today is Friday, 2025-03-14 at 09:05. Project: chatZ."
+        );
+    }
+
+    #[test]
+    fn test_unknown_and_malformed_left_verbatim() {
+        let vars = vars();
+        assert_eq!(substitute("Hi {{ nobody }}!", &vars), "Hi {{ nobody }}!");
+        assert_eq!(substitute("Open {{date", &vars), "Open {{date");
+        assert_eq!(substitute("{{ {{project_name}}", &vars), "{{ chatZ");
+        assert_eq!(substitute("No placeholders", &vars), "No placeholders");
+    }
+}
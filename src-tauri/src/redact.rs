@@ -0,0 +1,85 @@
+use regex::Regex;
+
+/// Finds text to redact, as a plain substring or a regular expression, and
+/// what to put in its place.
+pub struct Redactor {
+    pattern: Pattern,
+    replacement: String,
+}
+
+enum Pattern {
+    Literal(String),
+    Regex(Regex),
+}
+
+impl Redactor {
+    /// With `regex`, the replacement may refer to capture groups as `$1` or
+    /// `$name`. Patterns that match empty text are refused, as they would
+    /// insert the replacement between every character.
+    pub fn new(pattern: &str, replacement: &str, regex: bool) -> Result<Self, String> {
+        if pattern.is_empty() {
+            return Err("The pattern cannot be empty".to_string());
+        }
+        let pattern = if regex {
+            let regex = Regex::new(pattern).map_err(|e| format!("Invalid pattern: {}", e))?;
+            if regex.is_match("") {
+                return Err("The pattern must not match empty text".to_string());
+            }
+            Pattern::Regex(regex)
+        } else {
+            Pattern::Literal(pattern.to_string())
+        };
+        Ok(Self {
+            pattern,
+            replacement: replacement.to_string(),
+        })
+    }
+
+    /// The redacted text and how many spans were replaced, or `None` when
+    /// nothing matched.
+    pub fn apply(&self, text: &str) -> Option<(String, usize)> {
+        let (redacted, count) = match &self.pattern {
+            Pattern::Literal(pattern) => (
+                text.replace(pattern.as_str(), &self.replacement),
+                text.matches(pattern.as_str()).count(),
+            ),
+            Pattern::Regex(regex) => (
+                regex
+                    .replace_all(text, self.replacement.as_str())
+                    .into_owned(),
+                regex.find_iter(text).count(),
+            ),
+        };
+        (count > 0).then_some((redacted, count))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_literal() {
+        let redactor = Redactor::new("hunter2", "[redacted]", false).unwrap();
+        assert_eq!(
+            redactor.apply("pw hunter2, again hunter2"),
+            Some(("pw [redacted], again [redacted]".to_string(), 2))
+        );
+        assert_eq!(redactor.apply("nothing here"), None);
+        // Not a regex in this mode
+        let dots = Redactor::new("a.b", "x", false).unwrap();
+        assert_eq!(dots.apply("acb a.b"), Some(("acb x".to_string(), 1)));
+    }
+
+    #[test]
+    fn test_regex() {
+        let redactor = Redactor::new(r"(\w+)@example\.com", "$1@…", true).unwrap();
+        assert_eq!(
+            redactor.apply("mail ana@example.com or bo@example.com"),
+            Some(("mail ana@… or bo@…".to_string(), 2))
+        );
+        assert!(Redactor::new("(", "x", true).is_err());
+        assert!(Redactor::new("a*", "x", true).is_err());
+        assert!(Redactor::new("", "x", false).is_err());
+    }
+}
@@ -0,0 +1,64 @@
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+
+    let mut dot = 0.0;
+    let mut norm_a = 0.0;
+    let mut norm_b = 0.0;
+    for (x, y) in a.iter().zip(b) {
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a.sqrt() * norm_b.sqrt())
+}
+
+/// Ranks candidate vectors against the query and returns the ids of the
+/// `top_k` most similar ones, best match first.
+pub fn rank_by_similarity(
+    query: &[f32],
+    candidates: Vec<(i64, Vec<f32>)>,
+    top_k: usize,
+) -> Vec<(i64, f32)> {
+    let mut scored: Vec<(i64, f32)> = candidates
+        .into_iter()
+        .map(|(id, vector)| (id, cosine_similarity(query, &vector)))
+        .collect();
+
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+    scored.truncate(top_k);
+    scored
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cosine_similarity() {
+        assert!((cosine_similarity(&[1.0, 0.0], &[1.0, 0.0]) - 1.0).abs() < 1e-6);
+        assert!(cosine_similarity(&[1.0, 0.0], &[0.0, 1.0]).abs() < 1e-6);
+        assert!((cosine_similarity(&[1.0, 0.0], &[-1.0, 0.0]) + 1.0).abs() < 1e-6);
+
+        // Mismatched or zero vectors never match
+        assert_eq!(cosine_similarity(&[1.0], &[1.0, 0.0]), 0.0);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 0.0]), 0.0);
+    }
+
+    #[test]
+    fn test_rank_by_similarity() {
+        let candidates = vec![
+            (1, vec![0.0, 1.0]),
+            (2, vec![1.0, 0.1]),
+            (3, vec![1.0, 0.0]),
+        ];
+        let ranked = rank_by_similarity(&[1.0, 0.0], candidates, 2);
+        let ids: Vec<i64> = ranked.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, vec![3, 2]);
+    }
+}
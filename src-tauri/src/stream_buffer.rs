@@ -0,0 +1,162 @@
+use std::time::{Duration, Instant};
+
+use crate::ollama::StreamChunk;
+
+/// How long streamed text is held before being emitted, unless the
+/// `stream_flush_interval_ms` setting says otherwise.
+pub const DEFAULT_FLUSH_INTERVAL_MS: u64 = 30;
+
+/// Buffered characters that are emitted right away, however recent the last
+/// flush was.
+pub const FLUSH_SIZE: usize = 2048;
+
+/// Joins streamed chunks into fewer, larger ones. Fast models produce well
+/// over a hundred tokens a second, and an event per token floods the IPC
+/// bridge. Reasoning and answer text are buffered separately but never
+/// reordered; metrics pass straight through after whatever was buffered.
+pub struct ChunkCoalescer {
+    interval: Duration,
+    last_flush: Instant,
+    thinking: String,
+    content: String,
+}
+
+impl ChunkCoalescer {
+    /// A zero interval emits every chunk as it arrives.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_flush: Instant::now(),
+            thinking: String::new(),
+            content: String::new(),
+        }
+    }
+
+    /// Buffers a chunk, returning the chunks that are due to be emitted.
+    pub fn push(&mut self, chunk: StreamChunk) -> Vec<StreamChunk> {
+        self.push_at(chunk, Instant::now())
+    }
+
+    fn push_at(&mut self, chunk: StreamChunk, now: Instant) -> Vec<StreamChunk> {
+        let mut due = Vec::new();
+        match chunk {
+            StreamChunk::Thinking(text) => {
+                if !self.content.is_empty() {
+                    due.push(StreamChunk::Content(std::mem::take(&mut self.content)));
+                }
+                self.thinking.push_str(&text);
+            }
+            StreamChunk::Content(text) => {
+                if !self.thinking.is_empty() {
+                    due.push(StreamChunk::Thinking(std::mem::take(&mut self.thinking)));
+                }
+                self.content.push_str(&text);
+            }
+            StreamChunk::Metrics(metrics) => {
+                due.extend(self.flush_at(now));
+                due.push(StreamChunk::Metrics(metrics));
+                return due;
+            }
+        }
+        if now.duration_since(self.last_flush) >= self.interval
+            || self.thinking.len() + self.content.len() >= FLUSH_SIZE
+        {
+            due.extend(self.flush_at(now));
+        }
+        due
+    }
+
+    /// Everything still buffered. Called once the stream ends, before its
+    /// done or error event.
+    pub fn flush(&mut self) -> Vec<StreamChunk> {
+        self.flush_at(Instant::now())
+    }
+
+    fn flush_at(&mut self, now: Instant) -> Vec<StreamChunk> {
+        self.last_flush = now;
+        let mut due = Vec::new();
+        // At most one of them holds text, as a switch flushes the other
+        if !self.thinking.is_empty() {
+            due.push(StreamChunk::Thinking(std::mem::take(&mut self.thinking)));
+        }
+        if !self.content.is_empty() {
+            due.push(StreamChunk::Content(std::mem::take(&mut self.content)));
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn content(chunks: &[StreamChunk]) -> String {
+        chunks
+            .iter()
+            .filter_map(|chunk| match chunk {
+                StreamChunk::Content(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_emitted_chunks_add_up_to_the_content() {
+        let start = Instant::now();
+        let mut coalescer = ChunkCoalescer::new(Duration::from_millis(30));
+        coalescer.last_flush = start;
+        let tokens: Vec<String> = (0..500).map(|i| format!("tok{} ", i)).collect();
+
+        let mut emitted = Vec::new();
+        for (i, token) in tokens.iter().enumerate() {
+            // A token every 2 ms, about 500 a second
+            let now = start + Duration::from_millis(2 * i as u64);
+            emitted.extend(coalescer.push_at(StreamChunk::Content(token.clone()), now));
+        }
+        let before_flush = emitted.len();
+        emitted.extend(coalescer.flush());
+
+        assert_eq!(content(&emitted), tokens.concat());
+        // One event per 30 ms instead of one per token
+        assert!(before_flush <= 1000 / 30 + 1, "{} events", before_flush);
+    }
+
+    #[test]
+    fn test_size_limit_flushes_early() {
+        let start = Instant::now();
+        let mut coalescer = ChunkCoalescer::new(Duration::from_secs(60));
+        coalescer.last_flush = start;
+        let big = "x".repeat(FLUSH_SIZE);
+        assert!(coalescer
+            .push_at(StreamChunk::Content("a".to_string()), start)
+            .is_empty());
+        let due = coalescer.push_at(StreamChunk::Content(big.clone()), start);
+        assert_eq!(content(&due), format!("a{}", big));
+        assert!(coalescer.flush().is_empty());
+    }
+
+    #[test]
+    fn test_thinking_is_emitted_before_the_answer() {
+        let start = Instant::now();
+        let mut coalescer = ChunkCoalescer::new(Duration::from_secs(60));
+        coalescer.last_flush = start;
+        for text in ["Let ", "me ", "think"] {
+            assert!(coalescer
+                .push_at(StreamChunk::Thinking(text.to_string()), start)
+                .is_empty());
+        }
+        let due = coalescer.push_at(StreamChunk::Content("Answer".to_string()), start);
+        assert!(matches!(due.as_slice(), [StreamChunk::Thinking(t)] if t == "Let me think"));
+        let due = coalescer.flush();
+        assert!(matches!(due.as_slice(), [StreamChunk::Content(c)] if c == "Answer"));
+    }
+
+    #[test]
+    fn test_zero_interval_emits_every_chunk() {
+        let mut coalescer = ChunkCoalescer::new(Duration::ZERO);
+        for text in ["a", "b", "c"] {
+            let due = coalescer.push(StreamChunk::Content(text.to_string()));
+            assert_eq!(content(&due), text);
+        }
+    }
+}
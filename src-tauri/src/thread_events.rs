@@ -0,0 +1,161 @@
+use crate::db::Database;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Runtime};
+
+/// Sent when a thread is given a title without the user renaming it.
+#[derive(Clone, Serialize)]
+pub struct ThreadRenamedEvent {
+    pub thread_id: i64,
+    pub title: String,
+}
+
+#[derive(Clone, Serialize)]
+pub struct ThreadArchivedEvent {
+    pub thread_id: i64,
+}
+
+/// Sent when a thread is found to have had its reply cut off.
+#[derive(Clone, Serialize)]
+pub struct ThreadFlaggedEvent {
+    pub thread_id: i64,
+}
+
+/// Saves a generated title and emits `thread-renamed`.
+pub fn rename_thread<R: Runtime>(
+    app: &AppHandle<R>,
+    db: &Database,
+    thread_id: i64,
+    title: &str,
+) -> Result<(), String> {
+    db.update_thread_title(thread_id, title)
+        .map_err(|e| e.to_string())?;
+    let _ = app.emit(
+        "thread-renamed",
+        ThreadRenamedEvent {
+            thread_id,
+            title: title.to_string(),
+        },
+    );
+    Ok(())
+}
+
+/// Archives threads with no messages since `last_active_before`, emits
+/// `thread-archived` for each and returns their ids.
+pub fn archive_stale_threads<R: Runtime>(
+    app: &AppHandle<R>,
+    db: &Database,
+    last_active_before: DateTime<Utc>,
+) -> Result<Vec<i64>, String> {
+    let archived = db
+        .archive_stale_threads(last_active_before)
+        .map_err(|e| e.to_string())?;
+    for &thread_id in &archived {
+        let _ = app.emit("thread-archived", ThreadArchivedEvent { thread_id });
+    }
+    Ok(archived)
+}
+
+/// Flags threads whose reply was cut off, emits `thread-flagged` for each and
+/// returns their ids.
+pub fn flag_interrupted_threads<R: Runtime>(
+    app: &AppHandle<R>,
+    db: &Database,
+    unanswered_before: DateTime<Utc>,
+) -> Result<Vec<i64>, String> {
+    let flagged = db
+        .flag_interrupted_threads(unanswered_before)
+        .map_err(|e| e.to_string())?;
+    for &thread_id in &flagged {
+        let _ = app.emit("thread-flagged", ThreadFlaggedEvent { thread_id });
+    }
+    Ok(flagged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ollama::Role;
+    use serde_json::{json, Value};
+    use std::sync::mpsc::{self, Receiver};
+    use std::time::Duration;
+    use tauri::Listener;
+
+    fn listen<R: Runtime>(app: &AppHandle<R>, event: &str) -> Receiver<Value> {
+        let (tx, rx) = mpsc::channel();
+        app.listen_any(event, move |event| {
+            let _ = tx.send(serde_json::from_str(event.payload()).unwrap());
+        });
+        rx
+    }
+
+    fn received(rx: &Receiver<Value>) -> Vec<Value> {
+        let mut payloads = Vec::new();
+        while let Ok(payload) = rx.recv_timeout(Duration::from_millis(200)) {
+            payloads.push(payload);
+        }
+        payloads
+    }
+
+    #[test]
+    fn test_rename_emits_title() {
+        let app = tauri::test::mock_app();
+        let renamed = listen(app.handle(), "thread-renamed");
+        let db = Database::new(":memory:").unwrap();
+        let thread_id = db.create_thread("New Chat", None).unwrap();
+
+        rename_thread(app.handle(), &db, thread_id, "Trip plans").unwrap();
+        assert_eq!(
+            received(&renamed),
+            vec![json!({ "thread_id": thread_id, "title": "Trip plans" })]
+        );
+        assert_eq!(db.get_thread(thread_id).unwrap().title, "Trip plans");
+    }
+
+    #[test]
+    fn test_archive_emits_each_thread() {
+        let app = tauri::test::mock_app();
+        let archived = listen(app.handle(), "thread-archived");
+        let db = Database::new(":memory:").unwrap();
+        let stale = db.create_thread("Stale", None).unwrap();
+        db.add_message(stale, Role::User, "old", None, None, None, None)
+            .unwrap();
+        let already = db.create_thread("Already archived", None).unwrap();
+        db.archive_thread(already).unwrap();
+
+        let tomorrow = Utc::now() + chrono::Duration::days(1);
+        assert_eq!(
+            archive_stale_threads(app.handle(), &db, tomorrow).unwrap(),
+            vec![stale]
+        );
+        assert_eq!(received(&archived), vec![json!({ "thread_id": stale })]);
+
+        // Nothing left to archive, nothing announced
+        assert!(archive_stale_threads(app.handle(), &db, tomorrow)
+            .unwrap()
+            .is_empty());
+        assert!(received(&archived).is_empty());
+    }
+
+    #[test]
+    fn test_flag_emits_each_thread() {
+        let app = tauri::test::mock_app();
+        let flagged = listen(app.handle(), "thread-flagged");
+        let db = Database::new(":memory:").unwrap();
+        let unanswered = db.create_thread("Unanswered", None).unwrap();
+        db.add_message(unanswered, Role::User, "hello", None, None, None, None)
+            .unwrap();
+        let answered = db.create_thread("Answered", None).unwrap();
+        db.add_message(answered, Role::User, "hello", None, None, None, None)
+            .unwrap();
+        db.add_message(answered, Role::Assistant, "hi", None, None, None, None)
+            .unwrap();
+
+        let soon = Utc::now() + chrono::Duration::minutes(1);
+        assert_eq!(
+            flag_interrupted_threads(app.handle(), &db, soon).unwrap(),
+            vec![unanswered]
+        );
+        assert_eq!(received(&flagged), vec![json!({ "thread_id": unanswered })]);
+    }
+}
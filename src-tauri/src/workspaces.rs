@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Name of the workspace that uses the original database file.
+pub const DEFAULT_WORKSPACE: &str = "Default";
+
+/// A named set of chats with a database file of its own.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Workspace {
+    pub name: String,
+    pub db_path: String,
+}
+
+/// The workspaces there are and which one is open, kept as a small JSON file
+/// next to the default database.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WorkspaceIndex {
+    pub active: String,
+    pub workspaces: Vec<Workspace>,
+}
+
+impl WorkspaceIndex {
+    /// An index with only the default workspace, on `default_db_path`.
+    pub fn new(default_db_path: &str) -> Self {
+        Self {
+            active: DEFAULT_WORKSPACE.to_string(),
+            workspaces: vec![Workspace {
+                name: DEFAULT_WORKSPACE.to_string(),
+                db_path: default_db_path.to_string(),
+            }],
+        }
+    }
+
+    /// Reads the index at `path`. Before any workspace is created there is no
+    /// file, and only the default workspace exists.
+    pub fn load(path: &Path, default_db_path: &str) -> Result<Self, String> {
+        let json = match std::fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Self::new(default_db_path))
+            }
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        let mut index: Self =
+            serde_json::from_str(&json).map_err(|e| format!("{}: {}", path.display(), e))?;
+        // An edited file could have lost the active one
+        if index.get(&index.active).is_none() {
+            index.active = index
+                .workspaces
+                .first()
+                .map(|w| w.name.clone())
+                .ok_or_else(|| format!("{}: no workspaces", path.display()))?;
+        }
+        Ok(index)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, json).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Looks a workspace up by name, ignoring case.
+    pub fn get(&self, name: &str) -> Option<&Workspace> {
+        self.workspaces
+            .iter()
+            .find(|w| w.name.eq_ignore_ascii_case(name.trim()))
+    }
+
+    pub fn active(&self) -> &Workspace {
+        self.get(&self.active).unwrap_or(&self.workspaces[0])
+    }
+
+    /// Adds a workspace whose database goes in `dir`, named after it.
+    pub fn add(&mut self, name: &str, dir: &Path) -> Result<Workspace, String> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("Workspace name cannot be empty".to_string());
+        }
+        if self.get(name).is_some() {
+            return Err(format!("A workspace named {} already exists", name));
+        }
+
+        let slug: String = name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_lowercase()
+                } else {
+                    '-'
+                }
+            })
+            .collect();
+        let slug = slug.trim_matches('-');
+        let slug = if slug.is_empty() { "workspace" } else { slug };
+        // Names that differ only in punctuation would share a file otherwise
+        let db_path = (1..)
+            .map(|n| match n {
+                1 => format!("chat-{}.db", slug),
+                n => format!("chat-{}-{}.db", slug, n),
+            })
+            .map(|file| dir.join(file).to_string_lossy().into_owned())
+            .find(|path| !self.workspaces.iter().any(|w| &w.db_path == path))
+            .expect("some numbered file name is free");
+
+        let workspace = Workspace {
+            name: name.to_string(),
+            db_path,
+        };
+        self.workspaces.push(workspace.clone());
+        Ok(workspace)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_workspace() {
+        let mut index = WorkspaceIndex::new("chat.db");
+        let work = index.add(" Work ", Path::new("data")).unwrap();
+        assert_eq!(work.name, "Work");
+        assert_eq!(
+            Path::new(&work.db_path),
+            Path::new("data").join("chat-work.db")
+        );
+        // Same slug, different name
+        let other = index.add("work!", Path::new("data")).unwrap();
+        assert_eq!(
+            Path::new(&other.db_path),
+            Path::new("data").join("chat-work-2.db")
+        );
+
+        assert!(index.add("WORK", Path::new("data")).is_err());
+        assert!(index.add("  ", Path::new("data")).is_err());
+        assert_eq!(index.get("default").unwrap().db_path, "chat.db");
+        assert_eq!(index.active().name, DEFAULT_WORKSPACE);
+    }
+
+    #[test]
+    fn test_load_and_save() {
+        let path =
+            std::env::temp_dir().join(format!("chatz-workspaces-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(
+            WorkspaceIndex::load(&path, "chat.db").unwrap(),
+            WorkspaceIndex::new("chat.db")
+        );
+
+        let mut index = WorkspaceIndex::new("chat.db");
+        index.add("Personal", Path::new("")).unwrap();
+        index.active = "Personal".to_string();
+        index.save(&path).unwrap();
+        assert_eq!(WorkspaceIndex::load(&path, "chat.db").unwrap(), index);
+
+        index.active = "Deleted".to_string();
+        index.save(&path).unwrap();
+        assert_eq!(
+            WorkspaceIndex::load(&path, "chat.db").unwrap().active,
+            DEFAULT_WORKSPACE
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -5,7 +5,7 @@ import { Sidebar } from "./components/Sidebar";
 import { ChatArea } from "./components/ChatArea";
 import { ConfirmationModal } from "./components/ConfirmationModal";
 import { ContextConfigModal } from "./components/ContextConfigModal";
-import { Thread, Message, Theme, ChatMode } from "./types";
+import { Thread, Message, Theme, ChatMode, StreamChunkEvent, StreamDoneEvent, StreamErrorEvent, QueueUpdatedEvent, ThreadDeletedEvent, MessagesDeletedEvent, FileAttachment, NamedAttachment } from "./types";
 import "./App.css";
 import clsx from "clsx";
 
@@ -29,9 +29,12 @@ function App() {
   const [activeThreadId, setActiveThreadId] = useState<number | null>(null);
   const [messages, setMessages] = useState<Message[]>([]);
   const [streamingContent, setStreamingContent] = useState("");
+  const [streamingThinking, setStreamingThinking] = useState("");
   const [isStreaming, setIsStreaming] = useState(false);
   const [models, setModels] = useState<string[]>([]);
   const [selectedModel, setSelectedModel] = useState<string>("qwen3-vl");
+  const [supportsImages, setSupportsImages] = useState(true);
+  const [queuedCount, setQueuedCount] = useState(0);
   const [theme, setTheme] = useState<Theme>('dark');
   const [isSidebarOpen, setIsSidebarOpen] = useState(true);
   const [isTauriEnv, setIsTauriEnv] = useState(() => "__TAURI_INTERNALS__" in window);
@@ -68,6 +71,24 @@ function App() {
     }
   }, [isTauriEnv]);
 
+  useEffect(() => {
+    // Load the model ahead of the first message so it streams right away
+    if (isTauriEnv && selectedModel) {
+      invoke("warm_up_model", { name: selectedModel }).catch((error) =>
+        console.error("Failed to warm up model", error)
+      );
+    }
+  }, [isTauriEnv, selectedModel]);
+
+  useEffect(() => {
+    // Unknown capabilities (e.g. OpenAI-compatible servers) allow images
+    if (isTauriEnv && selectedModel) {
+      invoke<string[] | null>("get_model_capabilities", { name: selectedModel })
+        .then((capabilities) => setSupportsImages(!capabilities || capabilities.includes("vision")))
+        .catch(() => setSupportsImages(true));
+    }
+  }, [isTauriEnv, selectedModel]);
+
   useEffect(() => {
     if (activeThreadId && isTauriEnv) {
       loadMessages(activeThreadId);
@@ -79,21 +100,96 @@ function App() {
   useEffect(() => {
     if (!isTauriEnv) return;
 
-    const unlistenResponse = listen<string>("stream-response", (event) => {
-      setStreamingContent((prev) => prev + event.payload);
+    // Events carry their thread id; chunks from other threads are ignored
+    const unlistenResponse = listen<StreamChunkEvent>("stream-response", (event) => {
+      if (event.payload.thread_id !== activeThreadId) return;
+      // Queued messages start streaming right after the previous one is done
+      setIsStreaming(true);
+      setStreamingContent((prev) => prev + event.payload.chunk);
+    });
+
+    const unlistenThinking = listen<StreamChunkEvent>("stream-thinking", (event) => {
+      if (event.payload.thread_id !== activeThreadId) return;
+      setStreamingThinking((prev) => prev + event.payload.chunk);
     });
 
-    const unlistenDone = listen("stream-done", () => {
+    const unlistenDone = listen<StreamDoneEvent>("stream-done", (event) => {
       setIsStreaming(false);
-      if (activeThreadId) {
+      setStreamingContent("");
+      setStreamingThinking("");
+      if (event.payload.thread_id === activeThreadId) {
         loadMessages(activeThreadId);
       }
+    });
+
+    // Anything streamed before the failure is saved, so reload to show it
+    const unlistenError = listen<StreamErrorEvent>("stream-error", (event) => {
+      console.error("Generation failed:", event.payload.error);
+      setIsStreaming(false);
       setStreamingContent("");
+      setStreamingThinking("");
+      if (event.payload.thread_id === activeThreadId) {
+        loadMessages(activeThreadId);
+      }
+    });
+
+    const unlistenQueue = listen<QueueUpdatedEvent>("queue-updated", (event) => {
+      if (event.payload.thread_id !== activeThreadId) return;
+      setQueuedCount(event.payload.length);
+    });
+
+    // Changes saved by any window, patched in without a refetch
+    const upsertThread = (thread: Thread) => {
+      setThreads((prev) => {
+        const rest = prev.filter((t) => t.id !== thread.id);
+        if (thread.is_archived) return rest;
+        return prev.some((t) => t.id === thread.id)
+          ? prev.map((t) => (t.id === thread.id ? thread : t))
+          : [thread, ...prev];
+      });
+    };
+    const unlistenThreadCreated = listen<Thread>("thread-created", (event) => upsertThread(event.payload));
+    const unlistenThreadUpdated = listen<Thread>("thread-updated", (event) => upsertThread(event.payload));
+
+    const unlistenThreadDeleted = listen<ThreadDeletedEvent>("thread-deleted", (event) => {
+      setThreads((prev) => prev.filter((t) => t.id !== event.payload.thread_id));
+      if (event.payload.thread_id === activeThreadId) setActiveThreadId(null);
+    });
+
+    const unlistenMessageUpdated = listen<Message>("message-updated", (event) => {
+      if (event.payload.thread_id !== activeThreadId) return;
+      setMessages((prev) => prev.map((m) => (m.id === event.payload.id ? event.payload : m)));
+    });
+
+    const unlistenMessagesDeleted = listen<MessagesDeletedEvent>("messages-deleted", (event) => {
+      if (event.payload.thread_id !== activeThreadId) return;
+      const deleted = new Set(event.payload.message_ids);
+      setMessages((prev) => prev.filter((m) => !deleted.has(m.id)));
+    });
+
+    // Threads flagged or archived at startup are announced once these listeners exist
+    Promise.all([unlistenThreadCreated, unlistenThreadUpdated, unlistenThreadDeleted]).then(() =>
+      invoke("window_ready").catch((error) => console.error("Failed to finish startup", error))
+    );
+
+    // Another database is open; none of the current threads are in it
+    const unlistenWorkspace = listen("workspace-changed", () => {
+      setActiveThreadId(null);
+      loadThreads();
     });
 
     return () => {
+      unlistenThreadCreated.then((f) => f());
+      unlistenThreadUpdated.then((f) => f());
+      unlistenThreadDeleted.then((f) => f());
+      unlistenMessageUpdated.then((f) => f());
+      unlistenMessagesDeleted.then((f) => f());
+      unlistenWorkspace.then((f) => f());
+      unlistenQueue.then((f) => f());
       unlistenResponse.then((f) => f());
+      unlistenThinking.then((f) => f());
       unlistenDone.then((f) => f());
+      unlistenError.then((f) => f());
     };
   }, [activeThreadId, isTauriEnv]);
 
@@ -122,7 +218,7 @@ function App() {
       return;
     }
     try {
-      const models = await invoke<string[]>("list_models");
+      const models = await invoke<string[]>("list_model_names");
       if (models.length > 0) {
         setModels(models);
         setSelectedModel(models[0]);
@@ -191,7 +287,7 @@ function App() {
     }
   };
 
-  const handleSendMessage = async (content: string, images?: string[], pdfs?: string[], replyToId?: number) => {
+  const handleSendMessage = async (content: string, images?: NamedAttachment[], pdfs?: NamedAttachment[], replyToId?: number, files?: FileAttachment[]) => {
     if (!activeThreadId) return;
 
     const tempMsg: Message = {
@@ -199,13 +295,18 @@ function App() {
       thread_id: activeThreadId,
       role: "user",
       content,
-      images,
+      images: images?.map(image => image.data),
+      document_names: pdfs?.map(pdf => pdf.name),
       created_at: new Date().toISOString(),
       reply_to_id: replyToId,
     };
     setMessages((prev) => [...prev, tempMsg]);
-    setIsStreaming(true);
-    setStreamingContent("");
+    // While a response is streaming the message is queued behind it
+    if (!isStreaming) {
+      setIsStreaming(true);
+      setStreamingContent("");
+      setStreamingThinking("");
+    }
 
     if (!isTauriEnv) {
       setTimeout(() => {
@@ -221,14 +322,18 @@ function App() {
       return;
     }
 
+    // Lets the backend drop a repeated invoke of this same send
+    const clientRequestId = crypto.randomUUID();
     try {
       await invoke("send_message", {
         threadId: activeThreadId,
         content,
         images,
         pdfs,
+        files,
         model: selectedModel,
         replyToId,
+        clientRequestId,
       });
     } catch (error) {
       console.error("Failed to send message:", error);
@@ -236,11 +341,21 @@ function App() {
     }
   };
 
+  const handleStopGeneration = async () => {
+    if (!activeThreadId) return;
+    try {
+      await invoke("stop_generation", { threadId: activeThreadId });
+    } catch (error) {
+      console.error("Failed to stop generation:", error);
+    }
+  };
+
   const handleRetry = async () => {
     if (!activeThreadId || isStreaming) return;
 
     setIsStreaming(true);
     setStreamingContent("");
+    setStreamingThinking("");
 
     // Optimistically remove last assistant message if present
     setMessages(prev => {
@@ -266,6 +381,7 @@ function App() {
 
     setIsStreaming(true);
     setStreamingContent("");
+    setStreamingThinking("");
 
     // Optimistically remove the message and subsequent ones
     setMessages(prev => {
@@ -292,6 +408,7 @@ function App() {
 
     setIsStreaming(true);
     setStreamingContent("");
+    setStreamingThinking("");
 
     // Optimistically update UI
     setMessages(prev => {
@@ -409,8 +526,11 @@ function App() {
         <ChatArea
           messages={messages}
           streamingContent={streamingContent}
+          streamingThinking={streamingThinking}
           isStreaming={isStreaming}
           onSendMessage={handleSendMessage}
+          onStop={handleStopGeneration}
+          queuedCount={queuedCount}
           onRetry={handleRetry}
           onEdit={handleEdit}
           onDelete={handleDeleteMessage}
@@ -418,6 +538,7 @@ function App() {
           availableModels={models}
           onRegenerate={handleRegenerateResponse}
           selectedModel={selectedModel}
+          supportsImages={supportsImages}
           onArchive={handleArchiveThread}
           chatModes={CHAT_MODES}
           currentModeId={chatMode}
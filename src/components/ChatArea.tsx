@@ -1,5 +1,5 @@
-import { Send, Paperclip, X, FileText, Sparkles, Settings2, PanelLeft } from "lucide-react";
-import { Message, MessageNode, Theme, ChatMode } from "../types";
+import { Send, Square, Paperclip, X, FileText, Sparkles, Settings2, PanelLeft } from "lucide-react";
+import { Message, MessageNode, Theme, ChatMode, FileAttachment, NamedAttachment } from "../types";
 import { useState, useRef, useEffect, useMemo } from "react";
 import clsx from "clsx";
 import { ThreadItem } from "./ThreadItem";
@@ -8,8 +8,11 @@ import { Tooltip } from "./ui/Tooltip";
 interface ChatAreaProps {
   messages: Message[];
   streamingContent: string;
+  streamingThinking: string;
   isStreaming: boolean;
-  onSendMessage: (content: string, images?: string[], pdfs?: string[], replyToId?: number) => void;
+  onSendMessage: (content: string, images?: NamedAttachment[], pdfs?: NamedAttachment[], replyToId?: number, files?: FileAttachment[]) => void;
+  onStop: () => void;
+  queuedCount: number;
   onRetry: () => void;
   onEdit: (id: number, content: string) => void;
   onDelete: (id: number) => void;
@@ -17,6 +20,7 @@ interface ChatAreaProps {
   availableModels: string[];
   onRegenerate: (messageId: number, model: string) => void;
   selectedModel: string;
+  supportsImages: boolean;
   onArchive: (threadId: number) => void;
   chatModes: ChatMode[];
   currentModeId: string;
@@ -66,8 +70,11 @@ function buildMessageTree(messages: Message[]): MessageNode[] {
 export function ChatArea({
   messages,
   streamingContent,
+  streamingThinking,
   isStreaming,
   onSendMessage,
+  onStop,
+  queuedCount,
   onRetry,
   onEdit,
   onDelete,
@@ -75,6 +82,7 @@ export function ChatArea({
   availableModels,
   onRegenerate,
   selectedModel,
+  supportsImages,
   onArchive,
   chatModes,
   currentModeId,
@@ -84,7 +92,7 @@ export function ChatArea({
   isSidebarOpen
 }: ChatAreaProps) {
   const [input, setInput] = useState("");
-  const [attachments, setAttachments] = useState<{ type: 'image' | 'pdf', content: string, name: string }[]>([]);
+  const [attachments, setAttachments] = useState<{ type: 'image' | 'pdf' | 'file', content: string, name: string, mime?: string }[]>([]);
   const [replyingTo, setReplyingTo] = useState<Message | null>(null);
   const messagesEndRef = useRef<HTMLDivElement>(null);
   const fileInputRef = useRef<HTMLInputElement>(null);
@@ -134,6 +142,7 @@ export function ChatArea({
         thread_id: -1,
         role: 'assistant',
         content: streamingContent,
+        thinking_process: streamingThinking || undefined,
         created_at: new Date().toISOString(),
         children: []
       };
@@ -166,26 +175,34 @@ export function ChatArea({
       }
     }
     return tree;
-  }, [messages, isStreaming, streamingContent]);
+  }, [messages, isStreaming, streamingContent, streamingThinking]);
 
   const handleFileChange = (e: React.ChangeEvent<HTMLInputElement>) => {
     if (e.target.files) {
       const files = Array.from(e.target.files);
       for (const file of files) {
-        if (file.type.startsWith('image/')) {
+        if (file.type.startsWith('image/') && supportsImages) {
           const reader = new FileReader();
           reader.onload = (event) => {
             const content = event.target?.result as string;
             setAttachments(prev => [...prev, { type: 'image', content, name: file.name }]);
           };
           reader.readAsDataURL(file);
-        } else if (file.type === 'application/pdf') {
+        } else if (file.type === 'application/pdf' || file.name.toLowerCase().endsWith('.docx')) {
           const reader = new FileReader();
           reader.onload = (event) => {
             const content = event.target?.result as string;
             setAttachments(prev => [...prev, { type: 'pdf', content, name: file.name }]);
           };
           reader.readAsDataURL(file);
+        } else if (!file.type.startsWith('image/')) {
+          // Text and source files; the backend rejects anything binary
+          const reader = new FileReader();
+          reader.onload = (event) => {
+            const content = event.target?.result as string;
+            setAttachments(prev => [...prev, { type: 'file', content, name: file.name, mime: file.type }]);
+          };
+          reader.readAsDataURL(file);
         }
       }
       e.target.value = '';
@@ -198,16 +215,20 @@ export function ChatArea({
 
   const handleSubmit = (e: React.FormEvent) => {
     e.preventDefault();
-    if ((!input.trim() && attachments.length === 0) || isStreaming) return;
+    if (!input.trim() && attachments.length === 0) return;
 
-    const images = attachments.filter(a => a.type === 'image').map(a => a.content.split(',')[1]);
-    const pdfs = attachments.filter(a => a.type === 'pdf').map(a => a.content.split(',')[1]);
+    const images = attachments.filter(a => a.type === 'image').map(a => ({ name: a.name, data: a.content.split(',')[1] }));
+    const pdfs = attachments.filter(a => a.type === 'pdf').map(a => ({ name: a.name, data: a.content }));
+    const files = attachments
+      .filter(a => a.type === 'file')
+      .map(a => ({ name: a.name, mime: a.mime ?? '', data_base64: a.content.split(',')[1] ?? '' }));
 
     onSendMessage(
       input,
       images.length > 0 ? images : undefined,
       pdfs.length > 0 ? pdfs : undefined,
-      replyingTo?.id // Pass the reply ID
+      replyingTo?.id, // Pass the reply ID
+      files.length > 0 ? files : undefined
     );
     setInput("");
     setAttachments([]);
@@ -217,15 +238,19 @@ export function ChatArea({
   const handleKeyDown = (e: React.KeyboardEvent) => {
     if (e.key === 'Enter' && !e.shiftKey) {
       e.preventDefault();
-      if ((input.trim() || attachments.length > 0) && !isStreaming) {
-        const images = attachments.filter(a => a.type === 'image').map(a => a.content.split(',')[1]);
-        const pdfs = attachments.filter(a => a.type === 'pdf').map(a => a.content.split(',')[1]);
+      if (input.trim() || attachments.length > 0) {
+        const images = attachments.filter(a => a.type === 'image').map(a => ({ name: a.name, data: a.content.split(',')[1] }));
+        const pdfs = attachments.filter(a => a.type === 'pdf').map(a => ({ name: a.name, data: a.content }));
+        const files = attachments
+          .filter(a => a.type === 'file')
+          .map(a => ({ name: a.name, mime: a.mime ?? '', data_base64: a.content.split(',')[1] ?? '' }));
 
         onSendMessage(
           input,
           images.length > 0 ? images : undefined,
           pdfs.length > 0 ? pdfs : undefined,
-          replyingTo?.id
+          replyingTo?.id,
+          files.length > 0 ? files : undefined
         );
         setInput("");
         setAttachments([]);
@@ -390,10 +415,10 @@ export function ChatArea({
               ref={fileInputRef}
               onChange={handleFileChange}
               className="hidden"
-              accept="image/*,.pdf"
+              accept={supportsImages ? "image/*,.pdf,.docx,.txt,.md,.log,.csv,.json,.toml,.yaml,.yml,.xml,.html,.css,.js,.ts,.tsx,.jsx,.py,.rs,.go,.java,.c,.h,.cpp,.hpp,.cs,.rb,.php,.sh,.sql" : ".pdf,.docx,.txt,.md,.log,.csv,.json,.toml,.yaml,.yml,.xml,.html,.css,.js,.ts,.tsx,.jsx,.py,.rs,.go,.java,.c,.h,.cpp,.hpp,.cs,.rb,.php,.sh,.sql"}
               multiple
             />
-            <Tooltip content="Add attachment">
+            <Tooltip content={supportsImages ? "Add attachment" : "Add document or file (this model doesn't accept images)"}>
               <button
                 type="button"
                 onClick={() => fileInputRef.current?.click()}
@@ -416,7 +441,6 @@ export function ChatArea({
                 "flex-1 bg-transparent border-none focus:ring-0 px-2 py-3 focus:outline-none resize-none max-h-48 min-h-[44px] text-[15px] leading-relaxed",
                 isDark ? "text-white placeholder-gray-500" : "text-black placeholder-gray-400"
               )}
-              disabled={isStreaming}
               rows={1}
               style={{ height: 'auto', minHeight: '44px' }}
               onInput={(e) => {
@@ -425,13 +449,27 @@ export function ChatArea({
                 target.style.height = `${target.scrollHeight}px`;
               }}
             />
-            <Tooltip content="Send message">
+            {isStreaming && (
+              <Tooltip content={queuedCount > 0 ? `Stop (${queuedCount} queued)` : "Stop generating"}>
+                <button
+                  type="button"
+                  onClick={onStop}
+                  className={clsx(
+                    "p-3 rounded-full transition-colors mb-0.5",
+                    isDark ? "text-gray-400 hover:text-white hover:bg-[#252525]" : "text-gray-400 hover:text-black hover:bg-gray-100"
+                  )}
+                >
+                  <Square size={18} />
+                </button>
+              </Tooltip>
+            )}
+            <Tooltip content={isStreaming ? "Queue message" : "Send message"}>
               <button
                 type="submit"
-                disabled={(!input.trim() && attachments.length === 0) || isStreaming}
+                disabled={!input.trim() && attachments.length === 0}
                 className={clsx(
                   "p-3 rounded-full transition-all mb-0.5 shadow-sm",
-                  (!input.trim() && attachments.length === 0) || isStreaming
+                  !input.trim() && attachments.length === 0
                     ? "opacity-30 cursor-not-allowed bg-gray-500/20"
                     : (isDark ? "bg-white text-black hover:bg-gray-200 hover:scale-105 active:scale-95" : "bg-black text-white hover:bg-gray-800 hover:scale-105 active:scale-95")
                 )}
@@ -1,8 +1,9 @@
 import { useState } from "react";
+import { invoke } from "@tauri-apps/api/core";
 import { Message, Theme } from "../types";
 import ReactMarkdown from "react-markdown";
 import rehypeRaw from "rehype-raw";
-import { Copy, RefreshCw, Pencil, Brain, User, Bot, MoreHorizontal, MessageSquare, ChevronDown, Trash2, ChevronUp, Check } from "lucide-react";
+import { Copy, RefreshCw, Pencil, Brain, User, Bot, MoreHorizontal, MessageSquare, ChevronDown, Trash2, ChevronUp, Check, StickyNote } from "lucide-react";
 import clsx from "clsx";
 import { Tooltip } from "./ui/Tooltip";
 import { useToast } from "./ui/Toast";
@@ -30,9 +31,11 @@ export function MessageItem({ message, onEdit, onReply, theme, availableModels,
   const [editContent, setEditContent] = useState(message.content);
   const [showModelMenu, setShowModelMenu] = useState(false);
   const [showActionsMenu, setShowActionsMenu] = useState(false);
+  const [fullImage, setFullImage] = useState<string | null>(null);
   const { showToast } = useToast();
   const isDark = theme === 'dark';
   const isUser = message.role === 'user';
+  const isNote = message.role === 'note';
 
   const handleCopy = () => {
     navigator.clipboard.writeText(message.content);
@@ -40,6 +43,16 @@ export function MessageItem({ message, onEdit, onReply, theme, availableModels,
     setShowActionsMenu(false);
   };
 
+  // The list only has thumbnails; the full image is loaded when opened
+  const openImage = async (index: number) => {
+    try {
+      const image = await invoke<string>("get_full_image", { messageId: message.id, index });
+      setFullImage(image.startsWith('data:') ? image : `data:image/jpeg;base64,${image}`);
+    } catch (e) {
+      showToast(`Failed to open image: ${e}`, "error");
+    }
+  };
+
   const handleDelete = () => {
     onDelete(message.id);
     showToast("Message deleted", "success");
@@ -58,7 +71,7 @@ export function MessageItem({ message, onEdit, onReply, theme, availableModels,
     setIsEditing(false);
   };
 
-  // Parsing <think> tag
+  // Messages saved before reasoning was split out may still carry inline <think> tags
   const thinkMatch = message.content.match(/<think(?:[\s\S]*?)>([\s\S]*?)<\/think>/);
   const thinkContent = thinkMatch ? thinkMatch[1] : null;
   const mainContent = message.content.replace(/<think(?:[\s\S]*?)>[\s\S]*?<\/think>/, "").trim();
@@ -75,6 +88,11 @@ export function MessageItem({ message, onEdit, onReply, theme, availableModels,
     }
   }
 
+  // Newer messages keep reasoning in its own field instead of inline tags
+  if (message.thinking_process) {
+    effectiveThinkContent = message.thinking_process;
+  }
+
   const CodeBlock = ({ inline, className, children, ...props }: any) => {
     const match = /language-(\w+)/.exec(className || '');
     const language = match ? match[1] : '';
@@ -148,7 +166,7 @@ export function MessageItem({ message, onEdit, onReply, theme, availableModels,
   return (
     <motion.div
       initial={{ opacity: 0, y: 10 }}
-      animate={{ opacity: 1, y: 0 }}
+      animate={{ opacity: message.exclude_from_context ? 0.5 : 1, y: 0 }}
       transition={{ duration: 0.3 }}
       id={`message-${message.id}`}
       className={clsx(
@@ -161,9 +179,11 @@ export function MessageItem({ message, onEdit, onReply, theme, availableModels,
           "w-8 h-8 rounded-full flex items-center justify-center",
           isUser
             ? (isDark ? "bg-gray-700 text-gray-200" : "bg-white border border-gray-200 text-gray-700")
+            : isNote
+            ? (isDark ? "bg-amber-900/30 text-amber-400" : "bg-amber-50 text-amber-600 border border-amber-100")
             : (isDark ? "bg-blue-900/30 text-blue-400 ring-1 ring-blue-500/20" : "bg-blue-50 text-blue-600 border border-blue-100")
         )}>
-          {isUser ? <User size={16} /> : <Bot size={18} />}
+          {isUser ? <User size={16} /> : isNote ? <StickyNote size={16} /> : <Bot size={18} />}
         </div>
       </div>
 
@@ -172,7 +192,7 @@ export function MessageItem({ message, onEdit, onReply, theme, availableModels,
         {/* Header */}
         <div className="flex items-center gap-2 mb-1">
           <span className={clsx("font-semibold text-sm tracking-tight", isDark ? "text-gray-200" : "text-gray-900")}>
-            {isUser ? "You" : "Assistant"}
+            {isUser ? "You" : isNote ? "Note" : "Assistant"}
           </span>
           <span className={clsx("text-[11px] font-medium opacity-40", isDark ? "text-gray-400" : "text-gray-500")}>
             {new Date(message.created_at || Date.now()).toLocaleTimeString([], { hour: '2-digit', minute: '2-digit' })}
@@ -230,6 +250,12 @@ export function MessageItem({ message, onEdit, onReply, theme, availableModels,
                 </details>
               </div>
             )}
+            {!effectiveThinkContent && message.thinking_discarded && (
+              <div className="mb-4 flex items-center gap-2 text-[11px] uppercase tracking-wider opacity-50" title="Reasoning isn't saved for this thread">
+                <Brain size={12} />
+                Thinking not saved
+              </div>
+            )}
 
             {/* Images */}
             {message.images && message.images.length > 0 && (
@@ -240,7 +266,8 @@ export function MessageItem({ message, onEdit, onReply, theme, availableModels,
                     // Check if it's already a data URL or just base64
                     src={img.startsWith('data:') ? img : `data:image/jpeg;base64,${img}`}
                     alt="Attachment"
-                    className="max-h-80 rounded-xl border border-gray-500/20 object-contain bg-black/5"
+                    onClick={() => openImage(idx)}
+                    className="max-h-80 rounded-xl border border-gray-500/20 object-contain bg-black/5 cursor-zoom-in"
                   />
                 ))}
               </div>
@@ -278,6 +305,21 @@ export function MessageItem({ message, onEdit, onReply, theme, availableModels,
             ) : (
               !effectiveThinkContent && <span className="opacity-50 italic text-sm">Thinking...</span>
             )}
+
+            {message.is_incomplete && (
+              <div className={clsx("mt-3 flex items-center gap-2 text-xs", isDark ? "text-amber-400" : "text-amber-700")}>
+                <span>Response interrupted</span>
+                {message.model && (
+                  <button
+                    onClick={() => onRegenerate(message.id, message.model!)}
+                    className="flex items-center gap-1 underline underline-offset-2 hover:opacity-80"
+                  >
+                    <RefreshCw size={12} />
+                    Regenerate?
+                  </button>
+                )}
+              </div>
+            )}
           </div>
         )}
 
@@ -413,6 +455,15 @@ export function MessageItem({ message, onEdit, onReply, theme, availableModels,
           </div>
         )}
       </div>
+
+      {fullImage && (
+        <div
+          className="fixed inset-0 z-50 flex items-center justify-center bg-black/80 backdrop-blur-sm p-8 cursor-zoom-out"
+          onClick={() => setFullImage(null)}
+        >
+          <img src={fullImage} alt="Attachment" className="max-w-full max-h-full rounded-xl object-contain" />
+        </div>
+      )}
     </motion.div>
   );
 }
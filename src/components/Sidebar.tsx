@@ -118,6 +118,11 @@ export function Sidebar({
             ) : (
               <>
                 <span className="truncate flex-1 font-medium">{thread.title}</span>
+                {thread.needs_attention && (
+                  <Tooltip content="The last reply was interrupted">
+                    <span className="w-1.5 h-1.5 rounded-full bg-amber-500 shrink-0" aria-label="Reply interrupted" />
+                  </Tooltip>
+                )}
                 <div className={clsx("hidden group-hover:flex items-center gap-0.5 opacity-0 group-hover:opacity-100 transition-all absolute right-2 top-1/2 -translate-y-1/2 px-1 rounded-md", isDark ? "bg-[#1a1a1a] shadow-[-10px_0_10px_#1a1a1a]" : "bg-white/80 backdrop-blur-sm shadow-[-10px_0_10px_white]")}>
                   <Tooltip content="Rename chat">
                     <button
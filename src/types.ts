@@ -11,17 +11,64 @@ export interface Thread {
   created_at: string;
   system_prompt?: string;
   is_archived: boolean;
+  think?: boolean | null;
+  model_options?: ModelOptions | null;
+  // The last reply was cut off by the app closing or a failed stream
+  needs_attention?: boolean;
+  summary?: string | null;
+  // Send earlier replies' reasoning back to the model
+  resend_thinking?: boolean;
+  // Overrides the global save_thinking setting
+  save_thinking?: boolean | null;
+  // Only the newest messages are sent to the model; null sends them all
+  max_history_messages?: number | null;
+}
+
+export interface ModelOptions {
+  stop?: string[];
+  num_predict?: number;
+  repeat_penalty?: number;
+  repeat_last_n?: number;
+  mirostat?: 0 | 1 | 2;
+  mirostat_tau?: number;
+  mirostat_eta?: number;
+  num_gpu?: number;
+  num_thread?: number;
+  min_p?: number;
 }
 
 export interface Message {
   id: number;
   thread_id: number;
-  role: 'user' | 'assistant';
+  role: 'user' | 'assistant' | 'system' | 'tool' | 'note' | 'unknown';
   content: string;
   images?: string[];
   created_at: string;
   reply_to_id?: number;
   model?: string;
+  thinking_process?: string;
+  done_reason?: string;
+  first_token_ms?: number | null;
+  image_metadata?: ImageMetadata[] | null;
+  generation_options?: ModelOptions | null;
+  document_names?: string[] | null;
+  // The stream failed partway; content is what arrived before it did
+  is_incomplete?: boolean;
+  // Replaced by an answer from another model, kept for comparison
+  is_superseded?: boolean;
+  variant_of?: number | null;
+  // The model reasoned, but the reasoning wasn't stored
+  thinking_discarded?: boolean;
+  // Left out of the context sent to the model
+  exclude_from_context?: boolean;
+}
+
+export interface ImageMetadata {
+  width: number;
+  height: number;
+  original_width: number;
+  original_height: number;
+  name?: string;
 }
 
 export type MessageNode = Message & { children: MessageNode[] };
@@ -43,3 +90,164 @@ export interface ThemeColors {
   messageAiText: string;
   inputBg: string;
 }
+
+export interface StreamChunkEvent {
+  thread_id: number;
+  chunk: string;
+  // Set by continue_generation: the message the chunk is appended to
+  message_id?: number;
+  // Set while generating an alternative to this answer
+  variant_of?: number;
+}
+
+export interface StreamDoneEvent {
+  thread_id: number;
+  message_id?: number | null;
+  cancelled: boolean;
+  variant_of?: number;
+}
+
+export interface StreamErrorEvent {
+  thread_id: number;
+  error: string;
+}
+
+export interface ThreadDeletedEvent {
+  thread_id: number;
+}
+
+// Sent for messages deleted from a thread, or moved out of it
+export interface MessagesDeletedEvent {
+  thread_id: number;
+  message_ids: number[];
+}
+
+export interface QueueUpdatedEvent {
+  thread_id: number;
+  length: number;
+}
+
+export interface PdfExtractProgressEvent {
+  thread_id: number;
+  filename: string;
+  // Pages read so far, out of the total_pages selected
+  page: number;
+  total_pages: number;
+}
+
+export interface PdfExtractDoneEvent {
+  thread_id: number;
+  filename: string;
+  characters: number;
+}
+
+export interface PageRange {
+  start_page: number;
+  end_page: number;
+}
+
+// A PDF, DOCX or image sent to send_message, with its original file name
+export interface NamedAttachment {
+  name: string;
+  data: string;
+}
+
+export interface FileAttachment {
+  name: string;
+  mime: string;
+  data_base64: string;
+  // CSV files only: send every row instead of a preview
+  include_all_rows?: boolean;
+}
+
+export interface PdfMetadata {
+  title?: string | null;
+  author?: string | null;
+  producer?: string | null;
+  created?: string | null;
+  page_count: number;
+  size_bytes: number;
+  encrypted: boolean;
+}
+
+export interface AttachmentEstimate {
+  estimated_tokens: number;
+  context_length: number | null;
+  fits: boolean;
+}
+
+export interface AttachmentErrorEvent {
+  thread_id: number;
+  kind: 'document' | 'image' | 'url';
+  index: number;
+  name: string;
+  reason: string;
+}
+
+// Returned by get_limits; send_message rejects messages over them
+export interface MessageLimits {
+  max_content_chars: number;
+  max_images: number;
+  max_attachment_bytes: number;
+}
+
+export interface OllamaStatus {
+  reachable: boolean;
+  version: string | null;
+  error: string | null;
+}
+
+export interface AppDiagnostics {
+  app_version: string;
+  database: {
+    path: string;
+    size_bytes: number;
+    thread_count: number;
+    message_count: number;
+    schema_version: number;
+  };
+  // Passwords and query strings are replaced with REDACTED
+  ollama_url: string;
+  ollama_auth_configured: boolean;
+  ollama: OllamaStatus;
+  limits: MessageLimits;
+}
+
+export interface PreviewMessage {
+  role: Message['role'];
+  content: string;
+  images?: string[];
+  thinking?: string;
+  tool_calls?: unknown[];
+  tool_name?: string;
+  estimated_tokens: number;
+}
+
+export interface ContextPreview {
+  model: string;
+  messages: PreviewMessage[];
+  estimated_tokens: number;
+  budget: number | null;
+  // Oldest stored messages left out to fit the budget
+  dropped_messages: number;
+}
+
+export interface SendEstimate {
+  prompt_tokens: number;
+  context_length: number | null;
+  // The context_budget setting, or the context window less room for the reply
+  budget: number | null;
+  // Negative once the oldest messages would be left out
+  remaining: number | null;
+  dropped_messages: number;
+}
+
+export interface Workspace {
+  name: string;
+  db_path: string;
+}
+
+export interface WorkspaceIndex {
+  active: string;
+  workspaces: Workspace[];
+}